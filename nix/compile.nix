@@ -71,6 +71,7 @@ let
     ([ "bundled-libs" "lmdb" "sqlite" "k2v" ] ++ (lib.optionals release [
       "consul-discovery"
       "kubernetes-discovery"
+      "dns-discovery"
       "metrics"
       "telemetry-otlp"
       "syslog"
@@ -0,0 +1,571 @@
+use std::sync::Arc;
+
+use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use garage_util::data::*;
+use garage_util::time::*;
+
+use garage_table::*;
+
+use garage_model::admin_token_table::*;
+use garage_model::garage::Garage;
+use garage_model::helper::audit::AuditTarget;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::{hash_bearer_token, verify_bearer_token, ResBody};
+use crate::bucket::parse_bucket_id;
+use crate::error::*;
+use crate::router_v2::Endpoint;
+
+pub async fn handle_list_admin_tokens(garage: &Arc<Garage>) -> Result<Response<ResBody>, Error> {
+	let tokens = garage
+		.admin_token_table
+		.get_range(
+			&EmptyKey,
+			None,
+			Some(DeletedFilter::NotDeleted),
+			10000,
+			EnumerationOrder::Forward,
+		)
+		.await?;
+
+	let now = now_msec();
+	let res = tokens
+		.iter()
+		.map(|t| admin_token_info_results(t, now))
+		.collect::<Vec<_>>();
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_admin_token_info(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let token = get_existing_admin_token(garage, &id).await?;
+
+	let res = admin_token_info_results(&token, now_msec());
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_create_admin_token(
+	garage: &Arc<Garage>,
+	actor: &str,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<CreateAdminTokenRequest, _, Error>(req).await?;
+
+	let (mut token, secret_token) = AdminApiToken::new(&req.name, hash_bearer_token);
+	let params = token.params_mut().unwrap();
+
+	if let Some(exp) = &req.expiration {
+		params.expiration.update(Some(parse_expiration(exp)?));
+	}
+	params.scope.update(AdminApiTokenScope {
+		endpoints: parse_endpoint_scope(req.scope)?,
+		buckets: parse_bucket_scope(garage, req.buckets).await?,
+	});
+
+	garage.admin_token_table.insert(&token).await?;
+
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"CreateAdminToken",
+			AuditTarget::AdminToken(token.prefix.clone()),
+			None,
+		)
+		.await;
+
+	let res = CreateAdminTokenResponse {
+		secret_token,
+		info: admin_token_info_results(&token, now_msec()),
+	};
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_update_admin_token(
+	garage: &Arc<Garage>,
+	actor: &str,
+	id: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<UpdateAdminTokenRequest, _, Error>(req).await?;
+
+	let mut token = get_existing_admin_token(garage, &id).await?;
+	let target = AuditTarget::AdminToken(token.prefix.clone());
+	let before = garage.audit_helper().summary(&target).await?;
+	let params = token.params_mut().unwrap();
+
+	if let Some(name) = req.name {
+		params.name.update(name);
+	}
+	if req.never_expires {
+		params.expiration.update(None);
+	} else if let Some(exp) = &req.expiration {
+		params.expiration.update(Some(parse_expiration(exp)?));
+	}
+	if req.scope.is_some() || req.buckets.is_some() || req.all_buckets {
+		let mut scope = params.scope.get().clone();
+		if let Some(endpoints) = req.scope {
+			scope.endpoints = parse_endpoint_scope(endpoints)?;
+		}
+		if req.all_buckets {
+			scope.buckets = None;
+		} else if let Some(buckets) = req.buckets {
+			scope.buckets = parse_bucket_scope(garage, Some(buckets)).await?;
+		}
+		params.scope.update(scope);
+	}
+
+	garage.admin_token_table.insert(&token).await?;
+
+	garage
+		.audit_helper()
+		.record(actor, "UpdateAdminToken", target, before)
+		.await;
+
+	let res = admin_token_info_results(&token, now_msec());
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_delete_admin_token(
+	garage: &Arc<Garage>,
+	actor: &str,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let token = get_existing_admin_token(garage, &id).await?;
+	let target = AuditTarget::AdminToken(token.prefix.clone());
+	let before = garage.audit_helper().summary(&target).await?;
+
+	garage
+		.admin_token_table
+		.insert(&AdminApiToken::delete(token.prefix))
+		.await?;
+
+	garage
+		.audit_helper()
+		.record(actor, "DeleteAdminToken", target, before)
+		.await;
+
+	Ok(Response::builder()
+		.status(StatusCode::NO_CONTENT)
+		.body(empty_body())?)
+}
+
+// ---- authentication of requests ----
+
+/// Check a bearer token that is not the admin token from the configuration file
+/// against the tokens stored in the cluster, and return the ID and scope of the token.
+/// The last-used timestamp of the token is updated if it is older than
+/// `LAST_USED_RESOLUTION_MSEC`.
+pub(crate) async fn verify_cluster_admin_token(
+	garage: &Arc<Garage>,
+	token: &str,
+) -> Result<(String, AdminApiTokenScope), Error> {
+	let invalid = || Error::forbidden("Invalid authorization token");
+
+	let (prefix, secret) = token.split_once('.').ok_or_else(invalid)?;
+	let entry = garage
+		.admin_token_table
+		.get(&EmptyKey, &prefix.to_string())
+		.await?
+		.filter(|t| !t.is_deleted())
+		.ok_or_else(invalid)?;
+	let params = entry.params().unwrap();
+
+	if !verify_bearer_token(secret, &params.token_hash) {
+		return Err(invalid());
+	}
+
+	let now = now_msec();
+	if entry.is_expired(now) {
+		return Err(Error::forbidden("Authorization token has expired"));
+	}
+
+	let scope = params.scope.get().clone();
+
+	if now > params.last_used + LAST_USED_RESOLUTION_MSEC {
+		let mut entry = entry.clone();
+		entry.params_mut().unwrap().last_used = now;
+		garage.admin_token_table.insert(&entry).await?;
+	}
+
+	Ok((entry.prefix, scope))
+}
+
+/// For tokens that are restricted to a set of buckets, check that the bucket
+/// designated in the query parameters of the endpoint is one of them.
+/// Endpoints which take the bucket in their request body check it themselves
+/// using `check_bucket_scope`.
+///
+/// Endpoints which do not act on a bucket but on access keys or on the cluster
+/// (keys, layout, nodes, workers, blocks) can only be called by such tokens
+/// if they are listed by name in their scope, as allowing them through `*`
+/// would let the token escape its bucket restriction.
+pub(crate) async fn check_endpoint_bucket_scope(
+	garage: &Arc<Garage>,
+	scope: &AdminApiTokenScope,
+	endpoint: &Endpoint,
+) -> Result<(), Error> {
+	if scope.buckets.is_none() {
+		return Ok(());
+	}
+
+	if !acts_on_buckets(endpoint) && !scope.allows_endpoint_explicitly(endpoint.name()) {
+		return Err(Error::forbidden(format!(
+			"This token is restricted to a set of buckets and cannot call {} unless it is explicitly included in its scope",
+			endpoint.name()
+		)));
+	}
+
+	let bucket_id = match endpoint {
+		Endpoint::GetBucketInfo { id: Some(id), .. }
+		| Endpoint::DeleteBucket { id }
+		| Endpoint::UpdateBucket { id }
+		| Endpoint::GetBucketUsage { id }
+		| Endpoint::GetBucketUsageHistory { id, .. }
+		| Endpoint::SearchBucketObjects { id }
+		| Endpoint::GlobalAliasBucket { id, .. }
+		| Endpoint::GlobalUnaliasBucket { id, .. }
+		| Endpoint::LocalAliasBucket { id, .. }
+		| Endpoint::LocalUnaliasBucket { id, .. } => parse_bucket_id(id)?,
+		Endpoint::GetBucketInfo {
+			id: None,
+			global_alias: Some(alias),
+		} => garage
+			.bucket_helper()
+			.resolve_global_bucket_name(alias)
+			.await?
+			.ok_or_else(|| HelperError::NoSuchBucket(alias.to_string()))?,
+		Endpoint::CreateBucket => {
+			return Err(Error::forbidden(
+				"This token is restricted to a set of buckets and cannot create buckets",
+			))
+		}
+		_ => return Ok(()),
+	};
+
+	check_bucket_scope(Some(scope), &bucket_id)
+}
+
+/// Check that a bucket is allowed by the scope of the token used for the request,
+/// if the request was not made using the admin token from the configuration file.
+pub(crate) fn check_bucket_scope(
+	scope: Option<&AdminApiTokenScope>,
+	bucket_id: &Uuid,
+) -> Result<(), Error> {
+	match scope {
+		Some(s) if !s.allows_bucket(bucket_id) => Err(Error::forbidden(format!(
+			"This token is not allowed to access bucket {:?}",
+			bucket_id
+		))),
+		_ => Ok(()),
+	}
+}
+
+/// Returns true for endpoints whose effect is limited to buckets (which are
+/// then checked against the bucket scope of the token), or which only give
+/// general information about the cluster
+fn acts_on_buckets(endpoint: &Endpoint) -> bool {
+	matches!(
+		endpoint,
+		Endpoint::Options
+			| Endpoint::CheckDomain
+			| Endpoint::Health
+			| Endpoint::Metrics
+			| Endpoint::GetOpenApiSpec
+			| Endpoint::GetClusterStatus
+			| Endpoint::GetClusterHealth
+			| Endpoint::GetClusterStatistics
+			| Endpoint::ListBuckets
+			| Endpoint::CreateBucket
+			| Endpoint::GetBucketInfo { .. }
+			| Endpoint::DeleteBucket { .. }
+			| Endpoint::UpdateBucket { .. }
+			| Endpoint::GetBucketUsage { .. }
+			| Endpoint::GetBucketUsageHistory { .. }
+			| Endpoint::SearchBucketObjects { .. }
+			| Endpoint::BucketAllowKey
+			| Endpoint::BucketDenyKey
+			| Endpoint::GlobalAliasBucket { .. }
+			| Endpoint::GlobalUnaliasBucket { .. }
+			| Endpoint::LocalAliasBucket { .. }
+			| Endpoint::LocalUnaliasBucket { .. }
+	)
+}
+
+// ---- helpers ----
+
+async fn get_existing_admin_token(garage: &Garage, id: &str) -> Result<AdminApiToken, Error> {
+	garage
+		.admin_token_table
+		.get(&EmptyKey, &id.to_string())
+		.await?
+		.filter(|t| !t.is_deleted())
+		.ok_or_else(|| Error::NoSuchAdminToken(id.to_string()))
+}
+
+fn parse_expiration(exp: &str) -> Result<u64, Error> {
+	let exp = chrono::DateTime::parse_from_rfc3339(exp)
+		.ok_or_bad_request("Invalid expiration date, expected RFC 3339 format")?;
+	Ok(exp.timestamp_millis() as u64)
+}
+
+fn parse_endpoint_scope(endpoints: Vec<String>) -> Result<Vec<String>, Error> {
+	for e in endpoints.iter() {
+		if e != "*" && !Endpoint::NAMES.contains(&e.as_str()) {
+			return Err(Error::bad_request(format!(
+				"Invalid endpoint name in token scope: {}",
+				e
+			)));
+		}
+	}
+	Ok(endpoints)
+}
+
+async fn parse_bucket_scope(
+	garage: &Arc<Garage>,
+	buckets: Option<Vec<String>>,
+) -> Result<Option<Vec<Uuid>>, Error> {
+	let buckets = match buckets {
+		None => return Ok(None),
+		Some(b) => b,
+	};
+
+	let mut ret = Vec::with_capacity(buckets.len());
+	for b in buckets.iter() {
+		let bucket_id = parse_bucket_id(b)?;
+		garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		ret.push(bucket_id);
+	}
+	ret.sort();
+	ret.dedup();
+	Ok(Some(ret))
+}
+
+fn admin_token_info_results(token: &AdminApiToken, now: u64) -> GetAdminTokenInfoResponse {
+	let params = token.params().unwrap();
+	let scope = params.scope.get();
+
+	GetAdminTokenInfoResponse {
+		id: token.prefix.clone(),
+		name: params.name.get().to_string(),
+		created: msec_to_rfc3339(params.created),
+		expiration: params.expiration.get().map(msec_to_rfc3339),
+		expired: token.is_expired(now),
+		scope: scope.endpoints.clone(),
+		buckets: scope
+			.buckets
+			.as_ref()
+			.map(|b| b.iter().map(hex::encode).collect()),
+		last_used: Some(params.last_used)
+			.filter(|t| *t > 0)
+			.map(msec_to_rfc3339),
+	}
+}
+
+// ---- request and response types ----
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateAdminTokenRequest {
+	name: String,
+	expiration: Option<String>,
+	scope: Vec<String>,
+	buckets: Option<Vec<String>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateAdminTokenRequest {
+	name: Option<String>,
+	expiration: Option<String>,
+	#[serde(default)]
+	never_expires: bool,
+	scope: Option<Vec<String>>,
+	buckets: Option<Vec<String>>,
+	#[serde(default)]
+	all_buckets: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GetAdminTokenInfoResponse {
+	id: String,
+	name: String,
+	created: String,
+	expiration: Option<String>,
+	expired: bool,
+	scope: Vec<String>,
+	buckets: Option<Vec<String>>,
+	last_used: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CreateAdminTokenResponse {
+	secret_token: String,
+	#[serde(flatten)]
+	info: GetAdminTokenInfoResponse,
+}
+
+#[cfg(test)]
+mod tests {
+	use garage_api_common::common_error::CommonError;
+
+	use super::*;
+	use crate::test_util::*;
+
+	async fn insert_token(
+		garage: &Garage,
+		expiration: Option<u64>,
+		scope: AdminApiTokenScope,
+	) -> String {
+		let (mut token, secret_token) = AdminApiToken::new("test", hash_bearer_token);
+		let params = token.params_mut().unwrap();
+		params.expiration.update(expiration);
+		params.scope.update(scope);
+		garage.admin_token_table.insert(&token).await.unwrap();
+		secret_token
+	}
+
+	fn is_forbidden<T>(res: Result<T, Error>) -> bool {
+		matches!(res, Err(Error::Common(CommonError::Forbidden(_))))
+	}
+
+	fn all_endpoints() -> AdminApiTokenScope {
+		AdminApiTokenScope {
+			endpoints: vec!["*".into()],
+			buckets: None,
+		}
+	}
+
+	#[tokio::test]
+	async fn test_verify_admin_token() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+
+		let scope = AdminApiTokenScope {
+			endpoints: vec!["ListBuckets".into()],
+			buckets: None,
+		};
+		let token = insert_token(&garage, None, scope.clone()).await;
+		let (prefix, secret) = token.split_once('.').unwrap();
+
+		let (id, token_scope) = verify_cluster_admin_token(&garage, &token).await.unwrap();
+		assert_eq!(id, prefix);
+		assert_eq!(token_scope, scope);
+		let entry = get_existing_admin_token(&garage, &id).await.unwrap();
+		assert!(entry.params().unwrap().last_used > 0);
+
+		// Wrong secret, missing secret, unknown token
+		let wrong = format!("{}.{}", prefix, hex::encode([0u8; 32]));
+		assert!(is_forbidden(
+			verify_cluster_admin_token(&garage, &wrong).await
+		));
+		assert!(is_forbidden(
+			verify_cluster_admin_token(&garage, prefix).await
+		));
+		let unknown = format!("{}.{}", hex::encode([0u8; 12]), secret);
+		assert!(is_forbidden(
+			verify_cluster_admin_token(&garage, &unknown).await
+		));
+
+		// Deleted token
+		garage
+			.admin_token_table
+			.insert(&AdminApiToken::delete(id))
+			.await
+			.unwrap();
+		assert!(is_forbidden(
+			verify_cluster_admin_token(&garage, &token).await
+		));
+	}
+
+	#[tokio::test]
+	async fn test_admin_token_expiration() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+
+		let now = now_msec();
+		let expired = insert_token(&garage, Some(now - 1000), all_endpoints()).await;
+		let valid = insert_token(&garage, Some(now + 3_600_000), all_endpoints()).await;
+
+		assert!(is_forbidden(
+			verify_cluster_admin_token(&garage, &expired).await
+		));
+		assert!(verify_cluster_admin_token(&garage, &valid).await.is_ok());
+	}
+
+	#[tokio::test]
+	async fn test_admin_token_bucket_scope() {
+		let (garage, _dir) = test_garage();
+		let bucket = gen_uuid();
+		let other_bucket = gen_uuid();
+		let scope = AdminApiTokenScope {
+			endpoints: vec!["*".into(), "GetNodeStatistics".into()],
+			buckets: Some(vec![bucket]),
+		};
+		let check = |endpoint: Endpoint| {
+			let garage = garage.clone();
+			let scope = scope.clone();
+			async move { check_endpoint_bucket_scope(&garage, &scope, &endpoint).await }
+		};
+
+		// Bucket endpoints are only allowed on the buckets of the scope
+		let get_bucket = |id: Uuid| Endpoint::GetBucketInfo {
+			id: Some(hex::encode(id)),
+			global_alias: None,
+		};
+		assert!(check(get_bucket(bucket)).await.is_ok());
+		assert!(is_forbidden(check(get_bucket(other_bucket)).await));
+		assert!(is_forbidden(check(Endpoint::CreateBucket).await));
+		assert!(check(Endpoint::ListBuckets).await.is_ok());
+
+		// Key and cluster endpoints are not allowed through `*`
+		assert!(is_forbidden(
+			check(Endpoint::GetKeyInfo {
+				id: Some("GK123".into()),
+				search: None,
+				show_secret_key: Some("true".into()),
+			})
+			.await
+		));
+		assert!(is_forbidden(check(Endpoint::ImportKey).await));
+		assert!(is_forbidden(
+			check(Endpoint::DeleteKey { id: "GK123".into() }).await
+		));
+		assert!(is_forbidden(check(Endpoint::ApplyClusterLayout).await));
+		assert!(is_forbidden(
+			check(Endpoint::PurgeBlocks { node: "*".into() }).await
+		));
+
+		// ... but they are when listed explicitly
+		assert!(check(Endpoint::GetNodeStatistics { node: "*".into() })
+			.await
+			.is_ok());
+
+		// Tokens that are not restricted to buckets are not affected
+		let unrestricted = all_endpoints();
+		assert!(
+			check_endpoint_bucket_scope(&garage, &unrestricted, &Endpoint::ImportKey)
+				.await
+				.is_ok()
+		);
+	}
+
+	#[test]
+	fn test_parse_endpoint_scope() {
+		let scope = vec!["*".to_string(), "GetKeyInfo".to_string()];
+		assert_eq!(parse_endpoint_scope(scope.clone()).unwrap(), scope);
+		assert!(parse_endpoint_scope(vec!["GetKeyInfos".into()]).is_err());
+		assert!(parse_endpoint_scope(vec!["".into()]).is_err());
+	}
+}
@@ -3,7 +3,9 @@ use std::sync::Arc;
 
 use argon2::password_hash::PasswordHash;
 
-use http::header::{ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ALLOW};
+use http::header::{
+	ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN, ALLOW, AUTHORIZATION,
+};
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use tokio::sync::watch;
 
@@ -14,6 +16,7 @@ use opentelemetry_prometheus::PrometheusExporter;
 #[cfg(feature = "metrics")]
 use prometheus::{Encoder, TextEncoder};
 
+use garage_model::admin_token_table::AdminApiTokenScope;
 use garage_model::garage::Garage;
 use garage_rpc::system::ClusterHealthStatus;
 use garage_util::error::Error as GarageError;
@@ -22,17 +25,27 @@ use garage_util::socket_address::UnixOrTCPSocketAddress;
 use garage_api_common::generic_server::*;
 use garage_api_common::helpers::*;
 
+use crate::admin_token::*;
+use crate::audit::*;
+use crate::block::*;
 use crate::bucket::*;
 use crate::cluster::*;
+use crate::drain::*;
 use crate::error::*;
 use crate::key::*;
+use crate::layout_simulate::*;
+use crate::node::*;
+use crate::openapi::openapi_json;
 use crate::router_v0;
-use crate::router_v1::{Authorization, Endpoint};
+use crate::router_v1;
+use crate::router_v2::{Authorization, Endpoint};
+use crate::worker::*;
 
 pub type ResBody = BoxBody<Error>;
 
 pub struct AdminApiServer {
 	garage: Arc<Garage>,
+	node_rpc: Arc<AdminNodeRpcHandler>,
 	#[cfg(feature = "metrics")]
 	exporter: PrometheusExporter,
 	metrics_token: Option<String>,
@@ -41,14 +54,16 @@ pub struct AdminApiServer {
 
 impl AdminApiServer {
 	pub fn new(
-		garage: Arc<Garage>,
+		node_rpc: Arc<AdminNodeRpcHandler>,
 		#[cfg(feature = "metrics")] exporter: PrometheusExporter,
 	) -> Self {
+		let garage = node_rpc.garage.clone();
 		let cfg = &garage.config.admin;
 		let metrics_token = cfg.metrics_token.as_deref().map(hash_bearer_token);
 		let admin_token = cfg.admin_token.as_deref().map(hash_bearer_token);
 		Self {
 			garage,
+			node_rpc,
 			#[cfg(feature = "metrics")]
 			exporter,
 			metrics_token,
@@ -67,6 +82,42 @@ impl AdminApiServer {
 			.await
 	}
 
+	/// Authenticate a request to an endpoint that requires an admin token.
+	/// Returns the identity of the caller as recorded in the audit log, and
+	/// `None` if the request was made with the admin token from the
+	/// configuration file, which gives access to everything, or the scope of
+	/// the token if it was made using a token stored in the cluster.
+	async fn check_admin_token(
+		&self,
+		req: &Request<IncomingBody>,
+		endpoint: &Endpoint,
+	) -> Result<(String, Option<AdminApiTokenScope>), Error> {
+		let token = bearer_token(req)?;
+
+		if let Some(password_hash) = self.admin_token.as_deref() {
+			if verify_bearer_token(token, password_hash) {
+				return Ok(("admin_token".to_string(), None));
+			}
+		}
+
+		if matches!(endpoint.authorization_type(), Authorization::RootAdminToken) {
+			return Err(Error::forbidden(
+				"This endpoint can only be called using the admin token from the configuration file",
+			));
+		}
+
+		let (token_id, scope) = verify_cluster_admin_token(&self.garage, token).await?;
+		if !scope.allows_endpoint(endpoint.name()) {
+			return Err(Error::forbidden(format!(
+				"This token is not allowed to call {}",
+				endpoint.name()
+			)));
+		}
+		check_endpoint_bucket_scope(&self.garage, &scope, endpoint).await?;
+
+		Ok((format!("admin_token:{}", token_id), Some(scope)))
+	}
+
 	fn handle_options(&self, _req: &Request<IncomingBody>) -> Result<Response<ResBody>, Error> {
 		Ok(Response::builder()
 			.status(StatusCode::NO_CONTENT)
@@ -181,7 +232,7 @@ impl AdminApiServer {
 			),
 		};
 		let status_str = format!(
-			"{}\nConsult the full health check API endpoint at /v1/health for more details\n",
+			"{}\nConsult the full health check API endpoint at /v2/health for more details\n",
 			status_str
 		);
 
@@ -191,6 +242,13 @@ impl AdminApiServer {
 			.body(string_body(status_str))?)
 	}
 
+	fn handle_get_openapi_spec(&self) -> Result<Response<ResBody>, Error> {
+		Ok(Response::builder()
+			.status(StatusCode::OK)
+			.header(http::header::CONTENT_TYPE, "application/json")
+			.body(string_body(openapi_json()))?)
+	}
+
 	fn handle_metrics(&self) -> Result<Response<ResBody>, Error> {
 		#[cfg(feature = "metrics")]
 		{
@@ -230,7 +288,11 @@ impl ApiHandler for AdminApiServer {
 	fn parse_endpoint(&self, req: &Request<IncomingBody>) -> Result<Endpoint, Error> {
 		if req.uri().path().starts_with("/v0/") {
 			let endpoint_v0 = router_v0::Endpoint::from_request(req)?;
-			Endpoint::from_v0(endpoint_v0)
+			let endpoint_v1 = router_v1::Endpoint::from_v0(endpoint_v0)?;
+			Ok(Endpoint::from_v1(endpoint_v1))
+		} else if req.uri().path().starts_with("/v1/") {
+			let endpoint_v1 = router_v1::Endpoint::from_request(req)?;
+			Ok(Endpoint::from_v1(endpoint_v1))
 		} else {
 			Endpoint::from_request(req)
 		}
@@ -241,40 +303,44 @@ impl ApiHandler for AdminApiServer {
 		req: Request<IncomingBody>,
 		endpoint: Endpoint,
 	) -> Result<Response<ResBody>, Error> {
-		let required_auth_hash =
-			match endpoint.authorization_type() {
-				Authorization::None => None,
-				Authorization::MetricsToken => self.metrics_token.as_deref(),
-				Authorization::AdminToken => match self.admin_token.as_deref() {
-					None => return Err(Error::forbidden(
-						"Admin token isn't configured, admin API access is disabled for security.",
-					)),
-					Some(t) => Some(t),
-				},
-			};
-
-		if let Some(password_hash) = required_auth_hash {
-			match req.headers().get("Authorization") {
-				None => return Err(Error::forbidden("Authorization token must be provided")),
-				Some(authorization) => {
-					verify_bearer_token(&authorization, password_hash)?;
+		let (actor, scope) = match endpoint.authorization_type() {
+			Authorization::None => ("anonymous".to_string(), None),
+			Authorization::MetricsToken => {
+				if let Some(password_hash) = self.metrics_token.as_deref() {
+					let token = bearer_token(&req)?;
+					if !verify_bearer_token(token, password_hash) {
+						return Err(Error::forbidden("Invalid authorization token"));
+					}
 				}
+				("anonymous".to_string(), None)
 			}
-		}
+			Authorization::AdminToken | Authorization::RootAdminToken => {
+				self.check_admin_token(&req, &endpoint).await?
+			}
+		};
 
 		match endpoint {
 			Endpoint::Options => self.handle_options(&req),
 			Endpoint::CheckDomain => self.handle_check_domain(req).await,
 			Endpoint::Health => self.handle_health(),
 			Endpoint::Metrics => self.handle_metrics(),
+			Endpoint::GetOpenApiSpec => self.handle_get_openapi_spec(),
 			Endpoint::GetClusterStatus => handle_get_cluster_status(&self.garage).await,
 			Endpoint::GetClusterHealth => handle_get_cluster_health(&self.garage).await,
+			Endpoint::GetClusterStatistics => handle_get_cluster_statistics(&self.garage).await,
 			Endpoint::ConnectClusterNodes => handle_connect_cluster_nodes(&self.garage, req).await,
 			// Layout
 			Endpoint::GetClusterLayout => handle_get_cluster_layout(&self.garage).await,
-			Endpoint::UpdateClusterLayout => handle_update_cluster_layout(&self.garage, req).await,
-			Endpoint::ApplyClusterLayout => handle_apply_cluster_layout(&self.garage, req).await,
-			Endpoint::RevertClusterLayout => handle_revert_cluster_layout(&self.garage).await,
+			Endpoint::UpdateClusterLayout => {
+				handle_update_cluster_layout(&self.garage, &actor, req).await
+			}
+			Endpoint::ApplyClusterLayout => {
+				handle_apply_cluster_layout(&self.garage, &actor, req).await
+			}
+			Endpoint::RevertClusterLayout => {
+				handle_revert_cluster_layout(&self.garage, &actor).await
+			}
+			Endpoint::SimulateClusterLayout => handle_simulate_cluster_layout(&self.node_rpc).await,
 			// Keys
 			Endpoint::ListKeys => handle_list_keys(&self.garage).await,
 			Endpoint::GetKeyInfo {
@@ -285,42 +351,123 @@ impl ApiHandler for AdminApiServer {
 				let show_secret_key = show_secret_key.map(|x| x == "true").unwrap_or(false);
 				handle_get_key_info(&self.garage, id, search, show_secret_key).await
 			}
-			Endpoint::CreateKey => handle_create_key(&self.garage, req).await,
-			Endpoint::ImportKey => handle_import_key(&self.garage, req).await,
-			Endpoint::UpdateKey { id } => handle_update_key(&self.garage, id, req).await,
-			Endpoint::DeleteKey { id } => handle_delete_key(&self.garage, id).await,
+			Endpoint::CreateKey => handle_create_key(&self.garage, &actor, req).await,
+			Endpoint::ImportKey => handle_import_key(&self.garage, &actor, req).await,
+			Endpoint::UpdateKey { id } => handle_update_key(&self.garage, &actor, id, req).await,
+			Endpoint::DeleteKey { id } => handle_delete_key(&self.garage, &actor, id).await,
 			// Buckets
-			Endpoint::ListBuckets => handle_list_buckets(&self.garage).await,
+			Endpoint::ListBuckets => handle_list_buckets(&self.garage, scope.as_ref()).await,
 			Endpoint::GetBucketInfo { id, global_alias } => {
 				handle_get_bucket_info(&self.garage, id, global_alias).await
 			}
-			Endpoint::CreateBucket => handle_create_bucket(&self.garage, req).await,
-			Endpoint::DeleteBucket { id } => handle_delete_bucket(&self.garage, id).await,
-			Endpoint::UpdateBucket { id } => handle_update_bucket(&self.garage, id, req).await,
+			Endpoint::CreateBucket => handle_create_bucket(&self.garage, &actor, req).await,
+			Endpoint::DeleteBucket { id } => handle_delete_bucket(&self.garage, &actor, id).await,
+			Endpoint::GetBucketUsage { id } => handle_get_bucket_usage(&self.garage, id).await,
+			Endpoint::GetBucketUsageHistory {
+				id,
+				resolution,
+				since,
+				until,
+			} => handle_get_bucket_usage_history(&self.garage, id, resolution, since, until).await,
+			Endpoint::SearchBucketObjects { id } => {
+				handle_search_bucket_objects(&self.garage, id, req).await
+			}
+			Endpoint::UpdateBucket { id } => {
+				handle_update_bucket(&self.garage, &actor, id, req).await
+			}
 			// Bucket-key permissions
 			Endpoint::BucketAllowKey => {
-				handle_bucket_change_key_perm(&self.garage, req, true).await
+				handle_bucket_change_key_perm(&self.garage, &actor, req, true, scope.as_ref()).await
 			}
 			Endpoint::BucketDenyKey => {
-				handle_bucket_change_key_perm(&self.garage, req, false).await
+				handle_bucket_change_key_perm(&self.garage, &actor, req, false, scope.as_ref())
+					.await
 			}
 			// Bucket aliasing
 			Endpoint::GlobalAliasBucket { id, alias } => {
-				handle_global_alias_bucket(&self.garage, id, alias).await
+				handle_global_alias_bucket(&self.garage, &actor, id, alias).await
 			}
 			Endpoint::GlobalUnaliasBucket { id, alias } => {
-				handle_global_unalias_bucket(&self.garage, id, alias).await
+				handle_global_unalias_bucket(&self.garage, &actor, id, alias).await
 			}
 			Endpoint::LocalAliasBucket {
 				id,
 				access_key_id,
 				alias,
-			} => handle_local_alias_bucket(&self.garage, id, access_key_id, alias).await,
+			} => handle_local_alias_bucket(&self.garage, &actor, id, access_key_id, alias).await,
 			Endpoint::LocalUnaliasBucket {
 				id,
 				access_key_id,
 				alias,
-			} => handle_local_unalias_bucket(&self.garage, id, access_key_id, alias).await,
+			} => handle_local_unalias_bucket(&self.garage, &actor, id, access_key_id, alias).await,
+			// Admin API tokens
+			Endpoint::ListAdminTokens => handle_list_admin_tokens(&self.garage).await,
+			Endpoint::GetAdminTokenInfo { id } => {
+				handle_get_admin_token_info(&self.garage, id).await
+			}
+			Endpoint::CreateAdminToken => {
+				handle_create_admin_token(&self.garage, &actor, req).await
+			}
+			Endpoint::UpdateAdminToken { id } => {
+				handle_update_admin_token(&self.garage, &actor, id, req).await
+			}
+			Endpoint::DeleteAdminToken { id } => {
+				handle_delete_admin_token(&self.garage, &actor, id).await
+			}
+			// Audit log
+			Endpoint::ListAuditRecords {
+				since,
+				until,
+				limit,
+			} => handle_list_audit_records(&self.garage, since, until, limit).await,
+			// Node operations
+			Endpoint::GetNodeStatistics { node } => {
+				handle_get_node_statistics(&self.node_rpc, node).await
+			}
+			Endpoint::GetNodeGcQueue { node } => {
+				handle_get_node_gc_queue(&self.node_rpc, node).await
+			}
+			Endpoint::CreateMetadataSnapshot { node } => {
+				handle_create_metadata_snapshot(&self.node_rpc, node).await
+			}
+			Endpoint::LaunchRepairOperation { node } => {
+				handle_launch_repair_operation(&self.node_rpc, node, req).await
+			}
+			Endpoint::DrainClusterNode { node } => {
+				handle_drain_cluster_node(&self.garage, &self.node_rpc, &actor, node).await
+			}
+			Endpoint::GetNodeDrainStatus { node } => {
+				handle_get_node_drain_status(&self.garage, &self.node_rpc, node).await
+			}
+			Endpoint::SetNodeMaintenance { node } => {
+				handle_set_node_maintenance(&self.node_rpc, node, req).await
+			}
+			// Workers
+			Endpoint::ListWorkers {
+				node,
+				busy_only,
+				error_only,
+			} => handle_list_workers(&self.node_rpc, node, busy_only, error_only).await,
+			Endpoint::GetWorkerInfo { node, id } => {
+				handle_get_worker_info(&self.node_rpc, node, id).await
+			}
+			Endpoint::GetWorkerVariable { node, variable } => {
+				handle_get_worker_variable(&self.node_rpc, node, variable).await
+			}
+			Endpoint::SetWorkerVariable { node } => {
+				handle_set_worker_variable(&self.node_rpc, node, req).await
+			}
+			// Blocks
+			Endpoint::ListBlockErrors { node } => {
+				handle_list_block_errors(&self.node_rpc, node).await
+			}
+			Endpoint::GetBlockInfo { node, hash } => {
+				handle_get_block_info(&self.node_rpc, node, hash).await
+			}
+			Endpoint::RetryBlockResync { node } => {
+				handle_retry_block_resync(&self.node_rpc, node, req).await
+			}
+			Endpoint::PurgeBlocks { node } => handle_purge_blocks(&self.node_rpc, node, req).await,
 		}
 	}
 }
@@ -333,7 +480,7 @@ impl ApiEndpoint for Endpoint {
 	fn add_span_attributes(&self, _span: SpanRef<'_>) {}
 }
 
-fn hash_bearer_token(token: &str) -> String {
+pub(crate) fn hash_bearer_token(token: &str) -> String {
 	use argon2::{
 		password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
 		Argon2,
@@ -347,20 +494,27 @@ fn hash_bearer_token(token: &str) -> String {
 		.to_string()
 }
 
-fn verify_bearer_token(token: &hyper::http::HeaderValue, password_hash: &str) -> Result<(), Error> {
+pub(crate) fn verify_bearer_token(token: &str, password_hash: &str) -> bool {
 	use argon2::{password_hash::PasswordVerifier, Argon2};
 
-	let parsed_hash = PasswordHash::new(&password_hash).unwrap();
+	let parsed_hash = match PasswordHash::new(password_hash) {
+		Ok(h) => h,
+		Err(_) => return false,
+	};
 
-	token
+	Argon2::default()
+		.verify_password(token.trim().as_bytes(), &parsed_hash)
+		.is_ok()
+}
+
+fn bearer_token<B>(req: &Request<B>) -> Result<&str, Error> {
+	let authorization = req
+		.headers()
+		.get(AUTHORIZATION)
+		.ok_or_else(|| Error::forbidden("Authorization token must be provided"))?;
+	let token = authorization
 		.to_str()?
 		.strip_prefix("Bearer ")
-		.and_then(|token| {
-			Argon2::default()
-				.verify_password(token.trim().as_bytes(), &parsed_hash)
-				.ok()
-		})
 		.ok_or_else(|| Error::forbidden("Invalid authorization token"))?;
-
-	Ok(())
+	Ok(token.trim())
 }
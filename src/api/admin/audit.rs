@@ -0,0 +1,72 @@
+use std::sync::Arc;
+
+use hyper::Response;
+use serde::Serialize;
+
+use garage_util::time::*;
+
+use garage_model::audit_table::AuditRecord;
+use garage_model::garage::Garage;
+
+use garage_api_common::helpers::*;
+
+use crate::api_server::ResBody;
+use crate::error::*;
+
+/// Maximum number of records returned by a single call to ListAuditRecords
+const MAX_AUDIT_RECORDS: usize = 1000;
+
+pub async fn handle_list_audit_records(
+	garage: &Arc<Garage>,
+	since: Option<String>,
+	until: Option<String>,
+	limit: Option<String>,
+) -> Result<Response<ResBody>, Error> {
+	let since = since.as_deref().map(parse_time).transpose()?;
+	let until = until.as_deref().map(parse_time).transpose()?;
+	let limit = match limit {
+		Some(l) => l
+			.parse::<usize>()
+			.ok_or_bad_request("Invalid limit, expected a number")?
+			.min(MAX_AUDIT_RECORDS),
+		None => MAX_AUDIT_RECORDS,
+	};
+
+	let records = garage.audit_helper().list(since, until, limit).await?;
+
+	let res = records.iter().map(audit_record_results).collect::<Vec<_>>();
+	Ok(json_ok_response(&res)?)
+}
+
+/// Parse a time bound given in RFC 3339 format into msec since UNIX epoch
+pub(crate) fn parse_time(t: &str) -> Result<u64, Error> {
+	let t = chrono::DateTime::parse_from_rfc3339(t)
+		.ok_or_bad_request("Invalid date, expected RFC 3339 format")?;
+	Ok(t.timestamp_millis().max(0) as u64)
+}
+
+fn audit_record_results(record: &AuditRecord) -> AuditRecordResponse {
+	AuditRecordResponse {
+		id: record.id.clone(),
+		timestamp: msec_to_rfc3339(record.timestamp),
+		node: hex::encode(record.node),
+		actor: record.actor.clone(),
+		operation: record.operation.clone(),
+		target: record.target.clone(),
+		before: record.before.clone(),
+		after: record.after.clone(),
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct AuditRecordResponse {
+	id: String,
+	timestamp: String,
+	node: String,
+	actor: String,
+	operation: String,
+	target: String,
+	before: Option<String>,
+	after: Option<String>,
+}
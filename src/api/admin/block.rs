@@ -0,0 +1,398 @@
+use std::sync::Arc;
+
+use hyper::{body::Incoming as IncomingBody, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+use garage_util::time::now_msec;
+
+use garage_table::EmptyKey;
+
+use garage_model::garage::Garage;
+use garage_model::helper::error::OkOrBadRequest;
+use garage_model::s3::object_table::*;
+use garage_model::s3::version_table::*;
+
+use garage_api_common::helpers::{json_ok_response, parse_json_body};
+
+use crate::api_server::ResBody;
+use crate::error::*;
+use crate::node::*;
+
+pub async fn handle_list_block_errors(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::ListBlockErrors, |r| match r {
+			AdminNodeRpcResponse::BlockErrors(l) => Some(l),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_block_info(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	hash: String,
+) -> Result<Response<ResBody>, Error> {
+	let req = GetBlockInfoRequest { block_hash: hash };
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::GetBlockInfo(req), |r| match r {
+			AdminNodeRpcResponse::BlockInfo(i) => Some(i),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_retry_block_resync(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<RetryBlockResyncRequest, _, Error>(req).await?;
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::RetryBlockResync(req), |r| match r {
+			AdminNodeRpcResponse::BlockResyncRetried(c) => Some(c),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_purge_blocks(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<PurgeBlocksRequest, _, Error>(req).await?;
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::PurgeBlocks(req), |r| match r {
+			AdminNodeRpcResponse::BlocksPurged(p) => Some(p),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+// ---- local operations ----
+
+pub(crate) fn local_list_block_errors(
+	garage: &Arc<Garage>,
+) -> Result<ListBlockErrorsResponse, HelperError> {
+	let now = now_msec();
+	let errors = garage
+		.block_manager
+		.list_resync_errors()?
+		.into_iter()
+		.map(|e| BlockErrorResp {
+			block_hash: hex::encode(e.hash),
+			refcount: e.refcount,
+			error_count: e.error_count,
+			last_try_secs_ago: now.saturating_sub(e.last_try) / 1000,
+			next_try_in_secs: e.next_try.saturating_sub(now) / 1000,
+		})
+		.collect();
+	Ok(errors)
+}
+
+pub(crate) async fn local_get_block_info(
+	garage: &Arc<Garage>,
+	req: &GetBlockInfoRequest,
+) -> Result<GetBlockInfoResponse, HelperError> {
+	let hash = find_block_hash_by_prefix(garage, &req.block_hash)?;
+	let refcount = garage.block_manager.get_block_rc(&hash)?;
+	let block_refs = garage
+		.block_ref_table
+		.get_range(&hash, None, None, 10000, Default::default())
+		.await?;
+	let mut versions = vec![];
+	for br in block_refs {
+		if let Some(v) = garage.version_table.get(&br.version, &EmptyKey).await? {
+			let backlink = match &v.backlink {
+				VersionBacklink::Object { bucket_id, key } => BlockVersionBacklink::Object {
+					bucket_id: hex::encode(bucket_id),
+					key: key.to_string(),
+				},
+				VersionBacklink::MultipartUpload { upload_id } => {
+					let upload = garage.mpu_table.get(upload_id, &EmptyKey).await?;
+					BlockVersionBacklink::Upload {
+						upload_id: hex::encode(upload_id),
+						upload_deleted: upload.as_ref().map(|u| u.deleted.get()).unwrap_or(true),
+						upload_garbage_collected: upload.is_none(),
+						bucket_id: upload.as_ref().map(|u| hex::encode(u.bucket_id)),
+						key: upload.as_ref().map(|u| u.key.to_string()),
+					}
+				}
+			};
+			versions.push(BlockVersion {
+				version_id: hex::encode(br.version),
+				deleted: v.deleted.get(),
+				garbage_collected: false,
+				backlink: Some(backlink),
+			});
+		} else {
+			versions.push(BlockVersion {
+				version_id: hex::encode(br.version),
+				deleted: true,
+				garbage_collected: true,
+				backlink: None,
+			});
+		}
+	}
+	Ok(GetBlockInfoResponse {
+		block_hash: hex::encode(hash),
+		refcount,
+		versions,
+	})
+}
+
+pub fn local_retry_block_resync(
+	garage: &Arc<Garage>,
+	req: &RetryBlockResyncRequest,
+) -> Result<RetryBlockResyncResponse, HelperError> {
+	if req.all {
+		if !req.block_hashes.is_empty() {
+			return Err(HelperError::BadRequest(
+				"all was specified, cannot also specify blocks".into(),
+			));
+		}
+		let blocks = garage.block_manager.list_resync_errors()?;
+		for b in blocks.iter() {
+			garage.block_manager.resync.clear_backoff(&b.hash)?;
+		}
+		Ok(RetryBlockResyncResponse {
+			count: blocks.len() as u64,
+		})
+	} else {
+		for hash in req.block_hashes.iter() {
+			let hash = parse_block_hash(hash)?;
+			garage.block_manager.resync.clear_backoff(&hash)?;
+		}
+		Ok(RetryBlockResyncResponse {
+			count: req.block_hashes.len() as u64,
+		})
+	}
+}
+
+/// Delete all objects, versions and multipart uploads that reference
+/// the given blocks, so that they can be garbage collected
+pub async fn local_purge_blocks(
+	garage: &Arc<Garage>,
+	req: &PurgeBlocksRequest,
+) -> Result<PurgeBlocksResponse, HelperError> {
+	let mut obj_dels = 0;
+	let mut mpu_dels = 0;
+	let mut ver_dels = 0;
+
+	for hash in req.iter() {
+		let hash = parse_block_hash(hash)?;
+		let block_refs = garage
+			.block_ref_table
+			.get_range(&hash, None, None, 10000, Default::default())
+			.await?;
+
+		for br in block_refs {
+			if let Some(version) = garage.version_table.get(&br.version, &EmptyKey).await? {
+				purge_version_backlink(garage, &version, &mut obj_dels, &mut mpu_dels).await?;
+
+				if !version.deleted.get() {
+					let deleted_version = Version::new(version.uuid, version.backlink, true);
+					garage.version_table.insert(&deleted_version).await?;
+					ver_dels += 1;
+				}
+			}
+		}
+	}
+
+	Ok(PurgeBlocksResponse {
+		blocks_purged: req.len() as u64,
+		versions_deleted: ver_dels,
+		objects_deleted: obj_dels,
+		uploads_deleted: mpu_dels,
+	})
+}
+
+async fn purge_version_backlink(
+	garage: &Arc<Garage>,
+	version: &Version,
+	obj_dels: &mut u64,
+	mpu_dels: &mut u64,
+) -> Result<(), HelperError> {
+	let (bucket_id, key, ov_id) = match &version.backlink {
+		VersionBacklink::Object { bucket_id, key } => (*bucket_id, key.clone(), version.uuid),
+		VersionBacklink::MultipartUpload { upload_id } => {
+			if let Some(mut mpu) = garage.mpu_table.get(upload_id, &EmptyKey).await? {
+				if !mpu.deleted.get() {
+					mpu.parts.clear();
+					mpu.deleted.set();
+					garage.mpu_table.insert(&mpu).await?;
+					*mpu_dels += 1;
+				}
+				(mpu.bucket_id, mpu.key.clone(), *upload_id)
+			} else {
+				return Ok(());
+			}
+		}
+	};
+
+	if let Some(object) = garage.object_table.get(&bucket_id, &key).await? {
+		let ov = object.versions().iter().rev().find(|v| v.is_complete());
+		if let Some(ov) = ov {
+			if ov.uuid == ov_id {
+				let del_uuid = gen_uuid();
+				let deleted_object = Object::new(
+					bucket_id,
+					key,
+					vec![ObjectVersion {
+						uuid: del_uuid,
+						timestamp: ov.timestamp + 1,
+						state: ObjectVersionState::Complete(ObjectVersionData::DeleteMarker),
+					}],
+				);
+				garage.object_table.insert(&deleted_object).await?;
+				*obj_dels += 1;
+			}
+		}
+	}
+
+	Ok(())
+}
+
+// ---- helper functions ----
+
+fn parse_block_hash(hash: &str) -> Result<Hash, HelperError> {
+	let hash = hex::decode(hash).ok_or_bad_request("invalid hash")?;
+	Hash::try_from(&hash).ok_or_bad_request("invalid hash")
+}
+
+/// Find the full hash of a block that is referenced in the block ref table,
+/// given a prefix of its hex encoding of at least 4 characters
+pub fn find_block_hash_by_prefix(garage: &Garage, prefix: &str) -> Result<Hash, HelperError> {
+	if prefix.len() < 4 {
+		return Err(HelperError::BadRequest(
+			"Please specify at least 4 characters of the block hash".into(),
+		));
+	}
+
+	let prefix_bin = hex::decode(&prefix[..prefix.len() & !1]).ok_or_bad_request("invalid hash")?;
+
+	let iter = garage
+		.block_ref_table
+		.data
+		.store
+		.range(&prefix_bin[..]..)
+		.map_err(GarageError::from)?;
+	let mut found = None;
+	for item in iter {
+		let (k, _v) = item.map_err(GarageError::from)?;
+		let hash = Hash::try_from(&k[..32]).unwrap();
+		if hash.as_slice()[..prefix_bin.len()] != prefix_bin {
+			break;
+		}
+		if hex::encode(hash.as_slice()).starts_with(prefix) {
+			match &found {
+				Some(x) if *x == hash => (),
+				Some(_) => {
+					return Err(HelperError::BadRequest(format!(
+						"Several blocks match prefix `{}`",
+						prefix
+					)));
+				}
+				None => {
+					found = Some(hash);
+				}
+			}
+		}
+	}
+
+	found.ok_or_else(|| HelperError::BadRequest("No matching block found".into()))
+}
+
+// ---- request and response types ----
+
+pub type ListBlockErrorsResponse = Vec<BlockErrorResp>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockErrorResp {
+	pub block_hash: String,
+	pub refcount: u64,
+	pub error_count: u64,
+	pub last_try_secs_ago: u64,
+	pub next_try_in_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBlockInfoRequest {
+	pub block_hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetBlockInfoResponse {
+	pub block_hash: String,
+	pub refcount: u64,
+	pub versions: Vec<BlockVersion>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockVersion {
+	pub version_id: String,
+	pub deleted: bool,
+	pub garbage_collected: bool,
+	pub backlink: Option<BlockVersionBacklink>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BlockVersionBacklink {
+	#[serde(rename_all = "camelCase")]
+	Object { bucket_id: String, key: String },
+	#[serde(rename_all = "camelCase")]
+	Upload {
+		upload_id: String,
+		upload_deleted: bool,
+		upload_garbage_collected: bool,
+		bucket_id: Option<String>,
+		key: Option<String>,
+	},
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryBlockResyncRequest {
+	#[serde(default)]
+	pub all: bool,
+	#[serde(default)]
+	pub block_hashes: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RetryBlockResyncResponse {
+	pub count: u64,
+}
+
+pub type PurgeBlocksRequest = Vec<String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeBlocksResponse {
+	pub blocks_purged: u64,
+	pub objects_deleted: u64,
+	pub uploads_deleted: u64,
+	pub versions_deleted: u64,
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use garage_util::crdt::*;
 use garage_util::data::*;
@@ -10,21 +11,30 @@ use garage_util::time::*;
 
 use garage_table::*;
 
+use garage_model::admin_token_table::AdminApiTokenScope;
 use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
+use garage_model::helper::audit::{bucket_summary, AuditTarget};
 use garage_model::permission::*;
 use garage_model::s3::mpu_table;
+use garage_model::s3::object_search_table::*;
 use garage_model::s3::object_table::*;
+use garage_model::s3::usage_history_table::*;
 
 use garage_api_common::common_error::CommonError;
 use garage_api_common::helpers::*;
 
+use crate::admin_token::check_bucket_scope;
 use crate::api_server::ResBody;
+use crate::audit::parse_time;
 use crate::error::*;
 use crate::key::ApiBucketKeyPerm;
 
-pub async fn handle_list_buckets(garage: &Arc<Garage>) -> Result<Response<ResBody>, Error> {
+pub async fn handle_list_buckets(
+	garage: &Arc<Garage>,
+	scope: Option<&AdminApiTokenScope>,
+) -> Result<Response<ResBody>, Error> {
 	let buckets = garage
 		.bucket_table
 		.get_range(
@@ -38,6 +48,7 @@ pub async fn handle_list_buckets(garage: &Arc<Garage>) -> Result<Response<ResBod
 
 	let res = buckets
 		.into_iter()
+		.filter(|b| scope.map(|s| s.allows_bucket(&b.id)).unwrap_or(true))
 		.map(|b| {
 			let state = b.state.as_option().unwrap();
 			ListBucketResultItem {
@@ -66,24 +77,24 @@ pub async fn handle_list_buckets(garage: &Arc<Garage>) -> Result<Response<ResBod
 	Ok(json_ok_response(&res)?)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ListBucketResultItem {
+pub(crate) struct ListBucketResultItem {
 	id: String,
 	global_aliases: Vec<String>,
 	local_aliases: Vec<BucketLocalAlias>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct BucketLocalAlias {
+pub(crate) struct BucketLocalAlias {
 	access_key_id: String,
 	alias: String,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ApiBucketQuotas {
+pub(crate) struct ApiBucketQuotas {
 	max_size: Option<u64>,
 	max_objects: Option<u64>,
 }
@@ -229,14 +240,16 @@ async fn bucket_info_results(
 				max_size: quotas.max_size,
 				max_objects: quotas.max_objects,
 			},
+			replication_factor: *state.replication_factor.get(),
+			search_index: *state.search_index.get(),
 		};
 
 	Ok(json_ok_response(&res)?)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetBucketInfoResult {
+pub(crate) struct GetBucketInfoResult {
 	id: String,
 	global_aliases: Vec<String>,
 	website_access: bool,
@@ -250,30 +263,282 @@ struct GetBucketInfoResult {
 	unfinished_multipart_upload_parts: i64,
 	unfinished_multipart_upload_bytes: i64,
 	quotas: ApiBucketQuotas,
+	/// Number of copies kept of the data of objects in this bucket,
+	/// if lower than the replication factor of the cluster
+	replication_factor: Option<usize>,
+	/// Whether the metadata of objects in this bucket is indexed for search
+	#[serde(default)]
+	search_index: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetBucketInfoWebsiteResult {
+pub(crate) struct GetBucketInfoWebsiteResult {
 	index_document: String,
 	error_document: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetBucketInfoKey {
+pub(crate) struct GetBucketInfoKey {
 	access_key_id: String,
 	name: String,
 	permissions: ApiBucketKeyPerm,
 	bucket_local_aliases: Vec<String>,
 }
 
+pub async fn handle_get_bucket_usage(
+	garage: &Arc<Garage>,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let counters = garage
+		.object_counter_table
+		.table
+		.get(&bucket_id, &EmptyKey)
+		.await?
+		.map(|x| x.filtered_values(&garage.system.cluster_layout()))
+		.unwrap_or_default();
+
+	let prefixes = garage
+		.bucket_helper()
+		.get_prefix_usage(&bucket_id)
+		.await?
+		.into_iter()
+		.map(|(prefix, counters)| GetBucketUsagePrefix {
+			prefix,
+			objects: *counters.get(OBJECTS).unwrap_or(&0),
+			bytes: *counters.get(BYTES).unwrap_or(&0),
+			unfinished_uploads: *counters.get(UNFINISHED_UPLOADS).unwrap_or(&0),
+		})
+		.collect::<Vec<_>>();
+
+	let res = GetBucketUsageResult {
+		id: hex::encode(bucket_id),
+		objects: *counters.get(OBJECTS).unwrap_or(&0),
+		bytes: *counters.get(BYTES).unwrap_or(&0),
+		unfinished_uploads: *counters.get(UNFINISHED_UPLOADS).unwrap_or(&0),
+		prefix_usage_depths: garage.config.prefix_usage_depths.clone(),
+		prefixes,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetBucketUsageResult {
+	id: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+	/// Depths at which the usage of prefixes is counted,
+	/// from the configuration of the node answering the request
+	prefix_usage_depths: Vec<usize>,
+	prefixes: Vec<GetBucketUsagePrefix>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetBucketUsagePrefix {
+	prefix: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+}
+
+pub async fn handle_get_bucket_usage_history(
+	garage: &Arc<Garage>,
+	id: String,
+	resolution: Option<String>,
+	since: Option<String>,
+	until: Option<String>,
+) -> Result<Response<ResBody>, Error> {
+	let bucket_id = parse_bucket_id(&id)?;
+	let resolution = match resolution.as_deref() {
+		Some(r) => r.parse::<UsageResolution>().map_err(Error::bad_request)?,
+		None => UsageResolution::Hourly,
+	};
+	let since = since.as_deref().map(parse_time).transpose()?;
+	let until = until.as_deref().map(parse_time).transpose()?;
+
+	garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+
+	let samples = garage
+		.bucket_helper()
+		.get_usage_history(&bucket_id, resolution, since, until)
+		.await?
+		.into_iter()
+		.map(|s| GetBucketUsageHistorySample {
+			period_start: msec_to_rfc3339(s.period_start),
+			sampled_at: msec_to_rfc3339(s.sampled_at),
+			objects: s.objects,
+			bytes: s.bytes,
+			unfinished_uploads: s.unfinished_uploads,
+			min_bytes: s.min_bytes,
+			max_bytes: s.max_bytes,
+		})
+		.collect::<Vec<_>>();
+
+	let res = GetBucketUsageHistoryResult {
+		id: hex::encode(bucket_id),
+		resolution: resolution.to_string(),
+		samples,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetBucketUsageHistoryResult {
+	id: String,
+	resolution: String,
+	samples: Vec<GetBucketUsageHistorySample>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct GetBucketUsageHistorySample {
+	period_start: String,
+	sampled_at: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+	min_bytes: i64,
+	max_bytes: i64,
+}
+
+pub async fn handle_search_bucket_objects(
+	garage: &Arc<Garage>,
+	id: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<SearchBucketObjectsRequest, _, Error>(req).await?;
+	let bucket_id = parse_bucket_id(&id)?;
+
+	let bucket = garage
+		.bucket_helper()
+		.get_existing_bucket(bucket_id)
+		.await?;
+	if !*bucket.params().unwrap().search_index.get() {
+		return Err(Error::bad_request(
+			"The search index is not enabled for this bucket",
+		));
+	}
+
+	let filter = ObjectSearchFilter {
+		content_type_prefix: req.content_type_prefix,
+		min_size: req.min_size,
+		max_size: req.max_size,
+		modified_since: req.modified_since.as_deref().map(parse_time).transpose()?,
+		modified_until: req.modified_until.as_deref().map(parse_time).transpose()?,
+		metadata: req
+			.metadata
+			.into_iter()
+			.map(|(name, value)| (name.to_lowercase(), value))
+			.collect(),
+	};
+	let (index, mut range) = filter.index_range();
+	if let Some(token) = &req.continuation_token {
+		let pos = hex::decode(token)
+			.ok()
+			.and_then(|bytes| serde_json::from_slice(&bytes).ok())
+			.ok_or_bad_request("Invalid continuation token")?;
+		range.after = Some(pos);
+	}
+	let limit = req.limit.unwrap_or(100);
+	if limit == 0 || limit > 1000 {
+		return Err(Error::bad_request("limit must be between 1 and 1000"));
+	}
+
+	let (entries, next) = garage
+		.object_search_table
+		.get_index_range(&bucket_id, index, range, Some(filter), limit)
+		.await?;
+
+	let objects = entries
+		.into_iter()
+		.filter_map(|(_, e)| {
+			let meta = e.meta?;
+			Some(SearchBucketObjectsResultObject {
+				key: e.key,
+				size: meta.size,
+				etag: meta.etag,
+				last_modified: msec_to_rfc3339(e.timestamp),
+				content_type: meta.content_type,
+				metadata: meta.user_metadata.into_iter().collect(),
+				encrypted: meta.encrypted,
+			})
+		})
+		.collect::<Vec<_>>();
+	let next_continuation_token = next
+		.map(|pos| serde_json::to_vec(&pos).map(hex::encode))
+		.transpose()
+		.ok_or_internal_error("Could not serialize continuation token")?;
+
+	let res = SearchBucketObjectsResult {
+		id: hex::encode(bucket_id),
+		objects,
+		next_continuation_token,
+	};
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SearchBucketObjectsRequest {
+	content_type_prefix: Option<String>,
+	min_size: Option<u64>,
+	max_size: Option<u64>,
+	modified_since: Option<String>,
+	modified_until: Option<String>,
+	#[serde(default)]
+	metadata: HashMap<String, String>,
+	limit: Option<usize>,
+	continuation_token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SearchBucketObjectsResult {
+	id: String,
+	objects: Vec<SearchBucketObjectsResultObject>,
+	next_continuation_token: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct SearchBucketObjectsResultObject {
+	key: String,
+	size: u64,
+	etag: String,
+	last_modified: String,
+	content_type: Option<String>,
+	metadata: HashMap<String, String>,
+	encrypted: bool,
+}
+
 pub async fn handle_create_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let req = parse_json_body::<CreateBucketRequest, _, Error>(req).await?;
 
+	if let Some(rf) = req.replication_factor {
+		garage.bucket_helper().check_replication_factor(rf)?;
+	}
+
 	let helper = garage.locked_helper().await;
 
 	if let Some(ga) = &req.global_alias {
@@ -306,7 +571,14 @@ pub async fn handle_create_bucket(
 		}
 	}
 
-	let bucket = Bucket::new();
+	let mut bucket = Bucket::new();
+	if let Some(rf) = req.replication_factor {
+		bucket
+			.params_mut()
+			.unwrap()
+			.replication_factor
+			.update(Some(rf));
+	}
 	garage.bucket_table.insert(&bucket).await?;
 
 	if let Some(ga) = &req.global_alias {
@@ -334,19 +606,28 @@ pub async fn handle_create_bucket(
 		}
 	}
 
+	garage
+		.audit_helper()
+		.record(actor, "CreateBucket", AuditTarget::Bucket(bucket.id), None)
+		.await;
+
 	bucket_info_results(garage, bucket.id).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct CreateBucketRequest {
+pub(crate) struct CreateBucketRequest {
 	global_alias: Option<String>,
 	local_alias: Option<CreateBucketLocalAlias>,
+	/// Number of copies to keep of the data of objects in this bucket,
+	/// if lower than the replication factor of the cluster
+	#[serde(default)]
+	replication_factor: Option<usize>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct CreateBucketLocalAlias {
+pub(crate) struct CreateBucketLocalAlias {
 	access_key_id: String,
 	alias: String,
 	#[serde(default)]
@@ -355,6 +636,7 @@ struct CreateBucketLocalAlias {
 
 pub async fn handle_delete_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	id: String,
 ) -> Result<Response<ResBody>, Error> {
 	let helper = garage.locked_helper().await;
@@ -362,6 +644,7 @@ pub async fn handle_delete_bucket(
 	let bucket_id = parse_bucket_id(&id)?;
 
 	let mut bucket = helper.bucket().get_existing_bucket(bucket_id).await?;
+	let before = bucket_summary(&bucket);
 	let state = bucket.state.as_option().unwrap();
 
 	// Check bucket is empty
@@ -397,6 +680,16 @@ pub async fn handle_delete_bucket(
 	bucket.state = Deletable::delete();
 	garage.bucket_table.insert(&bucket).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"DeleteBucket",
+			AuditTarget::Bucket(bucket_id),
+			before,
+		)
+		.await;
+
 	Ok(Response::builder()
 		.status(StatusCode::NO_CONTENT)
 		.body(empty_body())?)
@@ -404,6 +697,7 @@ pub async fn handle_delete_bucket(
 
 pub async fn handle_update_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	id: String,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
@@ -414,6 +708,7 @@ pub async fn handle_update_bucket(
 		.bucket_helper()
 		.get_existing_bucket(bucket_id)
 		.await?;
+	let before = bucket_summary(&bucket);
 
 	let state = bucket.state.as_option_mut().unwrap();
 
@@ -442,21 +737,37 @@ pub async fn handle_update_bucket(
 		});
 	}
 
+	if let Some(search_index) = req.search_index {
+		state.search_index.update(search_index);
+	}
+
 	garage.bucket_table.insert(&bucket).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"UpdateBucket",
+			AuditTarget::Bucket(bucket_id),
+			before,
+		)
+		.await;
+
 	bucket_info_results(garage, bucket_id).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct UpdateBucketRequest {
+pub(crate) struct UpdateBucketRequest {
 	website_access: Option<UpdateBucketWebsiteAccess>,
 	quotas: Option<ApiBucketQuotas>,
+	/// Enable or disable the metadata search index of this bucket
+	search_index: Option<bool>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct UpdateBucketWebsiteAccess {
+pub(crate) struct UpdateBucketWebsiteAccess {
 	enabled: bool,
 	index_document: Option<String>,
 	error_document: Option<String>,
@@ -466,16 +777,20 @@ struct UpdateBucketWebsiteAccess {
 
 pub async fn handle_bucket_change_key_perm(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 	new_perm_flag: bool,
+	scope: Option<&AdminApiTokenScope>,
 ) -> Result<Response<ResBody>, Error> {
 	let req = parse_json_body::<BucketKeyPermChangeRequest, _, Error>(req).await?;
 
 	let helper = garage.locked_helper().await;
 
 	let bucket_id = parse_bucket_id(&req.bucket_id)?;
+	check_bucket_scope(scope, &bucket_id)?;
 
 	let bucket = helper.bucket().get_existing_bucket(bucket_id).await?;
+	let before = bucket_summary(&bucket);
 	let state = bucket.state.as_option().unwrap();
 
 	let key = helper.key().get_existing_key(&req.access_key_id).await?;
@@ -500,12 +815,22 @@ pub async fn handle_bucket_change_key_perm(
 		.set_bucket_key_permissions(bucket.id, &key.key_id, perm)
 		.await?;
 
+	let operation = if new_perm_flag {
+		"BucketAllowKey"
+	} else {
+		"BucketDenyKey"
+	};
+	garage
+		.audit_helper()
+		.record(actor, operation, AuditTarget::Bucket(bucket.id), before)
+		.await;
+
 	bucket_info_results(garage, bucket.id).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct BucketKeyPermChangeRequest {
+pub(crate) struct BucketKeyPermChangeRequest {
 	bucket_id: String,
 	access_key_id: String,
 	permissions: ApiBucketKeyPerm,
@@ -515,6 +840,7 @@ struct BucketKeyPermChangeRequest {
 
 pub async fn handle_global_alias_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	bucket_id: String,
 	alias: String,
 ) -> Result<Response<ResBody>, Error> {
@@ -522,13 +848,22 @@ pub async fn handle_global_alias_bucket(
 
 	let helper = garage.locked_helper().await;
 
+	let target = AuditTarget::Bucket(bucket_id);
+	let before = garage.audit_helper().summary(&target).await?;
+
 	helper.set_global_bucket_alias(bucket_id, &alias).await?;
 
+	garage
+		.audit_helper()
+		.record(actor, "GlobalAliasBucket", target, before)
+		.await;
+
 	bucket_info_results(garage, bucket_id).await
 }
 
 pub async fn handle_global_unalias_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	bucket_id: String,
 	alias: String,
 ) -> Result<Response<ResBody>, Error> {
@@ -536,13 +871,22 @@ pub async fn handle_global_unalias_bucket(
 
 	let helper = garage.locked_helper().await;
 
+	let target = AuditTarget::Bucket(bucket_id);
+	let before = garage.audit_helper().summary(&target).await?;
+
 	helper.unset_global_bucket_alias(bucket_id, &alias).await?;
 
+	garage
+		.audit_helper()
+		.record(actor, "GlobalUnaliasBucket", target, before)
+		.await;
+
 	bucket_info_results(garage, bucket_id).await
 }
 
 pub async fn handle_local_alias_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	bucket_id: String,
 	access_key_id: String,
 	alias: String,
@@ -551,15 +895,24 @@ pub async fn handle_local_alias_bucket(
 
 	let helper = garage.locked_helper().await;
 
+	let target = AuditTarget::Bucket(bucket_id);
+	let before = garage.audit_helper().summary(&target).await?;
+
 	helper
 		.set_local_bucket_alias(bucket_id, &access_key_id, &alias)
 		.await?;
 
+	garage
+		.audit_helper()
+		.record(actor, "LocalAliasBucket", target, before)
+		.await;
+
 	bucket_info_results(garage, bucket_id).await
 }
 
 pub async fn handle_local_unalias_bucket(
 	garage: &Arc<Garage>,
+	actor: &str,
 	bucket_id: String,
 	access_key_id: String,
 	alias: String,
@@ -568,16 +921,24 @@ pub async fn handle_local_unalias_bucket(
 
 	let helper = garage.locked_helper().await;
 
+	let target = AuditTarget::Bucket(bucket_id);
+	let before = garage.audit_helper().summary(&target).await?;
+
 	helper
 		.unset_local_bucket_alias(bucket_id, &access_key_id, &alias)
 		.await?;
 
+	garage
+		.audit_helper()
+		.record(actor, "LocalUnaliasBucket", target, before)
+		.await;
+
 	bucket_info_results(garage, bucket_id).await
 }
 
 // ---- HELPER ----
 
-fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
+pub(crate) fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
 	let id_hex = hex::decode(id).ok_or_bad_request("Invalid bucket id")?;
 	Ok(Uuid::try_from(&id_hex).ok_or_bad_request("Invalid bucket id")?)
 }
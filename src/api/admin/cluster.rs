@@ -4,6 +4,7 @@ use std::sync::Arc;
 
 use hyper::{body::Incoming as IncomingBody, Request, Response};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use garage_util::crdt::*;
 use garage_util::data::*;
@@ -11,6 +12,7 @@ use garage_util::data::*;
 use garage_rpc::layout;
 
 use garage_model::garage::Garage;
+use garage_model::helper::audit::{layout_summary, AuditTarget};
 
 use garage_api_common::helpers::{json_ok_response, parse_json_body};
 
@@ -32,6 +34,7 @@ pub async fn handle_get_cluster_status(garage: &Arc<Garage>) -> Result<Response<
 					hostname: i.status.hostname,
 					is_up: i.is_up,
 					last_seen_secs_ago: i.last_seen_secs_ago,
+					maintenance: i.status.maintenance,
 					data_partition: i
 						.status
 						.data_disk_avail
@@ -129,6 +132,7 @@ pub async fn handle_get_cluster_health(garage: &Arc<Garage>) -> Result<Response<
 		connected_nodes: health.connected_nodes,
 		storage_nodes: health.storage_nodes,
 		storage_nodes_ok: health.storage_nodes_ok,
+		storage_nodes_maintenance: health.storage_nodes_maintenance,
 		partitions: health.partitions,
 		partitions_quorum: health.partitions_quorum,
 		partitions_all_ok: health.partitions_all_ok,
@@ -213,7 +217,7 @@ fn format_cluster_layout(layout: &layout::LayoutHistory) -> GetClusterLayoutResp
 
 // ----
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct ClusterHealth {
 	status: &'static str,
@@ -221,16 +225,18 @@ pub struct ClusterHealth {
 	connected_nodes: usize,
 	storage_nodes: usize,
 	storage_nodes_ok: usize,
+	storage_nodes_maintenance: usize,
 	partitions: usize,
 	partitions_quorum: usize,
 	partitions_all_ok: usize,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetClusterStatusResponse {
+pub(crate) struct GetClusterStatusResponse {
 	node: String,
 	garage_version: &'static str,
+	#[schema(value_type = Option<Vec<String>>)]
 	garage_features: Option<&'static [&'static str]>,
 	rust_version: &'static str,
 	db_engine: String,
@@ -238,54 +244,56 @@ struct GetClusterStatusResponse {
 	nodes: Vec<NodeResp>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ApplyClusterLayoutResponse {
+pub(crate) struct ApplyClusterLayoutResponse {
 	message: Vec<String>,
 	layout: GetClusterLayoutResponse,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ConnectClusterNodesResponse {
+pub(crate) struct ConnectClusterNodesResponse {
 	success: bool,
 	error: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetClusterLayoutResponse {
+pub(crate) struct GetClusterLayoutResponse {
 	version: u64,
 	roles: Vec<NodeRoleResp>,
 	staged_role_changes: Vec<NodeRoleChange>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct NodeRoleResp {
+pub(crate) struct NodeRoleResp {
 	id: String,
 	zone: String,
 	capacity: Option<u64>,
 	tags: Vec<String>,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Debug, Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct FreeSpaceResp {
-	available: u64,
-	total: u64,
+pub struct FreeSpaceResp {
+	pub available: u64,
+	pub total: u64,
 }
 
-#[derive(Serialize, Default)]
+#[derive(Serialize, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct NodeResp {
+pub(crate) struct NodeResp {
 	id: String,
 	role: Option<NodeRoleResp>,
+	#[schema(value_type = Option<String>)]
 	addr: Option<SocketAddr>,
 	hostname: Option<String>,
 	is_up: bool,
 	last_seen_secs_ago: Option<u64>,
 	draining: bool,
+	maintenance: bool,
 	#[serde(skip_serializing_if = "Option::is_none")]
 	data_partition: Option<FreeSpaceResp>,
 	#[serde(skip_serializing_if = "Option::is_none")]
@@ -296,11 +304,13 @@ struct NodeResp {
 
 pub async fn handle_update_cluster_layout(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let updates = parse_json_body::<UpdateClusterLayoutRequest, _, Error>(req).await?;
 
 	let mut layout = garage.system.cluster_layout().inner().clone();
+	let before = layout_summary(&layout);
 
 	let mut roles = layout.current().roles.clone();
 	roles.merge(&layout.staging.get().roles);
@@ -336,17 +346,21 @@ pub async fn handle_update_cluster_layout(
 		.update_cluster_layout(&layout)
 		.await?;
 
+	record_layout_change(garage, actor, "UpdateClusterLayout", before, &layout).await;
+
 	let res = format_cluster_layout(&layout);
 	Ok(json_ok_response(&res)?)
 }
 
 pub async fn handle_apply_cluster_layout(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let param = parse_json_body::<ApplyLayoutRequest, _, Error>(req).await?;
 
 	let layout = garage.system.cluster_layout().inner().clone();
+	let before = layout_summary(&layout);
 	let (layout, msg) = layout.apply_staged_changes(Some(param.version))?;
 
 	garage
@@ -355,6 +369,8 @@ pub async fn handle_apply_cluster_layout(
 		.update_cluster_layout(&layout)
 		.await?;
 
+	record_layout_change(garage, actor, "ApplyClusterLayout", before, &layout).await;
+
 	let res = ApplyClusterLayoutResponse {
 		message: msg,
 		layout: format_cluster_layout(&layout),
@@ -364,8 +380,10 @@ pub async fn handle_apply_cluster_layout(
 
 pub async fn handle_revert_cluster_layout(
 	garage: &Arc<Garage>,
+	actor: &str,
 ) -> Result<Response<ResBody>, Error> {
 	let layout = garage.system.cluster_layout().inner().clone();
+	let before = layout_summary(&layout);
 	let layout = layout.revert_staged_changes()?;
 	garage
 		.system
@@ -373,33 +391,54 @@ pub async fn handle_revert_cluster_layout(
 		.update_cluster_layout(&layout)
 		.await?;
 
+	record_layout_change(garage, actor, "RevertClusterLayout", before, &layout).await;
+
 	let res = format_cluster_layout(&layout);
 	Ok(json_ok_response(&res)?)
 }
 
+async fn record_layout_change(
+	garage: &Arc<Garage>,
+	actor: &str,
+	operation: &str,
+	before: String,
+	layout: &layout::LayoutHistory,
+) {
+	garage
+		.audit_helper()
+		.record_with_after(
+			actor,
+			operation,
+			AuditTarget::ClusterLayout,
+			Some(before),
+			Some(layout_summary(layout)),
+		)
+		.await
+}
+
 // ----
 
-type UpdateClusterLayoutRequest = Vec<NodeRoleChange>;
+pub(crate) type UpdateClusterLayoutRequest = Vec<NodeRoleChange>;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ApplyLayoutRequest {
+pub(crate) struct ApplyLayoutRequest {
 	version: u64,
 }
 
 // ----
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct NodeRoleChange {
+pub(crate) struct NodeRoleChange {
 	id: String,
 	#[serde(flatten)]
 	action: NodeRoleChangeEnum,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(untagged)]
-enum NodeRoleChangeEnum {
+pub(crate) enum NodeRoleChangeEnum {
 	#[serde(rename_all = "camelCase")]
 	Remove { remove: bool },
 	#[serde(rename_all = "camelCase")]
@@ -409,3 +448,62 @@ enum NodeRoleChangeEnum {
 		tags: Vec<String>,
 	},
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	fn stage_role(layout: &mut layout::LayoutHistory, node: Uuid) {
+		let mut roles = layout.current().roles.clone();
+		roles.merge(&layout.staging.get().roles);
+		let role = layout::NodeRole {
+			zone: "dc2".into(),
+			capacity: Some(1 << 30),
+			tags: vec![],
+		};
+		layout
+			.staging
+			.get_mut()
+			.roles
+			.merge(&roles.update_mutator(node, layout::NodeRoleV(Some(role))));
+	}
+
+	#[tokio::test]
+	async fn test_layout_change_without_audit_log() {
+		// A new cluster has no layout, so the audit record cannot be written,
+		// but this must not make the layout change fail
+		let (garage, _dir) = test_garage();
+		let mut layout = garage.system.cluster_layout().inner().clone();
+		stage_role(&mut layout, garage.system.id);
+		garage
+			.system
+			.layout_manager
+			.update_cluster_layout(&layout)
+			.await
+			.unwrap();
+
+		let res = handle_revert_cluster_layout(&garage, "test").await.unwrap();
+		assert_eq!(res.status(), hyper::StatusCode::OK);
+		let layout = garage.system.cluster_layout().inner().clone();
+		assert!(layout.staging.get().roles.items().is_empty());
+		let records = garage.audit_helper().list(None, None, 10).await.unwrap();
+		assert!(records.is_empty());
+
+		// Once the node has a role, layout changes are recorded
+		apply_single_node_layout(&garage).await;
+		let mut layout = layout.clone();
+		stage_role(&mut layout, [1u8; 32].into());
+		garage
+			.system
+			.layout_manager
+			.update_cluster_layout(&layout)
+			.await
+			.unwrap();
+		handle_revert_cluster_layout(&garage, "test").await.unwrap();
+		let records = garage.audit_helper().list(None, None, 10).await.unwrap();
+		assert_eq!(records.len(), 1);
+		assert_eq!(records[0].operation, "RevertClusterLayout");
+		assert_eq!(records[0].actor, "test");
+	}
+}
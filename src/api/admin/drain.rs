@@ -0,0 +1,324 @@
+//! Removal of a node from the cluster, and tracking of the progress of the
+//! transfer of its data to the nodes that replace it
+use std::sync::Arc;
+
+use hyper::Response;
+use serde::{Deserialize, Serialize};
+
+use garage_util::crdt::*;
+use garage_util::data::*;
+
+use garage_rpc::layout;
+
+use garage_model::garage::Garage;
+use garage_model::helper::audit::{layout_summary, AuditTarget};
+use garage_model::helper::error::Error as HelperError;
+
+use garage_api_common::helpers::json_ok_response;
+
+use crate::api_server::ResBody;
+use crate::error::*;
+use crate::node::*;
+
+pub async fn handle_drain_cluster_node(
+	garage: &Arc<Garage>,
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	actor: &str,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let node = find_drained_node(garage, &node)?;
+	start_node_drain(garage, actor, node).await?;
+	let res = gather_node_drain_status(node_rpc, node).await;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_node_drain_status(
+	garage: &Arc<Garage>,
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let node = find_drained_node(garage, &node)?;
+	let res = gather_node_drain_status(node_rpc, node).await;
+
+	Ok(json_ok_response(&res)?)
+}
+
+/// Find a node given a prefix of its hex-encoded ID, among the nodes that
+/// have or had a role in the cluster layout and the nodes that are connected
+pub fn find_drained_node(garage: &Arc<Garage>, pattern: &str) -> Result<Uuid, HelperError> {
+	let mut candidates = vec![];
+	{
+		let layout = garage.system.cluster_layout();
+		let history = layout.inner();
+		for version in history.old_versions.iter().chain(history.versions.iter()) {
+			for (id, _, _) in version.roles.items().iter() {
+				candidates.push(*id);
+			}
+		}
+	}
+	candidates.extend(garage.system.get_known_nodes().iter().map(|n| n.id));
+	candidates.sort();
+	candidates.dedup();
+
+	let matching = candidates
+		.into_iter()
+		.filter(|id| hex::encode(id).starts_with(pattern))
+		.collect::<Vec<_>>();
+	match matching.as_slice() {
+		[id] => Ok(*id),
+		[] => Err(HelperError::BadRequest(format!(
+			"No node matches {}",
+			pattern
+		))),
+		_ => Err(HelperError::BadRequest(format!(
+			"Several nodes match {}",
+			pattern
+		))),
+	}
+}
+
+/// Stage the removal of the role of a node in the cluster layout. The drain
+/// starts when the operator applies the staged changes, after which the data
+/// of the node is transferred to other nodes.
+/// Returns false if the node had no role in the current layout version.
+pub async fn start_node_drain(
+	garage: &Arc<Garage>,
+	actor: &str,
+	node: Uuid,
+) -> Result<bool, HelperError> {
+	let mut layout = garage.system.cluster_layout().inner().clone();
+	if layout
+		.current()
+		.roles
+		.get(&node)
+		.and_then(|r| r.0.as_ref())
+		.is_none()
+	{
+		return Ok(false);
+	}
+
+	let before = layout_summary(&layout);
+
+	let roles = layout.current().roles.clone();
+	layout
+		.staging
+		.get_mut()
+		.roles
+		.merge(&roles.update_mutator(node, layout::NodeRoleV(None)));
+
+	garage
+		.system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await?;
+
+	info!(
+		"Removal of node {:?} staged, the drain starts when cluster layout version {} is applied",
+		node,
+		layout.current().version + 1
+	);
+
+	garage
+		.audit_helper()
+		.record_with_after(
+			actor,
+			"DrainClusterNode",
+			AuditTarget::ClusterLayout,
+			Some(before),
+			Some(layout_summary(&layout)),
+		)
+		.await;
+
+	Ok(true)
+}
+
+/// Calculate how much of the data of a node has been transferred to other nodes.
+///
+/// A partition that the node stored is handed over when the nodes that store it
+/// in the current layout version have synced its metadata, and when the node
+/// no longer holds any block that is referenced in that partition. The node is
+/// safe to remove once no active layout version assigns data to it anymore,
+/// and it has no more referenced blocks nor blocks waiting to be offloaded.
+pub async fn gather_node_drain_status(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: Uuid,
+) -> NodeDrainStatusResponse {
+	let garage = &node_rpc.garage;
+	let (has_role, removal_staged, apply_version, drained_partitions, layout_drained) = {
+		let layout = garage.system.cluster_layout();
+		(
+			layout
+				.current()
+				.roles
+				.get(&node)
+				.and_then(|r| r.0.as_ref())
+				.is_some(),
+			matches!(
+				layout.inner().staging.get().roles.get(&node),
+				Some(layout::NodeRoleV(None))
+			),
+			layout.current().version + 1,
+			layout.inner().drained_partitions(&node).unwrap_or_default(),
+			layout.inner().is_drained(&node),
+		)
+	};
+
+	let mut res = NodeDrainStatusResponse {
+		node: hex::encode(node),
+		state: NodeDrainState::Active,
+		progress: 0.,
+		partitions: drained_partitions.len() as u64,
+		partitions_synced: drained_partitions.iter().filter(|(_, s)| *s).count() as u64,
+		partitions_handed_over: 0,
+		layout_drained,
+		apply_layout_version: None,
+		blocks_remaining: None,
+		resync_queue_length: None,
+		error: None,
+	};
+	if has_role {
+		if removal_staged {
+			res.state = NodeDrainState::RemovalStaged;
+			res.apply_layout_version = Some(apply_version);
+		}
+		return res;
+	}
+
+	match node_rpc
+		.call_node(node, &AdminNodeRpc::GetReferencedBlocks)
+		.await
+	{
+		Ok(AdminNodeRpcResponse::ReferencedBlocks(blocks)) => {
+			res.partitions_handed_over = drained_partitions
+				.iter()
+				.filter(|(p, synced)| *synced && blocks.by_partition[*p as usize] == 0)
+				.count() as u64;
+			res.blocks_remaining = Some(blocks.by_partition.iter().sum());
+			res.resync_queue_length = Some(blocks.resync_queue_length);
+		}
+		Ok(_) => res.error = Some("Unexpected response from node".into()),
+		Err(e) => res.error = Some(e.to_string()),
+	}
+
+	let safe =
+		layout_drained && res.blocks_remaining == Some(0) && res.resync_queue_length == Some(0);
+	if safe {
+		res.state = NodeDrainState::SafeToRemove;
+		res.progress = 100.;
+	} else {
+		res.state = NodeDrainState::Draining;
+		// Only report 100% when the node can actually be removed, as the last
+		// steps (cleanup of old layout versions, deletion of offloaded blocks)
+		// happen after all partitions have been handed over
+		res.progress = if res.partitions > 0 {
+			f64::min(
+				99.,
+				100. * res.partitions_handed_over as f64 / res.partitions as f64,
+			)
+		} else {
+			0.
+		};
+	}
+
+	res
+}
+
+/// Blocks still referenced on a node, by partition of the cluster layout
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferencedBlocksResponse {
+	pub by_partition: Vec<u64>,
+	pub resync_queue_length: u64,
+}
+
+pub(crate) async fn local_get_referenced_blocks(
+	garage: &Arc<Garage>,
+) -> Result<ReferencedBlocksResponse, HelperError> {
+	Ok(ReferencedBlocksResponse {
+		by_partition: garage
+			.block_manager
+			.referenced_blocks_by_partition()
+			.await?,
+		resync_queue_length: garage.block_manager.resync.queue_len()? as u64,
+	})
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeDrainStatusResponse {
+	pub node: String,
+	pub state: NodeDrainState,
+	/// Percentage of the partitions of the node that have been handed over
+	pub progress: f64,
+	/// Number of partitions stored by the node before it was drained
+	pub partitions: u64,
+	/// Number of these partitions whose metadata has been synced by their new nodes
+	pub partitions_synced: u64,
+	/// Number of these partitions whose metadata has been synced and for which
+	/// the node holds no more referenced blocks
+	pub partitions_handed_over: u64,
+	/// Whether no active layout version assigns data to the node anymore
+	pub layout_drained: bool,
+	/// Version of the cluster layout to apply to start the drain, if the
+	/// removal of the node is staged
+	pub apply_layout_version: Option<u64>,
+	/// Number of blocks still referenced on the node (unknown if it is unreachable)
+	pub blocks_remaining: Option<u64>,
+	pub resync_queue_length: Option<u64>,
+	pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum NodeDrainState {
+	/// The node still has a role in the current layout version
+	Active,
+	/// The removal of the node is staged, the drain starts when the staged
+	/// changes are applied
+	RemovalStaged,
+	/// The node is being drained, its data is being transferred
+	Draining,
+	/// All data of the node has been transferred, it can be shut down
+	SafeToRemove,
+}
+
+impl std::fmt::Display for NodeDrainState {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			NodeDrainState::Active => write!(f, "active (not draining)"),
+			NodeDrainState::RemovalStaged => write!(f, "removal staged"),
+			NodeDrainState::Draining => write!(f, "draining"),
+			NodeDrainState::SafeToRemove => write!(f, "safe to remove"),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	use garage_util::background::BackgroundRunner;
+
+	#[tokio::test]
+	async fn test_drain_only_stages_removal() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+		let (_exit, must_exit) = tokio::sync::watch::channel(false);
+		let (bg, _) = BackgroundRunner::new(must_exit);
+		let node_rpc = AdminNodeRpcHandler::new(garage.clone(), bg);
+		let node = garage.system.id;
+
+		assert!(start_node_drain(&garage, "test", node).await.unwrap());
+
+		let layout = garage.system.cluster_layout();
+		assert_eq!(layout.current().version, 1);
+		assert!(layout.current().roles.get(&node).unwrap().0.is_some());
+		drop(layout);
+
+		let status = gather_node_drain_status(&node_rpc, node).await;
+		assert_eq!(status.state, NodeDrainState::RemovalStaged);
+		assert_eq!(status.apply_layout_version, Some(2));
+	}
+}
@@ -31,6 +31,10 @@ pub enum Error {
 		_0
 	)]
 	KeyAlreadyExists(String),
+
+	/// The admin API token does not exist
+	#[error(display = "Admin API token not found: {}", _0)]
+	NoSuchAdminToken(String),
 }
 
 commonErrorDerivative!(Error);
@@ -54,6 +58,7 @@ impl Error {
 			Error::Common(c) => c.aws_code(),
 			Error::NoSuchAccessKey(_) => "NoSuchAccessKey",
 			Error::KeyAlreadyExists(_) => "KeyAlreadyExists",
+			Error::NoSuchAdminToken(_) => "NoSuchAdminToken",
 		}
 	}
 }
@@ -63,7 +68,7 @@ impl ApiError for Error {
 	fn http_status_code(&self) -> StatusCode {
 		match self {
 			Error::Common(c) => c.http_status_code(),
-			Error::NoSuchAccessKey(_) => StatusCode::NOT_FOUND,
+			Error::NoSuchAccessKey(_) | Error::NoSuchAdminToken(_) => StatusCode::NOT_FOUND,
 			Error::KeyAlreadyExists(_) => StatusCode::CONFLICT,
 		}
 	}
@@ -3,10 +3,12 @@ use std::sync::Arc;
 
 use hyper::{body::Incoming as IncomingBody, Request, Response, StatusCode};
 use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
 
 use garage_table::*;
 
 use garage_model::garage::Garage;
+use garage_model::helper::audit::{key_summary, AuditTarget};
 use garage_model::key_table::*;
 
 use garage_api_common::helpers::*;
@@ -35,9 +37,9 @@ pub async fn handle_list_keys(garage: &Arc<Garage>) -> Result<Response<ResBody>,
 	Ok(json_ok_response(&res)?)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ListKeyResultItem {
+pub(crate) struct ListKeyResultItem {
 	id: String,
 	name: String,
 }
@@ -64,6 +66,7 @@ pub async fn handle_get_key_info(
 
 pub async fn handle_create_key(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let req = parse_json_body::<CreateKeyRequest, _, Error>(req).await?;
@@ -71,17 +74,28 @@ pub async fn handle_create_key(
 	let key = Key::new(req.name.as_deref().unwrap_or("Unnamed key"));
 	garage.key_table.insert(&key).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"CreateKey",
+			AuditTarget::Key(key.key_id.clone()),
+			None,
+		)
+		.await;
+
 	key_info_results(garage, key, true).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct CreateKeyRequest {
+pub(crate) struct CreateKeyRequest {
 	name: Option<String>,
 }
 
 pub async fn handle_import_key(
 	garage: &Arc<Garage>,
+	actor: &str,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let req = parse_json_body::<ImportKeyRequest, _, Error>(req).await?;
@@ -99,12 +113,22 @@ pub async fn handle_import_key(
 	.ok_or_bad_request("Invalid key format")?;
 	garage.key_table.insert(&imported_key).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"ImportKey",
+			AuditTarget::Key(imported_key.key_id.clone()),
+			None,
+		)
+		.await;
+
 	key_info_results(garage, imported_key, false).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct ImportKeyRequest {
+pub(crate) struct ImportKeyRequest {
 	access_key_id: String,
 	secret_access_key: String,
 	name: Option<String>,
@@ -112,12 +136,14 @@ struct ImportKeyRequest {
 
 pub async fn handle_update_key(
 	garage: &Arc<Garage>,
+	actor: &str,
 	id: String,
 	req: Request<IncomingBody>,
 ) -> Result<Response<ResBody>, Error> {
 	let req = parse_json_body::<UpdateKeyRequest, _, Error>(req).await?;
 
 	let mut key = garage.key_helper().get_existing_key(&id).await?;
+	let before = key_summary(&key);
 
 	let key_state = key.state.as_option_mut().unwrap();
 
@@ -137,12 +163,22 @@ pub async fn handle_update_key(
 
 	garage.key_table.insert(&key).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"UpdateKey",
+			AuditTarget::Key(key.key_id.clone()),
+			before,
+		)
+		.await;
+
 	key_info_results(garage, key, false).await
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct UpdateKeyRequest {
+pub(crate) struct UpdateKeyRequest {
 	name: Option<String>,
 	allow: Option<KeyPerm>,
 	deny: Option<KeyPerm>,
@@ -150,14 +186,26 @@ struct UpdateKeyRequest {
 
 pub async fn handle_delete_key(
 	garage: &Arc<Garage>,
+	actor: &str,
 	id: String,
 ) -> Result<Response<ResBody>, Error> {
 	let helper = garage.locked_helper().await;
 
 	let mut key = helper.key().get_existing_key(&id).await?;
+	let before = key_summary(&key);
 
 	helper.delete_key(&mut key).await?;
 
+	garage
+		.audit_helper()
+		.record(
+			actor,
+			"DeleteKey",
+			AuditTarget::Key(key.key_id.clone()),
+			before,
+		)
+		.await;
+
 	Ok(Response::builder()
 		.status(StatusCode::NO_CONTENT)
 		.body(empty_body())?)
@@ -241,9 +289,9 @@ async fn key_info_results(
 	Ok(json_ok_response(&res)?)
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct GetKeyInfoResult {
+pub(crate) struct GetKeyInfoResult {
 	name: String,
 	access_key_id: String,
 	#[serde(skip_serializing_if = "is_default")]
@@ -252,23 +300,23 @@ struct GetKeyInfoResult {
 	buckets: Vec<KeyInfoBucketResult>,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct KeyPerm {
+pub(crate) struct KeyPerm {
 	#[serde(default)]
 	create_bucket: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-struct KeyInfoBucketResult {
+pub(crate) struct KeyInfoBucketResult {
 	id: String,
 	global_aliases: Vec<String>,
 	local_aliases: Vec<String>,
 	permissions: ApiBucketKeyPerm,
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(Serialize, Deserialize, Default, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub(crate) struct ApiBucketKeyPerm {
 	#[serde(default)]
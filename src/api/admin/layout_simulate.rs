@@ -0,0 +1,231 @@
+//! Simulation of the application of the staged changes of the cluster layout,
+//! with an estimate of the amount of data that would be moved between nodes
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use futures::future::join_all;
+use hyper::Response;
+use serde::{Deserialize, Serialize};
+
+use garage_table::*;
+
+use garage_model::garage::Garage;
+use garage_model::helper::error::Error as HelperError;
+use garage_model::s3::object_table::BYTES;
+
+use garage_api_common::helpers::json_ok_response;
+
+use crate::api_server::ResBody;
+use crate::error::*;
+use crate::node::*;
+
+pub async fn handle_simulate_cluster_layout(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+) -> Result<Response<ResBody>, Error> {
+	let res = simulate_layout_changes(node_rpc).await?;
+	Ok(json_ok_response(&res)?)
+}
+
+/// Compute the layout version that would result from applying the staged
+/// changes, and estimate the data that would have to be transferred.
+///
+/// The number of blocks of each partition is the highest number of referenced
+/// blocks reported by the nodes that currently store it, and the size of a
+/// block is estimated from the total size of objects in the counter table.
+/// Each copy of a partition that a node has to receive is counted separately.
+pub async fn simulate_layout_changes(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+) -> Result<LayoutSimulationResponse, HelperError> {
+	let garage = &node_rpc.garage;
+
+	let layout = garage.system.cluster_layout().inner().clone();
+	let current = layout.current().clone();
+	let staging = layout.staging.get();
+	let has_staged_changes = staging
+		.roles
+		.items()
+		.iter()
+		.any(|(k, _, v)| current.roles.get(k) != Some(v))
+		|| *staging.parameters.get() != current.parameters;
+	if !has_staged_changes {
+		return Err(HelperError::BadRequest(
+			"There are no staged changes to the cluster layout".into(),
+		));
+	}
+
+	let (new_layout, message) = layout
+		.apply_staged_changes(Some(current.version + 1))
+		.map_err(|e| HelperError::BadRequest(e.to_string()))?;
+	let next = new_layout.current();
+
+	// Number of referenced blocks in each partition, as seen by the nodes
+	// that store it in the current layout version
+	let nodes = current.nongateway_nodes().to_vec();
+	let replies = join_all(
+		nodes
+			.iter()
+			.map(|node| node_rpc.call_node(*node, &AdminNodeRpc::GetReferencedBlocks)),
+	)
+	.await;
+	let mut by_node = HashMap::new();
+	let mut errors = HashMap::new();
+	for (node, reply) in nodes.iter().zip(replies) {
+		match reply {
+			Ok(AdminNodeRpcResponse::ReferencedBlocks(blocks)) => {
+				by_node.insert(*node, blocks.by_partition);
+			}
+			Ok(_) => {
+				errors.insert(hex::encode(node), "Unexpected response from node".into());
+			}
+			Err(e) => {
+				errors.insert(hex::encode(node), e.to_string());
+			}
+		}
+	}
+
+	let mut partition_blocks = current
+		.partitions()
+		.map(|(p, pos)| {
+			current
+				.nodes_of(&pos, current.replication_factor)
+				.filter_map(|n| by_node.get(&n).and_then(|b| b.get(p as usize)))
+				.max()
+				.copied()
+		})
+		.collect::<Vec<_>>();
+	// Partitions for which no node answered are assumed to hold an average
+	// number of blocks
+	let known = partition_blocks.iter().flatten().collect::<Vec<_>>();
+	let average = known.iter().copied().sum::<u64>() / std::cmp::max(known.len(), 1) as u64;
+	let partition_blocks = partition_blocks
+		.drain(..)
+		.map(|x| x.unwrap_or(average))
+		.collect::<Vec<_>>();
+	let total_blocks = partition_blocks.iter().sum::<u64>();
+
+	let total_bytes = total_object_bytes(garage).await?;
+	let block_bytes = |blocks: u64| {
+		if total_blocks > 0 {
+			(blocks as u128 * total_bytes as u128 / total_blocks as u128) as u64
+		} else {
+			0
+		}
+	};
+
+	// Aggregate partition transfers by pair of nodes and by pair of zones
+	let transfers = current.partition_transfers(next);
+	let mut partitions_moved = transfers.iter().map(|t| t.partition).collect::<Vec<_>>();
+	partitions_moved.dedup();
+
+	let mut node_matrix = BTreeMap::<(Option<String>, String), (u64, u64)>::new();
+	let mut zone_matrix = BTreeMap::<(Option<String>, String), (u64, u64)>::new();
+	for t in transfers.iter() {
+		let blocks = partition_blocks[t.partition as usize];
+
+		let key = (t.from.map(hex::encode), hex::encode(t.to));
+		let ent = node_matrix.entry(key).or_default();
+		ent.0 += 1;
+		ent.1 += blocks;
+
+		let from_zone = t
+			.from
+			.and_then(|n| current.get_node_zone(&n))
+			.map(str::to_string);
+		let to_zone = next.get_node_zone(&t.to).unwrap_or_default().to_string();
+		let ent = zone_matrix.entry((from_zone, to_zone)).or_default();
+		ent.0 += 1;
+		ent.1 += blocks;
+	}
+	let matrix = |m: BTreeMap<(Option<String>, String), (u64, u64)>| {
+		m.into_iter()
+			.map(|((from, to), (partitions, blocks))| LayoutTransferResp {
+				from,
+				to,
+				partitions,
+				blocks,
+				bytes: block_bytes(blocks),
+			})
+			.collect::<Vec<_>>()
+	};
+
+	let blocks_moved = transfers
+		.iter()
+		.map(|t| partition_blocks[t.partition as usize])
+		.sum::<u64>();
+
+	Ok(LayoutSimulationResponse {
+		current_version: current.version,
+		new_version: next.version,
+		partitions_moved: partitions_moved.len() as u64,
+		partition_transfers: transfers.len() as u64,
+		total_blocks,
+		total_bytes,
+		estimated_blocks_moved: blocks_moved,
+		estimated_bytes_moved: block_bytes(blocks_moved),
+		by_node: matrix(node_matrix),
+		by_zone: matrix(zone_matrix),
+		message,
+		errors,
+	})
+}
+
+/// Total size of the objects stored in all buckets, from the object counter table
+async fn total_object_bytes(garage: &Arc<Garage>) -> Result<u64, HelperError> {
+	let buckets = garage
+		.bucket_table
+		.get_range(
+			&EmptyKey,
+			None,
+			Some(DeletedFilter::NotDeleted),
+			10000,
+			EnumerationOrder::Forward,
+		)
+		.await?;
+
+	let mut total = 0;
+	for bucket in buckets.iter() {
+		let counters = garage
+			.object_counter_table
+			.table
+			.get(&bucket.id, &EmptyKey)
+			.await?
+			.map(|x| x.filtered_values(&garage.system.cluster_layout()))
+			.unwrap_or_default();
+		total += std::cmp::max(*counters.get(BYTES).unwrap_or(&0), 0) as u64;
+	}
+	Ok(total)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutSimulationResponse {
+	pub current_version: u64,
+	pub new_version: u64,
+	/// Number of partitions that would be stored on at least one new node
+	pub partitions_moved: u64,
+	/// Number of copies of partitions that nodes would have to receive
+	pub partition_transfers: u64,
+	pub total_blocks: u64,
+	pub total_bytes: u64,
+	pub estimated_blocks_moved: u64,
+	pub estimated_bytes_moved: u64,
+	/// Transfers aggregated by sending and receiving node
+	pub by_node: Vec<LayoutTransferResp>,
+	/// Transfers aggregated by sending and receiving zone
+	pub by_zone: Vec<LayoutTransferResp>,
+	/// Messages of the layout computation algorithm
+	pub message: Vec<String>,
+	/// Nodes whose number of blocks could not be obtained
+	pub errors: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutTransferResp {
+	/// Node or zone that currently stores the data, if any
+	pub from: Option<String>,
+	pub to: String,
+	pub partitions: u64,
+	pub blocks: u64,
+	pub bytes: u64,
+}
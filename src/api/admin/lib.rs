@@ -5,7 +5,21 @@ pub mod api_server;
 mod error;
 mod router_v0;
 mod router_v1;
+mod router_v2;
 
+mod admin_token;
+mod audit;
 mod bucket;
 mod cluster;
 mod key;
+mod openapi;
+
+#[cfg(test)]
+mod test_util;
+
+pub mod block;
+pub mod drain;
+pub mod layout_simulate;
+pub mod node;
+pub mod repair;
+pub mod worker;
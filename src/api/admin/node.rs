@@ -0,0 +1,641 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+
+use futures::future::FutureExt;
+use hyper::{body::Incoming as IncomingBody, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use garage_util::background::BackgroundRunner;
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+use garage_util::time::now_msec;
+
+use garage_table::replication::*;
+use garage_table::*;
+
+use garage_rpc::layout::PARTITION_BITS;
+use garage_rpc::*;
+
+use garage_model::garage::Garage;
+
+use garage_api_common::helpers::{json_ok_response, parse_json_body};
+
+use crate::api_server::ResBody;
+use crate::block::*;
+pub use crate::cluster::FreeSpaceResp;
+use crate::drain::*;
+use crate::error::*;
+use crate::repair::*;
+use crate::worker::*;
+
+pub const ADMIN_NODE_RPC_PATH: &str = "garage_api_admin/node.rs/Rpc";
+
+/// Admin API operations that are executed locally on a single node.
+/// When such an operation targets other nodes than the one that received
+/// the HTTP request, it is forwarded to them using this RPC.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum AdminNodeRpc {
+	GetNodeStatistics,
+	GetGcQueue,
+	CreateMetadataSnapshot,
+	LaunchRepairOperation(LaunchRepairOperationRequest),
+	ListWorkers(ListWorkersRequest),
+	GetWorkerInfo(GetWorkerInfoRequest),
+	GetWorkerVariable(GetWorkerVariableRequest),
+	SetWorkerVariable(SetWorkerVariableRequest),
+	ListBlockErrors,
+	GetBlockInfo(GetBlockInfoRequest),
+	RetryBlockResync(RetryBlockResyncRequest),
+	PurgeBlocks(PurgeBlocksRequest),
+	GetReferencedBlocks,
+	SetMaintenance(SetNodeMaintenanceRequest),
+}
+
+/// Replies to `AdminNodeRpc` requests
+#[derive(Debug, Serialize, Deserialize)]
+#[allow(clippy::large_enum_variant)]
+pub enum AdminNodeRpcResponse {
+	Ok,
+	NodeStatistics(NodeStatisticsResponse),
+	GcQueue(NodeGcQueueResponse),
+	WorkerList(ListWorkersResponse),
+	WorkerInfo(WorkerInfoResp),
+	WorkerVars(GetWorkerVariableResponse),
+	WorkerVarSet(SetWorkerVariableResponse),
+	BlockErrors(ListBlockErrorsResponse),
+	BlockInfo(GetBlockInfoResponse),
+	BlockResyncRetried(RetryBlockResyncResponse),
+	BlocksPurged(PurgeBlocksResponse),
+	ReferencedBlocks(ReferencedBlocksResponse),
+	Maintenance(SetNodeMaintenanceResponse),
+}
+
+impl Rpc for AdminNodeRpc {
+	type Response = Result<AdminNodeRpcResponse, HelperError>;
+}
+
+pub struct AdminNodeRpcHandler {
+	pub(crate) garage: Arc<Garage>,
+	background: Arc<BackgroundRunner>,
+	endpoint: Arc<Endpoint<AdminNodeRpc, Self>>,
+}
+
+impl AdminNodeRpcHandler {
+	pub fn new(garage: Arc<Garage>, background: Arc<BackgroundRunner>) -> Arc<Self> {
+		let endpoint = garage.system.netapp.endpoint(ADMIN_NODE_RPC_PATH.into());
+		let handler = Arc::new(Self {
+			garage,
+			background,
+			endpoint,
+		});
+		handler.endpoint.set_handler(handler.clone());
+		handler
+	}
+
+	/// Run an operation on all nodes designated by the `node` parameter
+	/// of the request, and gather their responses.
+	pub(crate) async fn call_nodes<T, F>(
+		&self,
+		node: &str,
+		req: AdminNodeRpc,
+		extract: F,
+	) -> Result<MultiNodeResponse<T>, Error>
+	where
+		F: Fn(AdminNodeRpcResponse) -> Option<T>,
+	{
+		let nodes = self.resolve_nodes(node)?;
+
+		let resps =
+			futures::future::join_all(nodes.iter().map(|node| self.call_node(*node, &req))).await;
+
+		let mut ret = MultiNodeResponse {
+			success: HashMap::new(),
+			error: HashMap::new(),
+		};
+		for (node, resp) in nodes.iter().zip(resps) {
+			let node = hex::encode(node);
+			match resp {
+				Ok(r) => match extract(r) {
+					Some(x) => {
+						ret.success.insert(node, x);
+					}
+					None => {
+						ret.error
+							.insert(node, "Unexpected response from node".to_string());
+					}
+				},
+				Err(e) => {
+					ret.error.insert(node, e.to_string());
+				}
+			}
+		}
+
+		Ok(ret)
+	}
+
+	pub(crate) async fn call_node(
+		&self,
+		node: Uuid,
+		req: &AdminNodeRpc,
+	) -> Result<AdminNodeRpcResponse, HelperError> {
+		if node == self.garage.system.id {
+			self.handle_local(req).await
+		} else {
+			self.endpoint
+				.call(&node.into(), req.clone(), PRIO_NORMAL)
+				.await?
+		}
+	}
+
+	/// Nodes can be designated by `self`, by `*` for all nodes of the cluster,
+	/// or by a (prefix of their) hex-encoded node ID.
+	fn resolve_nodes(&self, node: &str) -> Result<Vec<Uuid>, Error> {
+		let layout = self.garage.system.cluster_layout();
+		let mut all_nodes = layout.all_nodes().to_vec();
+		for known in self.garage.system.get_known_nodes().iter() {
+			if known.is_up && !all_nodes.contains(&known.id) {
+				all_nodes.push(known.id);
+			}
+		}
+
+		match node {
+			"self" => Ok(vec![self.garage.system.id]),
+			"*" => Ok(all_nodes),
+			prefix => {
+				let matching = all_nodes
+					.into_iter()
+					.filter(|id| hex::encode(id).starts_with(prefix))
+					.collect::<Vec<_>>();
+				match matching.len() {
+					0 => Err(Error::bad_request(format!("No node matches {}", prefix))),
+					1 => Ok(matching),
+					_ => Err(Error::bad_request(format!(
+						"Several nodes match {}",
+						prefix
+					))),
+				}
+			}
+		}
+	}
+
+	async fn handle_local(&self, req: &AdminNodeRpc) -> Result<AdminNodeRpcResponse, HelperError> {
+		let garage = &self.garage;
+		match req {
+			AdminNodeRpc::GetNodeStatistics => Ok(AdminNodeRpcResponse::NodeStatistics(
+				gather_node_statistics(garage)?,
+			)),
+			AdminNodeRpc::GetGcQueue => {
+				Ok(AdminNodeRpcResponse::GcQueue(gather_node_gc_queue(garage)?))
+			}
+			AdminNodeRpc::CreateMetadataSnapshot => {
+				garage_model::snapshot::async_snapshot_metadata(garage).await?;
+				Ok(AdminNodeRpcResponse::Ok)
+			}
+			AdminNodeRpc::LaunchRepairOperation(req) => {
+				launch_online_repair(garage, &self.background, req.repair_type.clone()).await?;
+				Ok(AdminNodeRpcResponse::Ok)
+			}
+			AdminNodeRpc::ListWorkers(req) => Ok(AdminNodeRpcResponse::WorkerList(
+				local_list_workers(&self.background, req),
+			)),
+			AdminNodeRpc::GetWorkerInfo(req) => Ok(AdminNodeRpcResponse::WorkerInfo(
+				local_get_worker_info(&self.background, req)?,
+			)),
+			AdminNodeRpc::GetWorkerVariable(req) => Ok(AdminNodeRpcResponse::WorkerVars(
+				local_get_worker_variable(garage, req)?,
+			)),
+			AdminNodeRpc::SetWorkerVariable(req) => Ok(AdminNodeRpcResponse::WorkerVarSet(
+				local_set_worker_variable(garage, req)?,
+			)),
+			AdminNodeRpc::ListBlockErrors => Ok(AdminNodeRpcResponse::BlockErrors(
+				local_list_block_errors(garage)?,
+			)),
+			AdminNodeRpc::GetBlockInfo(req) => Ok(AdminNodeRpcResponse::BlockInfo(
+				local_get_block_info(garage, req).await?,
+			)),
+			AdminNodeRpc::RetryBlockResync(req) => Ok(AdminNodeRpcResponse::BlockResyncRetried(
+				local_retry_block_resync(garage, req)?,
+			)),
+			AdminNodeRpc::PurgeBlocks(req) => Ok(AdminNodeRpcResponse::BlocksPurged(
+				local_purge_blocks(garage, req).await?,
+			)),
+			AdminNodeRpc::GetReferencedBlocks => Ok(AdminNodeRpcResponse::ReferencedBlocks(
+				local_get_referenced_blocks(garage).await?,
+			)),
+			AdminNodeRpc::SetMaintenance(req) => {
+				garage.system.set_maintenance(req.enabled).await;
+				Ok(AdminNodeRpcResponse::Maintenance(
+					SetNodeMaintenanceResponse {
+						maintenance: req.enabled,
+					},
+				))
+			}
+		}
+	}
+}
+
+impl EndpointHandler<AdminNodeRpc> for AdminNodeRpcHandler {
+	fn handle(
+		self: &Arc<Self>,
+		message: &AdminNodeRpc,
+		_from: NodeID,
+	) -> impl Future<Output = Result<AdminNodeRpcResponse, HelperError>> + Send {
+		let self2 = self.clone();
+		let message = message.clone();
+		async move { self2.handle_local(&message).await }.boxed()
+	}
+}
+
+/// Response to operations that are executed on several nodes,
+/// indexed by node ID
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiNodeResponse<T> {
+	pub success: HashMap<String, T>,
+	pub error: HashMap<String, String>,
+}
+
+// ---- statistics ----
+
+pub async fn handle_get_node_statistics(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::GetNodeStatistics, |r| match r {
+			AdminNodeRpcResponse::NodeStatistics(s) => Some(s),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_cluster_statistics(
+	garage: &Arc<Garage>,
+) -> Result<Response<ResBody>, Error> {
+	let res = gather_cluster_statistics(garage);
+
+	Ok(json_ok_response(&res)?)
+}
+
+/// Gather statistics about the metadata tables and the block manager of this node
+pub fn gather_node_statistics(garage: &Arc<Garage>) -> Result<NodeStatisticsResponse, GarageError> {
+	let table_stats = vec![
+		gather_table_stats(&garage.bucket_table)?,
+		gather_table_stats(&garage.key_table)?,
+		gather_table_stats(&garage.object_table)?,
+		gather_table_stats(&garage.version_table)?,
+		gather_table_stats(&garage.block_ref_table)?,
+	];
+
+	let block_manager_stats = BlockManagerStatistics {
+		rc_entries: garage.block_manager.rc_len()? as u64,
+		resync_queue_length: garage.block_manager.resync.queue_len()? as u64,
+		resync_errors: garage.block_manager.resync.errors_len()? as u64,
+	};
+
+	Ok(NodeStatisticsResponse {
+		node: hex::encode(garage.system.id),
+		garage_version: garage_util::version::garage_version().to_string(),
+		garage_features: garage_util::version::garage_features()
+			.map(|list| list.iter().map(ToString::to_string).collect()),
+		rust_version: garage_util::version::rust_version().to_string(),
+		db_engine: garage.db.engine(),
+		table_stats,
+		block_manager_stats,
+	})
+}
+
+fn gather_table_stats<F, R>(t: &Arc<Table<F, R>>) -> Result<TableStatistics, GarageError>
+where
+	F: TableSchema + 'static,
+	R: TableReplication + 'static,
+{
+	Ok(TableStatistics {
+		table_name: F::TABLE_NAME.to_string(),
+		items: t.data.store.len()? as u64,
+		merkle_items: t.merkle_updater.merkle_tree_len()? as u64,
+		merkle_todo: t.merkle_updater.todo_len()? as u64,
+		gc_todo: t.data.gc_todo_len()? as u64,
+	})
+}
+
+// ---- table GC queue ----
+
+pub async fn handle_get_node_gc_queue(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::GetGcQueue, |r| match r {
+			AdminNodeRpcResponse::GcQueue(q) => Some(q),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+/// Gather the state of the queues of tombstones waiting to be
+/// garbage collected in the tables of this node
+pub fn gather_node_gc_queue(garage: &Arc<Garage>) -> Result<NodeGcQueueResponse, GarageError> {
+	let tables = vec![
+		gather_table_gc_queue(&garage.bucket_table)?,
+		gather_table_gc_queue(&garage.bucket_alias_table)?,
+		gather_table_gc_queue(&garage.key_table)?,
+		gather_table_gc_queue(&garage.admin_token_table)?,
+		gather_table_gc_queue(&garage.audit_table)?,
+		gather_table_gc_queue(&garage.object_table)?,
+		gather_table_gc_queue(&garage.object_counter_table.table)?,
+		gather_table_gc_queue(&garage.prefix_counter_table.table)?,
+		gather_table_gc_queue(&garage.usage_history_table)?,
+		gather_table_gc_queue(&garage.object_search_table)?,
+		gather_table_gc_queue(&garage.mpu_table)?,
+		gather_table_gc_queue(&garage.mpu_counter_table.table)?,
+		gather_table_gc_queue(&garage.version_table)?,
+		gather_table_gc_queue(&garage.block_ref_table)?,
+	];
+	#[cfg(feature = "k2v")]
+	let tables = tables
+		.into_iter()
+		.chain([
+			gather_table_gc_queue(&garage.k2v.item_table)?,
+			gather_table_gc_queue(&garage.k2v.counter_table.table)?,
+		])
+		.collect();
+
+	Ok(NodeGcQueueResponse {
+		node: hex::encode(garage.system.id),
+		tables,
+	})
+}
+
+fn gather_table_gc_queue<F, R>(t: &Arc<Table<F, R>>) -> Result<TableGcQueue, GarageError>
+where
+	F: TableSchema + 'static,
+	R: TableReplication + 'static,
+{
+	Ok(TableGcQueue {
+		table_name: F::TABLE_NAME.to_string(),
+		pending: t.data.gc_todo_len()? as u64,
+		tombstone_retention_secs: t.gc_delay().as_secs(),
+		oldest_tombstone_age_secs: t
+			.data
+			.gc_todo_oldest()?
+			.map(|ts| now_msec().saturating_sub(ts) / 1000),
+	})
+}
+
+/// Gather storage node and free space statistics for current nodes
+pub fn gather_cluster_statistics(garage: &Arc<Garage>) -> ClusterStatisticsResponse {
+	let layout = &garage.system.cluster_layout();
+	let mut node_partition_count = HashMap::<Uuid, u64>::new();
+	for short_id in layout.current().ring_assignment_data.iter() {
+		let id = layout.current().node_id_vec[*short_id as usize];
+		*node_partition_count.entry(id).or_default() += 1;
+	}
+	let node_info = garage
+		.system
+		.get_known_nodes()
+		.into_iter()
+		.map(|n| (n.id, n))
+		.collect::<HashMap<_, _>>();
+
+	let mut storage_nodes = node_partition_count
+		.iter()
+		.map(|(id, parts)| {
+			let status = node_info.get(id).map(|x| &x.status);
+			let role = layout.current().roles.get(id).and_then(|x| x.0.as_ref());
+			StorageNodeStatistics {
+				id: hex::encode(id),
+				hostname: status.and_then(|x| x.hostname.clone()),
+				zone: role.map(|x| x.zone.clone()),
+				capacity: role.and_then(|x| x.capacity),
+				partitions: *parts,
+				data_partition: status
+					.and_then(|x| x.data_disk_avail)
+					.map(|(available, total)| FreeSpaceResp { available, total }),
+				metadata_partition: status
+					.and_then(|x| x.meta_disk_avail)
+					.map(|(available, total)| FreeSpaceResp { available, total }),
+			}
+		})
+		.collect::<Vec<_>>();
+	storage_nodes.sort_by(|x, y| x.id.cmp(&y.id));
+
+	let meta_part_avail = storage_nodes
+		.iter()
+		.filter_map(|n| {
+			n.metadata_partition
+				.as_ref()
+				.map(|c| c.available / n.partitions)
+		})
+		.collect::<Vec<_>>();
+	let data_part_avail = storage_nodes
+		.iter()
+		.filter_map(|n| {
+			n.data_partition
+				.as_ref()
+				.map(|c| c.available / n.partitions)
+		})
+		.collect::<Vec<_>>();
+
+	let estimated_available_space = if !meta_part_avail.is_empty() && !data_part_avail.is_empty() {
+		Some(EstimatedAvailableSpace {
+			data: data_part_avail.iter().min().unwrap() * (1 << PARTITION_BITS),
+			metadata: meta_part_avail.iter().min().unwrap() * (1 << PARTITION_BITS),
+			precise: meta_part_avail.len() == storage_nodes.len()
+				&& data_part_avail.len() == storage_nodes.len(),
+		})
+	} else {
+		None
+	};
+
+	ClusterStatisticsResponse {
+		storage_nodes,
+		estimated_available_space,
+	}
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeStatisticsResponse {
+	pub node: String,
+	pub garage_version: String,
+	pub garage_features: Option<Vec<String>>,
+	pub rust_version: String,
+	pub db_engine: String,
+	pub table_stats: Vec<TableStatistics>,
+	pub block_manager_stats: BlockManagerStatistics,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableStatistics {
+	pub table_name: String,
+	pub items: u64,
+	pub merkle_items: u64,
+	pub merkle_todo: u64,
+	pub gc_todo: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeGcQueueResponse {
+	pub node: String,
+	pub tables: Vec<TableGcQueue>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TableGcQueue {
+	pub table_name: String,
+	/// Number of tombstones waiting to be garbage collected
+	pub pending: u64,
+	/// Delay after which tombstones of this table are garbage collected
+	pub tombstone_retention_secs: u64,
+	/// Age of the oldest tombstone waiting to be garbage collected
+	pub oldest_tombstone_age_secs: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockManagerStatistics {
+	pub rc_entries: u64,
+	pub resync_queue_length: u64,
+	pub resync_errors: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterStatisticsResponse {
+	pub storage_nodes: Vec<StorageNodeStatistics>,
+	pub estimated_available_space: Option<EstimatedAvailableSpace>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageNodeStatistics {
+	pub id: String,
+	pub hostname: Option<String>,
+	pub zone: Option<String>,
+	pub capacity: Option<u64>,
+	pub partitions: u64,
+	pub data_partition: Option<FreeSpaceResp>,
+	pub metadata_partition: Option<FreeSpaceResp>,
+}
+
+/// Estimation of the storage space available cluster-wide, in bytes.
+/// This is only a lower bound if information is missing for some nodes
+/// (`precise` is false).
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EstimatedAvailableSpace {
+	pub data: u64,
+	pub metadata: u64,
+	pub precise: bool,
+}
+
+// ---- metadata snapshots ----
+
+pub async fn handle_create_metadata_snapshot(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+) -> Result<Response<ResBody>, Error> {
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::CreateMetadataSnapshot, |r| match r {
+			AdminNodeRpcResponse::Ok => Some(CreateMetadataSnapshotResponse {}),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreateMetadataSnapshotResponse {}
+
+// ---- maintenance ----
+
+pub async fn handle_set_node_maintenance(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<SetNodeMaintenanceRequest, _, Error>(req).await?;
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::SetMaintenance(req), |r| match r {
+			AdminNodeRpcResponse::Maintenance(m) => Some(m),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+/// Put a single node in maintenance or take it out of maintenance
+pub async fn set_node_maintenance(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: Uuid,
+	enabled: bool,
+) -> Result<SetNodeMaintenanceResponse, HelperError> {
+	match node_rpc
+		.call_node(
+			node,
+			&AdminNodeRpc::SetMaintenance(SetNodeMaintenanceRequest { enabled }),
+		)
+		.await?
+	{
+		AdminNodeRpcResponse::Maintenance(m) => Ok(m),
+		_ => Err(HelperError::Internal(GarageError::Message(
+			"Unexpected response from node".into(),
+		))),
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetNodeMaintenanceRequest {
+	pub enabled: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetNodeMaintenanceResponse {
+	pub maintenance: bool,
+}
+
+// ---- repair operations ----
+
+pub async fn handle_launch_repair_operation(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<LaunchRepairOperationRequest, _, Error>(req).await?;
+
+	let res = node_rpc
+		.call_nodes(
+			&node,
+			AdminNodeRpc::LaunchRepairOperation(req),
+			|r| match r {
+				AdminNodeRpcResponse::Ok => Some(LaunchRepairOperationResponse {}),
+				_ => None,
+			},
+		)
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LaunchRepairOperationRequest {
+	pub repair_type: RepairType,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LaunchRepairOperationResponse {}
@@ -0,0 +1,520 @@
+#![allow(dead_code, non_snake_case)]
+
+use utoipa::{Modify, OpenApi};
+
+use crate::bucket::*;
+use crate::cluster::*;
+use crate::key::*;
+
+// **********************************************
+//      Special endpoints
+// **********************************************
+
+#[utoipa::path(get,
+    path = "/v1/openapi.json",
+    tag = "Special endpoints",
+    description = "Returns the OpenAPI specification of the admin API, in JSON format.",
+    security(()),
+    responses(
+        (status = 200, description = "OpenAPI specification of the admin API", content_type = "application/json"),
+    ),
+)]
+fn GetOpenApiSpec() {}
+
+// **********************************************
+//      Cluster operations
+// **********************************************
+
+#[utoipa::path(get,
+    path = "/v1/status",
+    tag = "Nodes",
+    description = "Returns the cluster's current status, including the ID of the node being queried, its version of the Garage daemon, and the list of known nodes.",
+    responses(
+        (status = 200, description = "Cluster status report", body = GetClusterStatusResponse),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GetClusterStatus() {}
+
+#[utoipa::path(get,
+    path = "/v1/health",
+    tag = "Nodes",
+    description = "Returns the global status of the cluster, the number of connected nodes (over the number of known ones), the number of healthy storage nodes (over the declared ones), and the number of healthy partitions (over the total).",
+    responses(
+        (status = 200, description = "Cluster health report", body = ClusterHealth),
+    ),
+)]
+fn GetClusterHealth() {}
+
+#[utoipa::path(post,
+    path = "/v1/connect",
+    tag = "Nodes",
+    description = "Instructs this Garage node to connect to other Garage nodes at specified `<node_id>@<net_address>`. `node_id` is generated automatically on node start.",
+    request_body = Vec<String>,
+    responses(
+        (status = 200, description = "The request has been handled correctly but it does not mean that all connection requests succeeded; some might have fail, you need to check the body!", body = Vec<ConnectClusterNodesResponse>),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn ConnectClusterNodes() {}
+
+// **********************************************
+//      Layout operations
+// **********************************************
+
+#[utoipa::path(get,
+    path = "/v1/layout",
+    tag = "Layout",
+    description = "Returns the cluster's current layout, including the currently configured cluster layout and the staged changes to the cluster layout.",
+    responses(
+        (status = 200, description = "Current cluster layout", body = GetClusterLayoutResponse),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GetClusterLayout() {}
+
+#[utoipa::path(post,
+    path = "/v1/layout",
+    tag = "Layout",
+    description = "Send modifications to the cluster layout. These modifications will be included in the staged role changes, visible in subsequent calls of `GET /v1/layout`. Once the set of staged changes is satisfactory, the user may call `POST /v1/layout/apply` to apply the changes, or `POST /v1/layout/revert` to clear all of the staged changes in the layout.",
+    request_body = UpdateClusterLayoutRequest,
+    responses(
+        (status = 200, description = "Proposed changes have been added to the list of pending changes", body = GetClusterLayoutResponse),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn UpdateClusterLayout() {}
+
+#[utoipa::path(post,
+    path = "/v1/layout/apply",
+    tag = "Layout",
+    description = "Applies to the cluster the layout changes currently registered as staged layout changes.",
+    request_body = ApplyLayoutRequest,
+    responses(
+        (status = 200, description = "The updated cluster layout has been applied in the cluster", body = ApplyClusterLayoutResponse),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn ApplyClusterLayout() {}
+
+#[utoipa::path(post,
+    path = "/v1/layout/revert",
+    tag = "Layout",
+    description = "Clear staged layout changes",
+    responses(
+        (status = 200, description = "All pending changes to the cluster layout have been erased", body = GetClusterLayoutResponse),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn RevertClusterLayout() {}
+
+// **********************************************
+//      Access key operations
+// **********************************************
+
+#[utoipa::path(get,
+    path = "/v1/key?list",
+    tag = "Key",
+    description = "Returns all API access keys in the cluster.",
+    responses(
+        (status = 200, description = "Returns the key identifier (aka `AWS_ACCESS_KEY_ID`) and its associated, human friendly, name if any (otherwise return an empty string)", body = Vec<ListKeyResultItem>),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn ListKeys() {}
+
+#[utoipa::path(get,
+    path = "/v1/key",
+    tag = "Key",
+    description = "Return information about a specific key like its identifiers, its permissions and buckets on which it has permissions. You can search by specifying the exact key identifier (`id`) or by specifying a pattern (`search`). For confidentiality reasons, the secret key is not returned by default: you must pass the `showSecretKey` query parameter to get it.",
+    params(
+        ("id" = Option<String>, Query, description = "Access key ID"),
+        ("search" = Option<String>, Query, description = "Partial key ID or name to search for"),
+        ("showSecretKey" = Option<String>, Query, description = "Whether to return the secret access key (`true` or `false`)"),
+    ),
+    responses(
+        (status = 200, description = "Information about the access key", body = GetKeyInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GetKeyInfo() {}
+
+#[utoipa::path(post,
+    path = "/v1/key",
+    tag = "Key",
+    description = "Creates a new API access key.",
+    request_body = CreateKeyRequest,
+    responses(
+        (status = 200, description = "Access key has been created", body = GetKeyInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn CreateKey() {}
+
+#[utoipa::path(post,
+    path = "/v1/key/import",
+    tag = "Key",
+    description = "Imports an existing API key. This feature must only be used for migrations and backup restore. **Do not use it to generate custom key identifiers or you will break your Garage cluster.**",
+    request_body = ImportKeyRequest,
+    responses(
+        (status = 200, description = "Access key has been imported", body = GetKeyInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn ImportKey() {}
+
+#[utoipa::path(post,
+    path = "/v1/key?id={id}",
+    tag = "Key",
+    description = "Updates information about the specified API access key. *Note: the secret key is not returned in the response, `null` is sent instead.*",
+    params(
+        ("id" = String, Query, description = "Access key ID"),
+    ),
+    request_body = UpdateKeyRequest,
+    responses(
+        (status = 200, description = "Access key has been updated", body = GetKeyInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn UpdateKey() {}
+
+#[utoipa::path(delete,
+    path = "/v1/key?id={id}",
+    tag = "Key",
+    description = "Delete a key from the cluster. Its access will be removed from all the buckets. Buckets are not automatically deleted and can be dangling. You should manually delete them before.",
+    params(
+        ("id" = String, Query, description = "Access key ID"),
+    ),
+    responses(
+        (status = 204, description = "Access key has been deleted"),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn DeleteKey() {}
+
+// **********************************************
+//      Bucket operations
+// **********************************************
+
+#[utoipa::path(get,
+    path = "/v1/bucket?list",
+    tag = "Bucket",
+    description = "List all the buckets on the cluster with their UUID and their global and local aliases.",
+    responses(
+        (status = 200, description = "Returns the UUID of all the buckets and all their aliases", body = Vec<ListBucketResultItem>),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn ListBuckets() {}
+
+#[utoipa::path(get,
+    path = "/v1/bucket",
+    tag = "Bucket",
+    description = "Given a bucket identifier (`id`) or a global alias (`globalAlias`), get its information. It includes its aliases, its web configuration, keys that have some permissions on it, some statistics (number of objects, size), number of dangling multipart uploads, and its quotas (if any).",
+    params(
+        ("id" = Option<String>, Query, description = "Exact bucket ID to look up"),
+        ("globalAlias" = Option<String>, Query, description = "Global alias of bucket to look up"),
+    ),
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GetBucketInfo() {}
+
+#[utoipa::path(post,
+    path = "/v1/bucket",
+    tag = "Bucket",
+    description = "Creates a new bucket, either with a global alias, a local one, or no alias at all.",
+    request_body = CreateBucketRequest,
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn CreateBucket() {}
+
+#[utoipa::path(put,
+    path = "/v1/bucket?id={id}",
+    tag = "Bucket",
+    description = "All fields (`websiteAccess` and `quotas`) are optional. If they are present, the corresponding modifications are applied to the bucket, otherwise nothing is changed.",
+    params(
+        ("id" = String, Query, description = "ID of the bucket to update"),
+    ),
+    request_body = UpdateBucketRequest,
+    responses(
+        (status = 200, description = "Bucket has been updated", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn UpdateBucket() {}
+
+#[utoipa::path(delete,
+    path = "/v1/bucket?id={id}",
+    tag = "Bucket",
+    description = "Delete a bucket. Deletes a storage bucket. A bucket cannot be deleted if it is not empty.",
+    params(
+        ("id" = String, Query, description = "ID of the bucket to delete"),
+    ),
+    responses(
+        (status = 204, description = "Bucket has been deleted"),
+        (status = 400, description = "Bucket is not empty"),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn DeleteBucket() {}
+
+// **********************************************
+//      Operations on permissions for keys on buckets
+// **********************************************
+
+#[utoipa::path(post,
+    path = "/v1/bucket/allow",
+    tag = "Permission",
+    description = "Allows a key to do read/write/owner operations on a bucket. Flags in `permissions` which have the value `true` will be activated. Other flags will remain unchanged.",
+    request_body = BucketKeyPermChangeRequest,
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn BucketAllowKey() {}
+
+#[utoipa::path(post,
+    path = "/v1/bucket/deny",
+    tag = "Permission",
+    description = "Denies a key from doing read/write/owner operations on a bucket. Flags in `permissions` which have the value `true` will be deactivated. Other flags will remain unchanged.",
+    request_body = BucketKeyPermChangeRequest,
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn BucketDenyKey() {}
+
+// **********************************************
+//      Operations on bucket aliases
+// **********************************************
+
+#[utoipa::path(put,
+    path = "/v1/bucket/alias/global",
+    tag = "Bucket alias",
+    description = "Add a global alias to the target bucket",
+    params(
+        ("id" = String, Query, description = "ID of the bucket"),
+        ("alias" = String, Query, description = "Global alias to add"),
+    ),
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GlobalAliasBucket() {}
+
+#[utoipa::path(delete,
+    path = "/v1/bucket/alias/global",
+    tag = "Bucket alias",
+    description = "Remove a global alias from the target bucket",
+    params(
+        ("id" = String, Query, description = "ID of the bucket"),
+        ("alias" = String, Query, description = "Global alias to remove"),
+    ),
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn GlobalUnaliasBucket() {}
+
+#[utoipa::path(put,
+    path = "/v1/bucket/alias/local",
+    tag = "Bucket alias",
+    description = "Add a local alias to the target bucket, in the namespace of a specific access key",
+    params(
+        ("id" = String, Query, description = "ID of the bucket"),
+        ("accessKeyId" = String, Query, description = "Access key in whose namespace the alias is added"),
+        ("alias" = String, Query, description = "Local alias to add"),
+    ),
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn LocalAliasBucket() {}
+
+#[utoipa::path(delete,
+    path = "/v1/bucket/alias/local",
+    tag = "Bucket alias",
+    description = "Remove a local alias from the target bucket, in the namespace of a specific access key",
+    params(
+        ("id" = String, Query, description = "ID of the bucket"),
+        ("accessKeyId" = String, Query, description = "Access key in whose namespace the alias is removed"),
+        ("alias" = String, Query, description = "Local alias to remove"),
+    ),
+    responses(
+        (status = 200, description = "Returns exhaustive information about the bucket", body = GetBucketInfoResult),
+        (status = 500, description = "Internal server error"),
+    ),
+)]
+fn LocalUnaliasBucket() {}
+
+// **********************************************
+// **********************************************
+// **********************************************
+
+struct SecurityAddon;
+
+impl Modify for SecurityAddon {
+	fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+		use utoipa::openapi::security::*;
+		let components = openapi.components.as_mut().unwrap();
+		components.add_security_scheme(
+			"bearerAuth",
+			SecurityScheme::Http(Http::builder().scheme(HttpAuthScheme::Bearer).build()),
+		)
+	}
+}
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        version = "v1",
+        title = "Garage administration API",
+        description = "Administrate your Garage cluster programatically, including status, layout, keys, buckets, and maintainance tasks.",
+        contact(
+            name = "The Garage team",
+            email = "garagehq@deuxfleurs.fr",
+            url = "https://garagehq.deuxfleurs.fr/",
+        ),
+    ),
+    modifiers(&SecurityAddon),
+    security(("bearerAuth" = [])),
+    paths(
+        // Special endpoints
+        GetOpenApiSpec,
+        // Cluster operations
+        GetClusterStatus,
+        GetClusterHealth,
+        ConnectClusterNodes,
+        // Layout operations
+        GetClusterLayout,
+        UpdateClusterLayout,
+        ApplyClusterLayout,
+        RevertClusterLayout,
+        // Key operations
+        ListKeys,
+        GetKeyInfo,
+        CreateKey,
+        ImportKey,
+        UpdateKey,
+        DeleteKey,
+        // Bucket operations
+        ListBuckets,
+        GetBucketInfo,
+        CreateBucket,
+        UpdateBucket,
+        DeleteBucket,
+        // Operations on permissions
+        BucketAllowKey,
+        BucketDenyKey,
+        // Operations on aliases
+        GlobalAliasBucket,
+        GlobalUnaliasBucket,
+        LocalAliasBucket,
+        LocalUnaliasBucket,
+    ),
+    servers(
+        (url = "http://localhost:3903/", description = "A local server")
+    ),
+)]
+pub struct ApiDoc;
+
+/// Returns the OpenAPI specification of the v1 admin API, as pretty-printed JSON.
+pub fn openapi_json() -> String {
+	ApiDoc::openapi()
+		.to_pretty_json()
+		.expect("OpenAPI spec serialization cannot fail")
+}
+
+#[cfg(test)]
+mod tests {
+	use hyper::{Method, Request};
+	use utoipa::openapi::path::ParameterIn;
+	use utoipa::openapi::Required;
+
+	use super::*;
+	use crate::router_v1;
+
+	const SPEC_PATH: &str = concat!(
+		env!("CARGO_MANIFEST_DIR"),
+		"/../../../doc/api/garage-admin-v1.json"
+	);
+
+	/// The checked-in specification must be exactly what the handler types generate.
+	/// Run this test with `GARAGE_UPDATE_OPENAPI_SPEC=1` to regenerate the file.
+	#[test]
+	fn test_openapi_spec_up_to_date() {
+		let generated = openapi_json() + "\n";
+		if std::env::var("GARAGE_UPDATE_OPENAPI_SPEC").is_ok() {
+			std::fs::write(SPEC_PATH, &generated).unwrap();
+		}
+		let checked_in = std::fs::read_to_string(SPEC_PATH).unwrap();
+		assert!(
+			checked_in == generated,
+			"{} is out of date, run `GARAGE_UPDATE_OPENAPI_SPEC=1 cargo test -p garage_api_admin` to regenerate it",
+			SPEC_PATH
+		);
+	}
+
+	/// Every operation of the specification must be routed to the endpoint of the same name.
+	#[test]
+	fn test_openapi_spec_matches_router() {
+		let spec = ApiDoc::openapi();
+		let mut n_operations = 0;
+		for (path, item) in spec.paths.paths.iter() {
+			let path = path.strip_suffix("?list").unwrap_or(path);
+			let operations = [
+				(Method::GET, &item.get),
+				(Method::POST, &item.post),
+				(Method::PUT, &item.put),
+				(Method::DELETE, &item.delete),
+			];
+			for (method, op) in operations {
+				let op = match op {
+					Some(op) => op,
+					None => continue,
+				};
+				n_operations += 1;
+
+				// Fill in all required query parameters; endpoints that only have
+				// optional ones (e.g. GetKeyInfo) need at least the first of them.
+				let mut uri = path.replace("{id}", "xxx");
+				for (i, param) in op.parameters.iter().flatten().enumerate() {
+					if param.parameter_in == ParameterIn::Query
+						&& (param.required == Required::True || (i == 0 && !uri.contains('?')))
+						&& !uri.contains(&format!("{}=", param.name))
+					{
+						let sep = if uri.contains('?') { '&' } else { '?' };
+						uri = format!("{}{}{}=xxx", uri, sep, param.name);
+					}
+				}
+
+				let req = Request::builder()
+					.method(method.clone())
+					.uri(&uri)
+					.body(())
+					.unwrap();
+				let endpoint = router_v1::Endpoint::from_request(&req)
+					.unwrap_or_else(|_| panic!("{} {} is not routed", method, uri));
+				assert_eq!(
+					Some(endpoint.name()),
+					op.operation_id.as_deref(),
+					"{} {} is routed to the wrong endpoint",
+					method,
+					uri
+				);
+			}
+		}
+		assert_eq!(n_operations, 25);
+	}
+}
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use std::time::Duration;
 
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use tokio::sync::watch;
 
 use garage_block::manager::BlockManager;
@@ -11,6 +12,7 @@ use garage_block::repair::ScrubWorkerCommand;
 use garage_model::garage::Garage;
 use garage_model::s3::block_ref_table::*;
 use garage_model::s3::mpu_table::*;
+use garage_model::s3::object_search_table::*;
 use garage_model::s3::object_table::*;
 use garage_model::s3::version_table::*;
 
@@ -19,59 +21,100 @@ use garage_table::*;
 
 use garage_util::background::*;
 use garage_util::data::*;
-use garage_util::error::Error;
+use garage_util::error::{Error, OkOrMessage};
 use garage_util::migrate::Migrate;
 
-use crate::*;
-
 const RC_REPAIR_ITER_COUNT: usize = 64;
 
+/// Repair operations that can be launched on a running node
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RepairType {
+	/// Do a full sync of metadata tables
+	Tables,
+	/// Repair (resync/rebalance) the set of stored blocks in the cluster
+	Blocks,
+	/// Repropagate object deletions to the version table
+	Versions,
+	/// Repropagate object deletions to the multipart upload table
+	MultipartUploads,
+	/// Repropagate version deletions to the block ref table
+	BlockRefs,
+	/// Recalculate block reference counters
+	BlockRc,
+	/// Fix inconsistency in bucket aliases
+	Aliases,
+	/// Send a command to the scrub worker
+	Scrub(ScrubCommand),
+	/// Verify integrity of the items of all metadata tables
+	MetaScrub,
+	/// Rebalance data blocks among HDDs on individual nodes
+	Rebalance,
+	/// Rebuild the object search index of the buckets where it is enabled
+	SearchIndex,
+}
+
+/// Commands that can be sent to the block scrub worker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ScrubCommand {
+	Start,
+	/// Pause scrub (it will resume automatically after 24 hours)
+	Pause,
+	Resume,
+	Cancel,
+	/// Set tranquility level for in-progress and future scrubs
+	SetTranquility(u32),
+}
+
 pub async fn launch_online_repair(
 	garage: &Arc<Garage>,
 	bg: &BackgroundRunner,
-	opt: RepairOpt,
+	repair: RepairType,
 ) -> Result<(), Error> {
-	match opt.what {
-		RepairWhat::Tables => {
+	match repair {
+		RepairType::Tables => {
 			info!("Launching a full sync of tables");
 			garage.bucket_table.syncer.add_full_sync()?;
 			garage.object_table.syncer.add_full_sync()?;
 			garage.version_table.syncer.add_full_sync()?;
 			garage.block_ref_table.syncer.add_full_sync()?;
 			garage.key_table.syncer.add_full_sync()?;
+			garage.admin_token_table.syncer.add_full_sync()?;
+			garage.audit_table.syncer.add_full_sync()?;
 		}
-		RepairWhat::Versions => {
+		RepairType::Versions => {
 			info!("Repairing the versions table");
 			bg.spawn_worker(TableRepairWorker::new(garage.clone(), RepairVersions));
 		}
-		RepairWhat::MultipartUploads => {
+		RepairType::MultipartUploads => {
 			info!("Repairing the multipart uploads table");
 			bg.spawn_worker(TableRepairWorker::new(garage.clone(), RepairMpu));
 		}
-		RepairWhat::BlockRefs => {
+		RepairType::BlockRefs => {
 			info!("Repairing the block refs table");
 			bg.spawn_worker(TableRepairWorker::new(garage.clone(), RepairBlockRefs));
 		}
-		RepairWhat::BlockRc => {
+		RepairType::BlockRc => {
 			info!("Repairing the block reference counters");
 			bg.spawn_worker(BlockRcRepair::new(
 				garage.block_manager.clone(),
 				garage.block_ref_table.clone(),
 			));
 		}
-		RepairWhat::Blocks => {
+		RepairType::Blocks => {
 			info!("Repairing the stored blocks");
 			bg.spawn_worker(garage_block::repair::RepairWorker::new(
 				garage.block_manager.clone(),
 			));
 		}
-		RepairWhat::Scrub { cmd } => {
+		RepairType::Scrub(cmd) => {
 			let cmd = match cmd {
-				ScrubCmd::Start => ScrubWorkerCommand::Start,
-				ScrubCmd::Pause => ScrubWorkerCommand::Pause(Duration::from_secs(3600 * 24)),
-				ScrubCmd::Resume => ScrubWorkerCommand::Resume,
-				ScrubCmd::Cancel => ScrubWorkerCommand::Cancel,
-				ScrubCmd::SetTranquility { tranquility } => {
+				ScrubCommand::Start => ScrubWorkerCommand::Start,
+				ScrubCommand::Pause => ScrubWorkerCommand::Pause(Duration::from_secs(3600 * 24)),
+				ScrubCommand::Resume => ScrubWorkerCommand::Resume,
+				ScrubCommand::Cancel => ScrubWorkerCommand::Cancel,
+				ScrubCommand::SetTranquility(tranquility) => {
 					garage
 						.block_manager
 						.scrub_persister
@@ -82,13 +125,28 @@ pub async fn launch_online_repair(
 			info!("Sending command to scrub worker: {:?}", cmd);
 			garage.block_manager.send_scrub_command(cmd).await?;
 		}
-		RepairWhat::Rebalance => {
+		RepairType::MetaScrub => {
+			info!("Launching a scrub of metadata tables");
+			garage.spawn_meta_scrub_workers(bg)?;
+		}
+		RepairType::Rebalance => {
 			info!("Rebalancing the stored blocks among storage locations");
 			bg.spawn_worker(garage_block::repair::RebalanceWorker::new(
 				garage.block_manager.clone(),
 			));
 		}
-		RepairWhat::Aliases => {
+		RepairType::SearchIndex => {
+			info!("Repairing the object search index");
+			bg.spawn_worker(TableRepairWorker::new(
+				garage.clone(),
+				RepairSearchIndex::default(),
+			));
+			bg.spawn_worker(TableRepairWorker::new(
+				garage.clone(),
+				RepairSearchEntries::default(),
+			));
+		}
+		RepairType::Aliases => {
 			info!("Repairing bucket aliases (foreground)");
 			garage.locked_helper().await.repair_aliases().await?;
 		}
@@ -295,6 +353,114 @@ impl TableRepair for RepairMpu {
 	}
 }
 
+// ----
+
+/// Cache of the search index setting of the last bucket seen by a repair
+/// worker, as tables are iterated in the order of their partitions
+#[derive(Default)]
+struct SearchIndexEnabled(Option<(Uuid, bool)>);
+
+impl SearchIndexEnabled {
+	async fn get(&mut self, garage: &Garage, bucket_id: Uuid) -> Result<bool, Error> {
+		match self.0 {
+			Some((id, enabled)) if id == bucket_id => Ok(enabled),
+			_ => {
+				let enabled = garage
+					.bucket_table
+					.get(&EmptyKey, &bucket_id)
+					.await?
+					.and_then(|b| b.state.into_option())
+					.map(|p| *p.search_index.get())
+					.unwrap_or(false);
+				self.0 = Some((bucket_id, enabled));
+				Ok(enabled)
+			}
+		}
+	}
+}
+
+/// Add the objects that are missing from the search index
+#[derive(Default)]
+struct RepairSearchIndex {
+	enabled: SearchIndexEnabled,
+}
+
+impl TableRepair for RepairSearchIndex {
+	type T = ObjectTable;
+
+	fn table(garage: &Garage) -> &Table<Self::T, TableShardedReplication> {
+		&garage.object_table
+	}
+
+	async fn process(&mut self, garage: &Garage, object: Object) -> Result<bool, Error> {
+		if !self.enabled.get(garage, object.bucket_id).await? {
+			return Ok(false);
+		}
+
+		let expected = match ObjectSearchEntry::from_object(&object) {
+			Some(e) if !e.is_tombstone() => e,
+			_ => return Ok(false),
+		};
+		let current = garage
+			.object_search_table
+			.get(&object.bucket_id, &object.key)
+			.await?;
+		if current.as_ref() != Some(&expected) {
+			info!(
+				"Repair search index: indexing object {:?} in bucket {:?}",
+				object.key, object.bucket_id
+			);
+			garage.object_search_table.insert(&expected).await?;
+			return Ok(true);
+		}
+
+		Ok(false)
+	}
+}
+
+/// Remove the entries of the search index whose object has changed
+/// or whose bucket no longer has the search index enabled
+#[derive(Default)]
+struct RepairSearchEntries {
+	enabled: SearchIndexEnabled,
+}
+
+impl TableRepair for RepairSearchEntries {
+	type T = ObjectSearchTable;
+
+	fn table(garage: &Garage) -> &Table<Self::T, TableShardedReplication> {
+		&garage.object_search_table
+	}
+
+	async fn process(&mut self, garage: &Garage, entry: ObjectSearchEntry) -> Result<bool, Error> {
+		if entry.is_tombstone() {
+			return Ok(false);
+		}
+
+		let fixed = if self.enabled.get(garage, entry.bucket_id).await? {
+			let expected = garage
+				.object_table
+				.get(&entry.bucket_id, &entry.key)
+				.await?
+				.and_then(|o| ObjectSearchEntry::from_object(&o));
+			match expected {
+				Some(e) if e == entry => return Ok(false),
+				Some(e) if (e.timestamp, e.version) > (entry.timestamp, entry.version) => e,
+				_ => entry.into_deleted(),
+			}
+		} else {
+			entry.into_deleted()
+		};
+
+		info!(
+			"Repair search index: updating entry of object {:?} in bucket {:?}",
+			fixed.key, fixed.bucket_id
+		);
+		garage.object_search_table.insert(&fixed).await?;
+		Ok(true)
+	}
+}
+
 // ===== block reference counter repair =====
 
 pub struct BlockRcRepair {
@@ -323,7 +489,7 @@ impl BlockRcRepair {
 #[async_trait]
 impl Worker for BlockRcRepair {
 	fn name(&self) -> String {
-		format!("Block refcount repair worker")
+		"Block refcount repair worker".to_string()
 	}
 
 	fn status(&self) -> WorkerStatus {
@@ -389,3 +555,35 @@ impl Worker for BlockRcRepair {
 		unreachable!()
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	#[tokio::test]
+	async fn test_meta_scrub_not_started_twice() {
+		let (garage, _dir) = test_garage();
+		let (_exit, must_exit) = watch::channel(false);
+		let (bg, _) = BackgroundRunner::new(must_exit);
+
+		launch_online_repair(&garage, &bg, RepairType::MetaScrub)
+			.await
+			.unwrap();
+		assert!(launch_online_repair(&garage, &bg, RepairType::MetaScrub)
+			.await
+			.is_err());
+
+		// A new scrub can be started once the workers of the previous one are done
+		tokio::time::timeout(Duration::from_secs(10), async {
+			while launch_online_repair(&garage, &bg, RepairType::MetaScrub)
+				.await
+				.is_err()
+			{
+				tokio::time::sleep(Duration::from_millis(50)).await;
+			}
+		})
+		.await
+		.expect("metadata scrub workers did not exit");
+	}
+}
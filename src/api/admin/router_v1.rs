@@ -7,12 +7,6 @@ use garage_api_common::router_macros::*;
 use crate::error::*;
 use crate::router_v0;
 
-pub enum Authorization {
-	None,
-	MetricsToken,
-	AdminToken,
-}
-
 router_match! {@func
 
 /// List of all Admin API endpoints.
@@ -22,6 +16,7 @@ pub enum Endpoint {
 	CheckDomain,
 	Health,
 	Metrics,
+	GetOpenApiSpec,
 	GetClusterStatus,
 	GetClusterHealth,
 	ConnectClusterNodes,
@@ -98,6 +93,7 @@ impl Endpoint {
 			GET "/check" => CheckDomain,
 			GET "/health" => Health,
 			GET "/metrics" => Metrics,
+			GET "/v1/openapi.json" => GetOpenApiSpec,
 			GET "/v1/status" => GetClusterStatus,
 			GET "/v1/health" => GetClusterHealth,
 			POST "/v1/connect" => ConnectClusterNodes,
@@ -211,15 +207,6 @@ impl Endpoint {
 			))),
 		}
 	}
-	/// Get the kind of authorization which is required to perform the operation.
-	pub fn authorization_type(&self) -> Authorization {
-		match self {
-			Self::Health => Authorization::None,
-			Self::CheckDomain => Authorization::None,
-			Self::Metrics => Authorization::MetricsToken,
-			_ => Authorization::AdminToken,
-		}
-	}
 }
 
 generateQueryParameters! {
@@ -0,0 +1,490 @@
+use std::borrow::Cow;
+
+use hyper::{Method, Request};
+
+use garage_api_common::router_macros::*;
+
+use crate::error::*;
+use crate::router_v1;
+
+pub enum Authorization {
+	None,
+	MetricsToken,
+	AdminToken,
+	RootAdminToken,
+}
+
+router_match! {@func
+
+/// List of all Admin API endpoints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+	Options,
+	CheckDomain,
+	Health,
+	Metrics,
+	GetOpenApiSpec,
+	GetClusterStatus,
+	GetClusterHealth,
+	GetClusterStatistics,
+	ConnectClusterNodes,
+	// Layout
+	GetClusterLayout,
+	UpdateClusterLayout,
+	ApplyClusterLayout,
+	RevertClusterLayout,
+	SimulateClusterLayout,
+	// Keys
+	ListKeys,
+	CreateKey,
+	ImportKey,
+	GetKeyInfo {
+		id: Option<String>,
+		search: Option<String>,
+		show_secret_key: Option<String>,
+	},
+	DeleteKey {
+		id: String,
+	},
+	UpdateKey {
+		id: String,
+	},
+	// Buckets
+	ListBuckets,
+	CreateBucket,
+	GetBucketInfo {
+		id: Option<String>,
+		global_alias: Option<String>,
+	},
+	DeleteBucket {
+		id: String,
+	},
+	UpdateBucket {
+		id: String,
+	},
+	GetBucketUsage {
+		id: String,
+	},
+	GetBucketUsageHistory {
+		id: String,
+		resolution: Option<String>,
+		since: Option<String>,
+		until: Option<String>,
+	},
+	SearchBucketObjects {
+		id: String,
+	},
+	// Bucket-Key Permissions
+	BucketAllowKey,
+	BucketDenyKey,
+	// Bucket aliases
+	GlobalAliasBucket {
+		id: String,
+		alias: String,
+	},
+	GlobalUnaliasBucket {
+		id: String,
+		alias: String,
+	},
+	LocalAliasBucket {
+		id: String,
+		access_key_id: String,
+		alias: String,
+	},
+	LocalUnaliasBucket {
+		id: String,
+		access_key_id: String,
+		alias: String,
+	},
+	// Admin API tokens
+	ListAdminTokens,
+	GetAdminTokenInfo {
+		id: String,
+	},
+	CreateAdminToken,
+	UpdateAdminToken {
+		id: String,
+	},
+	DeleteAdminToken {
+		id: String,
+	},
+	// Audit log
+	ListAuditRecords {
+		since: Option<String>,
+		until: Option<String>,
+		limit: Option<String>,
+	},
+	// Node operations
+	GetNodeStatistics {
+		node: String,
+	},
+	GetNodeGcQueue {
+		node: String,
+	},
+	CreateMetadataSnapshot {
+		node: String,
+	},
+	LaunchRepairOperation {
+		node: String,
+	},
+	DrainClusterNode {
+		node: String,
+	},
+	GetNodeDrainStatus {
+		node: String,
+	},
+	SetNodeMaintenance {
+		node: String,
+	},
+	// Workers
+	ListWorkers {
+		node: String,
+		busy_only: Option<String>,
+		error_only: Option<String>,
+	},
+	GetWorkerInfo {
+		node: String,
+		id: String,
+	},
+	GetWorkerVariable {
+		node: String,
+		variable: Option<String>,
+	},
+	SetWorkerVariable {
+		node: String,
+	},
+	// Blocks
+	ListBlockErrors {
+		node: String,
+	},
+	GetBlockInfo {
+		node: String,
+		hash: String,
+	},
+	RetryBlockResync {
+		node: String,
+	},
+	PurgeBlocks {
+		node: String,
+	},
+}}
+
+impl Endpoint {
+	/// Determine which S3 endpoint a request is for using the request, and a bucket which was
+	/// possibly extracted from the Host header.
+	/// Returns Self plus bucket name, if endpoint is not Endpoint::ListBuckets
+	pub fn from_request<T>(req: &Request<T>) -> Result<Self, Error> {
+		let uri = req.uri();
+		let path = uri.path();
+		let query = uri.query();
+
+		let mut query = QueryParameters::from_query(query.unwrap_or_default())?;
+
+		let res = router_match!(@gen_path_parser (req.method(), path, query) [
+			OPTIONS _ => Options,
+			GET "/check" => CheckDomain,
+			GET "/health" => Health,
+			GET "/metrics" => Metrics,
+			GET "/v2/status" => GetClusterStatus,
+			GET "/v2/health" => GetClusterHealth,
+			GET "/v2/statistics" => GetClusterStatistics,
+			POST "/v2/connect" => ConnectClusterNodes,
+			// Layout endpoints
+			GET "/v2/layout" => GetClusterLayout,
+			POST "/v2/layout" => UpdateClusterLayout,
+			POST "/v2/layout/apply" => ApplyClusterLayout,
+			POST "/v2/layout/revert" => RevertClusterLayout,
+			GET "/v2/layout/simulate" => SimulateClusterLayout,
+			// API key endpoints
+			GET "/v2/key" if id => GetKeyInfo (query_opt::id, query_opt::search, query_opt::show_secret_key),
+			GET "/v2/key" if search => GetKeyInfo (query_opt::id, query_opt::search, query_opt::show_secret_key),
+			POST "/v2/key" if id => UpdateKey (query::id),
+			POST "/v2/key" => CreateKey,
+			POST "/v2/key/import" => ImportKey,
+			DELETE "/v2/key" if id => DeleteKey (query::id),
+			GET "/v2/key" => ListKeys,
+			// Bucket endpoints
+			GET "/v2/bucket" if id => GetBucketInfo (query_opt::id, query_opt::global_alias),
+			GET "/v2/bucket" if global_alias => GetBucketInfo (query_opt::id, query_opt::global_alias),
+			GET "/v2/bucket" => ListBuckets,
+			POST "/v2/bucket" => CreateBucket,
+			DELETE "/v2/bucket" if id => DeleteBucket (query::id),
+			PUT "/v2/bucket" if id => UpdateBucket (query::id),
+			GET "/v2/bucket/usage" => GetBucketUsage (query::id),
+			GET "/v2/bucket/usage/history" => GetBucketUsageHistory (query::id, query_opt::resolution, query_opt::since, query_opt::until),
+			POST "/v2/bucket/search" => SearchBucketObjects (query::id),
+			// Bucket-key permissions
+			POST "/v2/bucket/allow" => BucketAllowKey,
+			POST "/v2/bucket/deny" => BucketDenyKey,
+			// Bucket aliases
+			PUT "/v2/bucket/alias/global" => GlobalAliasBucket (query::id, query::alias),
+			DELETE "/v2/bucket/alias/global" => GlobalUnaliasBucket (query::id, query::alias),
+			PUT "/v2/bucket/alias/local" => LocalAliasBucket (query::id, query::access_key_id, query::alias),
+			DELETE "/v2/bucket/alias/local" => LocalUnaliasBucket (query::id, query::access_key_id, query::alias),
+			// Admin API token endpoints
+			GET "/v2/token" if id => GetAdminTokenInfo (query::id),
+			GET "/v2/token" => ListAdminTokens,
+			POST "/v2/token" if id => UpdateAdminToken (query::id),
+			POST "/v2/token" => CreateAdminToken,
+			DELETE "/v2/token" => DeleteAdminToken (query::id),
+			// Audit log
+			GET "/v2/audit" => ListAuditRecords (query_opt::since, query_opt::until, query_opt::limit),
+			// Node operations
+			GET "/v2/node/statistics" => GetNodeStatistics (query::node),
+			GET "/v2/node/gc" => GetNodeGcQueue (query::node),
+			POST "/v2/node/snapshot" => CreateMetadataSnapshot (query::node),
+			POST "/v2/node/repair" => LaunchRepairOperation (query::node),
+			POST "/v2/node/drain" => DrainClusterNode (query::node),
+			GET "/v2/node/drain" => GetNodeDrainStatus (query::node),
+			POST "/v2/node/maintenance" => SetNodeMaintenance (query::node),
+			// Worker endpoints
+			GET "/v2/worker" if id => GetWorkerInfo (query::node, query::id),
+			GET "/v2/worker" => ListWorkers (query::node, query_opt::busy_only, query_opt::error_only),
+			GET "/v2/worker/variable" => GetWorkerVariable (query::node, query_opt::variable),
+			POST "/v2/worker/variable" => SetWorkerVariable (query::node),
+			// Block endpoints
+			GET "/v2/block/errors" => ListBlockErrors (query::node),
+			GET "/v2/block" => GetBlockInfo (query::node, query::hash),
+			POST "/v2/block/retry" => RetryBlockResync (query::node),
+			POST "/v2/block/purge" => PurgeBlocks (query::node),
+		]);
+
+		if let Some(message) = query.nonempty_message() {
+			debug!("Unused query parameter: {}", message)
+		}
+
+		Ok(res)
+	}
+	/// All v1/ endpoints exist unchanged in v2/, so a v1/ call can always
+	/// be converted to its equivalent as if it was made using its v2/ URL.
+	pub fn from_v1(v1_endpoint: router_v1::Endpoint) -> Self {
+		match v1_endpoint {
+			router_v1::Endpoint::Options => Self::Options,
+			router_v1::Endpoint::CheckDomain => Self::CheckDomain,
+			router_v1::Endpoint::Health => Self::Health,
+			router_v1::Endpoint::Metrics => Self::Metrics,
+			router_v1::Endpoint::GetOpenApiSpec => Self::GetOpenApiSpec,
+
+			// Cluster endpoints
+			router_v1::Endpoint::GetClusterStatus => Self::GetClusterStatus,
+			router_v1::Endpoint::GetClusterHealth => Self::GetClusterHealth,
+			router_v1::Endpoint::ConnectClusterNodes => Self::ConnectClusterNodes,
+
+			// Layout endpoints
+			router_v1::Endpoint::GetClusterLayout => Self::GetClusterLayout,
+			router_v1::Endpoint::UpdateClusterLayout => Self::UpdateClusterLayout,
+			router_v1::Endpoint::ApplyClusterLayout => Self::ApplyClusterLayout,
+			router_v1::Endpoint::RevertClusterLayout => Self::RevertClusterLayout,
+
+			// Key endpoints
+			router_v1::Endpoint::ListKeys => Self::ListKeys,
+			router_v1::Endpoint::CreateKey => Self::CreateKey,
+			router_v1::Endpoint::ImportKey => Self::ImportKey,
+			router_v1::Endpoint::GetKeyInfo {
+				id,
+				search,
+				show_secret_key,
+			} => Self::GetKeyInfo {
+				id,
+				search,
+				show_secret_key,
+			},
+			router_v1::Endpoint::DeleteKey { id } => Self::DeleteKey { id },
+			router_v1::Endpoint::UpdateKey { id } => Self::UpdateKey { id },
+
+			// Bucket endpoints
+			router_v1::Endpoint::ListBuckets => Self::ListBuckets,
+			router_v1::Endpoint::CreateBucket => Self::CreateBucket,
+			router_v1::Endpoint::GetBucketInfo { id, global_alias } => {
+				Self::GetBucketInfo { id, global_alias }
+			}
+			router_v1::Endpoint::DeleteBucket { id } => Self::DeleteBucket { id },
+			router_v1::Endpoint::UpdateBucket { id } => Self::UpdateBucket { id },
+
+			// Bucket-key permissions
+			router_v1::Endpoint::BucketAllowKey => Self::BucketAllowKey,
+			router_v1::Endpoint::BucketDenyKey => Self::BucketDenyKey,
+
+			// Bucket alias endpoints
+			router_v1::Endpoint::GlobalAliasBucket { id, alias } => {
+				Self::GlobalAliasBucket { id, alias }
+			}
+			router_v1::Endpoint::GlobalUnaliasBucket { id, alias } => {
+				Self::GlobalUnaliasBucket { id, alias }
+			}
+			router_v1::Endpoint::LocalAliasBucket {
+				id,
+				access_key_id,
+				alias,
+			} => Self::LocalAliasBucket {
+				id,
+				access_key_id,
+				alias,
+			},
+			router_v1::Endpoint::LocalUnaliasBucket {
+				id,
+				access_key_id,
+				alias,
+			} => Self::LocalUnaliasBucket {
+				id,
+				access_key_id,
+				alias,
+			},
+		}
+	}
+	/// Get the kind of authorization which is required to perform the operation.
+	pub fn authorization_type(&self) -> Authorization {
+		match self {
+			Self::Health => Authorization::None,
+			Self::CheckDomain => Authorization::None,
+			Self::GetOpenApiSpec => Authorization::None,
+			Self::Metrics => Authorization::MetricsToken,
+			Self::ListAdminTokens
+			| Self::GetAdminTokenInfo { .. }
+			| Self::CreateAdminToken
+			| Self::UpdateAdminToken { .. }
+			| Self::DeleteAdminToken { .. } => Authorization::RootAdminToken,
+			_ => Authorization::AdminToken,
+		}
+	}
+}
+
+generateQueryParameters! {
+	keywords: [],
+	fields: [
+		"format" => format,
+		"id" => id,
+		"search" => search,
+		"globalAlias" => global_alias,
+		"alias" => alias,
+		"accessKeyId" => access_key_id,
+		"showSecretKey" => show_secret_key,
+		"node" => node,
+		"busyOnly" => busy_only,
+		"errorOnly" => error_only,
+		"variable" => variable,
+		"hash" => hash,
+		"since" => since,
+		"until" => until,
+		"limit" => limit,
+		"resolution" => resolution
+	]
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn parse(method: &str, uri: &str) -> Result<Endpoint, Error> {
+		let req = Request::builder().method(method).uri(uri).body(()).unwrap();
+		Endpoint::from_request(&req)
+	}
+
+	#[test]
+	fn test_node_endpoints() {
+		assert_eq!(
+			parse("GET", "/v2/worker?node=self&busyOnly=true").unwrap(),
+			Endpoint::ListWorkers {
+				node: "self".into(),
+				busy_only: Some("true".into()),
+				error_only: None,
+			}
+		);
+		assert_eq!(
+			parse("GET", "/v2/worker?node=*&id=12").unwrap(),
+			Endpoint::GetWorkerInfo {
+				node: "*".into(),
+				id: "12".into(),
+			}
+		);
+		assert_eq!(
+			parse("GET", "/v2/block?node=self&hash=0123abcd").unwrap(),
+			Endpoint::GetBlockInfo {
+				node: "self".into(),
+				hash: "0123abcd".into(),
+			}
+		);
+		assert_eq!(
+			parse("POST", "/v2/node/repair?node=*").unwrap(),
+			Endpoint::LaunchRepairOperation { node: "*".into() }
+		);
+
+		assert_eq!(
+			parse("GET", "/v2/token").unwrap(),
+			Endpoint::ListAdminTokens
+		);
+		assert_eq!(
+			parse("POST", "/v2/token?id=0123abcd").unwrap(),
+			Endpoint::UpdateAdminToken {
+				id: "0123abcd".into()
+			}
+		);
+		assert!(parse("DELETE", "/v2/token").is_err());
+
+		assert_eq!(
+			parse("GET", "/v2/audit?since=2024-01-01T00:00:00Z&limit=10").unwrap(),
+			Endpoint::ListAuditRecords {
+				since: Some("2024-01-01T00:00:00Z".into()),
+				until: None,
+				limit: Some("10".into()),
+			}
+		);
+
+		assert_eq!(
+			parse("GET", "/v2/layout/simulate").unwrap(),
+			Endpoint::SimulateClusterLayout
+		);
+
+		assert_eq!(
+			parse("GET", "/v2/node/drain?node=0123abcd").unwrap(),
+			Endpoint::GetNodeDrainStatus {
+				node: "0123abcd".into()
+			}
+		);
+
+		assert_eq!(
+			parse("GET", "/v2/bucket/usage?id=0123abcd").unwrap(),
+			Endpoint::GetBucketUsage {
+				id: "0123abcd".into()
+			}
+		);
+		assert!(parse("GET", "/v2/bucket/usage").is_err());
+		assert_eq!(
+			parse(
+				"GET",
+				"/v2/bucket/usage/history?id=0123abcd&resolution=daily&since=2024-01-01T00:00:00Z"
+			)
+			.unwrap(),
+			Endpoint::GetBucketUsageHistory {
+				id: "0123abcd".into(),
+				resolution: Some("daily".into()),
+				since: Some("2024-01-01T00:00:00Z".into()),
+				until: None,
+			}
+		);
+		assert_eq!(
+			parse("POST", "/v2/bucket/search?id=0123abcd").unwrap(),
+			Endpoint::SearchBucketObjects {
+				id: "0123abcd".into()
+			}
+		);
+
+		assert_eq!(
+			parse("GET", "/v2/node/gc?node=*").unwrap(),
+			Endpoint::GetNodeGcQueue { node: "*".into() }
+		);
+
+		assert_eq!(
+			parse("POST", "/v2/node/maintenance?node=self").unwrap(),
+			Endpoint::SetNodeMaintenance {
+				node: "self".into()
+			}
+		);
+
+		// node parameter is mandatory
+		assert!(parse("GET", "/v2/block/errors").is_err());
+		assert!(parse("POST", "/v2/node/snapshot").is_err());
+		assert!(parse("POST", "/v2/node/drain").is_err());
+		assert!(parse("POST", "/v2/node/maintenance").is_err());
+	}
+}
@@ -0,0 +1,59 @@
+//! Single-node Garage instance running in the test process, for testing
+//! admin API handlers without the network layer
+use std::sync::Arc;
+
+use garage_util::config::Config;
+use garage_util::crdt::Crdt;
+
+use garage_rpc::layout::{NodeRole, NodeRoleV};
+
+use garage_model::garage::Garage;
+
+/// Create a Garage instance whose metadata and data are stored in a
+/// temporary directory, which is deleted when the returned guard is dropped.
+/// The cluster layout of the instance is empty.
+pub(crate) fn test_garage() -> (Arc<Garage>, mktemp::Temp) {
+	let dir = mktemp::Temp::new_dir().unwrap();
+	let config = format!(
+		r#"
+		metadata_dir = "{path}/meta"
+		data_dir = "{path}/data"
+		db_engine = "lmdb"
+		replication_factor = 1
+		rpc_bind_addr = "127.0.0.1:3901"
+		rpc_secret = "c3ea8cb80333d04e208d136698b1a01ae370d463f0d435ab2177510b3478bf44"
+
+		[s3_api]
+		s3_region = "garage"
+		api_bind_addr = "127.0.0.1:3900"
+		"#,
+		path = dir.to_string_lossy()
+	);
+	let config: Config = toml::from_str(&config).unwrap();
+	(Garage::new(config).unwrap(), dir)
+}
+
+/// Give a storage role to the node and apply the layout,
+/// so that writes to the metadata tables can succeed
+pub(crate) async fn apply_single_node_layout(garage: &Garage) {
+	let mut layout = garage.system.cluster_layout().inner().clone();
+	let mut roles = layout.current().roles.clone();
+	roles.merge(&layout.staging.get().roles);
+	let role = NodeRole {
+		zone: "dc1".into(),
+		capacity: Some(1 << 30),
+		tags: vec![],
+	};
+	layout
+		.staging
+		.get_mut()
+		.roles
+		.merge(&roles.update_mutator(garage.system.id, NodeRoleV(Some(role))));
+	let (layout, _) = layout.apply_staged_changes(Some(1)).unwrap();
+	garage
+		.system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await
+		.unwrap();
+}
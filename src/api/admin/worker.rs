@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use hyper::{body::Incoming as IncomingBody, Request, Response};
+use serde::{Deserialize, Serialize};
+
+use garage_util::background::*;
+use garage_util::time::now_msec;
+
+use garage_model::garage::Garage;
+use garage_model::helper::error::OkOrBadRequest;
+
+use garage_api_common::helpers::{json_ok_response, parse_json_body};
+
+use crate::api_server::ResBody;
+use crate::error::*;
+use crate::node::*;
+
+pub async fn handle_list_workers(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	busy_only: Option<String>,
+	error_only: Option<String>,
+) -> Result<Response<ResBody>, Error> {
+	let req = ListWorkersRequest {
+		busy_only: busy_only.map(|x| x == "true").unwrap_or(false),
+		error_only: error_only.map(|x| x == "true").unwrap_or(false),
+	};
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::ListWorkers(req), |r| match r {
+			AdminNodeRpcResponse::WorkerList(l) => Some(l),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_worker_info(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	id: String,
+) -> Result<Response<ResBody>, Error> {
+	let id = id.parse::<u64>().ok_or_bad_request("Invalid worker id")?;
+
+	let res = node_rpc
+		.call_nodes(
+			&node,
+			AdminNodeRpc::GetWorkerInfo(GetWorkerInfoRequest { id }),
+			|r| match r {
+				AdminNodeRpcResponse::WorkerInfo(i) => Some(i),
+				_ => None,
+			},
+		)
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_get_worker_variable(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	variable: Option<String>,
+) -> Result<Response<ResBody>, Error> {
+	let res = node_rpc
+		.call_nodes(
+			&node,
+			AdminNodeRpc::GetWorkerVariable(GetWorkerVariableRequest { variable }),
+			|r| match r {
+				AdminNodeRpcResponse::WorkerVars(v) => Some(v),
+				_ => None,
+			},
+		)
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+pub async fn handle_set_worker_variable(
+	node_rpc: &Arc<AdminNodeRpcHandler>,
+	node: String,
+	req: Request<IncomingBody>,
+) -> Result<Response<ResBody>, Error> {
+	let req = parse_json_body::<SetWorkerVariableRequest, _, Error>(req).await?;
+
+	let res = node_rpc
+		.call_nodes(&node, AdminNodeRpc::SetWorkerVariable(req), |r| match r {
+			AdminNodeRpcResponse::WorkerVarSet(v) => Some(v),
+			_ => None,
+		})
+		.await?;
+
+	Ok(json_ok_response(&res)?)
+}
+
+// ---- local operations ----
+
+pub(crate) fn local_list_workers(
+	bg: &BackgroundRunner,
+	req: &ListWorkersRequest,
+) -> ListWorkersResponse {
+	let mut workers = bg
+		.get_worker_info()
+		.into_iter()
+		.filter(|(_, info)| {
+			!req.busy_only || matches!(info.state, WorkerState::Busy | WorkerState::Throttled(_))
+		})
+		.filter(|(_, info)| !req.error_only || info.errors > 0)
+		.map(|(id, info)| worker_info_to_api(id as u64, info))
+		.collect::<Vec<_>>();
+	workers.sort_by_key(|x| x.id);
+	workers
+}
+
+pub(crate) fn local_get_worker_info(
+	bg: &BackgroundRunner,
+	req: &GetWorkerInfoRequest,
+) -> Result<WorkerInfoResp, HelperError> {
+	let info = bg
+		.get_worker_info()
+		.remove(&(req.id as usize))
+		.ok_or_bad_request(format!("No worker with TID {}", req.id))?;
+	Ok(worker_info_to_api(req.id, info))
+}
+
+pub(crate) fn local_get_worker_variable(
+	garage: &Arc<Garage>,
+	req: &GetWorkerVariableRequest,
+) -> Result<GetWorkerVariableResponse, HelperError> {
+	let mut res = HashMap::new();
+	if let Some(v) = &req.variable {
+		res.insert(v.clone(), garage.bg_vars.get(v)?);
+	} else {
+		for (k, v) in garage.bg_vars.get_all() {
+			res.insert(k.to_string(), v);
+		}
+	}
+	Ok(res)
+}
+
+pub(crate) fn local_set_worker_variable(
+	garage: &Arc<Garage>,
+	req: &SetWorkerVariableRequest,
+) -> Result<SetWorkerVariableResponse, HelperError> {
+	garage.bg_vars.set(&req.variable, &req.value)?;
+	Ok(SetWorkerVariableResponse {
+		variable: req.variable.clone(),
+		value: req.value.clone(),
+	})
+}
+
+pub fn worker_info_to_api(id: u64, info: WorkerInfo) -> WorkerInfoResp {
+	WorkerInfoResp {
+		id,
+		name: info.name,
+		state: match info.state {
+			WorkerState::Busy => WorkerStateResp::Busy,
+			WorkerState::Throttled(t) => WorkerStateResp::Throttled { duration_secs: t },
+			WorkerState::Idle => WorkerStateResp::Idle,
+			WorkerState::Done => WorkerStateResp::Done,
+		},
+		errors: info.errors as u64,
+		consecutive_errors: info.consecutive_errors as u64,
+		last_error: info.last_error.map(|(message, t)| WorkerLastError {
+			message,
+			secs_ago: now_msec().saturating_sub(t) / 1000,
+		}),
+		tranquility: info.status.tranquility,
+		progress: info.status.progress,
+		queue_length: info.status.queue_length,
+		persistent_errors: info.status.persistent_errors,
+		freeform: info.status.freeform,
+	}
+}
+
+// ---- request and response types ----
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ListWorkersRequest {
+	pub busy_only: bool,
+	pub error_only: bool,
+}
+
+pub type ListWorkersResponse = Vec<WorkerInfoResp>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWorkerInfoRequest {
+	pub id: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerInfoResp {
+	pub id: u64,
+	pub name: String,
+	pub state: WorkerStateResp,
+	pub errors: u64,
+	pub consecutive_errors: u64,
+	pub last_error: Option<WorkerLastError>,
+	pub tranquility: Option<u32>,
+	pub progress: Option<String>,
+	pub queue_length: Option<u64>,
+	pub persistent_errors: Option<u64>,
+	pub freeform: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum WorkerStateResp {
+	Busy,
+	#[serde(rename_all = "camelCase")]
+	Throttled {
+		duration_secs: f32,
+	},
+	Idle,
+	Done,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkerLastError {
+	pub message: String,
+	pub secs_ago: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GetWorkerVariableRequest {
+	pub variable: Option<String>,
+}
+
+pub type GetWorkerVariableResponse = HashMap<String, String>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetWorkerVariableRequest {
+	pub variable: String,
+	pub value: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetWorkerVariableResponse {
+	pub variable: String,
+	pub value: String,
+}
@@ -123,6 +123,10 @@ macro_rules! router_match {
             )*
         }
         impl Endpoint {
+            /// Names of all the variants of this enum, as returned by `name()`
+            #[allow(dead_code)]
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($variant),)*];
+
             pub fn name(&self) -> &'static str {
                 match self {
                     $(Endpoint::$variant $({ $($name: _,)* .. })? => stringify!($variant),)*
@@ -190,6 +190,7 @@ async fn handle_copy_metaonly(
 	let ReqCtx {
 		garage,
 		bucket_id: dest_bucket_id,
+		bucket_params: dest_bucket_params,
 		..
 	} = ctx;
 
@@ -283,6 +284,7 @@ async fn handle_copy_metaonly(
 					block: b.1.hash,
 					version: new_uuid,
 					deleted: false.into(),
+					replication_factor: *dest_bucket_params.replication_factor.get(),
 				})
 				.collect::<Vec<_>>();
 			futures::try_join!(
@@ -366,7 +368,12 @@ pub async fn handle_upload_part_copy(
 		multipart::get_upload(&ctx, &dest_key, &dest_upload_id)
 	)?;
 
-	let ReqCtx { garage, .. } = ctx;
+	let ReqCtx {
+		garage,
+		bucket_params: dest_bucket_params,
+		..
+	} = ctx;
+	let replication_factor = *dest_bucket_params.replication_factor.get();
 
 	let (source_object_version, source_version_data, source_version_meta) =
 		extract_source_info(&source_object)?;
@@ -605,6 +612,7 @@ pub async fn handle_upload_part_copy(
 			block: final_hash,
 			version: dest_version_id,
 			deleted: false.into(),
+			replication_factor,
 		};
 
 		let (_, _, _, next) = futures::try_join!(
@@ -614,7 +622,13 @@ pub async fn handle_upload_part_copy(
 				if let Some(final_data) = data_to_upload {
 					garage
 						.block_manager
-						.rpc_put_block(final_hash, final_data, dest_encryption.is_encrypted(), None)
+						.rpc_put_block(
+							final_hash,
+							final_data,
+							dest_encryption.is_encrypted(),
+							replication_factor,
+							None,
+						)
 						.await
 				} else {
 					Ok(())
@@ -381,6 +381,7 @@ pub async fn handle_complete_multipart_upload(
 		block: b.hash,
 		version: upload_id,
 		deleted: false.into(),
+		replication_factor: *ctx.bucket_params.replication_factor.get(),
 	});
 	garage.block_ref_table.insert_many(block_refs).await?;
 
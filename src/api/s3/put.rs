@@ -553,7 +553,12 @@ async fn put_block_and_meta(
 	is_encrypted: bool,
 	order_tag: OrderTag,
 ) -> Result<(), GarageError> {
-	let ReqCtx { garage, .. } = ctx;
+	let ReqCtx {
+		garage,
+		bucket_params,
+		..
+	} = ctx;
+	let replication_factor = *bucket_params.replication_factor.get();
 
 	let mut version = version.clone();
 	version.blocks.put(
@@ -568,12 +573,17 @@ async fn put_block_and_meta(
 		block: hash,
 		version: version.uuid,
 		deleted: false.into(),
+		replication_factor,
 	};
 
 	futures::try_join!(
-		garage
-			.block_manager
-			.rpc_put_block(hash, block, is_encrypted, Some(order_tag)),
+		garage.block_manager.rpc_put_block(
+			hash,
+			block,
+			is_encrypted,
+			replication_factor,
+			Some(order_tag)
+		),
 		garage.version_table.insert(&version),
 		garage.block_ref_table.insert(&block_ref),
 	)?;
@@ -9,7 +9,7 @@ use garage_util::error::*;
 
 use garage_net::stream::ByteStream;
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 pub enum DataBlockHeader {
 	Plain,
 	Compressed,
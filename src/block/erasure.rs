@@ -0,0 +1,280 @@
+//! Erasure coding of data blocks.
+//!
+//! When erasure coding is enabled, a data block (after compression) is split
+//! into `data_shards` shards, to which `parity_shards` parity shards are added
+//! using Reed-Solomon coding. Shard number `i` is stored on the `i`-th node
+//! returned by `LayoutVersion::shard_nodes_of` for the current layout version:
+//! the nodes that store the block's partition, and then other storage nodes
+//! when there are more shards than the replication factor, so that shards are
+//! spread on different zones. The block can be reconstructed from any
+//! `data_shards` of its shards.
+//!
+//! Nodes that store shards of a block outside of its partition don't hold the
+//! reference count of the block. The nodes of the partition take care of the
+//! shards stored on them: they rebuild them when they are missing, and ask
+//! these nodes to delete them when the block is no longer referenced.
+//!
+//! Each shard is stored and transferred with a small header, which contains
+//! the parameters needed to decode it and a checksum of its content, so that
+//! shards can be verified individually.
+use std::convert::TryInto;
+use std::path::Path;
+
+use bytes::Bytes;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+
+use garage_util::config::ErasureCodingConfig;
+use garage_util::data::*;
+use garage_util::error::*;
+
+use crate::block::*;
+
+const SHARD_MAGIC: &[u8; 4] = b"GSH1";
+const SHARD_HEADER_LEN: usize = 4 + 4 + 8 + 32;
+
+/// Extension of the files in which shards are stored, followed by the shard number
+pub(crate) const SHARD_EXTENSION_PREFIX: &str = "s";
+
+/// Erasure coding parameters of the cluster
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErasureCoding {
+	pub data_shards: usize,
+	pub parity_shards: usize,
+}
+
+/// The parsed header of a shard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ShardHeader {
+	pub index: usize,
+	pub coding: ErasureCoding,
+	pub block_header: DataBlockHeader,
+	pub block_len: u64,
+}
+
+impl ErasureCoding {
+	/// Check erasure coding parameters given in the configuration file
+	pub fn new(config: &ErasureCodingConfig) -> Result<Self, Error> {
+		let ec = ErasureCoding {
+			data_shards: config.data_shards,
+			parity_shards: config.parity_shards,
+		};
+		if ec.data_shards < 1 || ec.parity_shards < 1 {
+			return Err(Error::Message(
+				"erasure_coding: data_shards and parity_shards must be at least 1".into(),
+			));
+		}
+		ec.reed_solomon()?;
+		Ok(ec)
+	}
+
+	pub fn total_shards(&self) -> usize {
+		self.data_shards + self.parity_shards
+	}
+
+	/// Number of shards that must be written for a block write to succeed:
+	/// enough shards to reconstruct the block, plus half of the parity shards
+	pub fn write_quorum(&self) -> usize {
+		self.data_shards + self.parity_shards.div_ceil(2)
+	}
+
+	fn reed_solomon(&self) -> Result<ReedSolomon, Error> {
+		ReedSolomon::new(self.data_shards, self.parity_shards)
+			.map_err(|e| Error::Message(format!("invalid erasure coding parameters: {:?}", e)))
+	}
+
+	/// Split a data block in shards, returned with their header
+	pub fn encode(&self, block: &DataBlock) -> Result<Vec<Bytes>, Error> {
+		let (block_header, data) = block.as_parts_ref();
+		let shard_len = std::cmp::max(1, data.len().div_ceil(self.data_shards));
+
+		let mut shards = (0..self.total_shards())
+			.map(|i| {
+				let start = std::cmp::min(i * shard_len, data.len());
+				let end = std::cmp::min((i + 1) * shard_len, data.len());
+				let mut shard = Vec::with_capacity(shard_len);
+				if i < self.data_shards {
+					shard.extend_from_slice(&data[start..end]);
+				}
+				shard.resize(shard_len, 0);
+				shard
+			})
+			.collect::<Vec<_>>();
+
+		self.reed_solomon()?
+			.encode(&mut shards)
+			.map_err(|e| Error::Message(format!("erasure coding failed: {:?}", e)))?;
+
+		Ok(shards
+			.into_iter()
+			.enumerate()
+			.map(|(index, payload)| {
+				let header = ShardHeader {
+					index,
+					coding: *self,
+					block_header,
+					block_len: data.len() as u64,
+				};
+				header.serialize_with(&payload)
+			})
+			.collect())
+	}
+}
+
+impl ShardHeader {
+	fn serialize_with(&self, payload: &[u8]) -> Bytes {
+		let mut ret = Vec::with_capacity(SHARD_HEADER_LEN + payload.len());
+		ret.extend_from_slice(SHARD_MAGIC);
+		ret.push(self.index as u8);
+		ret.push(self.coding.data_shards as u8);
+		ret.push(self.coding.parity_shards as u8);
+		ret.push(self.block_header.is_compressed() as u8);
+		ret.extend_from_slice(&self.block_len.to_be_bytes());
+		ret.extend_from_slice(blake2sum(payload).as_slice());
+		ret.extend_from_slice(payload);
+		ret.into()
+	}
+}
+
+/// Check the integrity of a shard, and return its header
+pub(crate) fn verify_shard(shard: &[u8]) -> Option<ShardHeader> {
+	if shard.len() < SHARD_HEADER_LEN || &shard[..4] != SHARD_MAGIC {
+		return None;
+	}
+	let payload = &shard[SHARD_HEADER_LEN..];
+	if blake2sum(payload).as_slice() != &shard[16..48] {
+		return None;
+	}
+	let block_header = match shard[7] {
+		0 => DataBlockHeader::Plain,
+		1 => DataBlockHeader::Compressed,
+		_ => return None,
+	};
+	Some(ShardHeader {
+		index: shard[4] as usize,
+		coding: ErasureCoding {
+			data_shards: shard[5] as usize,
+			parity_shards: shard[6] as usize,
+		},
+		block_header,
+		block_len: u64::from_be_bytes(shard[8..16].try_into().unwrap()),
+	})
+}
+
+/// Reconstruct a data block from some of its shards. The shards must
+/// have been verified with `verify_shard`. The reconstructed block is
+/// checked against its hash.
+pub(crate) fn decode_block(
+	hash: &Hash,
+	shards: &[(ShardHeader, Bytes)],
+) -> Result<DataBlock, Error> {
+	let (first, _) = shards.first().ok_or(Error::MissingBlock(*hash))?;
+	let coding = first.coding;
+
+	let mut parts: Vec<Option<Vec<u8>>> = vec![None; coding.total_shards()];
+	for (header, shard) in shards.iter() {
+		if header.coding != coding
+			|| header.block_header != first.block_header
+			|| header.block_len != first.block_len
+			|| header.index >= parts.len()
+		{
+			return Err(Error::CorruptData(*hash));
+		}
+		parts[header.index] = Some(shard[SHARD_HEADER_LEN..].to_vec());
+	}
+
+	coding
+		.reed_solomon()?
+		.reconstruct_data(&mut parts)
+		.map_err(|_| Error::MissingBlock(*hash))?;
+
+	let mut data = Vec::with_capacity(first.block_len as usize);
+	for part in parts.into_iter().take(coding.data_shards) {
+		data.extend_from_slice(&part.unwrap());
+	}
+	data.truncate(first.block_len as usize);
+
+	let block = DataBlock::from_parts(first.block_header, data.into());
+	block.verify(*hash)?;
+	Ok(block)
+}
+
+/// Name of the file in which a shard of a block is stored
+pub(crate) fn shard_filename(hash: &Hash, index: usize) -> String {
+	format!("{}.{}{}", hex::encode(hash), SHARD_EXTENSION_PREFIX, index)
+}
+
+/// If the path is the path of a shard file, return the number of the shard
+pub(crate) fn shard_index_of_path(path: &Path) -> Option<usize> {
+	path.extension()?
+		.to_str()?
+		.strip_prefix(SHARD_EXTENSION_PREFIX)?
+		.parse()
+		.ok()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_encode_decode() {
+		let ec = ErasureCoding {
+			data_shards: 3,
+			parity_shards: 2,
+		};
+		for len in [1usize, 2, 1000, 1001, 1002] {
+			let data = (0..len).map(|i| (i * 7) as u8).collect::<Vec<u8>>();
+			let hash = blake2sum(&data);
+			let shards = ec.encode(&DataBlock::plain(data.clone().into())).unwrap();
+			assert_eq!(shards.len(), 5);
+
+			let verified = shards
+				.iter()
+				.map(|s| (verify_shard(s).unwrap(), s.clone()))
+				.collect::<Vec<_>>();
+			for (i, (h, _)) in verified.iter().enumerate() {
+				assert_eq!(h.index, i);
+				assert_eq!(h.coding, ec);
+			}
+
+			// any 3 shards are enough
+			for skip in [[0, 1], [0, 4], [2, 3], [3, 4]] {
+				let subset = verified
+					.iter()
+					.filter(|(h, _)| !skip.contains(&h.index))
+					.cloned()
+					.collect::<Vec<_>>();
+				let block = decode_block(&hash, &subset).unwrap();
+				assert_eq!(&block.as_parts_ref().1[..], &data[..]);
+			}
+
+			// 2 shards are not
+			assert!(decode_block(&hash, &verified[3..]).is_err());
+		}
+	}
+
+	#[test]
+	fn test_corrupted_shard() {
+		let ec = ErasureCoding {
+			data_shards: 2,
+			parity_shards: 1,
+		};
+		let shards = ec
+			.encode(&DataBlock::plain(vec![42u8; 100].into()))
+			.unwrap();
+		let mut corrupted = shards[1].to_vec();
+		*corrupted.last_mut().unwrap() ^= 1;
+		assert!(verify_shard(&shards[1]).is_some());
+		assert!(verify_shard(&corrupted).is_none());
+	}
+
+	#[test]
+	fn test_shard_path() {
+		assert_eq!(
+			shard_index_of_path(Path::new("/data/ab/cd/abcd.s3")),
+			Some(3)
+		);
+		assert_eq!(shard_index_of_path(Path::new("/data/ab/cd/abcd.zst")), None);
+		assert_eq!(shard_index_of_path(Path::new("/data/ab/cd/abcd")), None);
+	}
+}
@@ -6,9 +6,10 @@ pub mod repair;
 pub mod resync;
 
 mod block;
+mod erasure;
 mod layout;
 mod metrics;
 mod rc;
 
 pub use block::zstd_encode;
-pub use rc::CalculateRefcount;
+pub use rc::{CalculateRefcount, CalculateReplicationFactor};
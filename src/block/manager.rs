@@ -1,10 +1,11 @@
 use std::convert::TryInto;
 use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use arc_swap::{ArcSwap, ArcSwapOption};
 use bytes::Bytes;
+use futures::stream::{FuturesUnordered, StreamExt};
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -36,6 +37,7 @@ use garage_rpc::*;
 use garage_table::replication::{TableReplication, TableShardedReplication};
 
 use crate::block::*;
+use crate::erasure::*;
 use crate::layout::*;
 use crate::metrics::*;
 use crate::rc::*;
@@ -50,6 +52,10 @@ pub const INLINE_THRESHOLD: usize = 3072;
 // to delete the block locally.
 pub(crate) const BLOCK_GC_DELAY: Duration = Duration::from_secs(600);
 
+/// Duration during which the counts of referenced blocks by partition are
+/// reused, as computing them requires a scan of the whole refcount table
+const REFERENCED_BLOCKS_CACHE_DURATION: Duration = Duration::from_secs(10);
+
 /// RPC messages used to share blocks of data between nodes
 #[derive(Debug, Serialize, Deserialize)]
 pub enum BlockRpc {
@@ -66,6 +72,18 @@ pub enum BlockRpc {
 	NeedBlockQuery(Hash),
 	/// Response : whether the node do require that block
 	NeedBlockReply(bool),
+	/// Message to send a shard of an erasure-coded block, either because
+	/// requested or for first delivery. The shard, including its header,
+	/// is sent as the stream of the message.
+	PutShard {
+		hash: Hash,
+		index: usize,
+	},
+	/// Message to ask for a shard of an erasure-coded block
+	GetShard(Hash, usize),
+	/// Ask a node that stores shards of a block without holding its
+	/// reference count to delete them, as the block is no longer referenced
+	DeleteShards(Hash),
 }
 
 impl Rpc for BlockRpc {
@@ -76,6 +94,9 @@ impl Rpc for BlockRpc {
 pub struct BlockManager {
 	/// Replication strategy, allowing to find on which node blocks should be located
 	pub replication: TableShardedReplication,
+	/// Erasure coding parameters, if blocks are stored as erasure-coded
+	/// shards instead of full copies
+	pub erasure: Option<ErasureCoding>,
 
 	/// Data layout
 	pub(crate) data_layout: ArcSwap<DataLayout>,
@@ -99,6 +120,9 @@ pub struct BlockManager {
 
 	pub scrub_persister: PersisterShared<ScrubWorkerPersisted>,
 	tx_scrub_command: ArcSwapOption<mpsc::Sender<ScrubWorkerCommand>>,
+
+	/// Last counts of referenced blocks by partition, and when they were computed
+	referenced_blocks_cache: Mutex<Option<(Instant, Vec<u64>)>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -137,6 +161,13 @@ impl BlockManager {
 			}
 		};
 		data_layout.check_markers()?;
+
+		let erasure = config
+			.erasure_coding
+			.as_ref()
+			.map(ErasureCoding::new)
+			.transpose()?;
+
 		data_layout_persister
 			.save(&data_layout)
 			.expect("cannot save data_layout");
@@ -167,6 +198,7 @@ impl BlockManager {
 
 		let block_manager = Arc::new(Self {
 			replication,
+			erasure,
 			data_layout: ArcSwap::new(Arc::new(data_layout)),
 			data_layout_persister,
 			data_fsync: config.data_fsync,
@@ -184,6 +216,7 @@ impl BlockManager {
 			metrics,
 			scrub_persister,
 			tx_scrub_command: ArcSwapOption::new(None),
+			referenced_blocks_cache: Mutex::new(None),
 		});
 		block_manager.endpoint.set_handler(block_manager.clone());
 		block_manager.scrub_persister.set_with(|_| ()).unwrap();
@@ -238,6 +271,29 @@ impl BlockManager {
 		self.rc.recalc_rc.store(Some(Arc::new(recalc)));
 	}
 
+	/// Initialization: set how the number of copies to keep of a block
+	/// is determined
+	pub fn set_calc_replication_factor(&self, calc_rf: CalculateReplicationFactor) {
+		self.rc.calc_rf.store(Some(Arc::new(calc_rf)));
+	}
+
+	/// Get the list of nodes that should store a block in the current
+	/// layout version, taking into account the replication factor
+	/// of the objects that reference it
+	pub(crate) fn block_storage_nodes(&self, hash: &Hash) -> Result<Vec<Uuid>, Error> {
+		let replication_factor = self.rc.block_replication_factor(hash)?;
+		Ok(self
+			.system
+			.cluster_layout()
+			.current_storage_nodes_with_factor(hash, replication_factor))
+	}
+
+	/// Number of nodes that must acknowledge the writing of a block
+	/// stored on the given set of nodes
+	pub(crate) fn block_write_quorum(&self, storage_nodes: &[Uuid]) -> usize {
+		std::cmp::min(self.replication.write_quorum(), storage_nodes.len())
+	}
+
 	/// Ask nodes that might have a (possibly compressed) block for it
 	/// Return it as a stream with a header
 	async fn rpc_get_raw_block_streaming(
@@ -284,53 +340,136 @@ impl BlockManager {
 		F: Fn(DataBlockStream) -> Fut,
 		Fut: futures::Future<Output = Result<T, Error>>,
 	{
+		if self.erasure.is_some() {
+			// Reconstruct the block from its shards. If that is not possible,
+			// the block might have been written before erasure coding was enabled,
+			// so we fall back to asking for a full copy of the block.
+			match self.rpc_get_block_from_shards(hash, priority).await {
+				Ok(block) => {
+					let (header, data) = block.into_parts();
+					let stream: ByteStream =
+						Box::pin(futures::stream::once(async move { Ok(data) }));
+					match f(DataBlockStream::from_parts(header, stream)).await {
+						Ok(ret) => return Ok(ret),
+						Err(e) => debug!(
+							"Get block {:?}: error reading reconstructed block: {}",
+							hash, e
+						),
+					}
+				}
+				Err(e) => {
+					debug!(
+						"Get block {:?}: could not reconstruct block from shards: {}",
+						hash, e
+					);
+				}
+			}
+		}
+
 		let who = self
 			.system
 			.rpc_helper()
 			.block_read_nodes_of(hash, self.system.rpc_helper());
 
-		for node in who.iter() {
-			let node_id = NodeID::from(*node);
-			let rpc = self.endpoint.call_streaming(
-				&node_id,
-				BlockRpc::GetBlock(*hash, order_tag),
-				priority,
-			);
-			tokio::select! {
-				res = rpc => {
-					let res = match res {
-						Ok(res) => res,
-						Err(e) => {
-							debug!("Get block {:?}: node {:?} could not be contacted: {}", hash, node, e);
-							continue;
-						}
-					};
-					let block_stream = match res.into_parts() {
-						(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => DataBlockStream::from_parts(header, stream),
-						(Ok(_), _) => {
-							debug!("Get block {:?}: node {:?} returned a malformed response", hash, node);
-							continue;
-						}
-						(Err(e), _) => {
-							debug!("Get block {:?}: node {:?} returned error: {}", hash, node, e);
-							continue;
+		// Ask nodes one by one. If a node does not answer within a delay derived
+		// from its ping times, the request is hedged: it is also sent to the next
+		// node, and the first node that answers is used. Once all nodes have been
+		// asked, we wait for the remaining requests until the RPC timeout.
+		let rpc_helper = self.system.rpc_helper();
+		let endpoint_path = self.endpoint.path();
+		let request = |node: Uuid, hedge: bool| async move {
+			let res = self
+				.endpoint
+				.call_streaming(
+					&NodeID::from(node),
+					BlockRpc::GetBlock(*hash, order_tag),
+					priority,
+				)
+				.await;
+			(node, hedge, res)
+		};
+		let next_delay = |node: &Uuid, remaining: usize| {
+			if remaining > 0 {
+				rpc_helper.hedge_delay(node)
+			} else {
+				rpc_helper.rpc_timeout()
+			}
+		};
+
+		let mut who = who.into_iter();
+		let mut in_flight = FuturesUnordered::new();
+		let mut hedge_at = Instant::now();
+		loop {
+			if in_flight.is_empty() {
+				match who.next() {
+					Some(node) => {
+						in_flight.push(request(node, false));
+						hedge_at = Instant::now() + next_delay(&node, who.len());
+					}
+					None => break,
+				}
+			}
+
+			let (node, hedge, res) = tokio::select! {
+				res = in_flight.next() => res.unwrap(),
+				_ = tokio::time::sleep_until(hedge_at.into()) => {
+					match who.next() {
+						Some(node) => {
+							debug!("Get block {:?}: no answer yet, also asking node {:?}", hash, node);
+							in_flight.push(request(node, true));
+							hedge_at = Instant::now() + next_delay(&node, who.len());
+							rpc_helper.record_hedge_sent(endpoint_path);
 						}
-					};
-					match f(block_stream).await {
-						Ok(ret) => return Ok(ret),
-						Err(e) => {
-							debug!("Get block {:?}: error reading stream from node {:?}: {}", hash, node, e);
+						None => {
+							debug!("Get block {:?}: nodes didn't return block in time", hash);
+							break;
 						}
 					}
+					continue;
 				}
-				// TODO: sleep less long (fail early), initiate a second request earlier
-				// if the first one doesn't succeed rapidly
-				// TODO: keep first request running when initiating a new one and take the
-				// one that finishes earlier
-				_ = tokio::time::sleep(self.system.rpc_helper().rpc_timeout()) => {
-					debug!("Get block {:?}: node {:?} didn't return block in time, trying next.", hash, node);
+			};
+
+			let res = match res {
+				Ok(res) => res,
+				Err(e) => {
+					debug!(
+						"Get block {:?}: node {:?} could not be contacted: {}",
+						hash, node, e
+					);
+					continue;
 				}
 			};
+			let block_stream = match res.into_parts() {
+				(Ok(BlockRpc::PutBlock { hash: _, header }), Some(stream)) => {
+					DataBlockStream::from_parts(header, stream)
+				}
+				(Ok(_), _) => {
+					debug!(
+						"Get block {:?}: node {:?} returned a malformed response",
+						hash, node
+					);
+					continue;
+				}
+				(Err(e), _) => {
+					debug!(
+						"Get block {:?}: node {:?} returned error: {}",
+						hash, node, e
+					);
+					continue;
+				}
+			};
+			if hedge {
+				rpc_helper.record_hedge_won(endpoint_path);
+			}
+			match f(block_stream).await {
+				Ok(ret) => return Ok(ret),
+				Err(e) => {
+					debug!(
+						"Get block {:?}: error reading stream from node {:?}: {}",
+						hash, node, e
+					);
+				}
+			}
 		}
 
 		let err = Error::MissingBlock(*hash);
@@ -362,20 +501,35 @@ impl BlockManager {
 		}
 	}
 
-	/// Send block to nodes that should have it
+	/// Send block to nodes that should have it. If `replication_factor`
+	/// is set, the block is only sent to that number of nodes instead of
+	/// using the replication factor of the cluster.
 	pub async fn rpc_put_block(
 		&self,
 		hash: Hash,
 		data: Bytes,
 		prevent_compression: bool,
+		replication_factor: Option<usize>,
 		order_tag: Option<OrderTag>,
 	) -> Result<(), Error> {
-		let who = self.system.cluster_layout().current_storage_nodes_of(&hash);
-
 		let compression_level = self.compression_level.filter(|_| !prevent_compression);
-		let (header, bytes) = DataBlock::from_buffer(data, compression_level)
-			.await
-			.into_parts();
+		let block = DataBlock::from_buffer(data, compression_level).await;
+
+		if let Some(ec) = &self.erasure {
+			// Blocks are erasure-coded instead of replicated,
+			// the replication factor of the block is not used
+			return self
+				.rpc_put_shards(hash, ec, &block, PRIO_NORMAL | PRIO_SECONDARY, order_tag)
+				.await;
+		}
+
+		let who = self
+			.system
+			.cluster_layout()
+			.current_storage_nodes_with_factor(&hash, replication_factor);
+		let quorum = self.block_write_quorum(&who);
+
+		let (header, bytes) = block.into_parts();
 
 		let permit = self
 			.buffer_kb_semaphore
@@ -400,7 +554,7 @@ impl BlockManager {
 				put_block_rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL | PRIO_SECONDARY)
 					.with_drop_on_completion(permit)
-					.with_quorum(self.replication.write_quorum()),
+					.with_quorum(quorum),
 			)
 			.await?;
 
@@ -412,6 +566,28 @@ impl BlockManager {
 		Ok(self.rc.rc_table.len()?)
 	}
 
+	/// Count the blocks that are still referenced by the metadata
+	/// stored on this node, grouped by partition of the cluster layout.
+	/// The counts are computed in a blocking thread, and reused by the
+	/// calls made in the following seconds.
+	pub async fn referenced_blocks_by_partition(self: &Arc<Self>) -> Result<Vec<u64>, Error> {
+		let mut cache = self.referenced_blocks_cache.lock().await;
+		if let Some((time, counts)) = cache.as_ref() {
+			if time.elapsed() < REFERENCED_BLOCKS_CACHE_DURATION {
+				return Ok(counts.clone());
+			}
+		}
+
+		let this = self.clone();
+		let counts = tokio::task::spawn_blocking(move || {
+			let layout = this.system.cluster_layout().current().clone();
+			this.rc.count_nonzero_by_partition(&layout)
+		})
+		.await??;
+		*cache = Some((Instant::now(), counts.clone()));
+		Ok(counts)
+	}
+
 	/// Send command to start/stop/manager scrub worker
 	pub async fn send_scrub_command(&self, cmd: ScrubWorkerCommand) -> Result<(), Error> {
 		let tx = self.tx_scrub_command.load();
@@ -454,22 +630,14 @@ impl BlockManager {
 		tx: &mut db::Transaction,
 		hash: Hash,
 	) -> db::TxOpResult<()> {
+		// When the reference counter goes from zero to one, there is
+		// normally a node that is responsible for sending us the
+		// data of the block. However that operation may fail,
+		// so in all cases we add the block here to the todo list
+		// to check later that it arrived correctly, and if not
+		// we will fecth it from someone.
 		if self.rc.block_incref(tx, &hash)? {
-			// When the reference counter is incremented, there is
-			// normally a node that is responsible for sending us the
-			// data of the block. However that operation may fail,
-			// so in all cases we add the block here to the todo list
-			// to check later that it arrived correctly, and if not
-			// we will fecth it from someone.
-			let this = self.clone();
-			tokio::spawn(async move {
-				if let Err(e) = this
-					.resync
-					.put_to_resync(&hash, 2 * this.system.rpc_helper().rpc_timeout())
-				{
-					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
-				}
-			});
+			self.spawn_put_to_resync(hash, 2 * self.system.rpc_helper().rpc_timeout());
 		}
 		Ok(())
 	}
@@ -480,25 +648,33 @@ impl BlockManager {
 		tx: &mut db::Transaction,
 		hash: Hash,
 	) -> db::TxOpResult<()> {
+		// When the RC drops to zero, we don't need the block anymore.
+		// There is a delay before we garbage collect it;
+		// make sure that it is handled in the resync loop
+		// after that delay has passed.
 		if self.rc.block_decref(tx, &hash)? {
-			// When the RC is decremented, it might drop to zero,
-			// indicating that we don't need the block.
-			// There is a delay before we garbage collect it;
-			// make sure that it is handled in the resync loop
-			// after that delay has passed.
-			let this = self.clone();
-			tokio::spawn(async move {
-				if let Err(e) = this
-					.resync
-					.put_to_resync(&hash, BLOCK_GC_DELAY + Duration::from_secs(10))
-				{
-					error!("Block {:?} could not be put in resync queue: {}.", hash, e);
-				}
-			});
+			self.spawn_put_to_resync(hash, BLOCK_GC_DELAY + Duration::from_secs(10));
 		}
 		Ok(())
 	}
 
+	/// Notify that the number of copies that must be kept of a block
+	/// has changed while it is still referenced, so that the block is
+	/// sent to the nodes that should now store it, or removed from
+	/// those that no longer need to
+	pub fn block_replication_changed(self: &Arc<Self>, hash: Hash) {
+		self.spawn_put_to_resync(hash, 2 * self.system.rpc_helper().rpc_timeout());
+	}
+
+	fn spawn_put_to_resync(self: &Arc<Self>, hash: Hash, delay: Duration) {
+		let this = self.clone();
+		tokio::spawn(async move {
+			if let Err(e) = this.resync.put_to_resync(&hash, delay) {
+				error!("Block {:?} could not be put in resync queue: {}.", hash, e);
+			}
+		});
+	}
+
 	// ---- Reading and writing blocks locally ----
 
 	async fn handle_put_block(
@@ -611,8 +787,20 @@ impl BlockManager {
 	/// Check if this node should have a block, but don't actually have it
 	async fn need_block(&self, hash: &Hash) -> Result<bool, Error> {
 		let rc = self.rc.get_block_rc(hash)?;
+		if self.erasure.is_some() {
+			// We never need full copies of erasure-coded blocks,
+			// but we might need some of their shards. If this node does not
+			// hold the reference count of the block, we rely on the node
+			// asking, which only does so for referenced blocks.
+			let referenced = rc.is_nonzero()
+				|| !self
+					.replication
+					.storage_nodes(hash)
+					.contains(&self.system.id);
+			return Ok(referenced && !self.missing_shards(hash).await.is_empty());
+		}
 		let exists = self.find_block(hash).await.is_some();
-		Ok(rc.is_nonzero() && !exists)
+		Ok(rc.is_nonzero() && !exists && self.block_storage_nodes(hash)?.contains(&self.system.id))
 	}
 
 	/// Delete block if it is not needed anymore
@@ -623,6 +811,15 @@ impl BlockManager {
 			.await
 	}
 
+	/// Delete block if it is still referenced but this node is not
+	/// supposed to store one of its copies
+	pub(crate) async fn delete_if_not_stored_here(&self, hash: &Hash) -> Result<(), Error> {
+		self.lock_mutate(hash)
+			.await
+			.delete_if_not_stored_here(hash, self)
+			.await
+	}
+
 	/// Find the path where a block is currently stored
 	pub(crate) async fn find_block(&self, hash: &Hash) -> Option<DataBlockPath> {
 		let data_layout = self.data_layout.load_full();
@@ -676,6 +873,396 @@ impl BlockManager {
 		Ok(data.as_parts_ref().1.len())
 	}
 
+	// ---- Erasure-coded blocks ----
+
+	/// Get the list of nodes that store the shards of a block in the current
+	/// layout version: shard `i` is stored on the `i`-th node
+	pub(crate) fn shard_nodes(&self, hash: &Hash) -> Vec<Uuid> {
+		let shards = self.erasure.map(|ec| ec.total_shards()).unwrap_or(0);
+		self.system
+			.cluster_layout()
+			.current_shard_nodes_of(hash, shards)
+	}
+
+	/// Get the nodes that store shards of a block in the current layout
+	/// version without holding its reference count, as (shard number, node)
+	/// pairs. These are the nodes used when there are more shards than the
+	/// replication factor.
+	pub(crate) fn shard_nodes_without_rc(&self, hash: &Hash) -> Vec<(usize, Uuid)> {
+		let rc_nodes = self.replication.storage_nodes(hash);
+		self.shard_nodes(hash)
+			.into_iter()
+			.enumerate()
+			.filter(|(_, node)| !rc_nodes.contains(node))
+			.collect()
+	}
+
+	/// Numbers of the shards of a block that are stored on this node
+	pub(crate) async fn local_shards(&self, hash: &Hash) -> Vec<usize> {
+		let mut ret = vec![];
+		for index in 0..self.erasure.map(|ec| ec.total_shards()).unwrap_or(0) {
+			if self.find_shard(hash, index).await.is_some() {
+				ret.push(index);
+			}
+		}
+		ret
+	}
+
+	/// Numbers of the shards of a block that this node should store
+	/// in the current layout version, but doesn't have
+	pub(crate) async fn missing_shards(&self, hash: &Hash) -> Vec<usize> {
+		let mut ret = vec![];
+		for (index, node) in self.shard_nodes(hash).into_iter().enumerate() {
+			if node == self.system.id && self.find_shard(hash, index).await.is_none() {
+				ret.push(index);
+			}
+		}
+		ret
+	}
+
+	/// Split a block in shards and send them to the nodes that should store them
+	pub(crate) async fn rpc_put_shards(
+		&self,
+		hash: Hash,
+		ec: &ErasureCoding,
+		block: &DataBlock,
+		priority: RequestPriority,
+		order_tag: Option<OrderTag>,
+	) -> Result<(), Error> {
+		let who = self.shard_nodes(&hash);
+		if who.len() < ec.total_shards() {
+			return Err(Error::Message(format!(
+				"Not enough nodes in the cluster layout to store the {} shards of block {:?}",
+				ec.total_shards(),
+				hash
+			)));
+		}
+
+		let shards = ec.encode(block)?;
+
+		let total_len = shards.iter().map(|s| s.len()).sum::<usize>();
+		let permit = self
+			.buffer_kb_semaphore
+			.clone()
+			.acquire_many_owned((total_len / 1024).try_into().unwrap())
+			.await
+			.ok_or_message("could not reserve space for buffer of data to send to remote nodes")?;
+
+		let mut requests = who
+			.iter()
+			.zip(shards)
+			.enumerate()
+			.map(|(index, (node, shard))| {
+				let req =
+					Req::new(BlockRpc::PutShard { hash, index })?.with_stream_from_buffer(shard);
+				let req = if let Some(tag) = order_tag {
+					req.with_order_tag(tag)
+				} else {
+					req
+				};
+				let system = self.system.clone();
+				let endpoint = self.endpoint.clone();
+				let node = *node;
+				Ok(async move {
+					system
+						.rpc_helper()
+						.call(
+							&endpoint,
+							node,
+							req,
+							RequestStrategy::with_priority(priority),
+						)
+						.await
+				})
+			})
+			.collect::<Result<FuturesUnordered<_>, Error>>()?;
+
+		let quorum = ec.write_quorum();
+		let mut successes = 0;
+		let mut errors = vec![];
+		while let Some(res) = requests.next().await {
+			match res {
+				Ok(_) => successes += 1,
+				Err(e) => errors.push(e.to_string()),
+			}
+			if successes >= quorum {
+				// Finish sending the remaining shards in the background
+				tokio::spawn(async move {
+					while requests.next().await.is_some() {}
+					drop(permit);
+				});
+				return Ok(());
+			}
+		}
+
+		Err(Error::Quorum(quorum, None, successes, who.len(), errors))
+	}
+
+	/// Ask nodes for the shards of a block, until enough of them have been
+	/// received to reconstruct the block
+	async fn rpc_get_block_from_shards(
+		&self,
+		hash: &Hash,
+		priority: RequestPriority,
+	) -> Result<DataBlock, Error> {
+		let ec = self
+			.erasure
+			.ok_or_message("erasure coding is not enabled")?;
+		let mut candidates = self
+			.system
+			.cluster_layout()
+			.shard_read_nodes_of(hash, ec.total_shards())
+			.into_iter();
+
+		let mut requests = candidates
+			.by_ref()
+			.take(ec.data_shards)
+			.map(|(index, node)| self.rpc_get_shard(hash, index, node, priority))
+			.collect::<FuturesUnordered<_>>();
+
+		let mut shards: Vec<(ShardHeader, Bytes)> = vec![];
+		while let Some(res) = requests.next().await {
+			match res {
+				Ok((header, shard)) if !shards.iter().any(|(h, _)| h.index == header.index) => {
+					shards.push((header, shard));
+					if shards.len() >= header.coding.data_shards {
+						return decode_block(hash, &shards);
+					}
+					continue;
+				}
+				Ok(_) => (),
+				Err(e) => debug!("Get block {:?}: could not get shard: {}", hash, e),
+			}
+			// This request did not give us a new shard, ask another node
+			if let Some((index, node)) = candidates.next() {
+				requests.push(self.rpc_get_shard(hash, index, node, priority));
+			}
+		}
+
+		Err(Error::MissingBlock(*hash))
+	}
+
+	/// Ask a node for a shard of a block
+	async fn rpc_get_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		node: Uuid,
+		priority: RequestPriority,
+	) -> Result<(ShardHeader, Bytes), Error> {
+		let get_shard = async {
+			let resp = self
+				.endpoint
+				.call_streaming(
+					&NodeID::from(node),
+					BlockRpc::GetShard(*hash, index),
+					priority,
+				)
+				.await?;
+			match resp.into_parts() {
+				(Ok(BlockRpc::PutShard { .. }), Some(stream)) => Ok(read_stream_to_end(stream)
+					.await
+					.err_context("error in shard data stream")?
+					.into_bytes()),
+				(Ok(m), _) => Err(Error::unexpected_rpc_message(m)),
+				(Err(e), _) => Err(e),
+			}
+		};
+		let shard = tokio::time::timeout(self.system.rpc_helper().rpc_timeout(), get_shard)
+			.await
+			.map_err(|_| Error::Timeout)??;
+
+		match verify_shard(&shard) {
+			Some(header) if header.index == index => Ok((header, shard)),
+			_ => Err(Error::Message(format!(
+				"node {:?} returned an invalid shard {} for block {:?}",
+				node, index, hash
+			))),
+		}
+	}
+
+	/// Send a shard stored on this node to another node
+	pub(crate) async fn rpc_send_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		node: Uuid,
+		priority: RequestPriority,
+	) -> Result<(), Error> {
+		let shard = self.read_shard(hash, index).await?;
+		self.rpc_put_shard(hash, index, shard, node, priority).await
+	}
+
+	/// Send a shard of a block to a node
+	pub(crate) async fn rpc_put_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		shard: Bytes,
+		node: Uuid,
+		priority: RequestPriority,
+	) -> Result<(), Error> {
+		let req =
+			Req::new(BlockRpc::PutShard { hash: *hash, index })?.with_stream_from_buffer(shard);
+		self.system
+			.rpc_helper()
+			.call(
+				&self.endpoint,
+				node,
+				req,
+				RequestStrategy::with_priority(priority),
+			)
+			.await?;
+		Ok(())
+	}
+
+	async fn handle_put_shard(
+		&self,
+		hash: Hash,
+		index: usize,
+		stream: Option<ByteStream>,
+	) -> Result<(), Error> {
+		let stream = stream.ok_or_message("missing stream")?;
+		let shard = read_stream_to_end(stream).await?.into_bytes();
+		match verify_shard(&shard) {
+			Some(header) if header.index == index => (),
+			_ => {
+				return Err(Error::Message(format!(
+					"received invalid shard {} for block {:?}",
+					index, hash
+				)))
+			}
+		}
+		self.write_shard(&hash, index, &shard).await
+	}
+
+	async fn handle_get_shard(&self, hash: &Hash, index: usize) -> Resp<BlockRpc> {
+		match self.read_shard(hash, index).await {
+			Ok(shard) => Resp::new(Ok(BlockRpc::PutShard { hash: *hash, index }))
+				.with_stream_from_buffer(shard),
+			Err(e) => Resp::new(Err(e)),
+		}
+	}
+
+	/// Write a shard to disk
+	pub(crate) async fn write_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		shard: &[u8],
+	) -> Result<(), Error> {
+		self.lock_mutate(hash)
+			.await
+			.write_shard(hash, index, shard, self)
+			.bound_record_duration(&self.metrics.block_write_duration)
+			.await
+	}
+
+	/// Read a shard from disk, verifying its integrity
+	pub(crate) async fn read_shard(&self, hash: &Hash, index: usize) -> Result<Bytes, Error> {
+		match self.find_shard(hash, index).await {
+			Some(path) => self.read_shard_from(hash, index, &path).await,
+			None => {
+				// Not found but maybe we should have had it ??
+				self.resync
+					.put_to_resync(hash, 2 * self.system.rpc_helper().rpc_timeout())?;
+				Err(Error::Message(format!(
+					"shard {} of block {:?} not found on node",
+					index, hash
+				)))
+			}
+		}
+	}
+
+	pub(crate) async fn read_shard_from(
+		&self,
+		hash: &Hash,
+		index: usize,
+		path: &PathBuf,
+	) -> Result<Bytes, Error> {
+		let data = fs::read(path).await?;
+		self.metrics.bytes_read.add(data.len() as u64);
+
+		match verify_shard(&data) {
+			Some(header) if header.index == index => Ok(data.into()),
+			_ => {
+				self.metrics.corruption_counter.add(1);
+
+				warn!(
+					"Shard {} of block {:?} is corrupted. Renaming to .corrupted and resyncing.",
+					index, hash
+				);
+				self.lock_mutate(hash)
+					.await
+					.move_shard_to_corrupted(path)
+					.await?;
+				self.resync.put_to_resync(hash, Duration::from_millis(0))?;
+
+				Err(Error::CorruptData(*hash))
+			}
+		}
+	}
+
+	/// Find the path where a shard of a block is currently stored
+	pub(crate) async fn find_shard(&self, hash: &Hash, index: usize) -> Option<PathBuf> {
+		let data_layout = self.data_layout.load_full();
+		let dirs = Some(data_layout.primary_block_dir(hash))
+			.into_iter()
+			.chain(data_layout.secondary_block_dirs(hash));
+
+		for mut path in dirs {
+			path.push(shard_filename(hash, index));
+			if fs::metadata(&path).await.is_ok() {
+				return Some(path);
+			}
+		}
+
+		None
+	}
+
+	/// Rewrite a shard at the primary location for its path and delete the old path.
+	/// Returns the number of bytes read/written
+	pub(crate) async fn fix_shard_location(
+		&self,
+		hash: &Hash,
+		index: usize,
+		wrong_path: PathBuf,
+	) -> Result<usize, Error> {
+		let data = self.read_shard_from(hash, index, &wrong_path).await?;
+		self.lock_mutate(hash)
+			.await
+			.write_shard_inner(hash, index, &data, self, Some(wrong_path))
+			.await?;
+		Ok(data.len())
+	}
+
+	/// Delete a shard of a block if this node is not supposed to store it
+	/// in the current layout version
+	pub(crate) async fn delete_shard_if_not_stored_here(
+		&self,
+		hash: &Hash,
+		index: usize,
+	) -> Result<(), Error> {
+		self.lock_mutate(hash)
+			.await
+			.delete_shard_if_not_stored_here(hash, index, self)
+			.await
+	}
+
+	/// Delete the shards of a block that are stored on this node, if it does
+	/// not hold a nonzero reference count for the block
+	async fn delete_unreferenced_shards(&self, hash: &Hash) -> Result<(), Error> {
+		self.lock_mutate(hash)
+			.await
+			.delete_unreferenced_shards(hash, self)
+			.await
+	}
+
+	/// Delete the full copy of a block once it has been converted to shards
+	pub(crate) async fn delete_full_copy(&self, hash: &Hash) -> Result<(), Error> {
+		self.lock_mutate(hash).await.delete_block(hash, self).await
+	}
+
 	async fn lock_mutate(&self, hash: &Hash) -> MutexGuard<'_, BlockManagerLocked> {
 		let tracer = opentelemetry::global::tracer("garage");
 		let ilock = u16::from_be_bytes([hash.as_slice()[0], hash.as_slice()[1]]) as usize
@@ -701,6 +1288,17 @@ impl StreamingEndpointHandler<BlockRpc> for BlockManager {
 			BlockRpc::NeedBlockQuery(h) => {
 				Resp::new(self.need_block(h).await.map(BlockRpc::NeedBlockReply))
 			}
+			BlockRpc::PutShard { hash, index } => Resp::new(
+				self.handle_put_shard(*hash, *index, message.take_stream())
+					.await
+					.map(|()| BlockRpc::Ok),
+			),
+			BlockRpc::GetShard(h, index) => self.handle_get_shard(h, *index).await,
+			BlockRpc::DeleteShards(h) => Resp::new(
+				self.delete_unreferenced_shards(h)
+					.await
+					.map(|()| BlockRpc::Ok),
+			),
 			m => Resp::new(Err(Error::unexpected_rpc_message(m))),
 		}
 	}
@@ -760,6 +1358,57 @@ impl BlockManagerLocked {
 		};
 		assert!(to_delete.as_ref() != Some(&tgt_path));
 
+		self.write_file(directory, tgt_path, data, to_delete, mgr)
+			.await
+	}
+
+	async fn write_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		shard: &[u8],
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		let existing_path = mgr.find_shard(hash, index).await;
+		self.write_shard_inner(hash, index, shard, mgr, existing_path)
+			.await
+	}
+
+	async fn write_shard_inner(
+		&self,
+		hash: &Hash,
+		index: usize,
+		shard: &[u8],
+		mgr: &BlockManager,
+		existing_path: Option<PathBuf>,
+	) -> Result<(), Error> {
+		let directory = mgr.data_layout.load().primary_block_dir(hash);
+
+		let mut tgt_path = directory.clone();
+		tgt_path.push(shard_filename(hash, index));
+
+		let to_delete = match existing_path {
+			// If the shard is already stored at the right place, we have nothing to do
+			Some(p) if p == tgt_path => return Ok(()),
+			// If the shard is stored in the wrong directory,
+			// write it again at the correct path and delete the old path
+			Some(p) => Some(p),
+			None => None,
+		};
+
+		self.write_file(directory, tgt_path, shard, to_delete, mgr)
+			.await
+	}
+
+	/// Write a file atomically, and then delete the old copy at `to_delete`
+	async fn write_file(
+		&self,
+		directory: PathBuf,
+		tgt_path: PathBuf,
+		data: &[u8],
+		to_delete: Option<PathBuf>,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
 		let mut path_tmp = tgt_path.clone();
 		let tmp_extension = format!("tmp{}", hex::encode(thread_rng().gen::<[u8; 4]>()));
 		path_tmp.set_extension(tmp_extension);
@@ -818,17 +1467,82 @@ impl BlockManagerLocked {
 		Ok(())
 	}
 
+	async fn move_shard_to_corrupted(&self, path: &PathBuf) -> Result<(), Error> {
+		let mut path2 = path.clone().into_os_string();
+		path2.push(".corrupted");
+		fs::rename(path, path2).await?;
+		Ok(())
+	}
+
 	async fn delete_if_unneeded(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
 		let rc = mgr.rc.get_block_rc(hash)?;
 		if rc.is_deletable() {
-			while let Some(path) = mgr.find_block(hash).await {
-				let (_header, path) = path.as_parts_ref();
-				fs::remove_file(path).await?;
-				mgr.metrics.delete_counter.add(1);
+			self.delete_block(hash, mgr).await?;
+			for index in mgr.local_shards(hash).await {
+				self.delete_shard(hash, index, mgr).await?;
+			}
+		}
+		Ok(())
+	}
+
+	async fn delete_if_not_stored_here(
+		&self,
+		hash: &Hash,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		let rc = mgr.rc.get_block_rc(hash)?;
+		if rc.is_nonzero() && !mgr.block_storage_nodes(hash)?.contains(&mgr.system.id) {
+			self.delete_block(hash, mgr).await?;
+		}
+		Ok(())
+	}
+
+	async fn delete_shard_if_not_stored_here(
+		&self,
+		hash: &Hash,
+		index: usize,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		if mgr.shard_nodes(hash).get(index) != Some(&mgr.system.id) {
+			self.delete_shard(hash, index, mgr).await?;
+		}
+		Ok(())
+	}
+
+	async fn delete_unreferenced_shards(
+		&self,
+		hash: &Hash,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		if !mgr.rc.get_block_rc(hash)?.is_nonzero() {
+			for index in mgr.local_shards(hash).await {
+				self.delete_shard(hash, index, mgr).await?;
 			}
 		}
 		Ok(())
 	}
+
+	async fn delete_shard(
+		&self,
+		hash: &Hash,
+		index: usize,
+		mgr: &BlockManager,
+	) -> Result<(), Error> {
+		while let Some(path) = mgr.find_shard(hash, index).await {
+			fs::remove_file(path).await?;
+			mgr.metrics.delete_counter.add(1);
+		}
+		Ok(())
+	}
+
+	async fn delete_block(&self, hash: &Hash, mgr: &BlockManager) -> Result<(), Error> {
+		while let Some(path) = mgr.find_block(hash).await {
+			let (_header, path) = path.as_parts_ref();
+			fs::remove_file(path).await?;
+			mgr.metrics.delete_counter.add(1);
+		}
+		Ok(())
+	}
 }
 
 struct DeleteOnDrop(Option<PathBuf>);
@@ -850,3 +1564,288 @@ impl Drop for DeleteOnDrop {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::net::TcpListener;
+
+	use tokio::sync::watch;
+
+	use garage_net::NetworkKey;
+	use garage_rpc::layout::{NodeRole, NodeRoleV};
+	use garage_rpc::replication_mode::{ConsistencyMode, ReplicationFactor};
+	use garage_util::crdt::Crdt;
+
+	use super::*;
+
+	struct TestNode {
+		system: Arc<System>,
+		block_manager: Arc<BlockManager>,
+		rpc_addr: String,
+		_dir: mktemp::Temp,
+	}
+
+	/// Start a node with erasure coding enabled, listening on a free local port
+	fn test_node(
+		must_exit: &watch::Receiver<bool>,
+		replication_factor: usize,
+		data_shards: usize,
+		parity_shards: usize,
+	) -> TestNode {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let port = TcpListener::bind("127.0.0.1:0")
+			.unwrap()
+			.local_addr()
+			.unwrap()
+			.port();
+		let config = format!(
+			r#"
+			metadata_dir = "{path}/meta"
+			data_dir = "{path}/data"
+			replication_factor = {replication_factor}
+			rpc_bind_addr = "127.0.0.1:{port}"
+			rpc_public_addr = "127.0.0.1:{port}"
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "127.0.0.1:3900"
+
+			[erasure_coding]
+			data_shards = {data_shards}
+			parity_shards = {parity_shards}
+			"#,
+			path = dir.to_string_lossy(),
+			port = port,
+			replication_factor = replication_factor,
+			data_shards = data_shards,
+			parity_shards = parity_shards,
+		);
+		let config: Config = toml::from_str(&config).unwrap();
+		std::fs::create_dir_all(dir.join("data")).unwrap();
+
+		let system = System::new(
+			NetworkKey::from_slice(&[0x42; 32]).unwrap(),
+			ReplicationFactor::new(replication_factor).unwrap(),
+			ConsistencyMode::Consistent,
+			&config,
+		)
+		.unwrap();
+		tokio::spawn(system.clone().run(must_exit.clone()));
+
+		let replication = TableShardedReplication {
+			system: system.clone(),
+			replication_factor,
+			read_quorum: 1,
+			write_quorum: replication_factor.div_ceil(2),
+		};
+		let db = db::memory_adapter::MemDb::init();
+		let block_manager = BlockManager::new(&db, &config, replication, system.clone()).unwrap();
+
+		TestNode {
+			system,
+			block_manager,
+			rpc_addr: format!("127.0.0.1:{}", port),
+			_dir: dir,
+		}
+	}
+
+	/// Start connected nodes that all store data, each in its own zone
+	async fn test_cluster(
+		must_exit: &watch::Receiver<bool>,
+		count: usize,
+		replication_factor: usize,
+		data_shards: usize,
+		parity_shards: usize,
+	) -> Vec<TestNode> {
+		let nodes = (0..count)
+			.map(|_| test_node(must_exit, replication_factor, data_shards, parity_shards))
+			.collect::<Vec<_>>();
+
+		for (i, node) in nodes.iter().enumerate() {
+			for other in nodes[i + 1..].iter() {
+				let spec = format!("{}@{}", hex::encode(other.system.id), other.rpc_addr);
+				// the other node might not be listening yet
+				for retry in 0.. {
+					match node.system.connect(&spec).await {
+						Ok(()) => break,
+						Err(e) if retry >= 50 => panic!("{}", e),
+						Err(_) => tokio::time::sleep(Duration::from_millis(100)).await,
+					}
+				}
+			}
+		}
+
+		let mut layout = nodes[0].system.cluster_layout().inner().clone();
+		let staging = layout.staging.get_mut();
+		for (i, node) in nodes.iter().enumerate() {
+			let role = NodeRole {
+				zone: format!("dc{}", i),
+				capacity: Some(1 << 30),
+				tags: vec![],
+			};
+			let update = staging
+				.roles
+				.update_mutator(node.system.id, NodeRoleV(Some(role)));
+			staging.roles.merge(&update);
+		}
+		let (layout, _) = layout.apply_staged_changes(Some(1)).unwrap();
+		for node in nodes.iter() {
+			node.system
+				.layout_manager
+				.update_cluster_layout(&layout)
+				.await
+				.unwrap();
+		}
+
+		nodes
+	}
+
+	#[tokio::test]
+	async fn test_shards_round_trip() {
+		let (_tx, must_exit) = watch::channel(false);
+		let nodes = test_cluster(&must_exit, 3, 3, 2, 1).await;
+		let manager = &nodes[0].block_manager;
+
+		let data = Bytes::from((0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+		let hash = blake2sum(&data);
+		manager
+			.rpc_put_block(hash, data.clone(), true, None, None)
+			.await
+			.unwrap();
+
+		// wait for the shards that are sent in the background
+		let shard_nodes = manager.shard_nodes(&hash);
+		assert_eq!(shard_nodes.len(), 3);
+		for _ in 0..100 {
+			let mut stored = 0;
+			for (index, node_id) in shard_nodes.iter().enumerate() {
+				let node = nodes.iter().find(|n| n.system.id == *node_id).unwrap();
+				if node.block_manager.find_shard(&hash, index).await.is_some() {
+					stored += 1;
+				}
+			}
+			if stored == 3 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+
+		let check_block = |block: DataBlock| {
+			let (header, bytes) = block.into_parts();
+			assert_eq!(header, DataBlockHeader::Plain);
+			assert_eq!(bytes, data);
+		};
+		check_block(
+			manager
+				.rpc_get_block_from_shards(&hash, PRIO_NORMAL)
+				.await
+				.unwrap(),
+		);
+
+		// remove the shards on the nodes that are asked first: the block
+		// can still be read as long as enough shards remain
+		let candidates = nodes[0]
+			.system
+			.cluster_layout()
+			.shard_read_nodes_of(&hash, 3);
+		let remove_shard = |(index, node_id): (usize, Uuid)| {
+			let node = nodes.iter().find(|n| n.system.id == node_id).unwrap();
+			async move {
+				let path = node.block_manager.find_shard(&hash, index).await.unwrap();
+				fs::remove_file(path).await.unwrap();
+			}
+		};
+		remove_shard(candidates[0]).await;
+		check_block(
+			manager
+				.rpc_get_block_from_shards(&hash, PRIO_NORMAL)
+				.await
+				.unwrap(),
+		);
+
+		remove_shard(candidates[1]).await;
+		assert!(matches!(
+			manager.rpc_get_block_from_shards(&hash, PRIO_NORMAL).await,
+			Err(Error::MissingBlock(h)) if h == hash
+		));
+	}
+
+	#[tokio::test]
+	async fn test_more_shards_than_replication_factor() {
+		let (_tx, must_exit) = watch::channel(false);
+		let nodes = test_cluster(&must_exit, 4, 2, 2, 2).await;
+		let manager = &nodes[0].block_manager;
+		let node_of = |id: &Uuid| nodes.iter().find(|n| n.system.id == *id).unwrap();
+
+		let data = Bytes::from((0..100_000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>());
+		let hash = blake2sum(&data);
+		manager
+			.rpc_put_block(hash, data.clone(), true, None, None)
+			.await
+			.unwrap();
+
+		// the four shards are stored on the four nodes, two of which
+		// do not store the partition of the block
+		let shard_nodes = manager.shard_nodes(&hash);
+		assert_eq!(shard_nodes.len(), 4);
+		let without_rc = manager.shard_nodes_without_rc(&hash);
+		assert_eq!(without_rc.len(), 2);
+		for _ in 0..100 {
+			let mut stored = 0;
+			for (index, node_id) in shard_nodes.iter().enumerate() {
+				if node_of(node_id)
+					.block_manager
+					.find_shard(&hash, index)
+					.await
+					.is_some()
+				{
+					stored += 1;
+				}
+			}
+			if stored == 4 {
+				break;
+			}
+			tokio::time::sleep(Duration::from_millis(50)).await;
+		}
+
+		let (header, bytes) = manager
+			.rpc_get_block_from_shards(&hash, PRIO_NORMAL)
+			.await
+			.unwrap()
+			.into_parts();
+		assert_eq!(header, DataBlockHeader::Plain);
+		assert_eq!(bytes, data);
+
+		// a node of the partition of the block that references it rebuilds
+		// the shard that is missing on a node outside of the partition
+		let holder = node_of(&shard_nodes[0]);
+		let rc = &holder.block_manager.rc;
+		rc.rc_table
+			.db()
+			.transaction(|tx| Ok::<_, db::TxError<()>>(rc.block_incref(tx, &hash)?))
+			.unwrap();
+		let (index, extra_id) = without_rc[0];
+		let extra = &node_of(&extra_id).block_manager;
+		let path = extra.find_shard(&hash, index).await.unwrap();
+		fs::remove_file(path).await.unwrap();
+		assert!(extra.need_block(&hash).await.unwrap());
+		holder
+			.block_manager
+			.resync
+			.resync_block(&holder.block_manager, &hash)
+			.await
+			.unwrap();
+		assert!(extra.find_shard(&hash, index).await.is_some());
+		assert!(!extra.need_block(&hash).await.unwrap());
+
+		// nodes only delete shards on request if they don't reference the block
+		holder
+			.block_manager
+			.delete_unreferenced_shards(&hash)
+			.await
+			.unwrap();
+		assert!(holder.block_manager.find_shard(&hash, 0).await.is_some());
+		extra.delete_unreferenced_shards(&hash).await.unwrap();
+		assert!(extra.find_shard(&hash, index).await.is_none());
+	}
+}
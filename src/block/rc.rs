@@ -8,14 +8,22 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::time::*;
 
+use garage_rpc::layout::{LayoutVersion, PARTITION_BITS};
+
 use crate::manager::BLOCK_GC_DELAY;
 
 pub type CalculateRefcount =
 	Box<dyn Fn(&db::Transaction, &Hash) -> db::TxResult<usize, Error> + Send + Sync>;
 
+/// Function returning the number of copies that must be kept of a block,
+/// or `None` if it must be stored with the replication factor of the cluster
+pub type CalculateReplicationFactor =
+	Box<dyn Fn(&Hash) -> Result<Option<usize>, Error> + Send + Sync>;
+
 pub struct BlockRc {
 	pub rc_table: db::Tree,
 	pub(crate) recalc_rc: ArcSwapOption<Vec<CalculateRefcount>>,
+	pub(crate) calc_rf: ArcSwapOption<CalculateReplicationFactor>,
 }
 
 impl BlockRc {
@@ -23,37 +31,42 @@ impl BlockRc {
 		Self {
 			rc_table: rc,
 			recalc_rc: ArcSwapOption::new(None),
+			calc_rf: ArcSwapOption::new(None),
 		}
 	}
 
 	/// Increment the reference counter associated to a hash.
-	/// Returns true if the RC goes from zero to nonzero.
+	/// Returns true if the block was not referenced before.
 	pub(crate) fn block_incref(
 		&self,
 		tx: &mut db::Transaction,
 		hash: &Hash,
 	) -> db::TxOpResult<bool> {
 		let old_rc = RcEntry::parse_opt(tx.get(&self.rc_table, hash)?);
+		let was_zero = old_rc.is_zero();
 		match old_rc.increment().serialize() {
 			Some(x) => tx.insert(&self.rc_table, hash, x)?,
 			None => unreachable!(),
 		};
-		Ok(old_rc.is_zero())
+		Ok(was_zero)
 	}
 
 	/// Decrement the reference counter associated to a hash.
-	/// Returns true if the RC is now zero.
+	/// Returns true if the block is no longer referenced.
 	pub(crate) fn block_decref(
 		&self,
 		tx: &mut db::Transaction,
 		hash: &Hash,
 	) -> db::TxOpResult<bool> {
-		let new_rc = RcEntry::parse_opt(tx.get(&self.rc_table, hash)?).decrement();
+		let old_rc = RcEntry::parse_opt(tx.get(&self.rc_table, hash)?);
+		let was_nonzero = old_rc.is_nonzero();
+		let new_rc = old_rc.decrement();
+		let is_zero = new_rc.is_zero();
 		match new_rc.serialize() {
 			Some(x) => tx.insert(&self.rc_table, hash, x)?,
 			None => tx.remove(&self.rc_table, hash)?,
 		};
-		Ok(matches!(new_rc, RcEntry::Deletable { .. }))
+		Ok(was_nonzero && is_zero)
 	}
 
 	/// Read a block's reference count
@@ -61,6 +74,32 @@ impl BlockRc {
 		Ok(RcEntry::parse_opt(self.rc_table.get(hash.as_ref())?))
 	}
 
+	/// Get the number of copies that must be kept of a block,
+	/// `None` meaning the replication factor of the cluster
+	pub(crate) fn block_replication_factor(&self, hash: &Hash) -> Result<Option<usize>, Error> {
+		match self.calc_rf.load().as_ref() {
+			Some(calc_rf) => calc_rf(hash),
+			None => Ok(None),
+		}
+	}
+
+	/// Count the blocks whose reference counter is nonzero,
+	/// grouped by partition of the given layout version
+	pub(crate) fn count_nonzero_by_partition(
+		&self,
+		layout: &LayoutVersion,
+	) -> Result<Vec<u64>, Error> {
+		let mut counts = vec![0; 1 << PARTITION_BITS];
+		for entry in self.rc_table.iter()? {
+			let (hash, rc) = entry?;
+			if RcEntry::parse(&rc).is_nonzero() {
+				let hash = Hash::try_from(&hash[..]).unwrap();
+				counts[layout.partition_of(&hash) as usize] += 1;
+			}
+		}
+		Ok(counts)
+	}
+
 	/// Delete an entry in the RC table if it is deletable and the
 	/// deletion time has passed
 	pub(crate) fn clear_deleted_block_rc(&self, hash: &Hash) -> Result<(), Error> {
@@ -238,3 +277,36 @@ impl RcEntry {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_rc_crosses_zero() {
+		let db = db::memory_adapter::MemDb::init();
+		let rc = BlockRc::new(db.open_tree("block_local_rc").unwrap());
+		let hash = blake2sum(b"block");
+
+		let incref = |rc: &BlockRc| -> bool {
+			db.transaction(|tx| Ok::<_, db::TxError<()>>(rc.block_incref(tx, &hash)?))
+				.unwrap()
+		};
+		let decref = |rc: &BlockRc| -> bool {
+			db.transaction(|tx| Ok::<_, db::TxError<()>>(rc.block_decref(tx, &hash)?))
+				.unwrap()
+		};
+
+		assert!(incref(&rc));
+		assert!(!incref(&rc));
+		assert!(!decref(&rc));
+		assert!(decref(&rc));
+		assert!(rc.get_block_rc(&hash).unwrap().is_zero());
+
+		// Decrementing a counter that is already zero does not cross zero again,
+		// and a deletable block that is referenced again is no longer deleted
+		assert!(!decref(&rc));
+		assert!(incref(&rc));
+		assert_eq!(rc.get_block_rc(&hash).unwrap().as_u64(), 1);
+	}
+}
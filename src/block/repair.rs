@@ -18,6 +18,7 @@ use garage_util::time::*;
 use garage_util::tranquilizer::Tranquilizer;
 
 use crate::block::*;
+use crate::erasure::shard_index_of_path;
 use crate::manager::*;
 
 // Full scrub every 25 days with a random element of 10 days mixed in below
@@ -447,8 +448,15 @@ impl Worker for ScrubWorker {
 				self.tranquilizer.reset();
 				let now = now_msec();
 
-				if let Some((_path, hash)) = iterator.next().await? {
-					match self.manager.read_block(&hash).await {
+				if let Some((path, hash)) = iterator.next().await? {
+					let res = if path.extension().map(|x| x == "corrupted").unwrap_or(false) {
+						Ok(())
+					} else if let Some(index) = shard_index_of_path(&path) {
+						self.manager.read_shard(&hash, index).await.map(|_| ())
+					} else {
+						self.manager.read_block(&hash).await.map(|_| ())
+					};
+					match res {
 						Err(Error::CorruptData(_)) => {
 							error!("Found corrupt data block during scrub: {:?}", hash);
 							self.persister.set_with(|p| p.corruptions_detected += 1)?;
@@ -583,6 +591,13 @@ impl Worker for RebalanceWorker {
 		if let Some((path, hash)) = self.block_iter.next().await? {
 			let prim_loc = self.manager.data_layout.load().primary_block_dir(&hash);
 			if path.ancestors().all(|x| x != prim_loc) {
+				if let Some(index) = shard_index_of_path(&path) {
+					debug!("rebalance: moving shard {:?} => {:?}", path, prim_loc);
+					let shard_len = self.manager.fix_shard_location(&hash, index, path).await?;
+					self.moved += 1;
+					self.moved_bytes += shard_len as u64;
+					return Ok(WorkerState::Busy);
+				}
 				let block_path = match path.extension() {
 					None => DataBlockPath::plain(path.clone()),
 					Some(x) if x.to_str() == Some("zst") => DataBlockPath::compressed(path.clone()),
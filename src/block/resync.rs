@@ -29,6 +29,8 @@ use garage_rpc::*;
 
 use garage_table::replication::TableReplication;
 
+use crate::block::*;
+use crate::erasure::ErasureCoding;
 use crate::manager::*;
 
 // The delay between the time where a resync operation fails
@@ -351,7 +353,15 @@ impl BlockResyncManager {
 		Ok(None)
 	}
 
-	async fn resync_block(&self, manager: &BlockManager, hash: &Hash) -> Result<(), Error> {
+	pub(crate) async fn resync_block(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+	) -> Result<(), Error> {
+		if let Some(ec) = manager.erasure {
+			return self.resync_block_shards(manager, hash, ec).await;
+		}
+
 		let existing_path = manager.find_block(hash).await;
 		let exists = existing_path.is_some();
 		let rc = manager.rc.get_block_rc(hash)?;
@@ -377,98 +387,73 @@ impl BlockResyncManager {
 			info!("Resync block {:?}: offloading and deleting", hash);
 			let existing_path = existing_path.unwrap();
 
-			let mut who = manager
+			let who = manager
 				.system
 				.cluster_layout()
 				.current_storage_nodes_of(hash);
 			if who.len() < manager.replication.write_quorum() {
 				return Err(Error::Message("Not trying to offload block because we don't have a quorum of nodes to write to".to_string()));
 			}
-			who.retain(|id| *id != manager.system.id);
 
-			let who_needs_resps = manager
-				.system
-				.rpc_helper()
-				.call_many(
-					&manager.endpoint,
-					&who,
-					BlockRpc::NeedBlockQuery(*hash),
-					RequestStrategy::with_priority(PRIO_BACKGROUND),
-				)
+			let (need, total) = self
+				.offload_block(manager, hash, &existing_path, who)
 				.await?;
-
-			let mut need_nodes = vec![];
-			for (node, needed) in who_needs_resps {
-				match needed.err_context("NeedBlockQuery RPC")? {
-					BlockRpc::NeedBlockReply(needed) => {
-						if needed {
-							need_nodes.push(node);
-						}
-					}
-					m => {
-						return Err(Error::unexpected_rpc_message(m));
-					}
-				}
-			}
-
-			if !need_nodes.is_empty() {
-				trace!(
-					"Block {:?} needed by {} nodes, sending",
-					hash,
-					need_nodes.len()
-				);
-
-				for node in need_nodes.iter() {
-					manager
-						.metrics
-						.resync_send_counter
-						.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
-				}
-
-				let block = manager.read_block_from(hash, &existing_path).await?;
-				let (header, bytes) = block.into_parts();
-				let put_block_message = Req::new(BlockRpc::PutBlock {
-					hash: *hash,
-					header,
-				})?
-				.with_stream_from_buffer(bytes);
-				manager
-					.system
-					.rpc_helper()
-					.try_call_many(
-						&manager.endpoint,
-						&need_nodes,
-						put_block_message,
-						RequestStrategy::with_priority(PRIO_BACKGROUND | PRIO_SECONDARY)
-							.with_quorum(need_nodes.len()),
-					)
-					.await
-					.err_context("PutBlock RPC")?;
-			}
 			info!(
 				"Deleting unneeded block {:?}, offload finished ({} / {})",
-				hash,
-				need_nodes.len(),
-				who.len()
+				hash, need, total
 			);
 
 			manager.delete_if_unneeded(hash).await?;
 
 			manager.rc.clear_deleted_block_rc(hash)?;
+			return Ok(());
 		}
 
-		if rc.is_nonzero() && !exists {
-			// The refcount is > 0, and the block is not present locally.
-			// We might need to fetch it from another node.
+		if !rc.is_nonzero() {
+			return Ok(());
+		}
 
-			// First, check whether we are still supposed to store that
-			// block in the latest cluster layout version.
-			let storage_nodes = manager
+		// The refcount is > 0: check whether we are still supposed to store
+		// that block in the latest cluster layout version.
+		let storage_nodes = manager.block_storage_nodes(hash)?;
+		let is_storage_node = storage_nodes.contains(&manager.system.id);
+
+		if exists && !is_storage_node {
+			// The block is stored with less copies than the replication
+			// factor of the cluster, and we are not one of the nodes that
+			// should keep it. Nodes that are not in the list of storage
+			// nodes for the full replication factor keep their blocks until
+			// the refcount drops to zero, as they might still be needed
+			// during a layout change.
+			let is_full_storage_node = manager
 				.system
 				.cluster_layout()
-				.current_storage_nodes_of(&hash);
+				.current_storage_nodes_of(hash)
+				.contains(&manager.system.id);
+			if !is_full_storage_node {
+				return Ok(());
+			}
+
+			info!(
+				"Resync block {:?}: offloading and deleting extra copy of block",
+				hash
+			);
+			let existing_path = existing_path.unwrap();
+			let (need, total) = self
+				.offload_block(manager, hash, &existing_path, storage_nodes)
+				.await?;
+			info!(
+				"Deleting extra copy of block {:?}, offload finished ({} / {})",
+				hash, need, total
+			);
 
-			if !storage_nodes.contains(&manager.system.id) {
+			manager.delete_if_not_stored_here(hash).await?;
+		}
+
+		if !exists {
+			// The refcount is > 0, and the block is not present locally.
+			// We might need to fetch it from another node.
+			if !is_storage_node {
 				info!(
 					"Resync block {:?}: block is absent with refcount > 0, but it will drop to zero after all metadata is synced. Not fetching the block.",
 					hash
@@ -501,6 +486,310 @@ impl BlockResyncManager {
 
 		Ok(())
 	}
+
+	/// Resync a block when erasure coding is enabled: hand over the shards
+	/// that are stored here but belong to other nodes, and rebuild the
+	/// shards that this node should store but doesn't have. The nodes that
+	/// hold the reference count of the block also do this for the nodes that
+	/// store shards of the block without holding its reference count.
+	async fn resync_block_shards(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		ec: ErasureCoding,
+	) -> Result<(), Error> {
+		let full_copy = manager.find_block(hash).await;
+		let local_shards = manager.local_shards(hash).await;
+		let rc = manager.rc.get_block_rc(hash)?;
+
+		let has_data = full_copy.is_some()
+			|| !local_shards.is_empty()
+			|| !manager.shard_nodes_without_rc(hash).is_empty();
+		if has_data && rc.is_deletable() {
+			if manager.rc.recalculate_rc(hash)?.0 > 0 {
+				return Err(Error::Message(format!(
+					"Refcount for block {:?} was inconsistent, retrying later",
+					hash
+				)));
+			}
+
+			info!("Resync block {:?}: offloading and deleting shards", hash);
+			// Shards are only offloaded to nodes that hold the reference count
+			// of the block, as the others would accept them unconditionally
+			let shard_nodes = manager.shard_nodes(hash);
+			let rc_nodes = manager.replication.storage_nodes(hash);
+			for index in local_shards {
+				if let Some(node) = shard_nodes
+					.get(index)
+					.filter(|n| **n != manager.system.id && rc_nodes.contains(n))
+				{
+					self.offload_shard(manager, hash, index, *node).await?;
+				}
+			}
+
+			for (_, node) in manager.shard_nodes_without_rc(hash) {
+				manager
+					.system
+					.rpc_helper()
+					.call(
+						&manager.endpoint,
+						node,
+						BlockRpc::DeleteShards(*hash),
+						RequestStrategy::with_priority(PRIO_BACKGROUND),
+					)
+					.await
+					.err_context("DeleteShards RPC")?;
+			}
+
+			manager.delete_if_unneeded(hash).await?;
+
+			manager.rc.clear_deleted_block_rc(hash)?;
+			return Ok(());
+		}
+
+		// A node that does not hold the reference count of the block only
+		// hands over the shards that are no longer stored on it
+		let holds_rc = manager
+			.replication
+			.storage_nodes(hash)
+			.contains(&manager.system.id);
+		if !rc.is_nonzero() && holds_rc {
+			return Ok(());
+		}
+
+		if let Some(path) = full_copy.filter(|_| rc.is_nonzero()) {
+			// This block was written before erasure coding was enabled:
+			// split it in shards, send them to their nodes, and delete
+			// the full copy.
+			info!("Resync block {:?}: converting full copy to shards", hash);
+			let block = manager.read_block_from(hash, &path).await?;
+			manager
+				.rpc_put_shards(*hash, &ec, &block, PRIO_BACKGROUND | PRIO_SECONDARY, None)
+				.await?;
+			manager.delete_full_copy(hash).await?;
+		}
+
+		// Hand over the shards that belong to other nodes
+		// in the current layout version
+		let shard_nodes = manager.shard_nodes(hash);
+		for index in manager.local_shards(hash).await {
+			match shard_nodes.get(index) {
+				Some(node) if *node == manager.system.id => (),
+				Some(node) => {
+					info!(
+						"Resync block {:?}: handing over shard {} to node {:?}",
+						hash, index, node
+					);
+					self.offload_shard(manager, hash, index, *node).await?;
+					manager.delete_shard_if_not_stored_here(hash, index).await?;
+				}
+				None => manager.delete_shard_if_not_stored_here(hash, index).await?,
+			}
+		}
+
+		if !rc.is_nonzero() {
+			return Ok(());
+		}
+
+		// Rebuild the shards that we should have
+		let missing = manager.missing_shards(hash).await;
+		if !missing.is_empty() {
+			info!(
+				"Resync block {:?}: rebuilding missing shards {:?}",
+				hash, missing
+			);
+
+			let block_data = manager
+				.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None)
+				.await;
+			if matches!(block_data, Err(Error::MissingBlock(_))) {
+				warn!(
+					"Could not fetch needed block {:?}, not enough nodes returned valid data. Checking that refcount is correct.",
+					hash
+				);
+				manager.rc.recalculate_rc(hash)?;
+			}
+			let shards = ec.encode(&block_data?)?;
+
+			manager.metrics.resync_recv_counter.add(1);
+
+			for index in missing {
+				manager.write_shard(hash, index, &shards[index]).await?;
+			}
+		}
+
+		self.rebuild_shards_without_rc(manager, hash, ec).await
+	}
+
+	/// Rebuild the shards that are missing on the nodes that store shards
+	/// of a block without holding its reference count, as these nodes cannot
+	/// tell by themselves that they should have them
+	async fn rebuild_shards_without_rc(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		ec: ErasureCoding,
+	) -> Result<(), Error> {
+		let mut need_shards = vec![];
+		for (index, node) in manager.shard_nodes_without_rc(hash) {
+			let needed = manager
+				.system
+				.rpc_helper()
+				.call(
+					&manager.endpoint,
+					node,
+					BlockRpc::NeedBlockQuery(*hash),
+					RequestStrategy::with_priority(PRIO_BACKGROUND),
+				)
+				.await
+				.err_context("NeedBlockQuery RPC")?;
+			match needed {
+				BlockRpc::NeedBlockReply(false) => (),
+				BlockRpc::NeedBlockReply(true) => need_shards.push((index, node)),
+				m => return Err(Error::unexpected_rpc_message(m)),
+			}
+		}
+		if need_shards.is_empty() {
+			return Ok(());
+		}
+
+		info!(
+			"Resync block {:?}: rebuilding shards {:?} on other nodes",
+			hash, need_shards
+		);
+		let block = manager
+			.rpc_get_raw_block(hash, PRIO_BACKGROUND | PRIO_SECONDARY, None)
+			.await?;
+		let shards = ec.encode(&block)?;
+		for (index, node) in need_shards {
+			manager
+				.metrics
+				.resync_send_counter
+				.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
+			manager
+				.rpc_put_shard(
+					hash,
+					index,
+					shards[index].clone(),
+					node,
+					PRIO_BACKGROUND | PRIO_SECONDARY,
+				)
+				.await
+				.err_context("PutShard RPC")?;
+		}
+		Ok(())
+	}
+
+	/// Send a shard that we have locally to the node that should store it,
+	/// if it doesn't already have it
+	async fn offload_shard(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		index: usize,
+		node: Uuid,
+	) -> Result<(), Error> {
+		let needed = manager
+			.system
+			.rpc_helper()
+			.call(
+				&manager.endpoint,
+				node,
+				BlockRpc::NeedBlockQuery(*hash),
+				RequestStrategy::with_priority(PRIO_BACKGROUND),
+			)
+			.await
+			.err_context("NeedBlockQuery RPC")?;
+		match needed {
+			BlockRpc::NeedBlockReply(false) => Ok(()),
+			BlockRpc::NeedBlockReply(true) => {
+				manager
+					.metrics
+					.resync_send_counter
+					.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
+				manager
+					.rpc_send_shard(hash, index, node, PRIO_BACKGROUND | PRIO_SECONDARY)
+					.await
+					.err_context("PutShard RPC")
+			}
+			m => Err(Error::unexpected_rpc_message(m)),
+		}
+	}
+
+	/// Send a block that we have locally to the nodes of `who` that need it,
+	/// before deleting it. Returns the number of nodes the block was sent to,
+	/// and the number of nodes that were asked.
+	async fn offload_block(
+		&self,
+		manager: &BlockManager,
+		hash: &Hash,
+		existing_path: &DataBlockPath,
+		mut who: Vec<Uuid>,
+	) -> Result<(usize, usize), Error> {
+		who.retain(|id| *id != manager.system.id);
+
+		let who_needs_resps = manager
+			.system
+			.rpc_helper()
+			.call_many(
+				&manager.endpoint,
+				&who,
+				BlockRpc::NeedBlockQuery(*hash),
+				RequestStrategy::with_priority(PRIO_BACKGROUND),
+			)
+			.await?;
+
+		let mut need_nodes = vec![];
+		for (node, needed) in who_needs_resps {
+			match needed.err_context("NeedBlockQuery RPC")? {
+				BlockRpc::NeedBlockReply(needed) => {
+					if needed {
+						need_nodes.push(node);
+					}
+				}
+				m => {
+					return Err(Error::unexpected_rpc_message(m));
+				}
+			}
+		}
+
+		if !need_nodes.is_empty() {
+			trace!(
+				"Block {:?} needed by {} nodes, sending",
+				hash,
+				need_nodes.len()
+			);
+
+			for node in need_nodes.iter() {
+				manager
+					.metrics
+					.resync_send_counter
+					.add(1, &[KeyValue::new("to", format!("{:?}", node))]);
+			}
+
+			let block = manager.read_block_from(hash, existing_path).await?;
+			let (header, bytes) = block.into_parts();
+			let put_block_message = Req::new(BlockRpc::PutBlock {
+				hash: *hash,
+				header,
+			})?
+			.with_stream_from_buffer(bytes);
+			manager
+				.system
+				.rpc_helper()
+				.try_call_many(
+					&manager.endpoint,
+					&need_nodes,
+					put_block_message,
+					RequestStrategy::with_priority(PRIO_BACKGROUND | PRIO_SECONDARY)
+						.with_quorum(need_nodes.len()),
+				)
+				.await
+				.err_context("PutBlock RPC")?;
+		}
+
+		Ok((need_nodes.len(), who.len()))
+	}
 }
 
 impl Drop for BusyBlock {
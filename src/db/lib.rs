@@ -3,6 +3,10 @@ extern crate tracing;
 
 #[cfg(feature = "lmdb")]
 pub mod lmdb_adapter;
+pub mod memory_adapter;
+pub mod migrate;
+#[cfg(feature = "redb")]
+pub mod redb_adapter;
 #[cfg(feature = "sqlite")]
 pub mod sqlite_adapter;
 
@@ -0,0 +1,493 @@
+use core::ops::Bound;
+
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, VecDeque};
+use std::iter::Peekable;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{
+	open_db, Db, Engine, Error, IDb, ITx, ITxFn, OnCommit, OpenOpt, Result, TxError, TxFnResult,
+	TxOpError, TxOpResult, TxResult, TxValueIter, Value, ValueIter,
+};
+
+/// Number of items read at once by iterators outside of transactions
+const ITER_BATCH_SIZE: usize = 64;
+
+// -- db
+
+#[derive(Clone)]
+struct MemTree {
+	name: String,
+	data: BTreeMap<Value, Value>,
+}
+
+pub struct MemDb {
+	trees: RwLock<Vec<MemTree>>,
+	// All operations that might write on the DB must take this lock first.
+	// This ensures that a transaction sees a consistent state of
+	// the database until it commits.
+	write_lock: Mutex<()>,
+}
+
+impl MemDb {
+	pub fn init() -> Db {
+		let s = Self {
+			trees: RwLock::new(vec![]),
+			write_lock: Mutex::new(()),
+		};
+		Db(Arc::new(s))
+	}
+
+	fn write_op<F>(&self, tree: usize, op: F) -> Result<()>
+	where
+		F: FnOnce(&mut BTreeMap<Value, Value>),
+	{
+		let _lock = self.write_lock.lock().unwrap();
+		let mut trees = self.trees.write().unwrap();
+		let tree = trees
+			.get_mut(tree)
+			.ok_or_else(|| Error("invalid tree id".into()))?;
+		op(&mut tree.data);
+		Ok(())
+	}
+
+	fn read_range(
+		&self,
+		tree: usize,
+		low: Bound<&[u8]>,
+		high: Bound<&[u8]>,
+		rev: bool,
+	) -> Result<ValueIter<'_>> {
+		let mut iter = MemIter {
+			db: self,
+			tree,
+			low: low.map(|x| x.to_vec()),
+			high: high.map(|x| x.to_vec()),
+			rev,
+			buf: VecDeque::new(),
+			done: false,
+		};
+		// Check tree id immediately
+		iter.fill_buf()?;
+		Ok(Box::new(iter))
+	}
+}
+
+impl IDb for MemDb {
+	fn engine(&self) -> String {
+		"in-memory".into()
+	}
+
+	fn open_tree(&self, name: &str) -> Result<usize> {
+		let mut trees = self.trees.write().unwrap();
+		if let Some(i) = trees.iter().position(|x| x.name == name) {
+			Ok(i)
+		} else {
+			let i = trees.len();
+			trees.push(MemTree {
+				name: name.to_string(),
+				data: BTreeMap::new(),
+			});
+			Ok(i)
+		}
+	}
+
+	fn list_trees(&self) -> Result<Vec<String>> {
+		let trees = self.trees.read().unwrap();
+		Ok(trees.iter().map(|t| t.name.clone()).collect())
+	}
+
+	fn snapshot(&self, to: &PathBuf) -> Result<()> {
+		// Snapshots are written in the format of one of the
+		// on-disk engines enabled in this build
+		let targets: &[(Engine, Option<&str>)] = &[
+			#[cfg(feature = "sqlite")]
+			(Engine::Sqlite, Some("db.sqlite")),
+			#[cfg(feature = "lmdb")]
+			(Engine::Lmdb, None),
+			#[cfg(feature = "redb")]
+			(Engine::Redb, Some("db.redb")),
+		];
+		let (engine, file) = targets.first().ok_or_else(|| {
+			Error("No on-disk database engine available in this build to write snapshot".into())
+		})?;
+
+		let trees = self.trees.read().unwrap().clone();
+
+		std::fs::create_dir_all(to)?;
+		let mut path = to.clone();
+		if let Some(file) = file {
+			path.push(file);
+		}
+		let opt = OpenOpt {
+			fsync: true,
+			..Default::default()
+		};
+		let db = open_db(&path, *engine, &opt)?;
+		for tree in trees {
+			let dst = db.open_tree(&tree.name)?;
+			db.transaction::<_, (), _>(|tx| {
+				for (k, v) in tree.data.iter() {
+					tx.insert(&dst, k, v)?;
+				}
+				Ok(())
+			})
+			.map_err(|e| match e {
+				TxError::Db(e) => e,
+				TxError::Abort(()) => Error("snapshot transaction aborted".into()),
+			})?;
+		}
+		Ok(())
+	}
+
+	// ----
+
+	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let trees = self.trees.read().unwrap();
+		let tree = get_tree(&trees, tree)?;
+		Ok(tree.get(key).cloned())
+	}
+
+	fn len(&self, tree: usize) -> Result<usize> {
+		let trees = self.trees.read().unwrap();
+		let tree = get_tree(&trees, tree)?;
+		Ok(tree.len())
+	}
+
+	fn insert(&self, tree: usize, key: &[u8], value: &[u8]) -> Result<()> {
+		self.write_op(tree, |tree| {
+			tree.insert(key.to_vec(), value.to_vec());
+		})
+	}
+
+	fn remove(&self, tree: usize, key: &[u8]) -> Result<()> {
+		self.write_op(tree, |tree| {
+			tree.remove(key);
+		})
+	}
+
+	fn clear(&self, tree: usize) -> Result<()> {
+		self.write_op(tree, |tree| tree.clear())
+	}
+
+	fn iter(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_range(tree, Bound::Unbounded, Bound::Unbounded, false)
+	}
+
+	fn iter_rev(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_range(tree, Bound::Unbounded, Bound::Unbounded, true)
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_range(tree, low, high, false)
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_range(tree, low, high, true)
+	}
+
+	// ----
+
+	fn transaction(&self, f: &dyn ITxFn) -> TxResult<OnCommit, ()> {
+		let _lock = self.write_lock.lock().unwrap();
+
+		let trees = self.trees.read().unwrap();
+		let mut tx = MemTx {
+			trees: &trees[..],
+			changes: trees.iter().map(|_| Changes::default()).collect(),
+		};
+		let res = f.try_on(&mut tx);
+		let changes = tx.changes;
+		drop(trees);
+
+		match res {
+			TxFnResult::Ok(on_commit) => {
+				let mut trees = self.trees.write().unwrap();
+				for (tree, changes) in trees.iter_mut().zip(changes) {
+					if changes.cleared {
+						tree.data.clear();
+					}
+					for (k, v) in changes.items {
+						match v {
+							Some(v) => tree.data.insert(k, v),
+							None => tree.data.remove(&k),
+						};
+					}
+				}
+				Ok(on_commit)
+			}
+			TxFnResult::Abort => Err(TxError::Abort(())),
+			TxFnResult::DbErr => Err(TxError::Db(Error(
+				"(this message will be discarded)".into(),
+			))),
+		}
+	}
+}
+
+// ----
+
+/// Writes of a transaction on a tree, which are applied when it commits
+#[derive(Default)]
+struct Changes {
+	cleared: bool,
+	items: BTreeMap<Value, Option<Value>>,
+}
+
+struct MemTx<'a> {
+	trees: &'a [MemTree],
+	changes: Vec<Changes>,
+}
+
+impl<'a> MemTx<'a> {
+	fn get_tree(&self, i: usize) -> TxOpResult<(&BTreeMap<Value, Value>, &Changes)> {
+		match (self.trees.get(i), self.changes.get(i)) {
+			(Some(tree), Some(changes)) => Ok((&tree.data, changes)),
+			_ => Err(TxOpError(Error(
+				"invalid tree id (it might have been opened after the transaction started)".into(),
+			))),
+		}
+	}
+
+	fn get_changes_mut(&mut self, i: usize) -> TxOpResult<&mut Changes> {
+		self.get_tree(i)?;
+		Ok(&mut self.changes[i])
+	}
+
+	fn tx_range(
+		&self,
+		tree: usize,
+		low: Bound<&[u8]>,
+		high: Bound<&[u8]>,
+		rev: bool,
+	) -> TxOpResult<TxValueIter<'_>> {
+		let (data, changes) = self.get_tree(tree)?;
+		if range_is_empty(low, high) {
+			return Ok(Box::new(std::iter::empty()));
+		}
+
+		let base: Box<dyn Iterator<Item = (&Value, &Value)>> = if changes.cleared {
+			Box::new(std::iter::empty())
+		} else if rev {
+			Box::new(data.range::<[u8], _>((low, high)).rev())
+		} else {
+			Box::new(data.range::<[u8], _>((low, high)))
+		};
+		let changes: Box<dyn Iterator<Item = (&Value, &Option<Value>)>> = if rev {
+			Box::new(changes.items.range::<[u8], _>((low, high)).rev())
+		} else {
+			Box::new(changes.items.range::<[u8], _>((low, high)))
+		};
+
+		Ok(Box::new(MergeIter {
+			base: base.peekable(),
+			changes: changes.peekable(),
+			rev,
+		}))
+	}
+}
+
+impl<'a> ITx for MemTx<'a> {
+	fn get(&self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
+		let (data, changes) = self.get_tree(tree)?;
+		match changes.items.get(key) {
+			Some(v) => Ok(v.clone()),
+			None if changes.cleared => Ok(None),
+			None => Ok(data.get(key).cloned()),
+		}
+	}
+	fn len(&self, tree: usize) -> TxOpResult<usize> {
+		let (data, changes) = self.get_tree(tree)?;
+		let mut len = if changes.cleared { 0 } else { data.len() };
+		for (k, v) in changes.items.iter() {
+			let in_base = !changes.cleared && data.contains_key(k);
+			match (v, in_base) {
+				(Some(_), false) => len += 1,
+				(None, true) => len -= 1,
+				_ => (),
+			}
+		}
+		Ok(len)
+	}
+
+	fn insert(&mut self, tree: usize, key: &[u8], value: &[u8]) -> TxOpResult<()> {
+		let changes = self.get_changes_mut(tree)?;
+		changes.items.insert(key.to_vec(), Some(value.to_vec()));
+		Ok(())
+	}
+	fn remove(&mut self, tree: usize, key: &[u8]) -> TxOpResult<()> {
+		let changes = self.get_changes_mut(tree)?;
+		changes.items.insert(key.to_vec(), None);
+		Ok(())
+	}
+	fn clear(&mut self, tree: usize) -> TxOpResult<()> {
+		let changes = self.get_changes_mut(tree)?;
+		changes.cleared = true;
+		changes.items.clear();
+		Ok(())
+	}
+
+	fn iter(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		self.tx_range(tree, Bound::Unbounded, Bound::Unbounded, false)
+	}
+	fn iter_rev(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		self.tx_range(tree, Bound::Unbounded, Bound::Unbounded, true)
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		self.tx_range(tree, low, high, false)
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		self.tx_range(tree, low, high, true)
+	}
+}
+
+// ---- iterators outside transactions ----
+// they do not keep the database locked between calls to next(),
+// instead they read a batch of items and then seek again after
+// the last item that was returned
+
+struct MemIter<'a> {
+	db: &'a MemDb,
+	tree: usize,
+	low: Bound<Value>,
+	high: Bound<Value>,
+	rev: bool,
+	buf: VecDeque<(Value, Value)>,
+	done: bool,
+}
+
+impl<'a> MemIter<'a> {
+	fn fill_buf(&mut self) -> Result<()> {
+		let trees = self.db.trees.read().unwrap();
+		let tree = get_tree(&trees, self.tree)?;
+
+		let low = self.low.as_ref().map(Vec::as_slice);
+		let high = self.high.as_ref().map(Vec::as_slice);
+		if range_is_empty(low, high) {
+			self.done = true;
+			return Ok(());
+		}
+
+		let range = tree.range::<[u8], _>((low, high));
+		let items = if self.rev {
+			range
+				.rev()
+				.take(ITER_BATCH_SIZE)
+				.map(|(k, v)| (k.clone(), v.clone()))
+				.collect::<Vec<_>>()
+		} else {
+			range
+				.take(ITER_BATCH_SIZE)
+				.map(|(k, v)| (k.clone(), v.clone()))
+				.collect::<Vec<_>>()
+		};
+		drop(trees);
+
+		match items.last() {
+			None => self.done = true,
+			Some((k, _)) if self.rev => self.high = Bound::Excluded(k.clone()),
+			Some((k, _)) => self.low = Bound::Excluded(k.clone()),
+		}
+		self.buf.extend(items);
+		Ok(())
+	}
+}
+
+impl<'a> Iterator for MemIter<'a> {
+	type Item = Result<(Value, Value)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.buf.is_empty() && !self.done {
+			if let Err(e) = self.fill_buf() {
+				self.done = true;
+				return Some(Err(e));
+			}
+		}
+		self.buf.pop_front().map(Ok)
+	}
+}
+
+// ---- iterators within transactions ----
+// they merge the content of the tree with the changes
+// written by the transaction
+
+struct MergeIter<'a, I, J>
+where
+	I: Iterator<Item = (&'a Value, &'a Value)>,
+	J: Iterator<Item = (&'a Value, &'a Option<Value>)>,
+{
+	base: Peekable<I>,
+	changes: Peekable<J>,
+	rev: bool,
+}
+
+impl<'a, I, J> Iterator for MergeIter<'a, I, J>
+where
+	I: Iterator<Item = (&'a Value, &'a Value)>,
+	J: Iterator<Item = (&'a Value, &'a Option<Value>)>,
+{
+	type Item = TxOpResult<(Value, Value)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			let order = match (self.base.peek(), self.changes.peek()) {
+				(None, None) => return None,
+				(Some(_), None) => Ordering::Less,
+				(None, Some(_)) => Ordering::Greater,
+				(Some((kb, _)), Some((kc, _))) if self.rev => kc.cmp(kb),
+				(Some((kb, _)), Some((kc, _))) => kb.cmp(kc),
+			};
+			if order == Ordering::Less {
+				let (k, v) = self.base.next().unwrap();
+				return Some(Ok((k.clone(), v.clone())));
+			}
+			if order == Ordering::Equal {
+				// value was overwritten or removed in the transaction
+				self.base.next();
+			}
+			if let (k, Some(v)) = self.changes.next().unwrap() {
+				return Some(Ok((k.clone(), v.clone())));
+			}
+		}
+	}
+}
+
+// ---- utility ----
+
+fn get_tree(trees: &[MemTree], i: usize) -> Result<&BTreeMap<Value, Value>> {
+	trees
+		.get(i)
+		.map(|t| &t.data)
+		.ok_or_else(|| Error("invalid tree id".into()))
+}
+
+/// BTreeMap::range panics on such bounds, so we check for them beforehand
+fn range_is_empty(low: Bound<&[u8]>, high: Bound<&[u8]>) -> bool {
+	match (low, high) {
+		(Bound::Included(l), Bound::Included(h)) => l > h,
+		(Bound::Included(l), Bound::Excluded(h))
+		| (Bound::Excluded(l), Bound::Included(h))
+		| (Bound::Excluded(l), Bound::Excluded(h)) => l >= h,
+		_ => false,
+	}
+}
@@ -0,0 +1,564 @@
+use core::ops::Bound;
+
+use std::cell::{Cell, RefCell};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+
+use crate::{
+	Db, Error, IDb, ITx, ITxFn, OnCommit, Result, TxError, TxFnResult, TxOpResult, TxResult,
+	TxValueIter, Value, ValueIter,
+};
+
+/// Number of items copied to the new database at once
+pub const MIGRATION_BATCH_SIZE: usize = 1000;
+
+/// Wrapper around a database, that allows its content to be migrated to
+/// another database engine while it is being used.
+///
+/// During a migration, all writes are done on both databases, while the
+/// content of the old database is copied to the new one in the background.
+/// When the copy is complete, the new database replaces the old one.
+pub struct MigratableDb {
+	// All operations that might write on the DB must take this lock first,
+	// including the copy of items to the new database during a migration
+	write_lock: Mutex<()>,
+	state: RwLock<State>,
+}
+
+struct State {
+	active: Db,
+	// name of each tree and its id in the active database
+	trees: Vec<(String, usize)>,
+	migration: Option<Migration>,
+}
+
+struct Migration {
+	new: Db,
+	// id of each tree in the new database
+	new_trees: Vec<usize>,
+	progress: Mutex<MigrationProgress>,
+}
+
+/// Progress of an ongoing migration
+#[derive(Clone, Debug, Default)]
+pub struct MigrationProgress {
+	pub from_engine: String,
+	pub to_engine: String,
+	/// Number of trees that have been fully copied
+	pub trees_done: usize,
+	pub trees_total: usize,
+	pub items_copied: u64,
+	/// Set if a write to the new database failed, in which case the
+	/// migration cannot continue
+	pub error: Option<String>,
+	last_key: Option<Value>,
+}
+
+/// Result of a migration step
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MigrationStep {
+	/// Some items were copied, more steps are needed
+	Continue,
+	/// All items were copied and the new database is now active
+	Finished,
+}
+
+impl MigratableDb {
+	pub fn new(db: Db) -> Arc<Self> {
+		Arc::new(Self {
+			write_lock: Mutex::new(()),
+			state: RwLock::new(State {
+				active: db,
+				trees: vec![],
+				migration: None,
+			}),
+		})
+	}
+
+	/// Get a database handle that goes through this wrapper
+	pub fn db(self: &Arc<Self>) -> Db {
+		Db(self.clone())
+	}
+
+	/// Start copying the content of the active database to `new`,
+	/// which must be empty. All writes are done on both databases
+	/// from now on.
+	pub fn start_migration(&self, new: Db) -> Result<()> {
+		let _lock = self.write_lock.lock().unwrap();
+		let mut state = self.state.write().unwrap();
+
+		if state.migration.is_some() {
+			return Err(Error("a migration is already in progress".into()));
+		}
+		let existing_trees = new.list_trees()?;
+		if !existing_trees.is_empty() {
+			return Err(Error(
+				format!(
+					"destination database already contains data: {:?}",
+					existing_trees
+				)
+				.into(),
+			));
+		}
+
+		// Make sure all trees of the active database are copied,
+		// even those that have not been opened yet
+		for name in state.active.list_trees()? {
+			if !state.trees.iter().any(|(n, _)| *n == name) {
+				let id = state.active.0.open_tree(&name)?;
+				state.trees.push((name, id));
+			}
+		}
+
+		let new_trees = state
+			.trees
+			.iter()
+			.map(|(name, _)| new.0.open_tree(name))
+			.collect::<Result<Vec<_>>>()?;
+
+		let progress = MigrationProgress {
+			from_engine: state.active.engine(),
+			to_engine: new.engine(),
+			trees_total: state.trees.len(),
+			..Default::default()
+		};
+		state.migration = Some(Migration {
+			new,
+			new_trees,
+			progress: Mutex::new(progress),
+		});
+		Ok(())
+	}
+
+	/// Copy a batch of items to the new database, and switch to the new
+	/// database once everything has been copied. `before_switch` is called
+	/// right before switching, while no write can happen, so that the caller
+	/// can durably record that the new database is in use. If it fails, the
+	/// old database is kept.
+	pub fn migration_step<F>(&self, before_switch: F) -> Result<MigrationStep>
+	where
+		F: FnOnce() -> Result<()>,
+	{
+		let _lock = self.write_lock.lock().unwrap();
+		let state = self.state.read().unwrap();
+		let migration = state
+			.migration
+			.as_ref()
+			.ok_or_else(|| Error("no migration in progress".into()))?;
+		let mut progress = migration.progress.lock().unwrap();
+
+		if let Some(e) = &progress.error {
+			return Err(Error(e.clone().into()));
+		}
+
+		if progress.trees_done < state.trees.len() {
+			let tree = progress.trees_done;
+			let low = match &progress.last_key {
+				Some(k) => Bound::Excluded(k.as_slice()),
+				None => Bound::Unbounded,
+			};
+			let items = state
+				.active
+				.0
+				.range(state.trees[tree].1, low, Bound::Unbounded)?
+				.take(MIGRATION_BATCH_SIZE)
+				.collect::<Result<Vec<_>>>()?;
+
+			let ops = items
+				.iter()
+				.map(|(k, v)| TxOp::Insert(tree, k.clone(), v.clone()))
+				.collect::<Vec<_>>();
+			if let Err(e) = replay(migration, &ops) {
+				progress.error = Some(e.to_string());
+				return Err(e);
+			}
+
+			progress.items_copied += items.len() as u64;
+			if items.len() < MIGRATION_BATCH_SIZE {
+				progress.trees_done += 1;
+				progress.last_key = None;
+			} else {
+				progress.last_key = items.last().map(|(k, _)| k.clone());
+			}
+			// Trees might have been opened since the start of the migration
+			progress.trees_total = state.trees.len();
+			return Ok(MigrationStep::Continue);
+		}
+
+		drop(progress);
+		drop(state);
+
+		// Switch to the new database. The write lock is still held,
+		// so no write can happen in between.
+		before_switch()?;
+		let mut state = self.state.write().unwrap();
+		let migration = state.migration.take().unwrap();
+		for (tree, new_id) in state.trees.iter_mut().zip(migration.new_trees) {
+			tree.1 = new_id;
+		}
+		state.active = migration.new;
+		Ok(MigrationStep::Finished)
+	}
+
+	/// Stop an ongoing migration, the new database is no longer written to
+	pub fn abort_migration(&self) {
+		let _lock = self.write_lock.lock().unwrap();
+		self.state.write().unwrap().migration = None;
+	}
+
+	/// Progress of the ongoing migration, if there is one
+	pub fn migration_progress(&self) -> Option<MigrationProgress> {
+		let state = self.state.read().unwrap();
+		let migration = state.migration.as_ref()?;
+		let progress = migration.progress.lock().unwrap().clone();
+		Some(progress)
+	}
+
+	fn write_op<F>(&self, tree: usize, op: F) -> Result<()>
+	where
+		F: Fn(&dyn IDb, usize) -> Result<()>,
+	{
+		let _lock = self.write_lock.lock().unwrap();
+		let state = self.state.read().unwrap();
+		op(&*state.active.0, state.get_tree(tree)?)?;
+		if let Some(migration) = &state.migration {
+			migration.dual_write(|| op(&*migration.new.0, migration.new_trees[tree]));
+		}
+		Ok(())
+	}
+
+	fn read_op<F>(&self, tree: usize, op: F) -> Result<ValueIter<'_>>
+	where
+		F: for<'a> FnOnce(&'a dyn IDb, usize) -> Result<ValueIter<'a>>,
+	{
+		let (db, tree) = {
+			let state = self.state.read().unwrap();
+			(state.active.0.clone(), state.get_tree(tree)?)
+		};
+		let iter = op(&*db, tree)?;
+		// This unsafe allows us to bypass lifetime checks: the iterator
+		// borrows the database object, which is kept alive as long as
+		// the iterator and dropped after it
+		let iter = unsafe { std::mem::transmute::<ValueIter<'_>, ValueIter<'static>>(iter) };
+		Ok(Box::new(DbAndIterator {
+			iter: Some(iter),
+			_db: db,
+		}))
+	}
+}
+
+impl State {
+	fn get_tree(&self, i: usize) -> Result<usize> {
+		self.trees
+			.get(i)
+			.map(|(_, id)| *id)
+			.ok_or_else(|| Error("invalid tree id".into()))
+	}
+}
+
+impl Migration {
+	fn dual_write<F>(&self, op: F)
+	where
+		F: FnOnce() -> Result<()>,
+	{
+		let mut progress = self.progress.lock().unwrap();
+		if progress.error.is_some() {
+			return;
+		}
+		if let Err(e) = op() {
+			error!(
+				"Write to new database failed, migration cannot continue: {}",
+				e
+			);
+			progress.error = Some(e.to_string());
+		}
+	}
+}
+
+impl IDb for MigratableDb {
+	fn engine(&self) -> String {
+		let state = self.state.read().unwrap();
+		match &state.migration {
+			Some(m) => format!(
+				"{} (migrating to {})",
+				state.active.engine(),
+				m.new.engine()
+			),
+			None => state.active.engine(),
+		}
+	}
+
+	fn open_tree(&self, name: &str) -> Result<usize> {
+		let mut state = self.state.write().unwrap();
+		if let Some(i) = state.trees.iter().position(|(n, _)| n == name) {
+			return Ok(i);
+		}
+
+		let id = state.active.0.open_tree(name)?;
+		if let Some(migration) = &mut state.migration {
+			let new_id = migration.new.0.open_tree(name)?;
+			migration.new_trees.push(new_id);
+		}
+		let i = state.trees.len();
+		state.trees.push((name.to_string(), id));
+		Ok(i)
+	}
+
+	fn list_trees(&self) -> Result<Vec<String>> {
+		self.state.read().unwrap().active.list_trees()
+	}
+
+	fn snapshot(&self, to: &PathBuf) -> Result<()> {
+		let active = self.state.read().unwrap().active.clone();
+		active.snapshot(to)
+	}
+
+	// ----
+
+	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let state = self.state.read().unwrap();
+		state.active.0.get(state.get_tree(tree)?, key)
+	}
+
+	fn len(&self, tree: usize) -> Result<usize> {
+		let state = self.state.read().unwrap();
+		state.active.0.len(state.get_tree(tree)?)
+	}
+
+	fn insert(&self, tree: usize, key: &[u8], value: &[u8]) -> Result<()> {
+		self.write_op(tree, |db, tree| db.insert(tree, key, value))
+	}
+
+	fn remove(&self, tree: usize, key: &[u8]) -> Result<()> {
+		self.write_op(tree, |db, tree| db.remove(tree, key))
+	}
+
+	fn clear(&self, tree: usize) -> Result<()> {
+		self.write_op(tree, |db, tree| db.clear(tree))
+	}
+
+	fn iter(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_op(tree, |db, tree| db.iter(tree))
+	}
+
+	fn iter_rev(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_op(tree, |db, tree| db.iter_rev(tree))
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_op(tree, |db, tree| db.range(tree, low, high))
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_op(tree, |db, tree| db.range_rev(tree, low, high))
+	}
+
+	// ----
+
+	fn transaction(&self, f: &dyn ITxFn) -> TxResult<OnCommit, ()> {
+		// Outside of a migration, transactions don't need to be serialized:
+		// a migration cannot start while we hold a read lock on the state
+		{
+			let state = self.state.read().unwrap();
+			if state.migration.is_none() {
+				let tx_fn = MigratableTxFn {
+					f,
+					trees: &state.trees[..],
+					log_writes: false,
+					log: RefCell::new(vec![]),
+				};
+				return state.active.0.transaction(&tx_fn);
+			}
+		}
+
+		let _lock = self.write_lock.lock().unwrap();
+		let state = self.state.read().unwrap();
+
+		let tx_fn = MigratableTxFn {
+			f,
+			trees: &state.trees[..],
+			log_writes: state.migration.is_some(),
+			log: RefCell::new(vec![]),
+		};
+		let on_commit = state.active.0.transaction(&tx_fn)?;
+
+		if let Some(migration) = &state.migration {
+			let ops = tx_fn.log.into_inner();
+			if !ops.is_empty() {
+				migration.dual_write(|| replay(migration, &ops));
+			}
+		}
+		Ok(on_commit)
+	}
+}
+
+// ---- transactions ----
+
+/// A write done in a transaction, that has to be replayed
+/// on the new database
+enum TxOp {
+	Insert(usize, Value, Value),
+	Remove(usize, Value),
+	Clear(usize),
+}
+
+struct MigratableTxFn<'a> {
+	f: &'a dyn ITxFn,
+	trees: &'a [(String, usize)],
+	log_writes: bool,
+	log: RefCell<Vec<TxOp>>,
+}
+
+impl<'a> ITxFn for MigratableTxFn<'a> {
+	fn try_on(&self, tx: &mut dyn ITx) -> TxFnResult {
+		let mut tx = MigratableTx {
+			tx,
+			trees: self.trees,
+			log: if self.log_writes { Some(vec![]) } else { None },
+		};
+		let res = self.f.try_on(&mut tx);
+		self.log.replace(tx.log.unwrap_or_default());
+		res
+	}
+}
+
+struct MigratableTx<'a> {
+	tx: &'a mut dyn ITx,
+	trees: &'a [(String, usize)],
+	log: Option<Vec<TxOp>>,
+}
+
+impl<'a> MigratableTx<'a> {
+	fn get_tree(&self, i: usize) -> TxOpResult<usize> {
+		self.trees.get(i).map(|(_, id)| *id).ok_or_else(|| {
+			crate::TxOpError(Error(
+				"invalid tree id (it might have been opened after the transaction started)".into(),
+			))
+		})
+	}
+
+	fn log(&mut self, op: TxOp) {
+		if let Some(log) = &mut self.log {
+			log.push(op);
+		}
+	}
+}
+
+impl<'a> ITx for MigratableTx<'a> {
+	fn get(&self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
+		self.tx.get(self.get_tree(tree)?, key)
+	}
+	fn len(&self, tree: usize) -> TxOpResult<usize> {
+		self.tx.len(self.get_tree(tree)?)
+	}
+
+	fn insert(&mut self, tree: usize, key: &[u8], value: &[u8]) -> TxOpResult<()> {
+		self.tx.insert(self.get_tree(tree)?, key, value)?;
+		self.log(TxOp::Insert(tree, key.to_vec(), value.to_vec()));
+		Ok(())
+	}
+	fn remove(&mut self, tree: usize, key: &[u8]) -> TxOpResult<()> {
+		self.tx.remove(self.get_tree(tree)?, key)?;
+		self.log(TxOp::Remove(tree, key.to_vec()));
+		Ok(())
+	}
+	fn clear(&mut self, tree: usize) -> TxOpResult<()> {
+		self.tx.clear(self.get_tree(tree)?)?;
+		self.log(TxOp::Clear(tree));
+		Ok(())
+	}
+
+	fn iter(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		self.tx.iter(self.get_tree(tree)?)
+	}
+	fn iter_rev(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		self.tx.iter_rev(self.get_tree(tree)?)
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		self.tx.range(self.get_tree(tree)?, low, high)
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		self.tx.range_rev(self.get_tree(tree)?, low, high)
+	}
+}
+
+/// Apply writes to the new database of a migration, in a single transaction
+fn replay(migration: &Migration, ops: &[TxOp]) -> Result<()> {
+	let replay_fn = ReplayTxFn {
+		ops,
+		new_trees: &migration.new_trees[..],
+		error: Cell::new(None),
+	};
+	match migration.new.0.transaction(&replay_fn) {
+		Ok(_) => Ok(()),
+		Err(TxError::Db(e)) => Err(replay_fn.error.take().unwrap_or(e)),
+		Err(TxError::Abort(())) => Err(Error("transaction aborted".into())),
+	}
+}
+
+struct ReplayTxFn<'a> {
+	ops: &'a [TxOp],
+	new_trees: &'a [usize],
+	error: Cell<Option<Error>>,
+}
+
+impl<'a> ITxFn for ReplayTxFn<'a> {
+	fn try_on(&self, tx: &mut dyn ITx) -> TxFnResult {
+		for op in self.ops.iter() {
+			let res = match op {
+				TxOp::Insert(tree, k, v) => tx.insert(self.new_trees[*tree], k, v),
+				TxOp::Remove(tree, k) => tx.remove(self.new_trees[*tree], k),
+				TxOp::Clear(tree) => tx.clear(self.new_trees[*tree]),
+			};
+			if let Err(e) = res {
+				self.error.set(Some(e.0));
+				return TxFnResult::DbErr;
+			}
+		}
+		TxFnResult::Ok(vec![])
+	}
+}
+
+// ---- iterators outside transactions ----
+// they keep a reference to the database they iterate on, so that
+// they stay valid if the active database changes
+
+struct DbAndIterator {
+	iter: Option<ValueIter<'static>>,
+	_db: Arc<dyn IDb>,
+}
+
+impl Drop for DbAndIterator {
+	fn drop(&mut self) {
+		// ensure the iterator is dropped before the database it references
+		drop(self.iter.take());
+	}
+}
+
+impl Iterator for DbAndIterator {
+	type Item = Result<(Value, Value)>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		self.iter.as_mut()?.next()
+	}
+}
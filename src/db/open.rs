@@ -11,6 +11,8 @@ use crate::{Db, Error, Result};
 pub enum Engine {
 	Lmdb,
 	Sqlite,
+	Redb,
+	Memory,
 }
 
 impl Engine {
@@ -19,6 +21,8 @@ impl Engine {
 		match self {
 			Self::Lmdb => "lmdb",
 			Self::Sqlite => "sqlite",
+			Self::Redb => "redb",
+			Self::Memory => "memory",
 		}
 	}
 }
@@ -36,10 +40,12 @@ impl std::str::FromStr for Engine {
 		match text {
 			"lmdb" | "heed" => Ok(Self::Lmdb),
 			"sqlite" | "sqlite3" | "rusqlite" => Ok(Self::Sqlite),
+			"redb" => Ok(Self::Redb),
+			"memory" => Ok(Self::Memory),
 			"sled" => Err(Error("Sled is no longer supported as a database engine. Converting your old metadata db can be done using an older Garage binary (e.g. v0.9.4).".into())),
 			kind => Err(Error(
 				format!(
-					"Invalid DB engine: {} (options are: lmdb, sqlite)",
+					"Invalid DB engine: {} (options are: lmdb, sqlite, redb, memory)",
 					kind
 				)
 				.into(),
@@ -114,6 +120,22 @@ pub fn open_db(path: &PathBuf, engine: Engine, opt: &OpenOpt) -> Result<Db> {
 			}
 		}
 
+		// ---- redb DB ----
+		#[cfg(feature = "redb")]
+		Engine::Redb => {
+			info!("Opening redb database at: {}", path.display());
+			match redb::Database::create(path) {
+				Err(e) => Err(Error(format!("Cannot open redb database: {}", e).into())),
+				Ok(db) => Ok(crate::redb_adapter::RedbDb::init(db, opt.fsync)),
+			}
+		}
+
+		// ---- In-memory DB ----
+		Engine::Memory => {
+			warn!("Using in-memory database, its content will be lost when the process exits");
+			Ok(crate::memory_adapter::MemDb::init())
+		}
+
 		// Pattern is unreachable when all supported DB engines are compiled into binary. The allow
 		// attribute is added so that we won't have to change this match in case stop building
 		// support for one or more engines by default.
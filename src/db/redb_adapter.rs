@@ -0,0 +1,405 @@
+use core::ops::Bound;
+
+use std::cell::OnceCell;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use redb::{
+	Database, Durability, ReadableTable, ReadableTableMetadata, Table, TableDefinition,
+	TableHandle, WriteTransaction,
+};
+
+use crate::{
+	Db, Error, IDb, ITx, ITxFn, OnCommit, Result, TxError, TxFnResult, TxOpError, TxOpResult,
+	TxResult, TxValueIter, Value, ValueIter,
+};
+
+pub use redb;
+
+type TableDef<'a> = TableDefinition<'a, &'static [u8], &'static [u8]>;
+type RedbTable<'a> = Table<'a, &'static [u8], &'static [u8]>;
+
+// -- err
+
+impl From<redb::Error> for Error {
+	fn from(e: redb::Error) -> Error {
+		Error(format!("redb: {}", e).into())
+	}
+}
+
+impl From<redb::StorageError> for Error {
+	fn from(e: redb::StorageError) -> Error {
+		redb::Error::from(e).into()
+	}
+}
+
+impl From<redb::TableError> for Error {
+	fn from(e: redb::TableError) -> Error {
+		redb::Error::from(e).into()
+	}
+}
+
+impl From<redb::TransactionError> for Error {
+	fn from(e: redb::TransactionError) -> Error {
+		redb::Error::from(e).into()
+	}
+}
+
+impl From<redb::CommitError> for Error {
+	fn from(e: redb::CommitError) -> Error {
+		redb::Error::from(e).into()
+	}
+}
+
+impl From<redb::DatabaseError> for Error {
+	fn from(e: redb::DatabaseError) -> Error {
+		redb::Error::from(e).into()
+	}
+}
+
+impl From<redb::StorageError> for TxOpError {
+	fn from(e: redb::StorageError) -> TxOpError {
+		TxOpError(e.into())
+	}
+}
+
+impl From<redb::TableError> for TxOpError {
+	fn from(e: redb::TableError) -> TxOpError {
+		TxOpError(e.into())
+	}
+}
+
+// -- db
+
+pub struct RedbDb {
+	db: Database,
+	fsync: bool,
+	trees: RwLock<Vec<Arc<str>>>,
+}
+
+impl RedbDb {
+	pub fn init(db: Database, fsync: bool) -> Db {
+		let s = Self {
+			db,
+			fsync,
+			trees: RwLock::new(vec![]),
+		};
+		Db(Arc::new(s))
+	}
+
+	fn get_tree(&self, i: usize) -> Result<Arc<str>> {
+		self.trees
+			.read()
+			.unwrap()
+			.get(i)
+			.cloned()
+			.ok_or_else(|| Error("invalid tree id".into()))
+	}
+
+	fn write_txn(&self) -> Result<WriteTransaction> {
+		let mut tx = self.db.begin_write()?;
+		tx.set_durability(self.durability());
+		Ok(tx)
+	}
+
+	fn durability(&self) -> Durability {
+		if self.fsync {
+			Durability::Immediate
+		} else {
+			Durability::Eventual
+		}
+	}
+
+	fn write_op<F>(&self, tree: usize, op: F) -> Result<()>
+	where
+		F: FnOnce(&mut RedbTable<'_>) -> Result<()>,
+	{
+		let tree = self.get_tree(tree)?;
+		let tx = self.write_txn()?;
+		{
+			let mut table = tx.open_table(TableDef::new(&tree))?;
+			op(&mut table)?;
+		}
+		tx.commit()?;
+		Ok(())
+	}
+
+	fn read_range(
+		&self,
+		tree: usize,
+		low: Bound<&[u8]>,
+		high: Bound<&[u8]>,
+		rev: bool,
+	) -> Result<ValueIter<'_>> {
+		let tree = self.get_tree(tree)?;
+		let tx = self.db.begin_read()?;
+		let table = tx.open_table(TableDef::new(&tree))?;
+		// The iterator holds a reference to the read transaction,
+		// which stays alive until the iterator is dropped
+		let range = table.range::<&[u8]>((low, high))?;
+		if rev {
+			Ok(Box::new(range.rev().map(iter_item)))
+		} else {
+			Ok(Box::new(range.map(iter_item)))
+		}
+	}
+}
+
+impl IDb for RedbDb {
+	fn engine(&self) -> String {
+		"redb".into()
+	}
+
+	fn open_tree(&self, name: &str) -> Result<usize> {
+		let mut trees = self.trees.write().unwrap();
+		if let Some(i) = trees.iter().position(|x| x.as_ref() == name) {
+			Ok(i)
+		} else {
+			// Create the table so that it appears in list_trees
+			let tx = self.write_txn()?;
+			tx.open_table(TableDef::new(name))?;
+			tx.commit()?;
+
+			let i = trees.len();
+			trees.push(Arc::from(name));
+			Ok(i)
+		}
+	}
+
+	fn list_trees(&self) -> Result<Vec<String>> {
+		let tx = self.db.begin_read()?;
+		let ret = tx
+			.list_tables()?
+			.map(|t| t.name().to_string())
+			.collect::<Vec<_>>();
+		Ok(ret)
+	}
+
+	fn snapshot(&self, to: &PathBuf) -> Result<()> {
+		std::fs::create_dir_all(to)?;
+		let mut path = to.clone();
+		path.push("db.redb");
+
+		// All tables are copied from a single read transaction,
+		// so the snapshot is consistent
+		let src = self.db.begin_read()?;
+		let dst_db = Database::create(&path)?;
+		let dst = dst_db.begin_write()?;
+		for handle in src.list_tables()? {
+			let def = TableDef::new(handle.name());
+			let src_table = src.open_table(def)?;
+			let mut dst_table = dst.open_table(def)?;
+			for item in src_table.iter()? {
+				let (k, v) = item?;
+				dst_table.insert(k.value(), v.value())?;
+			}
+		}
+		dst.commit()?;
+		Ok(())
+	}
+
+	// ----
+
+	fn get(&self, tree: usize, key: &[u8]) -> Result<Option<Value>> {
+		let tree = self.get_tree(tree)?;
+		let tx = self.db.begin_read()?;
+		let table = tx.open_table(TableDef::new(&tree))?;
+		let val = table.get(key)?;
+		Ok(val.map(|v| v.value().to_vec()))
+	}
+
+	fn len(&self, tree: usize) -> Result<usize> {
+		let tree = self.get_tree(tree)?;
+		let tx = self.db.begin_read()?;
+		let table = tx.open_table(TableDef::new(&tree))?;
+		Ok(table.len()? as usize)
+	}
+
+	fn insert(&self, tree: usize, key: &[u8], value: &[u8]) -> Result<()> {
+		self.write_op(tree, |table| {
+			table.insert(key, value)?;
+			Ok(())
+		})
+	}
+
+	fn remove(&self, tree: usize, key: &[u8]) -> Result<()> {
+		self.write_op(tree, |table| {
+			table.remove(key)?;
+			Ok(())
+		})
+	}
+
+	fn clear(&self, tree: usize) -> Result<()> {
+		self.write_op(tree, |table| {
+			table.retain(|_, _| false)?;
+			Ok(())
+		})
+	}
+
+	fn iter(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_range(tree, Bound::Unbounded, Bound::Unbounded, false)
+	}
+
+	fn iter_rev(&self, tree: usize) -> Result<ValueIter<'_>> {
+		self.read_range(tree, Bound::Unbounded, Bound::Unbounded, true)
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_range(tree, low, high, false)
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> Result<ValueIter<'_>> {
+		self.read_range(tree, low, high, true)
+	}
+
+	// ----
+
+	fn transaction(&self, f: &dyn ITxFn) -> TxResult<OnCommit, ()> {
+		let trees = self.trees.read().unwrap();
+		let tx = self.write_txn().map_err(TxError::Db)?;
+
+		let res = {
+			let mut itx = RedbTx {
+				trees: &trees[..],
+				tables: trees.iter().map(|_| OnceCell::new()).collect(),
+				tx: &tx,
+			};
+			f.try_on(&mut itx)
+		};
+
+		match res {
+			TxFnResult::Ok(on_commit) => {
+				tx.commit().map_err(Error::from).map_err(TxError::Db)?;
+				Ok(on_commit)
+			}
+			TxFnResult::Abort => {
+				tx.abort().map_err(Error::from).map_err(TxError::Db)?;
+				Err(TxError::Abort(()))
+			}
+			TxFnResult::DbErr => {
+				tx.abort().map_err(Error::from).map_err(TxError::Db)?;
+				Err(TxError::Db(Error(
+					"(this message will be discarded)".into(),
+				)))
+			}
+		}
+	}
+}
+
+// ----
+
+struct RedbTx<'a> {
+	trees: &'a [Arc<str>],
+	// Tables are opened lazily on first access, and are all
+	// closed before the transaction is committed
+	tables: Vec<OnceCell<RedbTable<'a>>>,
+	tx: &'a WriteTransaction,
+}
+
+impl<'a> RedbTx<'a> {
+	fn get_tree(&self, i: usize) -> TxOpResult<&RedbTable<'a>> {
+		let cell = self.tables.get(i).ok_or_else(|| {
+			TxOpError(Error(
+				"invalid tree id (it might have been opened after the transaction started)".into(),
+			))
+		})?;
+		if cell.get().is_none() {
+			let table = self.tx.open_table(TableDef::new(&self.trees[i]))?;
+			let _ = cell.set(table);
+		}
+		Ok(cell.get().unwrap())
+	}
+
+	fn get_tree_mut(&mut self, i: usize) -> TxOpResult<&mut RedbTable<'a>> {
+		self.get_tree(i)?;
+		Ok(self.tables[i].get_mut().unwrap())
+	}
+}
+
+impl<'a> ITx for RedbTx<'a> {
+	fn get(&self, tree: usize, key: &[u8]) -> TxOpResult<Option<Value>> {
+		let tree = self.get_tree(tree)?;
+		let val = tree.get(key)?;
+		Ok(val.map(|v| v.value().to_vec()))
+	}
+	fn len(&self, tree: usize) -> TxOpResult<usize> {
+		let tree = self.get_tree(tree)?;
+		Ok(tree.len()? as usize)
+	}
+
+	fn insert(&mut self, tree: usize, key: &[u8], value: &[u8]) -> TxOpResult<()> {
+		let tree = self.get_tree_mut(tree)?;
+		tree.insert(key, value)?;
+		Ok(())
+	}
+	fn remove(&mut self, tree: usize, key: &[u8]) -> TxOpResult<()> {
+		let tree = self.get_tree_mut(tree)?;
+		tree.remove(key)?;
+		Ok(())
+	}
+	fn clear(&mut self, tree: usize) -> TxOpResult<()> {
+		let tree = self.get_tree_mut(tree)?;
+		tree.retain(|_, _| false)?;
+		Ok(())
+	}
+
+	fn iter(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		let tree = self.get_tree(tree)?;
+		Ok(Box::new(tree.iter()?.map(tx_iter_item)))
+	}
+	fn iter_rev(&self, tree: usize) -> TxOpResult<TxValueIter<'_>> {
+		let tree = self.get_tree(tree)?;
+		Ok(Box::new(tree.iter()?.rev().map(tx_iter_item)))
+	}
+
+	fn range<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		let tree = self.get_tree(tree)?;
+		Ok(Box::new(
+			tree.range::<&[u8]>((low, high))?.map(tx_iter_item),
+		))
+	}
+	fn range_rev<'r>(
+		&self,
+		tree: usize,
+		low: Bound<&'r [u8]>,
+		high: Bound<&'r [u8]>,
+	) -> TxOpResult<TxValueIter<'_>> {
+		let tree = self.get_tree(tree)?;
+		Ok(Box::new(
+			tree.range::<&[u8]>((low, high))?.rev().map(tx_iter_item),
+		))
+	}
+}
+
+// ---- iterators ----
+
+type IteratorItem<'a> = std::result::Result<
+	(
+		redb::AccessGuard<'a, &'static [u8]>,
+		redb::AccessGuard<'a, &'static [u8]>,
+	),
+	redb::StorageError,
+>;
+
+fn iter_item(item: IteratorItem<'_>) -> Result<(Value, Value)> {
+	let (k, v) = item?;
+	Ok((k.value().to_vec(), v.value().to_vec()))
+}
+
+fn tx_iter_item(item: IteratorItem<'_>) -> TxOpResult<(Value, Value)> {
+	iter_item(item).map_err(TxOpError)
+}
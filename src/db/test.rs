@@ -122,6 +122,55 @@ fn test_suite(db: Db) {
 		Ok(())
 	})
 	.unwrap();
+
+	// ---- test reading writes of a transaction before it commits ----
+
+	let res = db.transaction::<(), _, _>(|tx| {
+		tx.remove(&tree, ka)?;
+		tx.insert(&tree, kint, va)?;
+		assert_eq!(tx.len(&tree)?, 2);
+		assert_eq!(tx.get(&tree, ka)?, None);
+
+		let items = tx.iter(&tree)?.collect::<TxOpResult<Vec<_>>>()?;
+		assert_eq!(
+			items,
+			vec![(kint.to_vec(), va.to_vec()), (kb.to_vec(), vc.to_vec())]
+		);
+		let items = tx.range_rev(&tree, ..kb)?.collect::<TxOpResult<Vec<_>>>()?;
+		assert_eq!(items, vec![(kint.to_vec(), va.to_vec())]);
+
+		tx.clear(&tree)?;
+		assert_eq!(tx.len(&tree)?, 0);
+		assert!(tx.iter(&tree)?.next().is_none());
+
+		Err(TxError::Abort(42))
+	});
+	assert!(matches!(res, Err(TxError::Abort(42))));
+	assert_eq!(tree.len().unwrap(), 2);
+	assert_eq!(tree.get(ka).unwrap().unwrap(), vb);
+	assert_eq!(tree.get(kint).unwrap(), None);
+
+	// ---- test iteration over many items ----
+
+	let tree2 = db.open_tree("tree2").unwrap();
+	for i in 0u32..200 {
+		tree2.insert(i.to_be_bytes(), va).unwrap();
+	}
+	let keys = tree2
+		.iter()
+		.unwrap()
+		.map(|x| x.unwrap().0)
+		.collect::<Vec<_>>();
+	assert_eq!(keys.len(), 200);
+	assert!(keys.windows(2).all(|w| w[0] < w[1]));
+	let keys = tree2
+		.range_rev(..100u32.to_be_bytes())
+		.unwrap()
+		.map(|x| x.unwrap().0)
+		.collect::<Vec<_>>();
+	assert_eq!(keys.len(), 100);
+	assert_eq!(keys[0], 99u32.to_be_bytes());
+	assert_eq!(keys[99], 0u32.to_be_bytes());
 }
 
 #[test]
@@ -148,3 +197,133 @@ fn test_sqlite_db() {
 	let db = SqliteDb::new(manager, false).unwrap();
 	test_suite(db);
 }
+
+#[test]
+#[cfg(feature = "redb")]
+fn test_redb_db() {
+	use crate::redb_adapter::RedbDb;
+
+	let path = mktemp::Temp::new_file().unwrap();
+	let db = redb::Database::create(&path).unwrap();
+	let db = RedbDb::init(db, false);
+	test_suite(db);
+	drop(path);
+}
+
+#[test]
+fn test_memory_db() {
+	use crate::memory_adapter::MemDb;
+
+	let db = MemDb::init();
+	test_suite(db);
+}
+
+#[test]
+#[cfg(feature = "sqlite")]
+fn test_memory_db_snapshot() {
+	use crate::memory_adapter::MemDb;
+
+	let db = MemDb::init();
+	let tree = db.open_tree("tree").unwrap();
+	tree.insert(b"a", b"plop").unwrap();
+	tree.insert(b"b", b"plip").unwrap();
+
+	let path = mktemp::Temp::new_dir().unwrap();
+	db.snapshot(&path.to_path_buf()).unwrap();
+
+	let snap = open_db(&path.join("db.sqlite"), Engine::Sqlite, &OpenOpt::default()).unwrap();
+	assert_eq!(snap.list_trees().unwrap(), vec!["tree".to_string()]);
+	let tree = snap.open_tree("tree").unwrap();
+	assert_eq!(tree.len().unwrap(), 2);
+	assert_eq!(tree.get(b"b").unwrap().unwrap(), b"plip");
+	drop(path);
+}
+
+#[test]
+fn test_migratable_db() {
+	use crate::memory_adapter::MemDb;
+	use crate::migrate::MigratableDb;
+
+	let db = MigratableDb::new(MemDb::init()).db();
+	test_suite(db);
+}
+
+#[test]
+fn test_migratable_db_migration() {
+	use crate::memory_adapter::MemDb;
+	use crate::migrate::{MigratableDb, MigrationStep, MIGRATION_BATCH_SIZE};
+
+	let old = MemDb::init();
+	let new = MemDb::init();
+	let mdb = MigratableDb::new(old.clone());
+	let db = mdb.db();
+
+	let tree = db.open_tree("tree").unwrap();
+	let n = 3 * MIGRATION_BATCH_SIZE as u32 + 10;
+	for i in 0..n {
+		tree.insert(i.to_be_bytes(), b"old").unwrap();
+	}
+	let old_tree = old.open_tree("other").unwrap();
+	old_tree.insert(b"k", b"v").unwrap();
+
+	mdb.start_migration(new.clone()).unwrap();
+	assert!(mdb.start_migration(MemDb::init()).is_err());
+	assert_eq!(
+		mdb.migration_step(|| Ok(())).unwrap(),
+		MigrationStep::Continue
+	);
+
+	// writes during the migration, on items already copied or not
+	tree.insert(0u32.to_be_bytes(), b"new").unwrap();
+	tree.remove(1u32.to_be_bytes()).unwrap();
+	db.transaction::<_, (), _>(|tx| {
+		tx.insert(&tree, (n - 1).to_be_bytes(), b"new")?;
+		tx.remove(&tree, (n - 2).to_be_bytes())?;
+		Ok(())
+	})
+	.unwrap();
+	let res = db.transaction::<(), _, _>(|tx| {
+		tx.insert(&tree, 2u32.to_be_bytes(), b"aborted")?;
+		Err(TxError::Abort(()))
+	});
+	assert!(matches!(res, Err(TxError::Abort(()))));
+	let tree2 = db.open_tree("tree2").unwrap();
+	tree2.insert(b"a", b"b").unwrap();
+
+	// the database is only switched once the switch has been recorded
+	while mdb.migration_step(|| Ok(())).unwrap() == MigrationStep::Continue {
+		if mdb.migration_progress().unwrap().trees_done == 3 {
+			break;
+		}
+	}
+	assert!(mdb
+		.migration_step(|| Err(Error("could not record switch".into())))
+		.is_err());
+	assert!(mdb.migration_progress().is_some());
+	assert_eq!(
+		mdb.migration_step(|| Ok(())).unwrap(),
+		MigrationStep::Finished
+	);
+	assert!(mdb.migration_progress().is_none());
+	assert_eq!(db.engine(), new.engine());
+
+	// reads now come from the new database
+	let new_tree = new.open_tree("tree").unwrap();
+	assert_eq!(new_tree.len().unwrap(), n as usize - 2);
+	assert_eq!(tree.len().unwrap(), n as usize - 2);
+	assert_eq!(tree.get(0u32.to_be_bytes()).unwrap().unwrap(), b"new");
+	assert_eq!(tree.get(1u32.to_be_bytes()).unwrap(), None);
+	assert_eq!(tree.get(2u32.to_be_bytes()).unwrap().unwrap(), b"old");
+	assert_eq!(tree.get((n - 1).to_be_bytes()).unwrap().unwrap(), b"new");
+	assert_eq!(tree.get((n - 2).to_be_bytes()).unwrap(), None);
+	assert_eq!(
+		new.open_tree("other").unwrap().get(b"k").unwrap().unwrap(),
+		b"v"
+	);
+	assert_eq!(tree2.get(b"a").unwrap().unwrap(), b"b");
+
+	// writes no longer go to the old database
+	tree.insert(b"x", b"y").unwrap();
+	assert_eq!(new_tree.get(b"x").unwrap().unwrap(), b"y");
+	assert_eq!(old.open_tree("tree").unwrap().get(b"x").unwrap(), None);
+}
@@ -8,6 +8,7 @@ use garage_table::*;
 
 use garage_model::bucket_alias_table::*;
 use garage_model::bucket_table::*;
+use garage_model::helper::audit::{self, AuditTarget};
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::permission::*;
 
@@ -16,18 +17,27 @@ use crate::cli::*;
 use super::*;
 
 impl AdminRpcHandler {
-	pub(super) async fn handle_bucket_cmd(&self, cmd: &BucketOperation) -> Result<AdminRpc, Error> {
+	pub(super) async fn handle_bucket_cmd(
+		&self,
+		cmd: &BucketOperation,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		match cmd {
 			BucketOperation::List => self.handle_list_buckets().await,
 			BucketOperation::Info(query) => self.handle_bucket_info(query).await,
-			BucketOperation::Create(query) => self.handle_create_bucket(&query.name).await,
-			BucketOperation::Delete(query) => self.handle_delete_bucket(query).await,
-			BucketOperation::Alias(query) => self.handle_alias_bucket(query).await,
-			BucketOperation::Unalias(query) => self.handle_unalias_bucket(query).await,
-			BucketOperation::Allow(query) => self.handle_bucket_allow(query).await,
-			BucketOperation::Deny(query) => self.handle_bucket_deny(query).await,
-			BucketOperation::Website(query) => self.handle_bucket_website(query).await,
-			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query).await,
+			BucketOperation::Usage(query) => self.handle_bucket_usage(query).await,
+			BucketOperation::UsageHistory(query) => self.handle_bucket_usage_history(query).await,
+			BucketOperation::Create(query) => self.handle_create_bucket(query, actor).await,
+			BucketOperation::Delete(query) => self.handle_delete_bucket(query, actor).await,
+			BucketOperation::Alias(query) => self.handle_alias_bucket(query, actor).await,
+			BucketOperation::Unalias(query) => self.handle_unalias_bucket(query, actor).await,
+			BucketOperation::Allow(query) => self.handle_bucket_allow(query, actor).await,
+			BucketOperation::Deny(query) => self.handle_bucket_deny(query, actor).await,
+			BucketOperation::Website(query) => self.handle_bucket_website(query, actor).await,
+			BucketOperation::SetQuotas(query) => self.handle_bucket_set_quotas(query, actor).await,
+			BucketOperation::SearchIndex(query) => {
+				self.handle_bucket_search_index(query, actor).await
+			}
 			BucketOperation::CleanupIncompleteUploads(query) => {
 				self.handle_bucket_cleanup_incomplete_uploads(query).await
 			}
@@ -57,6 +67,17 @@ impl AdminRpcHandler {
 			.admin_get_existing_matching_bucket(&query.name)
 			.await?;
 
+		self.bucket_info_result(bucket_id).await
+	}
+
+	/// Reply to a command that modified a bucket: the message is shown in
+	/// text mode, and the updated bucket info with `--json`
+	async fn bucket_updated_result(&self, bucket_id: Uuid, msg: String) -> Result<AdminRpc, Error> {
+		let info = self.bucket_info_result(bucket_id).await?;
+		Ok(AdminRpc::BucketUpdated(msg, Box::new(info)))
+	}
+
+	async fn bucket_info_result(&self, bucket_id: Uuid) -> Result<AdminRpc, Error> {
 		let bucket = self
 			.garage
 			.bucket_helper()
@@ -124,8 +145,81 @@ impl AdminRpcHandler {
 		})
 	}
 
+	async fn handle_bucket_usage(&self, query: &BucketUsageOpt) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.name)
+			.await?;
+
+		let counters = self
+			.garage
+			.object_counter_table
+			.table
+			.get(&bucket_id, &EmptyKey)
+			.await?
+			.map(|x| x.filtered_values(&self.garage.system.cluster_layout()))
+			.unwrap_or_default();
+
+		let prefixes = if query.by_prefix {
+			Some(
+				self.garage
+					.bucket_helper()
+					.get_prefix_usage(&bucket_id)
+					.await?,
+			)
+		} else {
+			None
+		};
+
+		Ok(AdminRpc::BucketUsage {
+			bucket_id,
+			counters,
+			prefix_usage_depths: self.garage.config.prefix_usage_depths.clone(),
+			prefixes,
+		})
+	}
+
+	async fn handle_bucket_usage_history(
+		&self,
+		query: &BucketUsageHistoryOpt,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.name)
+			.await?;
+
+		let (resolution, default_periods) = match query.daily {
+			false => (UsageResolution::Hourly, 48),
+			true => (UsageResolution::Daily, 30),
+		};
+		let since = match &query.since {
+			Some(t) => parse_time(t)?,
+			None => now_msec().saturating_sub(default_periods * resolution.period_msec()),
+		};
+		let until = query.until.as_deref().map(parse_time).transpose()?;
+
+		let samples = self
+			.garage
+			.bucket_helper()
+			.get_usage_history(&bucket_id, resolution, Some(since), until)
+			.await?;
+
+		Ok(AdminRpc::BucketUsageHistory {
+			bucket_id,
+			resolution,
+			samples,
+		})
+	}
+
 	#[allow(clippy::ptr_arg)]
-	async fn handle_create_bucket(&self, name: &String) -> Result<AdminRpc, Error> {
+	async fn handle_create_bucket(
+		&self,
+		query: &CreateBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
+		let name = &query.name;
 		if !is_valid_bucket_name(name, self.garage.config.allow_punycode) {
 			return Err(Error::BadRequest(format!(
 				"{}: {}",
@@ -133,6 +227,10 @@ impl AdminRpcHandler {
 			)));
 		}
 
+		if let Some(rf) = query.replication_factor {
+			self.garage.bucket_helper().check_replication_factor(rf)?;
+		}
+
 		let helper = self.garage.locked_helper().await;
 
 		if let Some(alias) = self.garage.bucket_alias_table.get(&EmptyKey, name).await? {
@@ -143,15 +241,32 @@ impl AdminRpcHandler {
 
 		// ---- done checking, now commit ----
 
-		let bucket = Bucket::new();
+		let mut bucket = Bucket::new();
+		if let Some(rf) = query.replication_factor {
+			bucket
+				.params_mut()
+				.unwrap()
+				.replication_factor
+				.update(Some(rf));
+		}
 		self.garage.bucket_table.insert(&bucket).await?;
 
 		helper.set_global_bucket_alias(bucket.id, name).await?;
 
-		Ok(AdminRpc::Ok(format!("Bucket {} was created.", name)))
+		self.garage
+			.audit_helper()
+			.record(actor, "CreateBucket", AuditTarget::Bucket(bucket.id), None)
+			.await;
+
+		self.bucket_updated_result(bucket.id, format!("Bucket {} was created.", name))
+			.await
 	}
 
-	async fn handle_delete_bucket(&self, query: &DeleteBucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_delete_bucket(
+		&self,
+		query: &DeleteBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
 		let bucket_id = helper
@@ -207,6 +322,9 @@ impl AdminRpcHandler {
 		}
 
 		// --- done checking, now commit ---
+		let target = AuditTarget::Bucket(bucket_id);
+		let before = self.garage.audit_helper().summary(&target).await?;
+
 		// 1. delete authorization from keys that had access
 		for (key_id, _) in bucket.authorized_keys() {
 			helper
@@ -225,10 +343,19 @@ impl AdminRpcHandler {
 		bucket.state = Deletable::delete();
 		self.garage.bucket_table.insert(&bucket).await?;
 
+		self.garage
+			.audit_helper()
+			.record(actor, "DeleteBucket", target, before)
+			.await;
+
 		Ok(AdminRpc::Ok(format!("Bucket {} was deleted.", query.name)))
 	}
 
-	async fn handle_alias_bucket(&self, query: &AliasBucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_alias_bucket(
+		&self,
+		query: &AliasBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
 		let bucket_id = helper
@@ -236,31 +363,51 @@ impl AdminRpcHandler {
 			.admin_get_existing_matching_bucket(&query.existing_bucket)
 			.await?;
 
-		if let Some(key_pattern) = &query.local {
+		let target = AuditTarget::Bucket(bucket_id);
+		let before = self.garage.audit_helper().summary(&target).await?;
+
+		let (operation, msg) = if let Some(key_pattern) = &query.local {
 			let key = helper.key().get_existing_matching_key(key_pattern).await?;
 
 			helper
 				.set_local_bucket_alias(bucket_id, &key.key_id, &query.new_name)
 				.await?;
-			Ok(AdminRpc::Ok(format!(
-				"Alias {} now points to bucket {:?} in namespace of key {}",
-				query.new_name, bucket_id, key.key_id
-			)))
+			(
+				"LocalAliasBucket",
+				format!(
+					"Alias {} now points to bucket {:?} in namespace of key {}",
+					query.new_name, bucket_id, key.key_id
+				),
+			)
 		} else {
 			helper
 				.set_global_bucket_alias(bucket_id, &query.new_name)
 				.await?;
-			Ok(AdminRpc::Ok(format!(
-				"Alias {} now points to bucket {:?}",
-				query.new_name, bucket_id
-			)))
-		}
+			(
+				"GlobalAliasBucket",
+				format!(
+					"Alias {} now points to bucket {:?}",
+					query.new_name, bucket_id
+				),
+			)
+		};
+
+		self.garage
+			.audit_helper()
+			.record(actor, operation, target, before)
+			.await;
+
+		self.bucket_updated_result(bucket_id, msg).await
 	}
 
-	async fn handle_unalias_bucket(&self, query: &UnaliasBucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_unalias_bucket(
+		&self,
+		query: &UnaliasBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
-		if let Some(key_pattern) = &query.local {
+		let (operation, bucket_id, before, msg) = if let Some(key_pattern) = &query.local {
 			let key = helper.key().get_existing_matching_key(key_pattern).await?;
 
 			let bucket_id = key
@@ -273,14 +420,25 @@ impl AdminRpcHandler {
 				.flatten()
 				.ok_or_bad_request("Bucket not found")?;
 
+			let before = self
+				.garage
+				.audit_helper()
+				.summary(&AuditTarget::Bucket(bucket_id))
+				.await?;
+
 			helper
 				.unset_local_bucket_alias(bucket_id, &key.key_id, &query.name)
 				.await?;
 
-			Ok(AdminRpc::Ok(format!(
-				"Alias {} no longer points to bucket {:?} in namespace of key {}",
-				&query.name, bucket_id, key.key_id
-			)))
+			(
+				"LocalUnaliasBucket",
+				bucket_id,
+				before,
+				format!(
+					"Alias {} no longer points to bucket {:?} in namespace of key {}",
+					&query.name, bucket_id, key.key_id
+				),
+			)
 		} else {
 			let bucket_id = helper
 				.bucket()
@@ -288,18 +446,40 @@ impl AdminRpcHandler {
 				.await?
 				.ok_or_bad_request("Bucket not found")?;
 
+			let before = self
+				.garage
+				.audit_helper()
+				.summary(&AuditTarget::Bucket(bucket_id))
+				.await?;
+
 			helper
 				.unset_global_bucket_alias(bucket_id, &query.name)
 				.await?;
 
-			Ok(AdminRpc::Ok(format!(
-				"Alias {} no longer points to bucket {:?}",
-				&query.name, bucket_id
-			)))
-		}
+			(
+				"GlobalUnaliasBucket",
+				bucket_id,
+				before,
+				format!(
+					"Alias {} no longer points to bucket {:?}",
+					&query.name, bucket_id
+				),
+			)
+		};
+
+		self.garage
+			.audit_helper()
+			.record(actor, operation, AuditTarget::Bucket(bucket_id), before)
+			.await;
+
+		self.bucket_updated_result(bucket_id, msg).await
 	}
 
-	async fn handle_bucket_allow(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_bucket_allow(
+		&self,
+		query: &PermBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
 		let bucket_id = helper
@@ -311,6 +491,9 @@ impl AdminRpcHandler {
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
 
+		let target = AuditTarget::Bucket(bucket_id);
+		let before = self.garage.audit_helper().summary(&target).await?;
+
 		let allow_read = query.read || key.allow_read(&bucket_id);
 		let allow_write = query.write || key.allow_write(&bucket_id);
 		let allow_owner = query.owner || key.allow_owner(&bucket_id);
@@ -328,13 +511,23 @@ impl AdminRpcHandler {
 			)
 			.await?;
 
-		Ok(AdminRpc::Ok(format!(
+		self.garage
+			.audit_helper()
+			.record(actor, "BucketAllowKey", target, before)
+			.await;
+
+		let msg = format!(
 			"New permissions for {} on {}: read {}, write {}, owner {}.",
 			&key.key_id, &query.bucket, allow_read, allow_write, allow_owner
-		)))
+		);
+		self.bucket_updated_result(bucket_id, msg).await
 	}
 
-	async fn handle_bucket_deny(&self, query: &PermBucketOpt) -> Result<AdminRpc, Error> {
+	async fn handle_bucket_deny(
+		&self,
+		query: &PermBucketOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
 		let bucket_id = helper
@@ -346,6 +539,9 @@ impl AdminRpcHandler {
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
 
+		let target = AuditTarget::Bucket(bucket_id);
+		let before = self.garage.audit_helper().summary(&target).await?;
+
 		let allow_read = !query.read && key.allow_read(&bucket_id);
 		let allow_write = !query.write && key.allow_write(&bucket_id);
 		let allow_owner = !query.owner && key.allow_owner(&bucket_id);
@@ -363,13 +559,23 @@ impl AdminRpcHandler {
 			)
 			.await?;
 
-		Ok(AdminRpc::Ok(format!(
+		self.garage
+			.audit_helper()
+			.record(actor, "BucketDenyKey", target, before)
+			.await;
+
+		let msg = format!(
 			"New permissions for {} on {}: read {}, write {}, owner {}.",
 			&key.key_id, &query.bucket, allow_read, allow_write, allow_owner
-		)))
+		);
+		self.bucket_updated_result(bucket_id, msg).await
 	}
 
-	async fn handle_bucket_website(&self, query: &WebsiteOpt) -> Result<AdminRpc, Error> {
+	async fn handle_bucket_website(
+		&self,
+		query: &WebsiteOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
 			.bucket_helper()
@@ -381,6 +587,7 @@ impl AdminRpcHandler {
 			.bucket_helper()
 			.get_existing_bucket(bucket_id)
 			.await?;
+		let before = audit::bucket_summary(&bucket);
 		let bucket_state = bucket.state.as_option_mut().unwrap();
 
 		if !(query.allow ^ query.deny) {
@@ -401,16 +608,81 @@ impl AdminRpcHandler {
 		bucket_state.website_config.update(website);
 		self.garage.bucket_table.insert(&bucket).await?;
 
+		self.garage
+			.audit_helper()
+			.record_with_after(
+				actor,
+				"UpdateBucket",
+				AuditTarget::Bucket(bucket_id),
+				before,
+				audit::bucket_summary(&bucket),
+			)
+			.await;
+
 		let msg = if query.allow {
 			format!("Website access allowed for {}", &query.bucket)
 		} else {
 			format!("Website access denied for {}", &query.bucket)
 		};
+		self.bucket_updated_result(bucket_id, msg).await
+	}
+
+	async fn handle_bucket_search_index(
+		&self,
+		query: &SearchIndexOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
+		let bucket_id = self
+			.garage
+			.bucket_helper()
+			.admin_get_existing_matching_bucket(&query.bucket)
+			.await?;
+
+		let mut bucket = self
+			.garage
+			.bucket_helper()
+			.get_existing_bucket(bucket_id)
+			.await?;
+		let before = audit::bucket_summary(&bucket);
+		let bucket_state = bucket.state.as_option_mut().unwrap();
+
+		if !(query.enable ^ query.disable) {
+			return Err(Error::BadRequest(
+				"You must specify exactly one flag, either --enable or --disable".to_string(),
+			));
+		}
+
+		bucket_state.search_index.update(query.enable);
+		self.garage.bucket_table.insert(&bucket).await?;
+
+		self.garage
+			.audit_helper()
+			.record_with_after(
+				actor,
+				"UpdateBucket",
+				AuditTarget::Bucket(bucket_id),
+				before,
+				audit::bucket_summary(&bucket),
+			)
+			.await;
 
-		Ok(AdminRpc::Ok(msg))
+		let msg = if query.enable {
+			format!(
+				"Search index enabled for {}.\n\
+				Objects already in the bucket are indexed by running `garage repair --all-nodes --yes search-index`.",
+				&query.bucket
+			)
+		} else {
+			format!("Search index disabled for {}", &query.bucket)
+		};
+		self.bucket_updated_result(bucket_id, msg).await
 	}
 
-	async fn handle_bucket_set_quotas(&self, query: &SetQuotasOpt) -> Result<AdminRpc, Error> {
+	async fn handle_bucket_set_quotas(
+		&self,
+		query: &SetQuotasOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let bucket_id = self
 			.garage
 			.bucket_helper()
@@ -422,6 +694,7 @@ impl AdminRpcHandler {
 			.bucket_helper()
 			.get_existing_bucket(bucket_id)
 			.await?;
+		let before = audit::bucket_summary(&bucket);
 		let bucket_state = bucket.state.as_option_mut().unwrap();
 
 		if query.max_size.is_none() && query.max_objects.is_none() {
@@ -457,10 +730,19 @@ impl AdminRpcHandler {
 		bucket_state.quotas.update(quotas);
 		self.garage.bucket_table.insert(&bucket).await?;
 
-		Ok(AdminRpc::Ok(format!(
-			"Quotas updated for {}",
-			&query.bucket
-		)))
+		self.garage
+			.audit_helper()
+			.record_with_after(
+				actor,
+				"UpdateBucket",
+				AuditTarget::Bucket(bucket_id),
+				before,
+				audit::bucket_summary(&bucket),
+			)
+			.await;
+
+		self.bucket_updated_result(bucket_id, format!("Quotas updated for {}", &query.bucket))
+			.await
 	}
 
 	async fn handle_bucket_cleanup_incomplete_uploads(
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 
 use garage_table::*;
 
+use garage_model::helper::audit::{key_summary, AuditTarget};
 use garage_model::helper::error::*;
 use garage_model::key_table::*;
 
@@ -10,16 +11,20 @@ use crate::cli::*;
 use super::*;
 
 impl AdminRpcHandler {
-	pub(super) async fn handle_key_cmd(&self, cmd: &KeyOperation) -> Result<AdminRpc, Error> {
+	pub(super) async fn handle_key_cmd(
+		&self,
+		cmd: &KeyOperation,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		match cmd {
 			KeyOperation::List => self.handle_list_keys().await,
 			KeyOperation::Info(query) => self.handle_key_info(query).await,
-			KeyOperation::Create(query) => self.handle_create_key(query).await,
-			KeyOperation::Rename(query) => self.handle_rename_key(query).await,
-			KeyOperation::Delete(query) => self.handle_delete_key(query).await,
-			KeyOperation::Allow(query) => self.handle_allow_key(query).await,
-			KeyOperation::Deny(query) => self.handle_deny_key(query).await,
-			KeyOperation::Import(query) => self.handle_import_key(query).await,
+			KeyOperation::Create(query) => self.handle_create_key(query, actor).await,
+			KeyOperation::Rename(query) => self.handle_rename_key(query, actor).await,
+			KeyOperation::Delete(query) => self.handle_delete_key(query, actor).await,
+			KeyOperation::Allow(query) => self.handle_allow_key(query, actor).await,
+			KeyOperation::Deny(query) => self.handle_deny_key(query, actor).await,
+			KeyOperation::Import(query) => self.handle_import_key(query, actor).await,
 		}
 	}
 
@@ -55,27 +60,39 @@ impl AdminRpcHandler {
 		self.key_info_result(key).await
 	}
 
-	async fn handle_create_key(&self, query: &KeyNewOpt) -> Result<AdminRpc, Error> {
+	async fn handle_create_key(&self, query: &KeyNewOpt, actor: &str) -> Result<AdminRpc, Error> {
 		let key = Key::new(&query.name);
 		self.garage.key_table.insert(&key).await?;
+		self.record_key_change(actor, "CreateKey", None, &key).await;
 		self.key_info_result(key).await
 	}
 
-	async fn handle_rename_key(&self, query: &KeyRenameOpt) -> Result<AdminRpc, Error> {
+	async fn handle_rename_key(
+		&self,
+		query: &KeyRenameOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let mut key = self
 			.garage
 			.key_helper()
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
+		let before = key_summary(&key);
 		key.params_mut()
 			.unwrap()
 			.name
 			.update(query.new_name.clone());
 		self.garage.key_table.insert(&key).await?;
+		self.record_key_change(actor, "UpdateKey", before, &key)
+			.await;
 		self.key_info_result(key).await
 	}
 
-	async fn handle_delete_key(&self, query: &KeyDeleteOpt) -> Result<AdminRpc, Error> {
+	async fn handle_delete_key(
+		&self,
+		query: &KeyDeleteOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		let helper = self.garage.locked_helper().await;
 
 		let mut key = helper
@@ -89,7 +106,10 @@ impl AdminRpcHandler {
 			));
 		}
 
+		let before = key_summary(&key);
 		helper.delete_key(&mut key).await?;
+		self.record_key_change(actor, "DeleteKey", before, &key)
+			.await;
 
 		Ok(AdminRpc::Ok(format!(
 			"Key {} was deleted successfully.",
@@ -97,33 +117,43 @@ impl AdminRpcHandler {
 		)))
 	}
 
-	async fn handle_allow_key(&self, query: &KeyPermOpt) -> Result<AdminRpc, Error> {
+	async fn handle_allow_key(&self, query: &KeyPermOpt, actor: &str) -> Result<AdminRpc, Error> {
 		let mut key = self
 			.garage
 			.key_helper()
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
+		let before = key_summary(&key);
 		if query.create_bucket {
 			key.params_mut().unwrap().allow_create_bucket.update(true);
 		}
 		self.garage.key_table.insert(&key).await?;
+		self.record_key_change(actor, "UpdateKey", before, &key)
+			.await;
 		self.key_info_result(key).await
 	}
 
-	async fn handle_deny_key(&self, query: &KeyPermOpt) -> Result<AdminRpc, Error> {
+	async fn handle_deny_key(&self, query: &KeyPermOpt, actor: &str) -> Result<AdminRpc, Error> {
 		let mut key = self
 			.garage
 			.key_helper()
 			.get_existing_matching_key(&query.key_pattern)
 			.await?;
+		let before = key_summary(&key);
 		if query.create_bucket {
 			key.params_mut().unwrap().allow_create_bucket.update(false);
 		}
 		self.garage.key_table.insert(&key).await?;
+		self.record_key_change(actor, "UpdateKey", before, &key)
+			.await;
 		self.key_info_result(key).await
 	}
 
-	async fn handle_import_key(&self, query: &KeyImportOpt) -> Result<AdminRpc, Error> {
+	async fn handle_import_key(
+		&self,
+		query: &KeyImportOpt,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
 		if !query.yes {
 			return Err(Error::BadRequest("This command is intended to re-import keys that were previously generated by Garage. If you want to create a new key, use `garage key new` instead. Add the --yes flag if you really want to re-import a key.".to_string()));
 		}
@@ -136,10 +166,31 @@ impl AdminRpcHandler {
 		let imported_key = Key::import(&query.key_id, &query.secret_key, &query.name)
 			.ok_or_bad_request("Invalid key format")?;
 		self.garage.key_table.insert(&imported_key).await?;
+		self.record_key_change(actor, "ImportKey", None, &imported_key)
+			.await;
 
 		self.key_info_result(imported_key).await
 	}
 
+	async fn record_key_change(
+		&self,
+		actor: &str,
+		operation: &str,
+		before: Option<String>,
+		key: &Key,
+	) {
+		self.garage
+			.audit_helper()
+			.record_with_after(
+				actor,
+				operation,
+				AuditTarget::Key(key.key_id.clone()),
+				before,
+				key_summary(key),
+			)
+			.await
+	}
+
 	async fn key_info_result(&self, key: Key) -> Result<AdminRpc, Error> {
 		let mut relevant_buckets = HashMap::new();
 
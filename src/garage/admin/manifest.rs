@@ -0,0 +1,853 @@
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use garage_util::time::*;
+
+use garage_table::*;
+
+use garage_model::bucket_alias_table::*;
+use garage_model::bucket_table::*;
+use garage_model::helper::audit::{self, perm_summary, AuditTarget};
+use garage_model::helper::error::{Error, OkOrBadRequest};
+use garage_model::helper::locked::LockedHelper;
+use garage_model::permission::*;
+
+use crate::cli::manifest::*;
+
+use super::*;
+
+impl AdminRpcHandler {
+	/// Compute the list of changes required for the cluster to match the manifest,
+	/// and apply them unless `dry_run` is set. Applying the same manifest twice
+	/// does not produce any change the second time.
+	pub(super) async fn handle_apply_manifest(
+		&self,
+		manifest: &Manifest,
+		dry_run: bool,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
+		let helper = self.garage.locked_helper().await;
+
+		self.check_manifest(manifest).await?;
+
+		let mut changes = vec![];
+		for key in manifest.keys.iter() {
+			self.apply_manifest_key(key, dry_run, actor, &mut changes)
+				.await?;
+		}
+		for bucket in manifest.buckets.iter() {
+			self.apply_manifest_bucket(&helper, bucket, dry_run, actor, &mut changes)
+				.await?;
+		}
+
+		Ok(AdminRpc::ManifestPlan {
+			changes,
+			applied: !dry_run,
+		})
+	}
+
+	/// Build a manifest describing all keys and buckets. Buckets are identified
+	/// by their first global alias, or by their ID if they have none.
+	pub(super) async fn handle_export_manifest(
+		&self,
+		show_secrets: bool,
+	) -> Result<AdminRpc, Error> {
+		let keys = self
+			.garage
+			.key_table
+			.get_range(
+				&EmptyKey,
+				None,
+				Some(KeyFilter::Deleted(DeletedFilter::NotDeleted)),
+				10000,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+		let buckets = self
+			.garage
+			.bucket_table
+			.get_range(
+				&EmptyKey,
+				None,
+				Some(DeletedFilter::NotDeleted),
+				10000,
+				EnumerationOrder::Forward,
+			)
+			.await?;
+
+		let mut manifest = Manifest::default();
+
+		for key in keys.iter() {
+			let p = key.params().unwrap();
+			manifest.keys.push(ManifestKey {
+				id: key.key_id.clone(),
+				name: p.name.get().clone(),
+				secret: Some(p.secret_key.clone()).filter(|_| show_secrets),
+				allow_create_bucket: *p.allow_create_bucket.get(),
+			});
+		}
+
+		for bucket in buckets.iter() {
+			let p = bucket.state.as_option().unwrap();
+
+			let mut aliases = p
+				.aliases
+				.items()
+				.iter()
+				.filter(|(_, _, active)| *active)
+				.map(|(n, _, _)| n.clone())
+				.collect::<Vec<_>>();
+			aliases.sort();
+			let (id, name) = if aliases.is_empty() {
+				(Some(hex::encode(bucket.id)), None)
+			} else {
+				(None, Some(aliases.remove(0)))
+			};
+
+			let local_aliases = p
+				.local_aliases
+				.items()
+				.iter()
+				.filter(|(_, _, active)| *active)
+				.map(|((key, alias), _, _)| ManifestLocalAlias {
+					key: key.clone(),
+					alias: alias.clone(),
+				})
+				.collect::<BTreeSet<_>>();
+
+			let permissions = p
+				.authorized_keys
+				.items()
+				.iter()
+				.filter(|(_, perm)| perm.is_any())
+				.map(|(key, perm)| ManifestPermission {
+					key: key.clone(),
+					read: perm.allow_read,
+					write: perm.allow_write,
+					owner: perm.allow_owner,
+				})
+				.collect::<Vec<_>>();
+
+			manifest.buckets.push(ManifestBucket {
+				id,
+				name,
+				aliases,
+				local_aliases: local_aliases.into_iter().collect(),
+				permissions,
+				quotas: Some(p.quotas.get().clone()).filter(|q| *q != BucketQuotas::default()),
+				website: p.website_config.get().clone(),
+				cors: p.cors_config.get().clone(),
+				lifecycle: p.lifecycle_config.get().clone(),
+				replication_factor: *p.replication_factor.get(),
+			});
+		}
+		// Buckets identified by their ID come last
+		manifest
+			.buckets
+			.sort_by(|a, b| (&a.id, &a.name).cmp(&(&b.id, &b.name)));
+
+		Ok(AdminRpc::Manifest(manifest))
+	}
+
+	/// Check the consistency of a manifest before starting to apply it,
+	/// so that errors in the manifest do not cause it to be half-applied.
+	async fn check_manifest(&self, manifest: &Manifest) -> Result<(), Error> {
+		let mut keys = HashSet::new();
+		for key in manifest.keys.iter() {
+			if !keys.insert(key.id.as_str()) {
+				return Err(Error::BadRequest(format!(
+					"Key {} is defined several times in the manifest",
+					key.id
+				)));
+			}
+		}
+
+		let mut aliases = HashSet::new();
+		let mut bucket_ids = HashSet::new();
+		for bucket in manifest.buckets.iter() {
+			match (&bucket.id, &bucket.name) {
+				(None, None) => {
+					return Err(Error::BadRequest(
+						"Buckets of the manifest must have a name or an id".into(),
+					));
+				}
+				(Some(id), _) => {
+					let id = parse_bucket_id(id)?;
+					if !bucket_ids.insert(id) {
+						return Err(Error::BadRequest(format!(
+							"Bucket {:?} is defined several times in the manifest",
+							id
+						)));
+					}
+				}
+				(None, Some(_)) => (),
+			}
+
+			for alias in bucket.global_aliases() {
+				if !is_valid_bucket_name(alias, self.garage.config.allow_punycode) {
+					return Err(Error::InvalidBucketName(alias.clone()));
+				}
+				if !aliases.insert(alias.as_str()) {
+					return Err(Error::BadRequest(format!(
+						"Global alias {} is used several times in the manifest",
+						alias
+					)));
+				}
+			}
+
+			if let Some(rf) = bucket.replication_factor {
+				self.garage.bucket_helper().check_replication_factor(rf)?;
+			}
+
+			let mut perm_keys = HashSet::new();
+			for perm in bucket.permissions.iter() {
+				if !perm_keys.insert(perm.key.as_str()) {
+					return Err(Error::BadRequest(format!(
+						"Permissions of key {} on bucket {} are defined several times",
+						perm.key,
+						bucket.label()
+					)));
+				}
+			}
+
+			let referenced_keys = bucket
+				.permissions
+				.iter()
+				.map(|p| &p.key)
+				.chain(bucket.local_aliases.iter().map(|a| &a.key));
+			for key_id in referenced_keys {
+				if !keys.contains(key_id.as_str()) {
+					self.garage.key_helper().get_existing_key(key_id).await?;
+				}
+			}
+
+			for la in bucket.local_aliases.iter() {
+				if !is_valid_bucket_name(&la.alias, self.garage.config.allow_punycode) {
+					return Err(Error::InvalidBucketName(la.alias.clone()));
+				}
+			}
+
+			for rule in bucket.lifecycle.iter().flatten() {
+				if let Some(LifecycleExpiration::AtDate(date)) = &rule.expiration {
+					parse_lifecycle_date(date).ok_or_bad_request(format!(
+						"Invalid lifecycle expiration date for bucket {}",
+						bucket.label()
+					))?;
+				}
+			}
+		}
+
+		Ok(())
+	}
+
+	async fn apply_manifest_key(
+		&self,
+		mk: &ManifestKey,
+		dry_run: bool,
+		actor: &str,
+		changes: &mut Vec<String>,
+	) -> Result<(), Error> {
+		let mut key = match self.garage.key_table.get(&EmptyKey, &mk.id).await? {
+			None => {
+				let secret = mk.secret.as_ref().ok_or_bad_request(format!(
+					"Key {} does not exist, its secret must be given in the manifest so that it can be imported",
+					mk.id
+				))?;
+				let mut key = Key::import(&mk.id, secret, &mk.name)
+					.ok_or_bad_request(format!("Invalid format for key {}", mk.id))?;
+				key.params_mut()
+					.unwrap()
+					.allow_create_bucket
+					.update(mk.allow_create_bucket);
+
+				changes.push(format!(
+					"+ import key {} (name: {}, allow_create_bucket: {})",
+					mk.id, mk.name, mk.allow_create_bucket
+				));
+				if !dry_run {
+					self.garage.key_table.insert(&key).await?;
+					self.garage
+						.audit_helper()
+						.record_with_after(
+							actor,
+							"ApplyManifest",
+							AuditTarget::Key(key.key_id.clone()),
+							None,
+							audit::key_summary(&key),
+						)
+						.await;
+				}
+				return Ok(());
+			}
+			Some(key) if key.is_deleted() => {
+				return Err(Error::BadRequest(format!(
+					"Key {} has been deleted and cannot be re-created",
+					mk.id
+				)));
+			}
+			Some(key) => key,
+		};
+
+		let before = audit::key_summary(&key);
+		let p = key.params_mut().unwrap();
+		let n_changes = changes.len();
+
+		if let Some(secret) = &mk.secret {
+			if *secret != p.secret_key {
+				return Err(Error::BadRequest(format!(
+					"The secret of key {} in the manifest does not match its current secret, which cannot be changed",
+					mk.id
+				)));
+			}
+		}
+		if *p.name.get() != mk.name {
+			changes.push(format!(
+				"~ key {}: rename {} -> {}",
+				mk.id,
+				p.name.get(),
+				mk.name
+			));
+			p.name.update(mk.name.clone());
+		}
+		if *p.allow_create_bucket.get() != mk.allow_create_bucket {
+			changes.push(format!(
+				"~ key {}: allow_create_bucket {} -> {}",
+				mk.id,
+				p.allow_create_bucket.get(),
+				mk.allow_create_bucket
+			));
+			p.allow_create_bucket.update(mk.allow_create_bucket);
+		}
+
+		if !dry_run && changes.len() > n_changes {
+			self.garage.key_table.insert(&key).await?;
+			self.garage
+				.audit_helper()
+				.record_with_after(
+					actor,
+					"ApplyManifest",
+					AuditTarget::Key(key.key_id.clone()),
+					before,
+					audit::key_summary(&key),
+				)
+				.await;
+		}
+
+		Ok(())
+	}
+
+	async fn apply_manifest_bucket(
+		&self,
+		helper: &LockedHelper<'_>,
+		mb: &ManifestBucket,
+		dry_run: bool,
+		actor: &str,
+		changes: &mut Vec<String>,
+	) -> Result<(), Error> {
+		let existing = match (&mb.id, &mb.name) {
+			(Some(id), _) => {
+				let id = parse_bucket_id(id)?;
+				match self.garage.bucket_table.get(&EmptyKey, &id).await? {
+					Some(bucket) if bucket.is_deleted() => {
+						return Err(Error::BadRequest(format!(
+							"Bucket {:?} has been deleted and cannot be re-created",
+							id
+						)));
+					}
+					bucket => bucket,
+				}
+			}
+			(None, Some(name)) => match helper.bucket().resolve_global_bucket_name(name).await? {
+				Some(id) => Some(helper.bucket().get_existing_bucket(id).await?),
+				None => None,
+			},
+			(None, None) => unreachable!(),
+		};
+		let existing_id = existing.as_ref().map(|b| b.id);
+		let n_changes = changes.len();
+
+		if let Some(bucket) = &existing {
+			let current_rf = *bucket.params().unwrap().replication_factor.get();
+			if current_rf != mb.replication_factor {
+				return Err(Error::BadRequest(format!(
+					"The replication factor of bucket {} cannot be changed after its creation",
+					mb.label()
+				)));
+			}
+		}
+
+		// Check that none of the aliases we want to add is already in use
+		// by another bucket
+		for alias in mb.global_aliases() {
+			if let Some(other) = helper.bucket().resolve_global_bucket_name(alias).await? {
+				if Some(other) != existing_id {
+					return Err(Error::BadRequest(format!(
+						"Alias {} already exists and points to different bucket: {:?}",
+						alias, other
+					)));
+				}
+			}
+		}
+		for la in mb.local_aliases.iter() {
+			let other = self
+				.garage
+				.key_table
+				.get(&EmptyKey, &la.key)
+				.await?
+				.and_then(|k| k.params()?.local_aliases.get(&la.alias).cloned().flatten());
+			if let Some(other) = other {
+				if Some(other) != existing_id {
+					return Err(Error::BadRequest(format!(
+						"Local alias {} of key {} already exists and points to different bucket: {:?}",
+						la.alias, la.key, other
+					)));
+				}
+			}
+		}
+
+		// Current state of the bucket, or the state of a freshly created bucket
+		let created = existing.is_none();
+		let (bucket_id, before, current) = match existing {
+			Some(bucket) => (
+				bucket.id,
+				audit::bucket_summary(&bucket),
+				bucket.state.into_option().unwrap(),
+			),
+			None => {
+				let mut bucket = Bucket::new();
+				if let Some(id) = &mb.id {
+					bucket.id = parse_bucket_id(id)?;
+				}
+				match mb.replication_factor {
+					Some(rf) => {
+						changes.push(format!(
+							"+ create bucket {} (replication factor {})",
+							mb.label(),
+							rf
+						));
+						bucket
+							.params_mut()
+							.unwrap()
+							.replication_factor
+							.update(Some(rf));
+					}
+					None => changes.push(format!("+ create bucket {}", mb.label())),
+				}
+				if !dry_run {
+					self.garage.bucket_table.insert(&bucket).await?;
+					if let Some(name) = &mb.name {
+						helper.set_global_bucket_alias(bucket.id, name).await?;
+					}
+				}
+				(bucket.id, None, bucket.state.into_option().unwrap())
+			}
+		};
+		// Changes are only made if this is not a dry run
+		let bucket_id = Some(bucket_id).filter(|_| !dry_run);
+
+		// ---- global and local aliases ----
+
+		let mut cur_aliases = current
+			.aliases
+			.items()
+			.iter()
+			.filter(|(_, _, active)| *active)
+			.map(|(n, _, _)| n.clone())
+			.collect::<BTreeSet<_>>();
+		if created {
+			cur_aliases.extend(mb.name.clone());
+		}
+		let want_aliases = mb.global_aliases().cloned().collect::<BTreeSet<_>>();
+
+		let cur_local_aliases = current
+			.local_aliases
+			.items()
+			.iter()
+			.filter(|(_, _, active)| *active)
+			.map(|(k, _, _)| k.clone())
+			.collect::<BTreeSet<_>>();
+		let want_local_aliases = mb
+			.local_aliases
+			.iter()
+			.map(|la| (la.key.clone(), la.alias.clone()))
+			.collect::<BTreeSet<_>>();
+
+		// Aliases are added before old ones are removed, so that the bucket
+		// always has at least one alias
+		for alias in want_aliases.difference(&cur_aliases) {
+			changes.push(format!(
+				"+ bucket {}: add global alias {}",
+				mb.label(),
+				alias
+			));
+			if let Some(id) = bucket_id {
+				helper.set_global_bucket_alias(id, alias).await?;
+			}
+		}
+		for (key, alias) in want_local_aliases.difference(&cur_local_aliases) {
+			changes.push(format!(
+				"+ bucket {}: add local alias {} of key {}",
+				mb.label(),
+				alias,
+				key
+			));
+			if let Some(id) = bucket_id {
+				helper.set_local_bucket_alias(id, key, alias).await?;
+			}
+		}
+		for alias in cur_aliases.difference(&want_aliases) {
+			changes.push(format!(
+				"- bucket {}: remove global alias {}",
+				mb.label(),
+				alias
+			));
+			if let Some(id) = bucket_id {
+				helper.unset_global_bucket_alias(id, alias).await?;
+			}
+		}
+		for (key, alias) in cur_local_aliases.difference(&want_local_aliases) {
+			changes.push(format!(
+				"- bucket {}: remove local alias {} of key {}",
+				mb.label(),
+				alias,
+				key
+			));
+			if let Some(id) = bucket_id {
+				helper.unset_local_bucket_alias(id, key, alias).await?;
+			}
+		}
+
+		// ---- permissions ----
+
+		let no_perm = BucketKeyPerm::NO_PERMISSIONS;
+		let mut perms = current
+			.authorized_keys
+			.items()
+			.iter()
+			.filter(|(_, perm)| perm.is_any())
+			.map(|(key, perm)| (key.clone(), (*perm, no_perm)))
+			.collect::<BTreeMap<_, _>>();
+		for mp in mb.permissions.iter() {
+			let want = BucketKeyPerm {
+				timestamp: now_msec(),
+				allow_read: mp.read,
+				allow_write: mp.write,
+				allow_owner: mp.owner,
+			};
+			perms.entry(mp.key.clone()).or_insert((no_perm, no_perm)).1 = want;
+		}
+
+		for (key, (cur, want)) in perms.iter() {
+			let same = cur.allow_read == want.allow_read
+				&& cur.allow_write == want.allow_write
+				&& cur.allow_owner == want.allow_owner;
+			if same {
+				continue;
+			}
+			changes.push(format!(
+				"~ bucket {}: permissions of key {}: {} -> {}",
+				mb.label(),
+				key,
+				perm_summary(cur),
+				perm_summary(want)
+			));
+			if let Some(id) = bucket_id {
+				let want = BucketKeyPerm {
+					timestamp: now_msec(),
+					..*want
+				};
+				helper.set_bucket_key_permissions(id, key, want).await?;
+			}
+		}
+
+		// ---- bucket configuration ----
+
+		let want_quotas = mb.quotas.clone().unwrap_or_default();
+		let update_quotas = *current.quotas.get() != want_quotas;
+		if update_quotas {
+			changes.push(format!(
+				"~ bucket {}: quotas {} -> {}",
+				mb.label(),
+				quotas_summary(current.quotas.get()),
+				quotas_summary(&want_quotas)
+			));
+		}
+
+		let update_website = *current.website_config.get() != mb.website;
+		if update_website {
+			changes.push(format!(
+				"~ bucket {}: website {} -> {}",
+				mb.label(),
+				website_summary(current.website_config.get()),
+				website_summary(&mb.website)
+			));
+		}
+
+		let update_cors = *current.cors_config.get() != mb.cors;
+		if update_cors {
+			changes.push(format!(
+				"~ bucket {}: CORS {} -> {}",
+				mb.label(),
+				rules_summary(current.cors_config.get()),
+				rules_summary(&mb.cors)
+			));
+		}
+
+		let update_lifecycle = *current.lifecycle_config.get() != mb.lifecycle;
+		if update_lifecycle {
+			changes.push(format!(
+				"~ bucket {}: lifecycle {} -> {}",
+				mb.label(),
+				rules_summary(current.lifecycle_config.get()),
+				rules_summary(&mb.lifecycle)
+			));
+		}
+
+		let bucket_id = match bucket_id {
+			Some(id) if changes.len() > n_changes => id,
+			_ => return Ok(()),
+		};
+
+		if update_quotas || update_website || update_cors || update_lifecycle {
+			// Aliases and permissions were written to the bucket entry above,
+			// read it again before updating its configuration
+			let mut bucket = helper.bucket().get_existing_bucket(bucket_id).await?;
+			let p = bucket.state.as_option_mut().unwrap();
+			if update_quotas {
+				p.quotas.update(want_quotas);
+			}
+			if update_website {
+				p.website_config.update(mb.website.clone());
+			}
+			if update_cors {
+				p.cors_config.update(mb.cors.clone());
+			}
+			if update_lifecycle {
+				p.lifecycle_config.update(mb.lifecycle.clone());
+			}
+			self.garage.bucket_table.insert(&bucket).await?;
+		}
+
+		self.garage
+			.audit_helper()
+			.record(
+				actor,
+				"ApplyManifest",
+				AuditTarget::Bucket(bucket_id),
+				before,
+			)
+			.await;
+
+		Ok(())
+	}
+}
+
+/// Parse the full hexadecimal ID of a bucket given in a manifest
+fn parse_bucket_id(id: &str) -> Result<Uuid, Error> {
+	hex::decode(id)
+		.ok()
+		.and_then(|x| Uuid::try_from(&x))
+		.ok_or_bad_request(format!("Invalid bucket id: {}", id))
+}
+
+fn quotas_summary(quotas: &BucketQuotas) -> String {
+	let fmt = |x: Option<u64>| x.map(|x| x.to_string()).unwrap_or_else(|| "none".into());
+	format!(
+		"(max_size: {}, max_objects: {})",
+		fmt(quotas.max_size),
+		fmt(quotas.max_objects)
+	)
+}
+
+fn website_summary(website: &Option<WebsiteConfig>) -> String {
+	match website {
+		None => "disabled".into(),
+		Some(w) => format!(
+			"(index: {}, error: {})",
+			w.index_document,
+			w.error_document.as_deref().unwrap_or("none")
+		),
+	}
+}
+
+fn rules_summary<T>(rules: &Option<Vec<T>>) -> String {
+	match rules {
+		None => "none".into(),
+		Some(r) => format!("{} rule(s)", r.len()),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	const KEY_ID: &str = "GK31c2f218a2e44f485b94239e";
+	const KEY_SECRET: &str = "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835";
+	const BUCKET_ID: &str = "8b7e6c1f1c0c4b6c0f8a1e2d3c4b5a69788796a5b4c3d2e1f0a9b8c7d6e5f4a3";
+
+	fn test_manifest() -> Manifest {
+		let bucket = |id: Option<&str>, name: Option<&str>| ManifestBucket {
+			id: id.map(String::from),
+			name: name.map(String::from),
+			aliases: vec![],
+			local_aliases: vec![],
+			permissions: vec![],
+			quotas: None,
+			website: None,
+			cors: None,
+			lifecycle: None,
+			replication_factor: None,
+		};
+		Manifest {
+			keys: vec![ManifestKey {
+				id: KEY_ID.into(),
+				name: "my-app".into(),
+				secret: Some(KEY_SECRET.into()),
+				allow_create_bucket: false,
+			}],
+			buckets: vec![
+				ManifestBucket {
+					aliases: vec!["my-other-alias".into()],
+					local_aliases: vec![ManifestLocalAlias {
+						key: KEY_ID.into(),
+						alias: "local".into(),
+					}],
+					permissions: vec![ManifestPermission {
+						key: KEY_ID.into(),
+						read: true,
+						write: true,
+						owner: false,
+					}],
+					quotas: Some(BucketQuotas {
+						max_size: Some(1 << 30),
+						max_objects: None,
+					}),
+					website: Some(WebsiteConfig {
+						index_document: "index.html".into(),
+						error_document: None,
+					}),
+					..bucket(None, Some("my-bucket"))
+				},
+				ManifestBucket {
+					local_aliases: vec![ManifestLocalAlias {
+						key: KEY_ID.into(),
+						alias: "private".into(),
+					}],
+					permissions: vec![ManifestPermission {
+						key: KEY_ID.into(),
+						read: true,
+						write: false,
+						owner: true,
+					}],
+					..bucket(Some(BUCKET_ID), None)
+				},
+			],
+		}
+	}
+
+	async fn apply(admin: &AdminRpcHandler, manifest: &Manifest, dry_run: bool) -> Vec<String> {
+		match admin
+			.handle_apply_manifest(manifest, dry_run, "test")
+			.await
+			.unwrap()
+		{
+			AdminRpc::ManifestPlan { changes, applied } => {
+				assert_eq!(applied, !dry_run);
+				changes
+			}
+			_ => panic!("unexpected response"),
+		}
+	}
+
+	async fn export(admin: &AdminRpcHandler) -> Manifest {
+		match admin.handle_export_manifest(true).await.unwrap() {
+			AdminRpc::Manifest(manifest) => manifest,
+			_ => panic!("unexpected response"),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_apply_then_export() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+		let admin = test_admin_rpc(&garage);
+		let manifest = test_manifest();
+
+		// a dry run does not change anything
+		let plan = apply(&admin, &manifest, true).await;
+		assert!(!plan.is_empty());
+		assert_eq!(export(&admin).await, Manifest::default());
+
+		assert_eq!(apply(&admin, &manifest, false).await, plan);
+		assert_eq!(export(&admin).await, manifest);
+
+		// the bucket without a global alias was created with its ID
+		let id = parse_bucket_id(BUCKET_ID).unwrap();
+		let bucket = garage
+			.bucket_helper()
+			.get_existing_bucket(id)
+			.await
+			.unwrap();
+		assert!(bucket.aliases().is_empty());
+
+		// applying the same manifest again is a no-op
+		assert!(apply(&admin, &manifest, false).await.is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_export_then_apply() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+		let admin = test_admin_rpc(&garage);
+		apply(&admin, &test_manifest(), false).await;
+
+		// the exported manifest describes the cluster exactly
+		let exported = export(&admin).await;
+		assert!(apply(&admin, &exported, false).await.is_empty());
+
+		// and recreates the same keys and buckets on another cluster
+		let (garage2, _dir2) = test_garage();
+		apply_single_node_layout(&garage2).await;
+		let admin2 = test_admin_rpc(&garage2);
+		assert!(!apply(&admin2, &exported, false).await.is_empty());
+		assert_eq!(export(&admin2).await, exported);
+
+		// changes to a bucket without global alias are found by its ID
+		let mut changed = exported.clone();
+		changed.buckets[1].local_aliases[0].alias = "renamed".into();
+		let plan = apply(&admin2, &changed, false).await;
+		assert_eq!(
+			plan,
+			vec![
+				format!(
+					"+ bucket {}: add local alias renamed of key {}",
+					BUCKET_ID, KEY_ID
+				),
+				format!(
+					"- bucket {}: remove local alias private of key {}",
+					BUCKET_ID, KEY_ID
+				),
+			]
+		);
+		assert_eq!(export(&admin2).await, changed);
+	}
+
+	#[tokio::test]
+	async fn test_check_manifest_bucket_ids() {
+		let (garage, _dir) = test_garage();
+		apply_single_node_layout(&garage).await;
+		let admin = test_admin_rpc(&garage);
+
+		let mut manifest = test_manifest();
+		manifest.buckets[0].name = None;
+		assert!(admin.check_manifest(&manifest).await.is_err());
+
+		let mut manifest = test_manifest();
+		manifest.buckets[1].id = Some("not-an-id".into());
+		assert!(admin.check_manifest(&manifest).await.is_err());
+
+		let mut manifest = test_manifest();
+		manifest.buckets[0].id = Some(BUCKET_ID.into());
+		assert!(admin.check_manifest(&manifest).await.is_err());
+	}
+}
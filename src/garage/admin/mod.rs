@@ -1,10 +1,11 @@
 mod block;
 mod bucket;
 mod key;
+mod manifest;
 
 use std::collections::HashMap;
-use std::fmt::Write;
 use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use futures::future::FutureExt;
@@ -17,23 +18,32 @@ use garage_util::background::BackgroundRunner;
 use garage_util::data::*;
 use garage_util::error::Error as GarageError;
 
-use garage_table::replication::*;
-use garage_table::*;
-
-use garage_rpc::layout::PARTITION_BITS;
 use garage_rpc::*;
 
 use garage_block::manager::BlockResyncErrorInfo;
 
+use garage_model::audit_table::AuditRecord;
 use garage_model::bucket_table::*;
 use garage_model::garage::Garage;
+use garage_model::helper::audit::AuditTarget;
 use garage_model::helper::error::{Error, OkOrBadRequest};
 use garage_model::key_table::*;
 use garage_model::s3::mpu_table::MultipartUpload;
+use garage_model::s3::usage_history_table::{UsageResolution, UsageSample};
 use garage_model::s3::version_table::Version;
 
+use garage_api_admin::drain::{
+	find_drained_node, gather_node_drain_status, start_node_drain, NodeDrainStatusResponse,
+};
+use garage_api_admin::layout_simulate::{simulate_layout_changes, LayoutSimulationResponse};
+use garage_api_admin::node::{
+	gather_cluster_statistics, gather_node_gc_queue, gather_node_statistics, set_node_maintenance,
+	AdminNodeRpcHandler, ClusterStatisticsResponse, NodeGcQueueResponse, NodeStatisticsResponse,
+};
+use garage_api_admin::repair::launch_online_repair;
+
+use crate::cli::manifest::Manifest;
 use crate::cli::*;
-use crate::repair::online::launch_online_repair;
 
 pub const ADMIN_RPC_PATH: &str = "garage/admin_rpc.rs/Rpc";
 
@@ -47,9 +57,26 @@ pub enum AdminRpc {
 	Worker(WorkerOperation),
 	BlockOperation(BlockOperation),
 	MetaOperation(MetaOperation),
+	AuditOperation(AuditOperation),
+	/// Sent by the CLI after it changed the cluster layout, so that the change
+	/// is recorded in the audit log. Contains the name of the operation and
+	/// a summary of the layout before the change.
+	AuditLayoutChange(String, String),
+	ApplyManifest {
+		manifest: Manifest,
+		dry_run: bool,
+	},
+	ExportManifest {
+		show_secrets: bool,
+	},
+	DrainNode(DrainNodeOpt),
+	NodeMaintenance(NodeMaintenanceOpt),
+	SimulateLayout,
 
 	// Replies
 	Ok(String),
+	/// A confirmation message and the updated bucket info
+	BucketUpdated(String, Box<AdminRpc>),
 	BucketList(Vec<Bucket>),
 	BucketInfo {
 		bucket: Bucket,
@@ -57,7 +84,35 @@ pub enum AdminRpc {
 		counters: HashMap<String, i64>,
 		mpu_counters: HashMap<String, i64>,
 	},
+	BucketUsage {
+		bucket_id: Uuid,
+		counters: HashMap<String, i64>,
+		prefix_usage_depths: Vec<usize>,
+		prefixes: Option<Vec<(String, HashMap<String, i64>)>>,
+	},
+	BucketUsageHistory {
+		bucket_id: Uuid,
+		resolution: UsageResolution,
+		samples: Vec<UsageSample>,
+	},
 	KeyList(Vec<(String, String)>),
+	NodeStats {
+		stats: NodeStatisticsResponse,
+		cluster: Option<ClusterStatisticsResponse>,
+	},
+	AllNodeStats {
+		nodes: Vec<(Uuid, Result<NodeStatisticsResponse, String>)>,
+		cluster: ClusterStatisticsResponse,
+	},
+	GcQueue(Vec<(Uuid, Result<NodeGcQueueResponse, String>)>),
+	AuditRecords(Vec<AuditRecord>),
+	NodeDrainStatus(NodeDrainStatusResponse),
+	LayoutSimulation(LayoutSimulationResponse),
+	ManifestPlan {
+		changes: Vec<String>,
+		applied: bool,
+	},
+	Manifest(Manifest),
 	KeyInfo(Key, HashMap<Uuid, Bucket>),
 	WorkerList(
 		HashMap<usize, garage_util::background::WorkerInfo>,
@@ -81,21 +136,57 @@ impl Rpc for AdminRpc {
 pub struct AdminRpcHandler {
 	garage: Arc<Garage>,
 	background: Arc<BackgroundRunner>,
+	node_rpc: Arc<AdminNodeRpcHandler>,
 	endpoint: Arc<Endpoint<AdminRpc, Self>>,
 }
 
 impl AdminRpcHandler {
-	pub fn new(garage: Arc<Garage>, background: Arc<BackgroundRunner>) -> Arc<Self> {
+	pub fn new(
+		garage: Arc<Garage>,
+		background: Arc<BackgroundRunner>,
+		node_rpc: Arc<AdminNodeRpcHandler>,
+	) -> Arc<Self> {
 		let endpoint = garage.system.netapp.endpoint(ADMIN_RPC_PATH.into());
 		let admin = Arc::new(Self {
 			garage,
 			background,
+			node_rpc,
 			endpoint,
 		});
 		admin.endpoint.set_handler(admin.clone());
 		admin
 	}
 
+	// ================ NODE DRAIN ====================
+
+	async fn handle_drain_node(&self, opt: &DrainNodeOpt, actor: &str) -> Result<AdminRpc, Error> {
+		let node = find_drained_node(&self.garage, &opt.node_id)?;
+		if !opt.status {
+			start_node_drain(&self.garage, actor, node).await?;
+		}
+		Ok(AdminRpc::NodeDrainStatus(
+			gather_node_drain_status(&self.node_rpc, node).await,
+		))
+	}
+
+	// ================ NODE MAINTENANCE ====================
+
+	async fn handle_node_maintenance(&self, opt: &NodeMaintenanceOpt) -> Result<AdminRpc, Error> {
+		let node = find_drained_node(&self.garage, &opt.node_id)?;
+		let res = set_node_maintenance(&self.node_rpc, node, !opt.off).await?;
+		if res.maintenance {
+			Ok(AdminRpc::Ok(format!(
+				"Node {:?} is now in maintenance. It will leave maintenance when it restarts, or with `garage node maintenance --off`.",
+				node
+			)))
+		} else {
+			Ok(AdminRpc::Ok(format!(
+				"Node {:?} is no longer in maintenance.",
+				node
+			)))
+		}
+	}
+
 	// ================ REPAIR COMMANDS ====================
 
 	async fn handle_launch_repair(self: &Arc<Self>, opt: RepairOpt) -> Result<AdminRpc, Error> {
@@ -133,7 +224,7 @@ impl AdminRpcHandler {
 				)))
 			}
 		} else {
-			launch_online_repair(&self.garage, &self.background, opt).await?;
+			launch_online_repair(&self.garage, &self.background, opt.what.into()).await?;
 			Ok(AdminRpc::Ok(format!(
 				"Repair launched on {:?}",
 				self.garage.system.id
@@ -145,7 +236,6 @@ impl AdminRpcHandler {
 
 	async fn handle_stats(&self, opt: StatsOpt) -> Result<AdminRpc, Error> {
 		if opt.all_nodes {
-			let mut ret = String::new();
 			let mut all_nodes = self.garage.system.cluster_layout().all_nodes().to_vec();
 			for node in self.garage.system.get_known_nodes().iter() {
 				if node.is_up && !all_nodes.contains(&node.id) {
@@ -153,210 +243,41 @@ impl AdminRpcHandler {
 				}
 			}
 
+			let mut nodes = vec![];
 			for node in all_nodes.iter() {
 				let mut opt = opt.clone();
 				opt.all_nodes = false;
 				opt.skip_global = true;
 
-				writeln!(&mut ret, "\n======================").unwrap();
-				writeln!(&mut ret, "Stats for node {:?}:", node).unwrap();
-
 				let node_id = (*node).into();
-				match self
+				let res = match self
 					.endpoint
 					.call(&node_id, AdminRpc::Stats(opt), PRIO_NORMAL)
 					.await
 				{
-					Ok(Ok(AdminRpc::Ok(s))) => writeln!(&mut ret, "{}", s).unwrap(),
-					Ok(Ok(x)) => writeln!(&mut ret, "Bad answer: {:?}", x).unwrap(),
-					Ok(Err(e)) => writeln!(&mut ret, "Remote error: {}", e).unwrap(),
-					Err(e) => writeln!(&mut ret, "Network error: {}", e).unwrap(),
-				}
+					Ok(Ok(AdminRpc::NodeStats { stats, .. })) => Ok(stats),
+					Ok(Ok(x)) => Err(format!("Bad answer: {:?}", x)),
+					Ok(Err(e)) => Err(format!("Remote error: {}", e)),
+					Err(e) => Err(format!("Network error: {}", e)),
+				};
+				nodes.push((*node, res));
 			}
 
-			writeln!(&mut ret, "\n======================").unwrap();
-			write!(
-				&mut ret,
-				"Cluster statistics:\n\n{}",
-				self.gather_cluster_stats()
-			)
-			.unwrap();
-
-			Ok(AdminRpc::Ok(ret))
+			Ok(AdminRpc::AllNodeStats {
+				nodes,
+				cluster: gather_cluster_statistics(&self.garage),
+			})
 		} else {
-			Ok(AdminRpc::Ok(self.gather_stats_local(opt)?))
-		}
-	}
-
-	fn gather_stats_local(&self, opt: StatsOpt) -> Result<String, Error> {
-		let mut ret = String::new();
-		writeln!(
-			&mut ret,
-			"\nGarage version: {} [features: {}]\nRust compiler version: {}",
-			garage_util::version::garage_version(),
-			garage_util::version::garage_features()
-				.map(|list| list.join(", "))
-				.unwrap_or_else(|| "(unknown)".into()),
-			garage_util::version::rust_version(),
-		)
-		.unwrap();
-
-		writeln!(&mut ret, "\nDatabase engine: {}", self.garage.db.engine()).unwrap();
-
-		// Gather table statistics
-		let mut table = vec!["  Table\tItems\tMklItems\tMklTodo\tGcTodo".into()];
-		table.push(self.gather_table_stats(&self.garage.bucket_table)?);
-		table.push(self.gather_table_stats(&self.garage.key_table)?);
-		table.push(self.gather_table_stats(&self.garage.object_table)?);
-		table.push(self.gather_table_stats(&self.garage.version_table)?);
-		table.push(self.gather_table_stats(&self.garage.block_ref_table)?);
-		write!(
-			&mut ret,
-			"\nTable stats:\n{}",
-			format_table_to_string(table)
-		)
-		.unwrap();
-
-		// Gather block manager statistics
-		writeln!(&mut ret, "\nBlock manager stats:").unwrap();
-		let rc_len = self.garage.block_manager.rc_len()?.to_string();
-
-		writeln!(
-			&mut ret,
-			"  number of RC entries (~= number of blocks): {}",
-			rc_len
-		)
-		.unwrap();
-		writeln!(
-			&mut ret,
-			"  resync queue length: {}",
-			self.garage.block_manager.resync.queue_len()?
-		)
-		.unwrap();
-		writeln!(
-			&mut ret,
-			"  blocks with resync errors: {}",
-			self.garage.block_manager.resync.errors_len()?
-		)
-		.unwrap();
-
-		if !opt.skip_global {
-			write!(&mut ret, "\n{}", self.gather_cluster_stats()).unwrap();
-		}
-
-		Ok(ret)
-	}
-
-	fn gather_cluster_stats(&self) -> String {
-		let mut ret = String::new();
-
-		// Gather storage node and free space statistics for current nodes
-		let layout = &self.garage.system.cluster_layout();
-		let mut node_partition_count = HashMap::<Uuid, u64>::new();
-		for short_id in layout.current().ring_assignment_data.iter() {
-			let id = layout.current().node_id_vec[*short_id as usize];
-			*node_partition_count.entry(id).or_default() += 1;
-		}
-		let node_info = self
-			.garage
-			.system
-			.get_known_nodes()
-			.into_iter()
-			.map(|n| (n.id, n))
-			.collect::<HashMap<_, _>>();
-
-		let mut table = vec!["  ID\tHostname\tZone\tCapacity\tPart.\tDataAvail\tMetaAvail".into()];
-		for (id, parts) in node_partition_count.iter() {
-			let info = node_info.get(id);
-			let status = info.map(|x| &x.status);
-			let role = layout.current().roles.get(id).and_then(|x| x.0.as_ref());
-			let hostname = status.and_then(|x| x.hostname.as_deref()).unwrap_or("?");
-			let zone = role.map(|x| x.zone.as_str()).unwrap_or("?");
-			let capacity = role
-				.map(|x| x.capacity_string())
-				.unwrap_or_else(|| "?".into());
-			let avail_str = |x| match x {
-				Some((avail, total)) => {
-					let pct = (avail as f64) / (total as f64) * 100.;
-					let avail = bytesize::ByteSize::b(avail);
-					let total = bytesize::ByteSize::b(total);
-					format!("{}/{} ({:.1}%)", avail, total, pct)
-				}
-				None => "?".into(),
+			let cluster = if opt.skip_global {
+				None
+			} else {
+				Some(gather_cluster_statistics(&self.garage))
 			};
-			let data_avail = avail_str(status.and_then(|x| x.data_disk_avail));
-			let meta_avail = avail_str(status.and_then(|x| x.meta_disk_avail));
-			table.push(format!(
-				"  {:?}\t{}\t{}\t{}\t{}\t{}\t{}",
-				id, hostname, zone, capacity, parts, data_avail, meta_avail
-			));
-		}
-		write!(
-			&mut ret,
-			"Storage nodes:\n{}",
-			format_table_to_string(table)
-		)
-		.unwrap();
-
-		let meta_part_avail = node_partition_count
-			.iter()
-			.filter_map(|(id, parts)| {
-				node_info
-					.get(id)
-					.and_then(|x| x.status.meta_disk_avail)
-					.map(|c| c.0 / *parts)
-			})
-			.collect::<Vec<_>>();
-		let data_part_avail = node_partition_count
-			.iter()
-			.filter_map(|(id, parts)| {
-				node_info
-					.get(id)
-					.and_then(|x| x.status.data_disk_avail)
-					.map(|c| c.0 / *parts)
+			Ok(AdminRpc::NodeStats {
+				stats: gather_node_statistics(&self.garage)?,
+				cluster,
 			})
-			.collect::<Vec<_>>();
-		if !meta_part_avail.is_empty() && !data_part_avail.is_empty() {
-			let meta_avail =
-				bytesize::ByteSize(meta_part_avail.iter().min().unwrap() * (1 << PARTITION_BITS));
-			let data_avail =
-				bytesize::ByteSize(data_part_avail.iter().min().unwrap() * (1 << PARTITION_BITS));
-			writeln!(
-				&mut ret,
-				"\nEstimated available storage space cluster-wide (might be lower in practice):"
-			)
-			.unwrap();
-			if meta_part_avail.len() < node_partition_count.len()
-				|| data_part_avail.len() < node_partition_count.len()
-			{
-				writeln!(&mut ret, "  data: < {}", data_avail).unwrap();
-				writeln!(&mut ret, "  metadata: < {}", meta_avail).unwrap();
-				writeln!(&mut ret, "A precise estimate could not be given as information is missing for some storage nodes.").unwrap();
-			} else {
-				writeln!(&mut ret, "  data: {}", data_avail).unwrap();
-				writeln!(&mut ret, "  metadata: {}", meta_avail).unwrap();
-			}
 		}
-
-		ret
-	}
-
-	fn gather_table_stats<F, R>(&self, t: &Arc<Table<F, R>>) -> Result<String, Error>
-	where
-		F: TableSchema + 'static,
-		R: TableReplication + 'static,
-	{
-		let data_len = t.data.store.len().map_err(GarageError::from)?.to_string();
-		let mkl_len = t.merkle_updater.merkle_tree_len()?.to_string();
-
-		Ok(format!(
-			"  {}\t{}\t{}\t{}\t{}",
-			F::TABLE_NAME,
-			data_len,
-			mkl_len,
-			t.merkle_updater.todo_len()?,
-			t.data.gc_todo_len()?
-		))
 	}
 
 	// ================ WORKER COMMANDS ====================
@@ -512,29 +433,148 @@ impl AdminRpcHandler {
 				garage_model::snapshot::async_snapshot_metadata(&self.garage).await?;
 				Ok(AdminRpc::Ok("Snapshot has been saved.".into()))
 			}
+			MetaOperation::Migrate { engine, yes } => {
+				if !yes {
+					return Err(Error::BadRequest(
+						"Please add the --yes flag to start the migration".into(),
+					));
+				}
+				let engine = garage_db::Engine::from_str(engine)
+					.map_err(|e| Error::BadRequest(e.to_string()))?;
+				let msg = garage_model::db_migration::launch_db_migration(
+					&self.garage,
+					&self.background,
+					engine,
+				)?;
+				Ok(AdminRpc::Ok(msg))
+			}
+			MetaOperation::GcQueue { all_nodes: true } => {
+				let mut all_nodes = self.garage.system.cluster_layout().all_nodes().to_vec();
+				for node in self.garage.system.get_known_nodes().iter() {
+					if node.is_up && !all_nodes.contains(&node.id) {
+						all_nodes.push(node.id);
+					}
+				}
+
+				let resps = futures::future::join_all(all_nodes.iter().map(|node| async move {
+					let node_id = (*node).into();
+					self.endpoint
+						.call(
+							&node_id,
+							AdminRpc::MetaOperation(MetaOperation::GcQueue { all_nodes: false }),
+							PRIO_NORMAL,
+						)
+						.await
+				}))
+				.await;
+
+				let mut nodes = vec![];
+				for (node, resp) in all_nodes.into_iter().zip(resps) {
+					let res = match resp {
+						Ok(Ok(AdminRpc::GcQueue(mut q))) if q.len() == 1 => q.pop().unwrap().1,
+						Ok(Ok(x)) => Err(format!("Bad answer: {:?}", x)),
+						Ok(Err(e)) => Err(format!("Remote error: {}", e)),
+						Err(e) => Err(format!("Network error: {}", e)),
+					};
+					nodes.push((node, res));
+				}
+				Ok(AdminRpc::GcQueue(nodes))
+			}
+			MetaOperation::GcQueue { all_nodes: false } => Ok(AdminRpc::GcQueue(vec![(
+				self.garage.system.id,
+				Ok(gather_node_gc_queue(&self.garage)?),
+			)])),
+			MetaOperation::Restore(_) => Err(Error::BadRequest(
+				"Snapshots must be restored while Garage is stopped".into(),
+			)),
+		}
+	}
+
+	// ================ AUDIT LOG COMMANDS ====================
+
+	async fn handle_audit_cmd(&self, ao: &AuditOperation) -> Result<AdminRpc, Error> {
+		match ao {
+			AuditOperation::List(opt) => {
+				let since = opt.since.as_deref().map(parse_time).transpose()?;
+				let until = opt.until.as_deref().map(parse_time).transpose()?;
+
+				let records = self
+					.garage
+					.audit_helper()
+					.list(since, until, opt.limit)
+					.await?;
+				Ok(AdminRpc::AuditRecords(records))
+			}
 		}
 	}
+
+	async fn handle_audit_layout_change(
+		&self,
+		operation: &str,
+		before: &str,
+		actor: &str,
+	) -> Result<AdminRpc, Error> {
+		self.garage
+			.audit_helper()
+			.record(
+				actor,
+				operation,
+				AuditTarget::ClusterLayout,
+				Some(before.to_string()),
+			)
+			.await;
+		Ok(AdminRpc::Ok("Layout change recorded in audit log".into()))
+	}
 }
 
 impl EndpointHandler<AdminRpc> for AdminRpcHandler {
 	fn handle(
 		self: &Arc<Self>,
 		message: &AdminRpc,
-		_from: NodeID,
+		from: NodeID,
 	) -> impl Future<Output = Result<AdminRpc, Error>> + Send {
 		let self2 = self.clone();
+		// Changes made using the CLI are attributed to the node whose
+		// key was used to connect to the cluster
+		let actor = format!("cli:{:?}", Uuid::from(from));
 		async move {
 			match message {
-				AdminRpc::BucketOperation(bo) => self2.handle_bucket_cmd(bo).await,
-				AdminRpc::KeyOperation(ko) => self2.handle_key_cmd(ko).await,
+				AdminRpc::BucketOperation(bo) => self2.handle_bucket_cmd(bo, &actor).await,
+				AdminRpc::KeyOperation(ko) => self2.handle_key_cmd(ko, &actor).await,
 				AdminRpc::LaunchRepair(opt) => self2.handle_launch_repair(opt.clone()).await,
 				AdminRpc::Stats(opt) => self2.handle_stats(opt.clone()).await,
 				AdminRpc::Worker(wo) => self2.handle_worker_cmd(wo).await,
 				AdminRpc::BlockOperation(bo) => self2.handle_block_cmd(bo).await,
 				AdminRpc::MetaOperation(mo) => self2.handle_meta_cmd(mo).await,
+				AdminRpc::AuditOperation(ao) => self2.handle_audit_cmd(ao).await,
+				AdminRpc::AuditLayoutChange(operation, before) => {
+					self2
+						.handle_audit_layout_change(operation, before, &actor)
+						.await
+				}
+				AdminRpc::ApplyManifest { manifest, dry_run } => {
+					self2
+						.handle_apply_manifest(manifest, *dry_run, &actor)
+						.await
+				}
+				AdminRpc::ExportManifest { show_secrets } => {
+					self2.handle_export_manifest(*show_secrets).await
+				}
+				AdminRpc::DrainNode(opt) => self2.handle_drain_node(opt, &actor).await,
+				AdminRpc::NodeMaintenance(opt) => self2.handle_node_maintenance(opt).await,
+				AdminRpc::SimulateLayout => Ok(AdminRpc::LayoutSimulation(
+					simulate_layout_changes(&self2.node_rpc).await?,
+				)),
 				m => Err(GarageError::unexpected_rpc_message(m).into()),
 			}
 		}
 		.boxed()
 	}
 }
+
+/// Parse a date given in RFC 3339 format into msec since UNIX epoch
+fn parse_time(t: &str) -> Result<u64, Error> {
+	let t = chrono::DateTime::parse_from_rfc3339(t)
+		.ok_or_bad_request(format!("Invalid date {}, expected RFC 3339 format", t))?;
+	Ok(t.timestamp_millis().max(0) as u64)
+}
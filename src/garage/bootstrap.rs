@@ -0,0 +1,335 @@
+//! Creation of the initial layout, keys and buckets declared in the
+//! `[bootstrap]` section of the configuration file
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use garage_util::config::*;
+use garage_util::crdt::Crdt;
+use garage_util::time::*;
+
+use garage_rpc::layout::{NodeRole, NodeRoleV};
+use garage_rpc::system::ClusterHealthStatus;
+
+use garage_table::*;
+
+use garage_model::bucket_alias_table::is_valid_bucket_name;
+use garage_model::bucket_table::Bucket;
+use garage_model::garage::Garage;
+use garage_model::helper::audit::{layout_summary, AuditTarget};
+use garage_model::helper::error::{Error, OkOrBadRequest};
+use garage_model::key_table::*;
+use garage_model::permission::BucketKeyPerm;
+
+/// Name under which changes made by the bootstrap procedure appear in the audit log
+const BOOTSTRAP_ACTOR: &str = "bootstrap";
+
+/// Apply the bootstrap configuration: assign a role to this node if the cluster
+/// has no layout yet, wait for the cluster to be healthy, and then create the
+/// keys and buckets that don't exist yet. Keys and buckets that already exist
+/// are not modified, except for the permissions listed in the configuration,
+/// so this can safely be run every time the node starts.
+pub async fn run_bootstrap(
+	garage: Arc<Garage>,
+	config: BootstrapConfig,
+	must_exit: watch::Receiver<bool>,
+) {
+	let layout_before = match &config.layout {
+		Some(role) => match bootstrap_layout(&garage, role).await {
+			Ok(before) => before,
+			Err(e) => {
+				error!("Bootstrap: could not assign role to this node: {}", e);
+				return;
+			}
+		},
+		None => None,
+	};
+
+	if !wait_healthy(&garage, must_exit).await {
+		return;
+	}
+
+	if let Some(before) = layout_before {
+		garage
+			.audit_helper()
+			.record(
+				BOOTSTRAP_ACTOR,
+				"ApplyClusterLayout",
+				AuditTarget::ClusterLayout,
+				Some(before),
+			)
+			.await;
+	}
+
+	for key in config.keys.iter() {
+		if let Err(e) = bootstrap_key(&garage, key).await {
+			error!("Bootstrap: could not import key {}: {}", key.id, e);
+		}
+	}
+
+	for bucket in config.buckets.iter() {
+		if let Err(e) = bootstrap_bucket(&garage, bucket).await {
+			error!("Bootstrap: could not create bucket {}: {}", bucket.name, e);
+		}
+	}
+
+	info!("Bootstrap configuration applied.");
+}
+
+/// Assign a role to this node and apply the layout, if no layout has been
+/// applied yet. Returns the summary of the layout before the change if
+/// it was changed.
+async fn bootstrap_layout(
+	garage: &Garage,
+	role: &BootstrapLayoutConfig,
+) -> Result<Option<String>, Error> {
+	let mut layout = garage.system.cluster_layout().inner().clone();
+	if layout.current().version > 0 {
+		return Ok(None);
+	}
+	let before = layout_summary(&layout);
+
+	let mut roles = layout.current().roles.clone();
+	roles.merge(&layout.staging.get().roles);
+
+	let new_role = NodeRole {
+		zone: role.zone.clone(),
+		capacity: Some(role.capacity as u64),
+		tags: role.tags.clone(),
+	};
+	layout
+		.staging
+		.get_mut()
+		.roles
+		.merge(&roles.update_mutator(garage.system.id, NodeRoleV(Some(new_role))));
+
+	let (layout, msg) = layout.apply_staged_changes(Some(1))?;
+	garage
+		.system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await?;
+
+	info!("Bootstrap: applied initial cluster layout");
+	for line in msg.iter() {
+		debug!("{}", line);
+	}
+
+	Ok(Some(before))
+}
+
+/// Wait until the cluster is healthy. Returns false if the node is
+/// shutting down before that happens.
+async fn wait_healthy(garage: &Garage, mut must_exit: watch::Receiver<bool>) -> bool {
+	while !*must_exit.borrow() {
+		if garage.system.health().status == ClusterHealthStatus::Healthy {
+			return true;
+		}
+		tokio::select! {
+			_ = tokio::time::sleep(Duration::from_secs(1)) => (),
+			_ = must_exit.changed() => (),
+		}
+	}
+	false
+}
+
+async fn bootstrap_key(garage: &Garage, cfg: &BootstrapKeyConfig) -> Result<(), Error> {
+	match garage.key_table.get(&EmptyKey, &cfg.id).await? {
+		Some(key) if key.is_deleted() => Err(Error::BadRequest(
+			"key has been deleted and cannot be re-created".into(),
+		)),
+		Some(key) if key.params().unwrap().secret_key != cfg.secret => Err(Error::BadRequest(
+			"key already exists with a different secret".into(),
+		)),
+		Some(_) => Ok(()),
+		None => {
+			let mut key = Key::import(&cfg.id, &cfg.secret, &cfg.name)
+				.ok_or_bad_request("Invalid key format")?;
+			key.params_mut()
+				.unwrap()
+				.allow_create_bucket
+				.update(cfg.allow_create_bucket);
+			garage.key_table.insert(&key).await?;
+
+			garage
+				.audit_helper()
+				.record(
+					BOOTSTRAP_ACTOR,
+					"ImportKey",
+					AuditTarget::Key(key.key_id.clone()),
+					None,
+				)
+				.await;
+			info!("Bootstrap: imported key {}", cfg.id);
+			Ok(())
+		}
+	}
+}
+
+async fn bootstrap_bucket(garage: &Garage, cfg: &BootstrapBucketConfig) -> Result<(), Error> {
+	let helper = garage.locked_helper().await;
+
+	let bucket_id = match helper
+		.bucket()
+		.resolve_global_bucket_name(&cfg.name)
+		.await?
+	{
+		Some(id) => id,
+		None => {
+			if !is_valid_bucket_name(&cfg.name, garage.config.allow_punycode) {
+				return Err(Error::InvalidBucketName(cfg.name.clone()));
+			}
+
+			let mut bucket = Bucket::new();
+			if let Some(rf) = cfg.replication_factor {
+				garage.bucket_helper().check_replication_factor(rf)?;
+				bucket
+					.params_mut()
+					.unwrap()
+					.replication_factor
+					.update(Some(rf));
+			}
+			garage.bucket_table.insert(&bucket).await?;
+			helper.set_global_bucket_alias(bucket.id, &cfg.name).await?;
+
+			garage
+				.audit_helper()
+				.record(
+					BOOTSTRAP_ACTOR,
+					"CreateBucket",
+					AuditTarget::Bucket(bucket.id),
+					None,
+				)
+				.await;
+			info!("Bootstrap: created bucket {}", cfg.name);
+			bucket.id
+		}
+	};
+
+	for grant in cfg.grants.iter() {
+		let key = helper.key().get_existing_key(&grant.key).await?;
+		let perm = key.bucket_permissions(&bucket_id);
+		if perm.allow_read == grant.read
+			&& perm.allow_write == grant.write
+			&& perm.allow_owner == grant.owner
+		{
+			continue;
+		}
+
+		let target = AuditTarget::Bucket(bucket_id);
+		let before = garage.audit_helper().summary(&target).await?;
+		helper
+			.set_bucket_key_permissions(
+				bucket_id,
+				&key.key_id,
+				BucketKeyPerm {
+					timestamp: now_msec(),
+					allow_read: grant.read,
+					allow_write: grant.write,
+					allow_owner: grant.owner,
+				},
+			)
+			.await?;
+		garage
+			.audit_helper()
+			.record(BOOTSTRAP_ACTOR, "BucketAllowKey", target, before)
+			.await;
+	}
+
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	const BOOTSTRAP_CONFIG: &str = r#"
+		[bootstrap.layout]
+		zone = "dc1"
+		capacity = "1G"
+
+		[[bootstrap.keys]]
+		id = "GK31c2f218a2e44f485b94239e"
+		secret = "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835"
+		name = "test"
+
+		[[bootstrap.buckets]]
+		name = "test-bucket"
+		grants = [{ key = "GK31c2f218a2e44f485b94239e", read = true, write = true }]
+	"#;
+
+	async fn start_and_bootstrap(dir: &std::path::Path) -> Arc<Garage> {
+		let garage = test_garage_in(dir, BOOTSTRAP_CONFIG);
+		let config = garage.config.bootstrap.clone().unwrap();
+		let (_exit, must_exit) = watch::channel(false);
+		tokio::time::timeout(
+			Duration::from_secs(10),
+			run_bootstrap(garage.clone(), config, must_exit),
+		)
+		.await
+		.expect("bootstrap did not complete");
+		garage
+	}
+
+	#[tokio::test]
+	async fn test_bootstrap_idempotent_across_restarts() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+
+		let garage = start_and_bootstrap(&dir).await;
+		let node_id = garage.system.id;
+		let bucket_id = garage
+			.bucket_helper()
+			.resolve_global_bucket_name(&"test-bucket".to_string())
+			.await
+			.unwrap()
+			.unwrap();
+		let records = garage.audit_helper().list(None, None, 100).await.unwrap();
+		let operations = records
+			.iter()
+			.map(|r| r.operation.as_str())
+			.collect::<Vec<_>>();
+		assert_eq!(
+			operations,
+			[
+				"ApplyClusterLayout",
+				"ImportKey",
+				"CreateBucket",
+				"BucketAllowKey"
+			]
+		);
+		drop(garage);
+
+		// Restarting the node with the same configuration
+		// must not change anything
+		let garage = start_and_bootstrap(&dir).await;
+		assert_eq!(garage.system.id, node_id);
+		assert_eq!(garage.system.cluster_layout().current().version, 1);
+		assert_eq!(
+			garage
+				.bucket_helper()
+				.resolve_global_bucket_name(&"test-bucket".to_string())
+				.await
+				.unwrap(),
+			Some(bucket_id)
+		);
+		let key = garage
+			.key_table
+			.get(&EmptyKey, &"GK31c2f218a2e44f485b94239e".to_string())
+			.await
+			.unwrap()
+			.unwrap();
+		let perm = key.bucket_permissions(&bucket_id);
+		assert!(perm.allow_read && perm.allow_write && !perm.allow_owner);
+		assert_eq!(
+			garage
+				.audit_helper()
+				.list(None, None, 100)
+				.await
+				.unwrap()
+				.len(),
+			records.len()
+		);
+	}
+}
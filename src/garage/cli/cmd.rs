@@ -8,9 +8,12 @@ use garage_rpc::layout::*;
 use garage_rpc::system::*;
 use garage_rpc::*;
 
+use garage_model::helper::audit::layout_summary;
 use garage_model::helper::error::Error as HelperError;
 
 use crate::admin::*;
+use crate::cli::json::*;
+use crate::cli::manifest::*;
 use crate::cli::*;
 
 pub async fn cli_command_dispatch(
@@ -18,40 +21,145 @@ pub async fn cli_command_dispatch(
 	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
 	admin_rpc_endpoint: &Endpoint<AdminRpc, ()>,
 	rpc_host: NodeID,
+	json: bool,
 ) -> Result<(), HelperError> {
 	match cmd {
-		Command::Status => Ok(cmd_status(system_rpc_endpoint, rpc_host).await?),
+		Command::Status => Ok(cmd_status(system_rpc_endpoint, rpc_host, json).await?),
 		Command::Node(NodeOperation::Connect(connect_opt)) => {
 			Ok(cmd_connect(system_rpc_endpoint, rpc_host, connect_opt).await?)
 		}
+		Command::Node(NodeOperation::Drain(drain_opt)) => {
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::DrainNode(drain_opt),
+			)
+			.await
+		}
+		Command::Node(NodeOperation::Maintenance(maintenance_opt)) => {
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::NodeMaintenance(maintenance_opt),
+			)
+			.await
+		}
+		Command::Layout(LayoutOperation::Simulate) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, json, AdminRpc::SimulateLayout).await
+		}
 		Command::Layout(layout_opt) => {
-			Ok(cli_layout_command_dispatch(layout_opt, system_rpc_endpoint, rpc_host).await?)
+			cmd_layout(
+				layout_opt,
+				system_rpc_endpoint,
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+			)
+			.await
 		}
 		Command::Bucket(bo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::BucketOperation(bo)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::BucketOperation(bo),
+			)
+			.await
 		}
 		Command::Key(ko) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::KeyOperation(ko)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::KeyOperation(ko),
+			)
+			.await
 		}
 		Command::Repair(ro) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::LaunchRepair(ro)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::LaunchRepair(ro),
+			)
+			.await
+		}
+		Command::Stats(so) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, json, AdminRpc::Stats(so)).await
+		}
+		Command::Worker(wo) => {
+			cmd_admin(admin_rpc_endpoint, rpc_host, json, AdminRpc::Worker(wo)).await
 		}
-		Command::Stats(so) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Stats(so)).await,
-		Command::Worker(wo) => cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::Worker(wo)).await,
 		Command::Block(bo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::BlockOperation(bo)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::BlockOperation(bo),
+			)
+			.await
 		}
 		Command::Meta(mo) => {
-			cmd_admin(admin_rpc_endpoint, rpc_host, AdminRpc::MetaOperation(mo)).await
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::MetaOperation(mo),
+			)
+			.await
+		}
+		Command::Audit(ao) => {
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::AuditOperation(ao),
+			)
+			.await
+		}
+		Command::Apply(apply_opt) => {
+			let manifest = Manifest::load(&apply_opt.file)?;
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::ApplyManifest {
+					manifest,
+					dry_run: !apply_opt.yes,
+				},
+			)
+			.await
+		}
+		Command::Export(export_opt) => {
+			cmd_admin(
+				admin_rpc_endpoint,
+				rpc_host,
+				json,
+				AdminRpc::ExportManifest {
+					show_secrets: export_opt.show_secrets,
+				},
+			)
+			.await
 		}
 		_ => unreachable!(),
 	}
 }
 
-pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) -> Result<(), Error> {
+pub async fn cmd_status(
+	rpc_cli: &Endpoint<SystemRpc, ()>,
+	rpc_host: NodeID,
+	json: bool,
+) -> Result<(), Error> {
 	let status = fetch_status(rpc_cli, rpc_host).await?;
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
 
+	if json {
+		print_json(&status_json(&status, &layout));
+		return Ok(());
+	}
+
 	println!("==== HEALTHY NODES ====");
 	let mut healthy_nodes =
 		vec!["ID\tHostname\tAddress\tTags\tZone\tCapacity\tDataAvail".to_string()];
@@ -71,6 +179,11 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 				}
 				None => "?".into(),
 			};
+			let capacity = if adv.status.maintenance {
+				format!("{} (maintenance)", cfg.capacity_string())
+			} else {
+				cfg.capacity_string()
+			};
 			healthy_nodes.push(format!(
 				"{id:?}\t{host}\t{addr}\t[{tags}]\t{zone}\t{capacity}\t{data_avail}",
 				id = adv.id,
@@ -78,7 +191,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 				addr = addr,
 				tags = cfg.tags.join(","),
 				zone = cfg.zone,
-				capacity = cfg.capacity_string(),
+				capacity = capacity,
 				data_avail = data_avail,
 			));
 		} else {
@@ -125,6 +238,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 	let tf = timeago::Formatter::new();
 	let mut drain_msg = false;
 	let mut failed_nodes = vec!["ID\tHostname\tTags\tZone\tCapacity\tLast seen".to_string()];
+	let mut maintenance_nodes = failed_nodes.clone();
 	let mut listed = HashSet::new();
 	for ver in layout.versions.iter().rev() {
 		for (node, _, role) in ver.roles.items().iter() {
@@ -160,7 +274,7 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 				drain_msg = true;
 				"draining metadata...".to_string()
 			};
-			failed_nodes.push(format!(
+			let line = format!(
 				"{id:?}\t{host}\t[{tags}]\t{zone}\t{capacity}\t{last_seen}",
 				id = node,
 				host = host,
@@ -168,10 +282,21 @@ pub async fn cmd_status(rpc_cli: &Endpoint<SystemRpc, ()>, rpc_host: NodeID) ->
 				zone = cfg.zone,
 				capacity = capacity,
 				last_seen = last_seen,
-			));
+			);
+			// Nodes in maintenance are expected to be down
+			if adv.map(|x| x.status.maintenance).unwrap_or(false) {
+				maintenance_nodes.push(line);
+			} else {
+				failed_nodes.push(line);
+			}
 		}
 	}
 
+	if maintenance_nodes.len() > 1 {
+		println!("\n==== NODES IN MAINTENANCE ====");
+		format_table(maintenance_nodes);
+	}
+
 	if failed_nodes.len() > 1 {
 		println!("\n==== FAILED NODES ====");
 		format_table(failed_nodes);
@@ -211,13 +336,57 @@ pub async fn cmd_connect(
 	}
 }
 
+/// Run a layout command, and if it modified the layout, ask the node
+/// to record the change in the audit log.
+pub async fn cmd_layout(
+	cmd: LayoutOperation,
+	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
+	admin_rpc_endpoint: &Endpoint<AdminRpc, ()>,
+	rpc_host: NodeID,
+	json: bool,
+) -> Result<(), HelperError> {
+	let operation = layout_audit_operation(&cmd);
+	let before = match operation {
+		Some(_) => Some(layout_summary(
+			&fetch_layout(system_rpc_endpoint, rpc_host).await?,
+		)),
+		None => None,
+	};
+
+	cli_layout_command_dispatch(cmd, system_rpc_endpoint, rpc_host, json).await?;
+
+	// The layout change has already been made at this point,
+	// failing to record it should not make the command fail.
+	if let (Some(operation), Some(before)) = (operation, before) {
+		let res = admin_rpc_endpoint
+			.call(
+				&rpc_host,
+				AdminRpc::AuditLayoutChange(operation.to_string(), before),
+				PRIO_NORMAL,
+			)
+			.await;
+		match res {
+			Ok(Ok(_)) => (),
+			Ok(Err(e)) => warn!("Could not record layout change in audit log: {}", e),
+			Err(e) => warn!("Could not record layout change in audit log: {}", e),
+		}
+	}
+	Ok(())
+}
+
 pub async fn cmd_admin(
 	rpc_cli: &Endpoint<AdminRpc, ()>,
 	rpc_host: NodeID,
+	json: bool,
 	args: AdminRpc,
 ) -> Result<(), HelperError> {
-	match rpc_cli.call(&rpc_host, args, PRIO_NORMAL).await?? {
-		AdminRpc::Ok(msg) => {
+	let resp = rpc_cli.call(&rpc_host, args, PRIO_NORMAL).await??;
+	if json {
+		return print_admin_rpc_json(resp);
+	}
+
+	match resp {
+		AdminRpc::Ok(msg) | AdminRpc::BucketUpdated(msg, _) => {
 			println!("{}", msg);
 		}
 		AdminRpc::BucketList(bl) => {
@@ -231,12 +400,70 @@ pub async fn cmd_admin(
 		} => {
 			print_bucket_info(&bucket, &relevant_keys, &counters, &mpu_counters);
 		}
+		AdminRpc::BucketUsage {
+			bucket_id,
+			counters,
+			prefix_usage_depths,
+			prefixes,
+		} => {
+			print_bucket_usage(
+				&bucket_id,
+				&counters,
+				&prefix_usage_depths,
+				prefixes.as_deref(),
+			);
+		}
+		AdminRpc::BucketUsageHistory {
+			bucket_id,
+			resolution,
+			samples,
+		} => {
+			print_bucket_usage_history(&bucket_id, resolution, &samples);
+		}
 		AdminRpc::KeyList(kl) => {
 			print_key_list(kl);
 		}
 		AdminRpc::KeyInfo(key, rb) => {
 			print_key_info(&key, &rb);
 		}
+		AdminRpc::NodeStats { stats, cluster } => {
+			print_node_stats(&stats);
+			if let Some(cluster) = cluster {
+				println!();
+				print_cluster_stats(&cluster);
+			}
+		}
+		AdminRpc::AllNodeStats { nodes, cluster } => {
+			for (node, res) in nodes {
+				println!("\n======================");
+				println!("Stats for node {:?}:", node);
+				match res {
+					Ok(stats) => print_node_stats(&stats),
+					Err(e) => println!("{}", e),
+				}
+			}
+			println!("\n======================");
+			println!("Cluster statistics:\n");
+			print_cluster_stats(&cluster);
+		}
+		AdminRpc::GcQueue(nodes) => {
+			print_gc_queue(nodes);
+		}
+		AdminRpc::AuditRecords(records) => {
+			print_audit_records(records);
+		}
+		AdminRpc::NodeDrainStatus(status) => {
+			print_node_drain_status(&status);
+		}
+		AdminRpc::LayoutSimulation(res) => {
+			print_layout_simulation(&res);
+		}
+		AdminRpc::ManifestPlan { changes, applied } => {
+			print_manifest_plan(changes, applied);
+		}
+		AdminRpc::Manifest(manifest) => {
+			print_manifest(&manifest);
+		}
 		AdminRpc::WorkerList(wi, wlo) => {
 			print_worker_list(wi, wlo);
 		}
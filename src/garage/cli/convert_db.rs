@@ -11,7 +11,7 @@ pub struct ConvertDbOpt {
 	/// https://garagehq.deuxfleurs.fr/documentation/reference-manual/configuration/#db-engine-since-v0-8-0)
 	#[structopt(short = "i")]
 	input_path: PathBuf,
-	/// Input database engine (lmdb or sqlite; limited by db engines
+	/// Input database engine (lmdb, sqlite or redb; limited by db engines
 	/// enabled in this build)
 	#[structopt(short = "a")]
 	input_engine: Engine,
@@ -51,6 +51,11 @@ pub(crate) fn do_conversion(args: ConvertDbOpt) -> Result<()> {
 	if args.input_engine == args.output_engine {
 		return Err(Error("input and output database engine must differ".into()));
 	}
+	if args.input_engine == Engine::Memory || args.output_engine == Engine::Memory {
+		return Err(Error(
+			"the in-memory database engine cannot be used for conversion".into(),
+		));
+	}
 
 	let opt = OpenOpt {
 		#[cfg(feature = "lmdb")]
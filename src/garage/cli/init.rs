@@ -2,9 +2,11 @@ use std::path::PathBuf;
 
 use garage_util::error::*;
 
+use crate::cli::json::{print_json, NodeIdJson};
+
 pub const READ_KEY_ERROR: &str = "Unable to read node key. It will be generated by your garage node the first time is it launched. Ensure that your garage node is currently running. (The node key is supposed to be stored in your metadata directory.)";
 
-pub fn node_id_command(config_file: PathBuf, quiet: bool) -> Result<(), Error> {
+pub fn node_id_command(config_file: PathBuf, quiet: bool, json: bool) -> Result<(), Error> {
 	let config = garage_util::config::read_config(config_file.clone()).err_context(format!(
 		"Unable to read configuration file {}",
 		config_file.to_string_lossy(),
@@ -13,13 +15,24 @@ pub fn node_id_command(config_file: PathBuf, quiet: bool) -> Result<(), Error> {
 	let node_id =
 		garage_rpc::system::read_node_id(&config.metadata_dir).err_context(READ_KEY_ERROR)?;
 
+	if json {
+		print_json(&NodeIdJson {
+			id: hex::encode(node_id),
+			address: config.rpc_public_addr.clone(),
+		});
+	}
+
 	let idstr = if let Some(addr) = config.rpc_public_addr {
 		let idstr = format!("{}@{}", hex::encode(node_id), addr);
-		println!("{}", idstr);
+		if !json {
+			println!("{}", idstr);
+		}
 		idstr
 	} else {
 		let idstr = hex::encode(node_id);
-		println!("{}", idstr);
+		if !json {
+			println!("{}", idstr);
+		}
 
 		if !quiet {
 			warn!("WARNING: I don't know the public address to reach this node.");
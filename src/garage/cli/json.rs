@@ -0,0 +1,915 @@
+//! Structured output of CLI commands, used when the `--json` flag is given.
+//! Field names follow the conventions of the admin API.
+
+use std::collections::HashMap;
+
+use serde::Serialize;
+
+use garage_util::background::{WorkerInfo, WorkerState};
+use garage_util::data::*;
+use garage_util::error::Error as GarageError;
+use garage_util::time::*;
+
+use garage_rpc::layout::*;
+use garage_rpc::system::KnownNodeInfo;
+
+use garage_block::manager::BlockResyncErrorInfo;
+
+use garage_model::audit_table::AuditRecord;
+use garage_model::bucket_table::*;
+use garage_model::helper::error::Error as HelperError;
+use garage_model::key_table::*;
+use garage_model::s3::mpu_table::{self, MultipartUpload};
+use garage_model::s3::object_table;
+use garage_model::s3::usage_history_table::UsageSample;
+use garage_model::s3::version_table::*;
+
+use garage_api_admin::block::{BlockErrorResp, BlockVersion, BlockVersionBacklink};
+use garage_api_admin::node::{
+	ClusterStatisticsResponse, FreeSpaceResp, NodeStatisticsResponse, TableGcQueue,
+};
+use garage_api_admin::worker::{worker_info_to_api, WorkerInfoResp};
+
+use crate::admin::AdminRpc;
+use crate::cli::structs::WorkerListOpt;
+
+pub fn print_json<T: Serialize + ?Sized>(value: &T) {
+	println!(
+		"{}",
+		serde_json::to_string_pretty(value).expect("could not serialize output to JSON")
+	);
+}
+
+/// Print the response to an admin RPC as JSON
+pub fn print_admin_rpc_json(resp: AdminRpc) -> Result<(), HelperError> {
+	match resp {
+		AdminRpc::Ok(message) => print_json(&MessageJson { message }),
+		AdminRpc::BucketUpdated(_, info) => return print_admin_rpc_json(*info),
+		AdminRpc::BucketList(bl) => {
+			print_json(&bl.iter().map(bucket_list_item_json).collect::<Vec<_>>())
+		}
+		AdminRpc::BucketInfo {
+			bucket,
+			relevant_keys,
+			counters,
+			mpu_counters,
+		} => print_json(&bucket_info_json(
+			&bucket,
+			&relevant_keys,
+			&counters,
+			&mpu_counters,
+		)),
+		AdminRpc::BucketUsage {
+			bucket_id,
+			counters,
+			prefix_usage_depths,
+			prefixes,
+		} => print_json(&BucketUsageJson {
+			id: hex::encode(bucket_id),
+			objects: *counters.get(object_table::OBJECTS).unwrap_or(&0),
+			bytes: *counters.get(object_table::BYTES).unwrap_or(&0),
+			unfinished_uploads: *counters.get(object_table::UNFINISHED_UPLOADS).unwrap_or(&0),
+			prefix_usage_depths,
+			prefixes: prefixes.map(|p| {
+				p.into_iter()
+					.map(|(prefix, c)| BucketPrefixUsageJson {
+						prefix,
+						objects: *c.get(object_table::OBJECTS).unwrap_or(&0),
+						bytes: *c.get(object_table::BYTES).unwrap_or(&0),
+						unfinished_uploads: *c.get(object_table::UNFINISHED_UPLOADS).unwrap_or(&0),
+					})
+					.collect()
+			}),
+		}),
+		AdminRpc::BucketUsageHistory {
+			bucket_id,
+			resolution,
+			samples,
+		} => print_json(&BucketUsageHistoryJson {
+			id: hex::encode(bucket_id),
+			resolution: resolution.to_string(),
+			samples: samples.iter().map(usage_sample_json).collect(),
+		}),
+		AdminRpc::KeyList(kl) => print_json(
+			&kl.into_iter()
+				.map(|(id, name)| KeyListItemJson { id, name })
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::KeyInfo(key, rb) => print_json(&key_info_json(&key, &rb)),
+		AdminRpc::NodeStats { stats, cluster } => print_json(&NodeStatsJson {
+			node: stats,
+			cluster,
+		}),
+		AdminRpc::AllNodeStats { nodes, cluster } => print_json(&AllNodeStatsJson {
+			nodes: nodes
+				.into_iter()
+				.map(|(id, res)| match res {
+					Ok(stats) => NodeStatsResultJson {
+						id: hex::encode(id),
+						stats: Some(stats),
+						error: None,
+					},
+					Err(e) => NodeStatsResultJson {
+						id: hex::encode(id),
+						stats: None,
+						error: Some(e),
+					},
+				})
+				.collect(),
+			cluster,
+		}),
+		AdminRpc::GcQueue(nodes) => print_json(
+			&nodes
+				.into_iter()
+				.map(|(id, res)| match res {
+					Ok(queue) => NodeGcQueueResultJson {
+						id: hex::encode(id),
+						tables: Some(queue.tables),
+						error: None,
+					},
+					Err(e) => NodeGcQueueResultJson {
+						id: hex::encode(id),
+						tables: None,
+						error: Some(e),
+					},
+				})
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::WorkerList(wi, wlo) => print_json(&worker_list_json(wi, wlo)),
+		AdminRpc::WorkerInfo(tid, wi) => print_json(&worker_info_to_api(tid as u64, wi)),
+		AdminRpc::WorkerVars(wv) => print_json(
+			&wv.into_iter()
+				.map(|(node, variable, value)| WorkerVarJson {
+					node: hex::encode(node),
+					variable,
+					value,
+				})
+				.collect::<Vec<_>>(),
+		),
+		AdminRpc::BlockErrorList(el) => print_json(&block_error_list_json(el)),
+		AdminRpc::BlockInfo {
+			hash,
+			refcount,
+			versions,
+			uploads,
+		} => print_json(&block_info_json(hash, refcount, versions, uploads)),
+		AdminRpc::AuditRecords(records) => {
+			print_json(&records.iter().map(audit_record_json).collect::<Vec<_>>())
+		}
+		AdminRpc::ManifestPlan { changes, applied } => {
+			print_json(&ManifestPlanJson { changes, applied })
+		}
+		AdminRpc::Manifest(manifest) => print_json(&manifest),
+		AdminRpc::NodeDrainStatus(status) => print_json(&status),
+		AdminRpc::LayoutSimulation(res) => print_json(&res),
+		r => {
+			return Err(HelperError::Internal(GarageError::Message(format!(
+				"Unexpected response: {:?}",
+				r
+			))))
+		}
+	}
+	Ok(())
+}
+
+// ---- generic ----
+
+#[derive(Serialize)]
+pub struct MessageJson {
+	pub message: String,
+}
+
+// ---- manifests ----
+
+#[derive(Serialize)]
+struct ManifestPlanJson {
+	changes: Vec<String>,
+	applied: bool,
+}
+
+// ---- buckets ----
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketListItemJson {
+	id: String,
+	global_aliases: Vec<String>,
+	local_aliases: Vec<BucketLocalAliasJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketLocalAliasJson {
+	access_key_id: String,
+	alias: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketInfoJson {
+	id: String,
+	deleted: bool,
+	global_aliases: Vec<String>,
+	local_aliases: Vec<BucketLocalAliasJson>,
+	website_access: bool,
+	website_config: Option<BucketWebsiteJson>,
+	keys: Vec<BucketKeyJson>,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+	unfinished_multipart_uploads: i64,
+	unfinished_multipart_upload_bytes: i64,
+	quotas: BucketQuotasJson,
+	replication_factor: Option<usize>,
+	search_index: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketUsageJson {
+	id: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+	prefix_usage_depths: Vec<usize>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	prefixes: Option<Vec<BucketPrefixUsageJson>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketPrefixUsageJson {
+	prefix: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketUsageHistoryJson {
+	id: String,
+	resolution: String,
+	samples: Vec<UsageSampleJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageSampleJson {
+	period_start: String,
+	sampled_at: String,
+	objects: i64,
+	bytes: i64,
+	unfinished_uploads: i64,
+	min_bytes: i64,
+	max_bytes: i64,
+}
+
+fn usage_sample_json(s: &UsageSample) -> UsageSampleJson {
+	UsageSampleJson {
+		period_start: msec_to_rfc3339(s.period_start),
+		sampled_at: msec_to_rfc3339(s.sampled_at),
+		objects: s.objects,
+		bytes: s.bytes,
+		unfinished_uploads: s.unfinished_uploads,
+		min_bytes: s.min_bytes,
+		max_bytes: s.max_bytes,
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketWebsiteJson {
+	index_document: String,
+	error_document: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketKeyJson {
+	access_key_id: String,
+	name: Option<String>,
+	permissions: PermissionsJson,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BucketQuotasJson {
+	max_size: Option<u64>,
+	max_objects: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct PermissionsJson {
+	read: bool,
+	write: bool,
+	owner: bool,
+}
+
+fn active_global_aliases(bucket: &Bucket) -> Vec<String> {
+	bucket
+		.aliases()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(name, _, _)| name.to_string())
+		.collect()
+}
+
+fn active_local_aliases(bucket: &Bucket) -> Vec<BucketLocalAliasJson> {
+	bucket
+		.local_aliases()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|((k, n), _, _)| BucketLocalAliasJson {
+			access_key_id: k.to_string(),
+			alias: n.to_string(),
+		})
+		.collect()
+}
+
+fn bucket_list_item_json(bucket: &Bucket) -> BucketListItemJson {
+	BucketListItemJson {
+		id: hex::encode(bucket.id),
+		global_aliases: active_global_aliases(bucket),
+		local_aliases: active_local_aliases(bucket),
+	}
+}
+
+fn bucket_info_json(
+	bucket: &Bucket,
+	relevant_keys: &HashMap<String, Key>,
+	counters: &HashMap<String, i64>,
+	mpu_counters: &HashMap<String, i64>,
+) -> BucketInfoJson {
+	let params = bucket.state.as_option();
+	let website_config = params.and_then(|p| p.website_config.get().clone());
+	let quotas = params.map(|p| p.quotas.get().clone()).unwrap_or_default();
+
+	let keys = params
+		.map(|p| {
+			p.authorized_keys
+				.items()
+				.iter()
+				.filter(|(_, perm)| perm.is_any())
+				.map(|(k, perm)| BucketKeyJson {
+					access_key_id: k.to_string(),
+					name: relevant_keys
+						.get(k)
+						.and_then(|k| k.params())
+						.map(|p| p.name.get().to_string()),
+					permissions: PermissionsJson {
+						read: perm.allow_read,
+						write: perm.allow_write,
+						owner: perm.allow_owner,
+					},
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+
+	BucketInfoJson {
+		id: hex::encode(bucket.id),
+		deleted: bucket.is_deleted(),
+		global_aliases: active_global_aliases(bucket),
+		local_aliases: active_local_aliases(bucket),
+		website_access: website_config.is_some(),
+		website_config: website_config.map(|wsc| BucketWebsiteJson {
+			index_document: wsc.index_document,
+			error_document: wsc.error_document,
+		}),
+		keys,
+		objects: *counters.get(object_table::OBJECTS).unwrap_or(&0),
+		bytes: *counters.get(object_table::BYTES).unwrap_or(&0),
+		unfinished_uploads: *counters.get(object_table::UNFINISHED_UPLOADS).unwrap_or(&0),
+		unfinished_multipart_uploads: *mpu_counters.get(mpu_table::UPLOADS).unwrap_or(&0),
+		unfinished_multipart_upload_bytes: *mpu_counters.get(mpu_table::BYTES).unwrap_or(&0),
+		quotas: BucketQuotasJson {
+			max_size: quotas.max_size,
+			max_objects: quotas.max_objects,
+		},
+		replication_factor: params.and_then(|p| *p.replication_factor.get()),
+		search_index: params.map(|p| *p.search_index.get()).unwrap_or(false),
+	}
+}
+
+// ---- keys ----
+
+#[derive(Serialize)]
+struct KeyListItemJson {
+	id: String,
+	name: String,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyInfoJson {
+	access_key_id: String,
+	deleted: bool,
+	name: Option<String>,
+	secret_access_key: Option<String>,
+	create_bucket: bool,
+	buckets: Vec<KeyBucketJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct KeyBucketJson {
+	id: String,
+	global_aliases: Vec<String>,
+	local_aliases: Vec<String>,
+	permissions: PermissionsJson,
+}
+
+fn key_info_json(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) -> KeyInfoJson {
+	let params = key.params();
+
+	let buckets = params
+		.map(|p| {
+			p.authorized_buckets
+				.items()
+				.iter()
+				.filter(|(_, perm)| perm.is_any())
+				.map(|(bucket_id, perm)| KeyBucketJson {
+					id: hex::encode(bucket_id),
+					global_aliases: relevant_buckets
+						.get(bucket_id)
+						.map(active_global_aliases)
+						.unwrap_or_default(),
+					local_aliases: p
+						.local_aliases
+						.items()
+						.iter()
+						.filter(|(_, _, a)| *a == Some(*bucket_id))
+						.map(|(a, _, _)| a.clone())
+						.collect(),
+					permissions: PermissionsJson {
+						read: perm.allow_read,
+						write: perm.allow_write,
+						owner: perm.allow_owner,
+					},
+				})
+				.collect()
+		})
+		.unwrap_or_default();
+
+	KeyInfoJson {
+		access_key_id: key.key_id.clone(),
+		deleted: key.is_deleted(),
+		name: params.map(|p| p.name.get().to_string()),
+		secret_access_key: params.map(|p| p.secret_key.clone()),
+		create_bucket: params
+			.map(|p| *p.allow_create_bucket.get())
+			.unwrap_or(false),
+		buckets,
+	}
+}
+
+// ---- stats ----
+
+#[derive(Serialize)]
+struct NodeStatsJson {
+	node: NodeStatisticsResponse,
+	cluster: Option<ClusterStatisticsResponse>,
+}
+
+#[derive(Serialize)]
+struct AllNodeStatsJson {
+	nodes: Vec<NodeStatsResultJson>,
+	cluster: ClusterStatisticsResponse,
+}
+
+#[derive(Serialize)]
+struct NodeStatsResultJson {
+	id: String,
+	stats: Option<NodeStatisticsResponse>,
+	error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct NodeGcQueueResultJson {
+	id: String,
+	tables: Option<Vec<TableGcQueue>>,
+	error: Option<String>,
+}
+
+// ---- workers ----
+
+#[derive(Serialize)]
+struct WorkerVarJson {
+	node: String,
+	variable: String,
+	value: String,
+}
+
+fn worker_list_json(wi: HashMap<usize, WorkerInfo>, wlo: WorkerListOpt) -> Vec<WorkerInfoResp> {
+	let mut workers = wi
+		.into_iter()
+		.filter(|(_, info)| {
+			!wlo.busy || matches!(info.state, WorkerState::Busy | WorkerState::Throttled(_))
+		})
+		.filter(|(_, info)| !wlo.errors || info.errors > 0)
+		.map(|(id, info)| worker_info_to_api(id as u64, info))
+		.collect::<Vec<_>>();
+	workers.sort_by_key(|w| w.id);
+	workers
+}
+
+// ---- blocks ----
+
+fn block_error_list_json(el: Vec<BlockResyncErrorInfo>) -> Vec<BlockErrorResp> {
+	let now = now_msec();
+	el.into_iter()
+		.map(|e| BlockErrorResp {
+			block_hash: hex::encode(e.hash),
+			refcount: e.refcount,
+			error_count: e.error_count,
+			last_try_secs_ago: now.saturating_sub(e.last_try) / 1000,
+			next_try_in_secs: e.next_try.saturating_sub(now) / 1000,
+		})
+		.collect()
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BlockInfoJson {
+	block_hash: String,
+	refcount: u64,
+	versions: Vec<BlockVersion>,
+}
+
+fn block_info_json(
+	hash: Hash,
+	refcount: u64,
+	versions: Vec<Result<Version, Uuid>>,
+	uploads: Vec<MultipartUpload>,
+) -> BlockInfoJson {
+	let versions = versions
+		.into_iter()
+		.map(|v| match v {
+			Ok(ver) => {
+				let backlink = match &ver.backlink {
+					VersionBacklink::Object { bucket_id, key } => BlockVersionBacklink::Object {
+						bucket_id: hex::encode(bucket_id),
+						key: key.to_string(),
+					},
+					VersionBacklink::MultipartUpload { upload_id } => {
+						let upload = uploads.iter().find(|x| x.upload_id == *upload_id);
+						BlockVersionBacklink::Upload {
+							upload_id: hex::encode(upload_id),
+							upload_deleted: upload.map(|u| u.deleted.get()).unwrap_or(true),
+							upload_garbage_collected: upload.is_none(),
+							bucket_id: upload.map(|u| hex::encode(u.bucket_id)),
+							key: upload.map(|u| u.key.to_string()),
+						}
+					}
+				};
+				BlockVersion {
+					version_id: hex::encode(ver.uuid),
+					deleted: ver.deleted.get(),
+					garbage_collected: false,
+					backlink: Some(backlink),
+				}
+			}
+			Err(vid) => BlockVersion {
+				version_id: hex::encode(vid),
+				deleted: true,
+				garbage_collected: true,
+				backlink: None,
+			},
+		})
+		.collect();
+
+	BlockInfoJson {
+		block_hash: hex::encode(hash),
+		refcount,
+		versions,
+	}
+}
+
+// ---- audit log ----
+
+#[derive(Serialize)]
+struct AuditRecordJson<'a> {
+	id: &'a str,
+	timestamp: String,
+	node: String,
+	actor: &'a str,
+	operation: &'a str,
+	target: &'a str,
+	before: Option<&'a str>,
+	after: Option<&'a str>,
+}
+
+fn audit_record_json(r: &AuditRecord) -> AuditRecordJson<'_> {
+	AuditRecordJson {
+		id: &r.id,
+		timestamp: msec_to_rfc3339(r.timestamp),
+		node: hex::encode(r.node),
+		actor: &r.actor,
+		operation: &r.operation,
+		target: &r.target,
+		before: r.before.as_deref(),
+		after: r.after.as_deref(),
+	}
+}
+
+// ---- cluster status and layout ----
+
+#[derive(Serialize)]
+pub struct NodeIdJson {
+	pub id: String,
+	pub address: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusJson {
+	layout_version: u64,
+	nodes: Vec<NodeJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeJson {
+	id: String,
+	hostname: Option<String>,
+	addr: Option<String>,
+	is_up: bool,
+	last_seen_secs_ago: Option<u64>,
+	role: Option<NodeRoleJson>,
+	draining: bool,
+	maintenance: bool,
+	data_partition: Option<FreeSpaceResp>,
+	metadata_partition: Option<FreeSpaceResp>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeRoleJson {
+	id: String,
+	zone: String,
+	capacity: Option<u64>,
+	tags: Vec<String>,
+}
+
+fn node_role_json(id: &Uuid, role: &NodeRole) -> NodeRoleJson {
+	NodeRoleJson {
+		id: hex::encode(id),
+		zone: role.zone.clone(),
+		capacity: role.capacity,
+		tags: role.tags.clone(),
+	}
+}
+
+pub fn status_json(status: &[KnownNodeInfo], layout: &LayoutHistory) -> StatusJson {
+	let mut nodes = status
+		.iter()
+		.map(|adv| {
+			let current_role = match layout.current().roles.get(&adv.id) {
+				Some(NodeRoleV(Some(r))) => Some(r),
+				_ => None,
+			};
+			let prev_role = layout
+				.versions
+				.iter()
+				.rev()
+				.find_map(|x| match x.roles.get(&adv.id) {
+					Some(NodeRoleV(Some(cfg))) => Some(cfg),
+					_ => None,
+				});
+			let free_space = |x: Option<(u64, u64)>| {
+				x.map(|(available, total)| FreeSpaceResp { available, total })
+			};
+			NodeJson {
+				id: hex::encode(adv.id),
+				hostname: adv.status.hostname.clone(),
+				addr: adv.addr.map(|a| a.to_string()),
+				is_up: adv.is_up,
+				last_seen_secs_ago: adv.last_seen_secs_ago,
+				role: current_role
+					.or(prev_role)
+					.map(|r| node_role_json(&adv.id, r)),
+				draining: current_role.is_none() && prev_role.is_some(),
+				maintenance: adv.status.maintenance,
+				data_partition: free_space(adv.status.data_disk_avail),
+				metadata_partition: free_space(adv.status.meta_disk_avail),
+			}
+		})
+		.collect::<Vec<_>>();
+
+	// Nodes that have a role in the layout but that the node we are
+	// talking to has never seen
+	for ver in layout.versions.iter().rev() {
+		for (id, _, role) in ver.roles.items().iter() {
+			let role = match role {
+				NodeRoleV(Some(r)) => r,
+				_ => continue,
+			};
+			let id_hex = hex::encode(id);
+			if nodes.iter().any(|n| n.id == id_hex) {
+				continue;
+			}
+			nodes.push(NodeJson {
+				id: id_hex,
+				hostname: None,
+				addr: None,
+				is_up: false,
+				last_seen_secs_ago: None,
+				role: Some(node_role_json(id, role)),
+				draining: ver.version != layout.current().version,
+				maintenance: false,
+				data_partition: None,
+				metadata_partition: None,
+			});
+		}
+	}
+
+	StatusJson {
+		layout_version: layout.current().version,
+		nodes,
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutJson {
+	version: u64,
+	replication_factor: usize,
+	zone_redundancy: String,
+	objective: String,
+	partition_size: u64,
+	roles: Vec<LayoutRoleJson>,
+	staged_role_changes: Vec<StagedRoleChangeJson>,
+	staged_zone_redundancy: Option<String>,
+	staged_objective: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutRoleJson {
+	#[serde(flatten)]
+	role: NodeRoleJson,
+	usable_capacity: Option<u64>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct StagedRoleChangeJson {
+	id: String,
+	remove: bool,
+	zone: Option<String>,
+	capacity: Option<u64>,
+	tags: Option<Vec<String>>,
+}
+
+pub fn layout_json(layout: &LayoutHistory) -> LayoutJson {
+	let current = layout.current();
+
+	let roles = current
+		.roles
+		.items()
+		.iter()
+		.filter_map(|(id, _, role)| {
+			let role = role.0.as_ref()?;
+			let usable_capacity = match current.get_node_usage(id) {
+				Ok(usage) if role.capacity.is_some() => Some(usage as u64 * current.partition_size),
+				_ => None,
+			};
+			Some(LayoutRoleJson {
+				role: node_role_json(id, role),
+				usable_capacity,
+			})
+		})
+		.collect();
+
+	let staging = layout.staging.get();
+	let staged_role_changes = staging
+		.roles
+		.items()
+		.iter()
+		.filter(|(k, _, v)| current.roles.get(k) != Some(v))
+		.map(|(id, _, role)| match &role.0 {
+			Some(r) => StagedRoleChangeJson {
+				id: hex::encode(id),
+				remove: false,
+				zone: Some(r.zone.clone()),
+				capacity: r.capacity,
+				tags: Some(r.tags.clone()),
+			},
+			None => StagedRoleChangeJson {
+				id: hex::encode(id),
+				remove: true,
+				zone: None,
+				capacity: None,
+				tags: None,
+			},
+		})
+		.collect();
+
+	let staged_parameters = staging.parameters.get();
+
+	LayoutJson {
+		version: current.version,
+		replication_factor: current.replication_factor,
+		zone_redundancy: current.parameters.zone_redundancy.to_string(),
+		objective: current.parameters.objective.to_string(),
+		partition_size: current.partition_size,
+		roles,
+		staged_role_changes,
+		staged_zone_redundancy: Some(staged_parameters)
+			.filter(|p| **p != current.parameters)
+			.map(|p| p.zone_redundancy.to_string()),
+		staged_objective: Some(staged_parameters)
+			.filter(|p| **p != current.parameters)
+			.map(|p| p.objective.to_string()),
+	}
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutChangeJson {
+	pub messages: Vec<String>,
+	pub layout: LayoutJson,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LayoutHistoryJson {
+	current_version: u64,
+	min_stored: u64,
+	versions: Vec<LayoutVersionJson>,
+	update_trackers: HashMap<String, UpdateTrackersJson>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LayoutVersionJson {
+	version: u64,
+	status: &'static str,
+	storage_nodes: usize,
+	gateway_nodes: usize,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateTrackersJson {
+	ack: u64,
+	sync: u64,
+	sync_ack: u64,
+}
+
+pub fn layout_history_json(layout: &LayoutHistory) -> LayoutHistoryJson {
+	let min_stored = layout.min_stored();
+
+	let versions = layout
+		.versions
+		.iter()
+		.rev()
+		.chain(layout.old_versions.iter().rev())
+		.map(|ver| {
+			let count = |gateway: bool| {
+				ver.roles
+					.items()
+					.iter()
+					.filter(
+						|(_, _, x)| matches!(x, NodeRoleV(Some(c)) if c.capacity.is_none() == gateway),
+					)
+					.count()
+			};
+			LayoutVersionJson {
+				version: ver.version,
+				status: if ver.version == layout.current().version {
+					"current"
+				} else if ver.version >= min_stored {
+					"draining"
+				} else {
+					"historical"
+				},
+				storage_nodes: count(false),
+				gateway_nodes: count(true),
+			}
+		})
+		.collect();
+
+	let update_trackers = layout
+		.get_all_nodes()
+		.iter()
+		.map(|node| {
+			(
+				hex::encode(node),
+				UpdateTrackersJson {
+					ack: layout.update_trackers.ack_map.get(node, min_stored),
+					sync: layout.update_trackers.sync_map.get(node, min_stored),
+					sync_ack: layout.update_trackers.sync_ack_map.get(node, min_stored),
+				},
+			)
+		})
+		.collect();
+
+	LayoutHistoryJson {
+		current_version: layout.current().version,
+		min_stored,
+		versions,
+		update_trackers,
+	}
+}
@@ -8,42 +8,63 @@ use garage_rpc::layout::*;
 use garage_rpc::system::*;
 use garage_rpc::*;
 
+use crate::cli::json::*;
 use crate::cli::*;
 
 pub async fn cli_layout_command_dispatch(
 	cmd: LayoutOperation,
 	system_rpc_endpoint: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
+	json: bool,
 ) -> Result<(), Error> {
-	match cmd {
+	let (layout, messages) = match cmd {
 		LayoutOperation::Assign(assign_opt) => {
-			cmd_assign_role(system_rpc_endpoint, rpc_host, assign_opt).await
+			cmd_assign_role(system_rpc_endpoint, rpc_host, assign_opt).await?
 		}
 		LayoutOperation::Remove(remove_opt) => {
-			cmd_remove_role(system_rpc_endpoint, rpc_host, remove_opt).await
+			cmd_remove_role(system_rpc_endpoint, rpc_host, remove_opt).await?
 		}
-		LayoutOperation::Show => cmd_show_layout(system_rpc_endpoint, rpc_host).await,
+		LayoutOperation::Show => return cmd_show_layout(system_rpc_endpoint, rpc_host, json).await,
 		LayoutOperation::Apply(apply_opt) => {
-			cmd_apply_layout(system_rpc_endpoint, rpc_host, apply_opt).await
+			cmd_apply_layout(system_rpc_endpoint, rpc_host, apply_opt).await?
 		}
 		LayoutOperation::Revert(revert_opt) => {
-			cmd_revert_layout(system_rpc_endpoint, rpc_host, revert_opt).await
+			cmd_revert_layout(system_rpc_endpoint, rpc_host, revert_opt).await?
 		}
 		LayoutOperation::Config(config_opt) => {
-			cmd_config_layout(system_rpc_endpoint, rpc_host, config_opt).await
+			cmd_config_layout(system_rpc_endpoint, rpc_host, config_opt).await?
+		}
+		LayoutOperation::History => {
+			return cmd_layout_history(system_rpc_endpoint, rpc_host, json).await
 		}
-		LayoutOperation::History => cmd_layout_history(system_rpc_endpoint, rpc_host).await,
 		LayoutOperation::SkipDeadNodes(assume_sync_opt) => {
-			cmd_layout_skip_dead_nodes(system_rpc_endpoint, rpc_host, assume_sync_opt).await
+			cmd_layout_skip_dead_nodes(system_rpc_endpoint, rpc_host, assume_sync_opt).await?
+		}
+		LayoutOperation::Simulate => {
+			return Err(Error::Message(
+				"layout simulation is computed by the admin RPC handler".into(),
+			))
+		}
+	};
+
+	if json {
+		print_json(&LayoutChangeJson {
+			messages,
+			layout: layout_json(&layout),
+		});
+	} else {
+		for line in messages.iter() {
+			println!("{}", line);
 		}
 	}
+	Ok(())
 }
 
 pub async fn cmd_assign_role(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	args: AssignRoleOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	let status = match rpc_cli
 		.call(&rpc_host, SystemRpc::GetKnownNodes, PRIO_NORMAL)
 		.await??
@@ -143,19 +164,21 @@ pub async fn cmd_assign_role(
 			.merge(&roles.update_mutator(added_node, NodeRoleV(Some(new_entry))));
 	}
 
-	send_layout(rpc_cli, rpc_host, layout).await?;
+	send_layout(rpc_cli, rpc_host, layout.clone()).await?;
 
-	println!("Role changes are staged but not yet committed.");
-	println!("Use `garage layout show` to view staged role changes,");
-	println!("and `garage layout apply` to enact staged changes.");
-	Ok(())
+	let messages = vec![
+		"Role changes are staged but not yet committed.".to_string(),
+		"Use `garage layout show` to view staged role changes,".to_string(),
+		"and `garage layout apply` to enact staged changes.".to_string(),
+	];
+	Ok((layout, messages))
 }
 
 pub async fn cmd_remove_role(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	args: RemoveRoleOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	let mut layout = fetch_layout(rpc_cli, rpc_host).await?;
 
 	let mut roles = layout.current().roles.clone();
@@ -170,20 +193,28 @@ pub async fn cmd_remove_role(
 		.roles
 		.merge(&roles.update_mutator(deleted_node, NodeRoleV(None)));
 
-	send_layout(rpc_cli, rpc_host, layout).await?;
+	send_layout(rpc_cli, rpc_host, layout.clone()).await?;
 
-	println!("Role removal is staged but not yet committed.");
-	println!("Use `garage layout show` to view staged role changes,");
-	println!("and `garage layout apply` to enact staged changes.");
-	Ok(())
+	let messages = vec![
+		"Role removal is staged but not yet committed.".to_string(),
+		"Use `garage layout show` to view staged role changes,".to_string(),
+		"and `garage layout apply` to enact staged changes.".to_string(),
+	];
+	Ok((layout, messages))
 }
 
 pub async fn cmd_show_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
+	json: bool,
 ) -> Result<(), Error> {
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
 
+	if json {
+		print_json(&layout_json(&layout));
+		return Ok(());
+	}
+
 	println!("==== CURRENT CLUSTER LAYOUT ====");
 	print_cluster_layout(layout.current(), "No nodes currently have a role in the cluster.\nSee `garage status` to view available nodes.");
 	println!();
@@ -230,27 +261,25 @@ pub async fn cmd_apply_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	apply_opt: ApplyLayoutOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
 
-	let (layout, msg) = layout.apply_staged_changes(apply_opt.version)?;
-	for line in msg.iter() {
-		println!("{}", line);
-	}
-
-	send_layout(rpc_cli, rpc_host, layout).await?;
+	let (layout, mut messages) = layout.apply_staged_changes(apply_opt.version)?;
 
-	println!("New cluster layout with updated role assignment has been applied in cluster.");
-	println!("Data will now be moved around between nodes accordingly.");
+	send_layout(rpc_cli, rpc_host, layout.clone()).await?;
 
-	Ok(())
+	messages.push(
+		"New cluster layout with updated role assignment has been applied in cluster.".into(),
+	);
+	messages.push("Data will now be moved around between nodes accordingly.".into());
+	Ok((layout, messages))
 }
 
 pub async fn cmd_revert_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	revert_opt: RevertLayoutOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	if !revert_opt.yes {
 		return Err(Error::Message(
 			"Please add the --yes flag to run the layout revert operation".into(),
@@ -261,20 +290,20 @@ pub async fn cmd_revert_layout(
 
 	let layout = layout.revert_staged_changes()?;
 
-	send_layout(rpc_cli, rpc_host, layout).await?;
+	send_layout(rpc_cli, rpc_host, layout.clone()).await?;
 
-	println!("All proposed role changes in cluster layout have been canceled.");
-	Ok(())
+	let messages = vec!["All proposed role changes in cluster layout have been canceled.".into()];
+	Ok((layout, messages))
 }
 
 pub async fn cmd_config_layout(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	config_opt: ConfigLayoutOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	let mut layout = fetch_layout(rpc_cli, rpc_host).await?;
 
-	let mut did_something = false;
+	let mut messages = vec![];
 	match config_opt.redundancy {
 		None => (),
 		Some(r_str) => {
@@ -295,31 +324,46 @@ pub async fn cmd_config_layout(
 				}
 			}
 
-			layout
-				.staging
-				.get_mut()
-				.parameters
-				.update(LayoutParameters { zone_redundancy: r });
-			println!("The zone redundancy parameter has been set to '{}'.", r);
-			did_something = true;
+			let mut parameters = *layout.staging.get().parameters.get();
+			parameters.zone_redundancy = r;
+			layout.staging.get_mut().parameters.update(parameters);
+			messages.push(format!(
+				"The zone redundancy parameter has been set to '{}'.",
+				r
+			));
 		}
 	}
+	if let Some(o_str) = config_opt.objective {
+		let o = o_str
+			.parse::<LayoutObjective>()
+			.map_err(|e| Error::Message(e.into()))?;
+		let mut parameters = *layout.staging.get().parameters.get();
+		parameters.objective = o;
+		layout.staging.get_mut().parameters.update(parameters);
+		messages.push(format!("The layout objective has been set to '{}'.", o));
+	}
 
-	if !did_something {
+	if messages.is_empty() {
 		return Err(Error::Message(
 			"Please specify an action for `garage layout config`".into(),
 		));
 	}
 
-	send_layout(rpc_cli, rpc_host, layout).await?;
-	Ok(())
+	send_layout(rpc_cli, rpc_host, layout.clone()).await?;
+	Ok((layout, messages))
 }
 
 pub async fn cmd_layout_history(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
+	json: bool,
 ) -> Result<(), Error> {
 	let layout = fetch_layout(rpc_cli, rpc_host).await?;
+
+	if json {
+		print_json(&layout_history_json(&layout));
+		return Ok(());
+	}
 	let min_stored = layout.min_stored();
 
 	println!("==== LAYOUT HISTORY ====");
@@ -413,7 +457,7 @@ pub async fn cmd_layout_skip_dead_nodes(
 	rpc_cli: &Endpoint<SystemRpc, ()>,
 	rpc_host: NodeID,
 	opt: SkipDeadNodesOpt,
-) -> Result<(), Error> {
+) -> Result<(LayoutHistory, Vec<String>), Error> {
 	let status = fetch_status(rpc_cli, rpc_host).await?;
 	let mut layout = fetch_layout(rpc_cli, rpc_host).await?;
 
@@ -436,29 +480,27 @@ pub async fn cmd_layout_skip_dead_nodes(
 	}
 
 	let all_nodes = layout.get_all_nodes();
-	let mut did_something = false;
+	let mut messages = vec![];
 	for node in all_nodes.iter() {
 		// Update ACK tracker for dead nodes or for all nodes if --allow-missing-data
 		if opt.allow_missing_data || !status.iter().any(|x| x.id == *node && x.is_up) {
 			if layout.update_trackers.ack_map.set_max(*node, opt.version) {
-				println!("Increased the ACK tracker for node {:?}", node);
-				did_something = true;
+				messages.push(format!("Increased the ACK tracker for node {:?}", node));
 			}
 		}
 
 		// If --allow-missing-data, update SYNC tracker for all nodes.
 		if opt.allow_missing_data {
 			if layout.update_trackers.sync_map.set_max(*node, opt.version) {
-				println!("Increased the SYNC tracker for node {:?}", node);
-				did_something = true;
+				messages.push(format!("Increased the SYNC tracker for node {:?}", node));
 			}
 		}
 	}
 
-	if did_something {
-		send_layout(rpc_cli, rpc_host, layout).await?;
-		println!("Success.");
-		Ok(())
+	if !messages.is_empty() {
+		send_layout(rpc_cli, rpc_host, layout.clone()).await?;
+		messages.push("Success.".into());
+		Ok((layout, messages))
 	} else if !opt.allow_missing_data {
 		Err(Error::Message("Nothing was done, try passing the `--allow-missing-data` flag to force progress even when not enough nodes can complete a metadata sync.".into()))
 	} else {
@@ -467,6 +509,19 @@ pub async fn cmd_layout_skip_dead_nodes(
 		))
 	}
 }
+/// Name of the operation recorded in the audit log for layout commands
+/// that modify the cluster layout, or None for read-only commands.
+pub fn layout_audit_operation(cmd: &LayoutOperation) -> Option<&'static str> {
+	match cmd {
+		LayoutOperation::Assign(_) | LayoutOperation::Remove(_) | LayoutOperation::Config(_) => {
+			Some("UpdateClusterLayout")
+		}
+		LayoutOperation::Apply(_) => Some("ApplyClusterLayout"),
+		LayoutOperation::Revert(_) => Some("RevertClusterLayout"),
+		LayoutOperation::SkipDeadNodes(_) => Some("SkipDeadNodes"),
+		LayoutOperation::Show | LayoutOperation::History | LayoutOperation::Simulate => None,
+	}
+}
 
 // --- utility ---
 
@@ -532,6 +587,7 @@ pub fn print_cluster_layout(layout: &LayoutVersion, empty_msg: &str) {
 		format_table(table);
 		println!();
 		println!("Zone redundancy: {}", layout.parameters.zone_redundancy);
+		println!("Layout objective: {}", layout.parameters.objective);
 	} else {
 		println!("{}", empty_msg);
 	}
@@ -576,6 +632,7 @@ pub fn print_staging_role_changes(layout: &LayoutHistory) -> bool {
 				"Zone redundancy: {}",
 				staging.parameters.get().zone_redundancy
 			);
+			println!("Layout objective: {}", staging.parameters.get().objective);
 		}
 		true
 	} else {
@@ -0,0 +1,156 @@
+use std::io::Read;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use garage_util::error::Error;
+
+use garage_model::bucket_table::{BucketQuotas, CorsRule, LifecycleRule, WebsiteConfig};
+
+/// Declarative description of the keys and buckets of a cluster,
+/// as read by `garage apply` and written by `garage export`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+	#[serde(default)]
+	pub keys: Vec<ManifestKey>,
+	#[serde(default)]
+	pub buckets: Vec<ManifestBucket>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestKey {
+	/// Access key ID
+	pub id: String,
+	/// Friendly name of the key
+	#[serde(default)]
+	pub name: String,
+	/// Secret key, required to import keys that do not exist yet in the cluster
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub secret: Option<String>,
+	/// Whether the key is allowed to create buckets
+	#[serde(default)]
+	pub allow_create_bucket: bool,
+}
+
+/// A bucket, identified by its ID if it is given, or else by its main global
+/// alias. All other properties are authoritative: aliases, permissions and
+/// configurations of the bucket that are not listed in the manifest are
+/// removed when it is applied.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestBucket {
+	/// Full ID of the bucket in hexadecimal, required for buckets
+	/// that have no global alias
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub id: Option<String>,
+	/// Main global alias of the bucket
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub name: Option<String>,
+	/// Additional global aliases
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub aliases: Vec<String>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub local_aliases: Vec<ManifestLocalAlias>,
+	#[serde(default, skip_serializing_if = "Vec::is_empty")]
+	pub permissions: Vec<ManifestPermission>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub quotas: Option<BucketQuotas>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub website: Option<WebsiteConfig>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub cors: Option<Vec<CorsRule>>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub lifecycle: Option<Vec<LifecycleRule>>,
+	/// Number of copies kept of the data of the bucket, if lower than the
+	/// replication factor of the cluster (can only be set at creation)
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub replication_factor: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestLocalAlias {
+	/// Access key ID in whose namespace the alias is defined
+	pub key: String,
+	pub alias: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct ManifestPermission {
+	/// Access key ID
+	pub key: String,
+	#[serde(default)]
+	pub read: bool,
+	#[serde(default)]
+	pub write: bool,
+	#[serde(default)]
+	pub owner: bool,
+}
+
+impl ManifestBucket {
+	/// Name of the bucket in messages: its main global alias, or its ID
+	pub fn label(&self) -> &str {
+		self.name
+			.as_deref()
+			.or(self.id.as_deref())
+			.unwrap_or("(unnamed)")
+	}
+
+	/// Main global alias and additional global aliases of the bucket
+	pub fn global_aliases(&self) -> impl Iterator<Item = &String> {
+		self.name.iter().chain(self.aliases.iter())
+	}
+}
+
+impl Manifest {
+	/// Read a manifest from a file (or from standard input if the path is `-`).
+	/// Files with a `.json` extension are parsed as JSON, all others as YAML.
+	pub fn load(path: &Path) -> Result<Self, Error> {
+		let mut content = String::new();
+		if path == Path::new("-") {
+			std::io::stdin().read_to_string(&mut content)
+		} else {
+			std::fs::File::open(path).and_then(|mut f| f.read_to_string(&mut content))
+		}
+		.map_err(|e| Error::Message(format!("Unable to read {}: {}", path.display(), e)))?;
+
+		let is_json = path.extension().map(|e| e == "json").unwrap_or(false);
+		let manifest = if is_json {
+			serde_json::from_str(&content).map_err(|e| e.to_string())
+		} else {
+			serde_yaml::from_str(&content).map_err(|e| e.to_string())
+		};
+		manifest.map_err(|e| Error::Message(format!("Invalid manifest {}: {}", path.display(), e)))
+	}
+}
+
+pub fn print_manifest_plan(changes: Vec<String>, applied: bool) {
+	if changes.is_empty() {
+		println!("Nothing to do, the cluster already matches the manifest.");
+		return;
+	}
+
+	for change in changes.iter() {
+		println!("{}", change);
+	}
+
+	println!();
+	if applied {
+		println!("{} change(s) applied.", changes.len());
+	} else {
+		println!(
+			"{} change(s) to apply. Add the --yes flag to apply them.",
+			changes.len()
+		);
+	}
+}
+
+pub fn print_manifest(manifest: &Manifest) {
+	match serde_yaml::to_string(manifest) {
+		Ok(s) => print!("{}", s),
+		Err(e) => error!("Unable to serialize manifest: {}", e),
+	}
+}
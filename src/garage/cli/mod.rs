@@ -1,6 +1,9 @@
 pub(crate) mod cmd;
 pub(crate) mod init;
+pub(crate) mod json;
 pub(crate) mod layout;
+pub(crate) mod manifest;
+pub(crate) mod restore;
 pub(crate) mod structs;
 pub(crate) mod util;
 
@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use garage_util::config::*;
+use garage_util::error::*;
+
+use garage_model::snapshot::install_snapshot;
+use garage_model::snapshot_s3::SnapshotS3Client;
+
+use crate::cli::structs::*;
+use crate::secrets::{fill_secrets, Secrets};
+
+pub async fn meta_restore(
+	config_file: PathBuf,
+	secrets: Secrets,
+	opt: MetaRestoreOpt,
+) -> Result<(), Error> {
+	let config = fill_secrets(read_config(config_file)?, secrets)?;
+	let s3_config = config
+		.metadata_snapshots_s3
+		.as_ref()
+		.ok_or_message("`metadata_snapshots_s3` is not set in the configuration file")?;
+
+	let node = match &opt.node {
+		Some(node) => node.clone(),
+		None => hex::encode(
+			garage_rpc::system::read_node_id(&config.metadata_dir)
+				.err_context("Unable to read node key, use --node to set the ID of the node whose snapshots should be restored")?,
+		),
+	};
+	let client = SnapshotS3Client::new(s3_config, &node)?;
+	let snapshots = client.list_snapshots().await?;
+
+	if opt.list {
+		for snapshot in snapshots {
+			println!("{}", snapshot);
+		}
+		return Ok(());
+	}
+
+	let snapshot = match &opt.snapshot {
+		Some(s) if snapshots.contains(s) => s.clone(),
+		Some(s) => {
+			return Err(Error::Message(format!(
+				"Snapshot {} not found, use --list to list available snapshots",
+				s
+			)))
+		}
+		None => snapshots
+			.last()
+			.cloned()
+			.ok_or_message("No snapshot found in S3 bucket for this node")?,
+	};
+
+	if !opt.yes {
+		return Err(Error::Message(format!(
+			"Snapshot {} will replace the metadata db of this node. Make sure Garage is stopped on this node, and add the --yes flag to proceed.",
+			snapshot
+		)));
+	}
+
+	let download_dir = config
+		.metadata_dir
+		.join(format!("restore-{}", snapshot.replace(':', "")));
+	if download_dir.exists() {
+		std::fs::remove_dir_all(&download_dir)?;
+	}
+	println!("Downloading snapshot {}...", snapshot);
+	client.download_snapshot(&snapshot, &download_dir).await?;
+
+	println!("{}", install_snapshot(&config, &download_dir)?);
+	Ok(())
+}
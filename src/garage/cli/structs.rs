@@ -1,8 +1,12 @@
+use std::path::PathBuf;
+
 use serde::{Deserialize, Serialize};
 use structopt::StructOpt;
 
 use garage_util::version::garage_version;
 
+use garage_api_admin::repair::{RepairType, ScrubCommand};
+
 use crate::cli::convert_db;
 
 #[derive(StructOpt, Debug)]
@@ -56,6 +60,19 @@ pub enum Command {
 	#[structopt(name = "meta", version = garage_version())]
 	Meta(MetaOperation),
 
+	/// Read the log of changes made to the cluster configuration
+	#[structopt(name = "audit", version = garage_version())]
+	Audit(AuditOperation),
+
+	/// Apply a manifest describing keys and buckets to the cluster
+	#[structopt(name = "apply", version = garage_version())]
+	Apply(ApplyOpt),
+
+	/// Export the keys and buckets of the cluster as a manifest
+	/// that can be used with `garage apply`
+	#[structopt(name = "export", version = garage_version())]
+	Export(ExportOpt),
+
 	/// Convert metadata db between database engine formats
 	#[structopt(name = "convert-db", version = garage_version())]
 	ConvertDb(convert_db::ConvertDbOpt),
@@ -71,6 +88,16 @@ pub enum NodeOperation {
 	/// Connect to Garage node that is currently isolated from the system
 	#[structopt(name = "connect", version = garage_version())]
 	Connect(ConnectNodeOpt),
+
+	/// Stage the removal of a node from the cluster layout, and show the
+	/// progress of the transfer of its data to the other nodes once applied
+	#[structopt(name = "drain", version = garage_version())]
+	Drain(DrainNodeOpt),
+
+	/// Put a node in maintenance before a planned restart: the other nodes stop
+	/// sending it requests, and keep the writes it misses until it is back
+	#[structopt(name = "maintenance", version = garage_version())]
+	Maintenance(NodeMaintenanceOpt),
 }
 
 #[derive(StructOpt, Debug)]
@@ -88,6 +115,26 @@ pub struct ConnectNodeOpt {
 	pub(crate) node: String,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct DrainNodeOpt {
+	/// Node to drain (a prefix of its ID is enough)
+	pub(crate) node_id: String,
+
+	/// Only show the progress of the drain, without staging the removal of the node
+	#[structopt(long = "status")]
+	pub(crate) status: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
+pub struct NodeMaintenanceOpt {
+	/// Node to put in maintenance (a prefix of its ID is enough)
+	pub(crate) node_id: String,
+
+	/// Take the node out of maintenance instead
+	#[structopt(long = "off")]
+	pub(crate) off: bool,
+}
+
 #[derive(StructOpt, Debug)]
 pub enum LayoutOperation {
 	/// Assign role to Garage node
@@ -106,6 +153,10 @@ pub enum LayoutOperation {
 	#[structopt(name = "show", version = garage_version())]
 	Show,
 
+	/// Estimate the data that would be moved between nodes by applying staged changes
+	#[structopt(name = "simulate", version = garage_version())]
+	Simulate,
+
 	/// Apply staged changes to cluster layout
 	#[structopt(name = "apply", version = garage_version())]
 	Apply(ApplyLayoutOpt),
@@ -161,6 +212,12 @@ pub struct ConfigLayoutOpt {
 	/// Zone redundancy parameter ('none'/'max' or integer)
 	#[structopt(short = "r", long = "redundancy")]
 	pub(crate) redundancy: Option<String>,
+
+	/// Objective of the layout computation: 'capacity' to maximize usable capacity,
+	/// or 'movement:<tolerance>' to minimize data movement, accepting a usable
+	/// capacity up to <tolerance> percent below the optimal value
+	#[structopt(short = "o", long = "objective")]
+	pub(crate) objective: Option<String>,
 }
 
 #[derive(StructOpt, Debug)]
@@ -200,9 +257,17 @@ pub enum BucketOperation {
 	#[structopt(name = "info", version = garage_version())]
 	Info(BucketOpt),
 
+	/// Get the storage usage of a bucket
+	#[structopt(name = "usage", version = garage_version())]
+	Usage(BucketUsageOpt),
+
+	/// Show the history of the storage usage of a bucket
+	#[structopt(name = "usage-history", version = garage_version())]
+	UsageHistory(BucketUsageHistoryOpt),
+
 	/// Create bucket
 	#[structopt(name = "create", version = garage_version())]
-	Create(BucketOpt),
+	Create(CreateBucketOpt),
 
 	/// Delete bucket
 	#[structopt(name = "delete", version = garage_version())]
@@ -232,6 +297,10 @@ pub enum BucketOperation {
 	#[structopt(name = "set-quotas", version = garage_version())]
 	SetQuotas(SetQuotasOpt),
 
+	/// Enable or disable the object metadata search index of this bucket
+	#[structopt(name = "search-index", version = garage_version())]
+	SearchIndex(SearchIndexOpt),
+
 	/// Clean up (abort) old incomplete multipart uploads
 	#[structopt(name = "cleanup-incomplete-uploads", version = garage_version())]
 	CleanupIncompleteUploads(CleanupIncompleteUploadsOpt),
@@ -259,12 +328,68 @@ pub struct WebsiteOpt {
 	pub error_document: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct SearchIndexOpt {
+	/// Index the metadata of objects written to the bucket
+	#[structopt(long = "enable")]
+	pub enable: bool,
+
+	/// Stop indexing the metadata of objects
+	#[structopt(long = "disable")]
+	pub disable: bool,
+
+	/// Bucket name
+	pub bucket: String,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct BucketOpt {
 	/// Bucket name
 	pub name: String,
 }
 
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct BucketUsageOpt {
+	/// Bucket name
+	pub name: String,
+
+	/// Show the usage of each prefix of the bucket, at the depths set in
+	/// `prefix_usage_depths` in the configuration file
+	#[structopt(long = "by-prefix")]
+	pub by_prefix: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct BucketUsageHistoryOpt {
+	/// Bucket name
+	pub name: String,
+
+	/// Show daily samples instead of hourly samples
+	#[structopt(long = "daily")]
+	pub daily: bool,
+
+	/// Only show periods starting at or after this date (RFC 3339 format),
+	/// defaults to 48 hours ago, or 30 days ago with `--daily`
+	#[structopt(long = "since")]
+	pub since: Option<String>,
+
+	/// Only show periods starting before this date (RFC 3339 format)
+	#[structopt(long = "until")]
+	pub until: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug)]
+pub struct CreateBucketOpt {
+	/// Bucket name
+	pub name: String,
+
+	/// Number of copies to keep of the data of objects in this bucket,
+	/// if lower than the replication factor of the cluster
+	/// (cannot be changed after the bucket is created)
+	#[structopt(long = "replication-factor")]
+	pub replication_factor: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug)]
 pub struct DeleteBucketOpt {
 	/// Bucket name
@@ -487,9 +612,15 @@ pub enum RepairWhat {
 		#[structopt(subcommand)]
 		cmd: ScrubCmd,
 	},
+	/// Verify integrity of the items of all metadata tables
+	#[structopt(name = "meta-scrub", version = garage_version())]
+	MetaScrub,
 	/// Rebalance data blocks among HDDs on individual nodes
 	#[structopt(name = "rebalance", version = garage_version())]
 	Rebalance,
+	/// Rebuild the object search index of the buckets where it is enabled
+	#[structopt(name = "search-index", version = garage_version())]
+	SearchIndex,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
@@ -514,6 +645,32 @@ pub enum ScrubCmd {
 	},
 }
 
+impl From<RepairWhat> for RepairType {
+	fn from(what: RepairWhat) -> RepairType {
+		match what {
+			RepairWhat::Tables => RepairType::Tables,
+			RepairWhat::Blocks => RepairType::Blocks,
+			RepairWhat::Versions => RepairType::Versions,
+			RepairWhat::MultipartUploads => RepairType::MultipartUploads,
+			RepairWhat::BlockRefs => RepairType::BlockRefs,
+			RepairWhat::BlockRc => RepairType::BlockRc,
+			RepairWhat::Aliases => RepairType::Aliases,
+			RepairWhat::Scrub { cmd } => RepairType::Scrub(match cmd {
+				ScrubCmd::Start => ScrubCommand::Start,
+				ScrubCmd::Pause => ScrubCommand::Pause,
+				ScrubCmd::Resume => ScrubCommand::Resume,
+				ScrubCmd::Cancel => ScrubCommand::Cancel,
+				ScrubCmd::SetTranquility { tranquility } => {
+					ScrubCommand::SetTranquility(tranquility)
+				}
+			}),
+			RepairWhat::MetaScrub => RepairType::MetaScrub,
+			RepairWhat::Rebalance => RepairType::Rebalance,
+			RepairWhat::SearchIndex => RepairType::SearchIndex,
+		}
+	}
+}
+
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
 pub struct OfflineRepairOpt {
 	/// Confirm the launch of the repair operation
@@ -524,6 +681,8 @@ pub struct OfflineRepairOpt {
 	pub what: OfflineRepairWhat,
 }
 
+// Variant names follow the names of the repair subcommands
+#[allow(clippy::enum_variant_names)]
 #[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
 pub enum OfflineRepairWhat {
 	/// Repair K2V item counters
@@ -533,6 +692,9 @@ pub enum OfflineRepairWhat {
 	/// Repair object counters
 	#[structopt(name = "object_counters", version = garage_version())]
 	ObjectCounters,
+	/// Repair object counters per prefix, e.g. after `prefix_usage_depths` was changed
+	#[structopt(name = "prefix_counters", version = garage_version())]
+	PrefixCounters,
 }
 
 #[derive(Serialize, Deserialize, StructOpt, Debug, Clone)]
@@ -622,7 +784,7 @@ pub enum BlockOperation {
 	},
 }
 
-#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone, Copy)]
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
 pub enum MetaOperation {
 	/// Save a snapshot of the metadata db file
 	#[structopt(name = "snapshot", version = garage_version())]
@@ -631,4 +793,84 @@ pub enum MetaOperation {
 		#[structopt(long = "all")]
 		all: bool,
 	},
+	/// Migrate the metadata db of the node to another engine, without stopping it
+	#[structopt(name = "migrate", version = garage_version())]
+	Migrate {
+		/// Engine to migrate to (lmdb, sqlite or redb; limited by db engines
+		/// enabled in this build)
+		engine: String,
+		/// Confirm the operation
+		#[structopt(long = "yes")]
+		yes: bool,
+	},
+	/// Show the tombstones waiting to be garbage collected in each table
+	#[structopt(name = "gc-queue", version = garage_version())]
+	GcQueue {
+		/// Show the queues of all nodes instead of only local node
+		#[structopt(short = "a", long = "all-nodes")]
+		all_nodes: bool,
+	},
+	/// Download a metadata snapshot from the S3 bucket set in `metadata_snapshots_s3`
+	/// and install it as the metadata db of this node (Garage must be stopped)
+	#[structopt(name = "restore", version = garage_version())]
+	Restore(MetaRestoreOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct MetaRestoreOpt {
+	/// Snapshot to restore (the most recent one if not set)
+	pub snapshot: Option<String>,
+
+	/// Only list the snapshots available in the S3 bucket
+	#[structopt(long = "list")]
+	pub list: bool,
+
+	/// Restore a snapshot uploaded by another node (full node ID),
+	/// for instance if the node key of this node has been lost
+	#[structopt(long = "node")]
+	pub node: Option<String>,
+
+	/// Confirm the operation
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub enum AuditOperation {
+	/// List changes made to buckets, keys, admin tokens and the cluster layout
+	#[structopt(name = "list", version = garage_version())]
+	List(AuditListOpt),
+}
+
+#[derive(Serialize, Deserialize, StructOpt, Debug, Eq, PartialEq, Clone)]
+pub struct AuditListOpt {
+	/// Only show changes made at or after this date (RFC 3339 format)
+	#[structopt(long = "since")]
+	pub since: Option<String>,
+
+	/// Only show changes made before this date (RFC 3339 format)
+	#[structopt(long = "until")]
+	pub until: Option<String>,
+
+	/// Maximum number of changes to show
+	#[structopt(long = "limit", default_value = "100")]
+	pub limit: usize,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ApplyOpt {
+	/// Manifest file in YAML or JSON format (`-` to read from standard input)
+	#[structopt(short = "f", long = "file")]
+	pub file: PathBuf,
+
+	/// Apply the changes; without this flag, only print them
+	#[structopt(long = "yes")]
+	pub yes: bool,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct ExportOpt {
+	/// Include the secrets of access keys in the manifest
+	#[structopt(long = "show-secrets")]
+	pub show_secrets: bool,
 }
@@ -10,10 +10,18 @@ use garage_util::time::*;
 
 use garage_block::manager::BlockResyncErrorInfo;
 
+use garage_api_admin::drain::{NodeDrainState, NodeDrainStatusResponse};
+use garage_api_admin::layout_simulate::{LayoutSimulationResponse, LayoutTransferResp};
+use garage_api_admin::node::{
+	ClusterStatisticsResponse, FreeSpaceResp, NodeGcQueueResponse, NodeStatisticsResponse,
+};
+
+use garage_model::audit_table::AuditRecord;
 use garage_model::bucket_table::*;
 use garage_model::key_table::*;
 use garage_model::s3::mpu_table::{self, MultipartUpload};
 use garage_model::s3::object_table;
+use garage_model::s3::usage_history_table::{UsageResolution, UsageSample};
 use garage_model::s3::version_table::*;
 
 use crate::cli::structs::WorkerListOpt;
@@ -132,6 +140,136 @@ pub fn print_key_info(key: &Key, relevant_buckets: &HashMap<Uuid, Bucket>) {
 	}
 }
 
+pub fn print_bucket_usage(
+	bucket_id: &Uuid,
+	counters: &HashMap<String, i64>,
+	prefix_usage_depths: &[usize],
+	prefixes: Option<&[(String, HashMap<String, i64>)]>,
+) {
+	let size = |c: &HashMap<String, i64>| {
+		bytesize::ByteSize::b(*c.get(object_table::BYTES).unwrap_or(&0) as u64)
+	};
+
+	println!("Bucket: {}", hex::encode(bucket_id));
+	println!(
+		"\nSize: {} ({})",
+		size(counters).to_string_as(true),
+		size(counters).to_string_as(false)
+	);
+	println!(
+		"Objects: {}",
+		*counters.get(object_table::OBJECTS).unwrap_or(&0)
+	);
+	println!(
+		"Unfinished uploads: {}",
+		*counters.get(object_table::UNFINISHED_UPLOADS).unwrap_or(&0)
+	);
+
+	let prefixes = match prefixes {
+		Some(p) => p,
+		None => return,
+	};
+	if prefix_usage_depths.is_empty() {
+		println!("\nUsage is not counted per prefix, set `prefix_usage_depths` in the configuration file to enable it.");
+		return;
+	}
+	println!(
+		"\nUsage by prefix (depths {}):",
+		prefix_usage_depths
+			.iter()
+			.map(|d| d.to_string())
+			.collect::<Vec<_>>()
+			.join(", ")
+	);
+	let mut table = vec!["  Prefix\tSize\tObjects\tUnfinished uploads".to_string()];
+	for (prefix, c) in prefixes.iter() {
+		table.push(format!(
+			"  {}\t{}\t{}\t{}",
+			prefix,
+			size(c).to_string_as(true),
+			*c.get(object_table::OBJECTS).unwrap_or(&0),
+			*c.get(object_table::UNFINISHED_UPLOADS).unwrap_or(&0)
+		));
+	}
+	format_table(table);
+}
+
+pub fn print_bucket_usage_history(
+	bucket_id: &Uuid,
+	resolution: UsageResolution,
+	samples: &[UsageSample],
+) {
+	println!("Bucket: {}", hex::encode(bucket_id));
+
+	let (first, last) = match (samples.first(), samples.last()) {
+		(Some(first), Some(last)) => (first, last),
+		_ => {
+			println!("\nNo {} usage samples in this time range.", resolution);
+			return;
+		}
+	};
+	println!(
+		"\nUsage ({}) from {} to {}:",
+		resolution,
+		msec_to_rfc3339(first.period_start),
+		msec_to_rfc3339(last.period_start + resolution.period_msec())
+	);
+
+	// One character per period, periods without a sample are left blank
+	let period = resolution.period_msec();
+	let n_periods = ((last.period_start - first.period_start) / period + 1) as usize;
+	let mut bytes = vec![None; n_periods];
+	let mut objects = vec![None; n_periods];
+	for s in samples.iter() {
+		let i = ((s.period_start - first.period_start) / period) as usize;
+		bytes[i] = Some(s.bytes);
+		objects[i] = Some(s.objects);
+	}
+
+	let size = |b: i64| bytesize::ByteSize::b(b.max(0) as u64).to_string_as(true);
+	let min_bytes = samples.iter().map(|s| s.min_bytes).min().unwrap_or(0);
+	let max_bytes = samples.iter().map(|s| s.max_bytes).max().unwrap_or(0);
+	let min_objects = samples.iter().map(|s| s.objects).min().unwrap_or(0);
+	let max_objects = samples.iter().map(|s| s.objects).max().unwrap_or(0);
+
+	format_table(vec![
+		format!(
+			"  Size\t{}\tmin: {}\tmax: {}\tlast: {}",
+			sparkline(&bytes),
+			size(min_bytes),
+			size(max_bytes),
+			size(last.bytes)
+		),
+		format!(
+			"  Objects\t{}\tmin: {}\tmax: {}\tlast: {}",
+			sparkline(&objects),
+			min_objects,
+			max_objects,
+			last.objects
+		),
+	]);
+}
+
+/// Draw a series of values as a line of block characters,
+/// scaled between the smallest and largest values of the series
+fn sparkline(values: &[Option<i64>]) -> String {
+	const BARS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+	let min = values.iter().flatten().min().copied().unwrap_or(0);
+	let max = values.iter().flatten().max().copied().unwrap_or(0);
+	values
+		.iter()
+		.map(|v| match v {
+			None => ' ',
+			Some(_) if max == min => BARS[0],
+			Some(v) => {
+				let level = (*v - min) as f64 / (max - min) as f64 * (BARS.len() - 1) as f64;
+				BARS[level.round() as usize]
+			}
+		})
+		.collect()
+}
+
 pub fn print_bucket_info(
 	bucket: &Bucket,
 	relevant_keys: &HashMap<String, Key>,
@@ -176,7 +314,12 @@ pub fn print_bucket_info(
 				mpu_size.to_string_as(false),
 			);
 
+			if let Some(rf) = p.replication_factor.get() {
+				println!("Replication factor: {}", rf);
+			}
+
 			println!("\nWebsite access: {}", p.website_config.get().is_some());
+			println!("Search index: {}", p.search_index.get());
 
 			let quotas = p.quotas.get();
 			if quotas.max_size.is_some() || quotas.max_objects.is_some() {
@@ -375,6 +518,271 @@ pub fn print_worker_vars(wv: Vec<(Uuid, String, String)>) {
 	format_table(table);
 }
 
+pub fn print_node_stats(stats: &NodeStatisticsResponse) {
+	println!(
+		"\nGarage version: {} [features: {}]\nRust compiler version: {}",
+		stats.garage_version,
+		stats
+			.garage_features
+			.as_ref()
+			.map(|list| list.join(", "))
+			.unwrap_or_else(|| "(unknown)".into()),
+		stats.rust_version,
+	);
+
+	println!("\nDatabase engine: {}", stats.db_engine);
+
+	// Table statistics
+	println!("\nTable stats:");
+	let mut table = vec!["  Table\tItems\tMklItems\tMklTodo\tGcTodo".into()];
+	for t in stats.table_stats.iter() {
+		table.push(format!(
+			"  {}\t{}\t{}\t{}\t{}",
+			t.table_name, t.items, t.merkle_items, t.merkle_todo, t.gc_todo
+		));
+	}
+	format_table(table);
+
+	// Block manager statistics
+	let bm = &stats.block_manager_stats;
+	println!("\nBlock manager stats:");
+	println!(
+		"  number of RC entries (~= number of blocks): {}",
+		bm.rc_entries
+	);
+	println!("  resync queue length: {}", bm.resync_queue_length);
+	println!("  blocks with resync errors: {}", bm.resync_errors);
+}
+
+pub fn print_gc_queue(nodes: Vec<(Uuid, Result<NodeGcQueueResponse, String>)>) {
+	let mut tf = timeago::Formatter::new();
+	tf.ago("");
+
+	let several = nodes.len() > 1;
+	for (node, res) in nodes {
+		if several {
+			println!("\n======================");
+			println!("GC queue of node {:?}:", node);
+		}
+		let queue = match res {
+			Ok(queue) => queue,
+			Err(e) => {
+				println!("{}", e);
+				continue;
+			}
+		};
+
+		let mut table = vec!["Table\tPending\tRetention\tOldest tombstone".into()];
+		for t in queue.tables.iter() {
+			let oldest = match t.oldest_tombstone_age_secs {
+				None => "-".to_string(),
+				Some(age) if age > t.tombstone_retention_secs => {
+					format!("{} (overdue)", tf.convert(Duration::from_secs(age)))
+				}
+				Some(age) => tf.convert(Duration::from_secs(age)),
+			};
+			table.push(format!(
+				"{}\t{}\t{}\t{}",
+				t.table_name,
+				t.pending,
+				tf.convert(Duration::from_secs(t.tombstone_retention_secs)),
+				oldest
+			));
+		}
+		format_table(table);
+	}
+}
+
+pub fn print_node_drain_status(status: &NodeDrainStatusResponse) {
+	let unknown = || "(unknown)".to_string();
+	println!("Node: {}", status.node);
+	println!("State: {}", status.state);
+	match status.state {
+		NodeDrainState::Active => {
+			println!("The node still has a role in the cluster layout.");
+			return;
+		}
+		NodeDrainState::RemovalStaged => {
+			println!("The removal of the node is staged, but the drain has not started yet.");
+			println!(
+				"Review the staged changes with `garage layout show`, and start the drain with:"
+			);
+			println!(
+				"    garage layout apply --version {}",
+				status.apply_layout_version.unwrap_or_default()
+			);
+			return;
+		}
+		_ => (),
+	}
+
+	println!("Progress: {:.1}%", status.progress);
+	println!();
+	format_table(vec![
+		format!("Partitions previously stored:\t{}", status.partitions),
+		format!(
+			"  metadata synced by new nodes:\t{}",
+			status.partitions_synced
+		),
+		format!("  fully handed over:\t{}", status.partitions_handed_over),
+		format!(
+			"Still in an active layout version:\t{}",
+			if status.layout_drained { "no" } else { "yes" }
+		),
+		format!(
+			"Blocks still referenced:\t{}",
+			status
+				.blocks_remaining
+				.map(|x| x.to_string())
+				.unwrap_or_else(unknown)
+		),
+		format!(
+			"Resync queue length:\t{}",
+			status
+				.resync_queue_length
+				.map(|x| x.to_string())
+				.unwrap_or_else(unknown)
+		),
+	]);
+	if let Some(e) = &status.error {
+		println!();
+		println!("Could not get block information from node: {}", e);
+	}
+
+	println!();
+	if status.state == NodeDrainState::SafeToRemove {
+		println!("All data has been transferred, the node can now be shut down.");
+	} else {
+		println!("Data is still being transferred, do not shut down the node yet.");
+	}
+}
+
+pub fn print_layout_simulation(res: &LayoutSimulationResponse) {
+	for line in res.message.iter() {
+		println!("{}", line);
+	}
+	println!();
+	println!(
+		"==== SIMULATED CHANGE: LAYOUT VERSION {} -> {} (NOT APPLIED) ====",
+		res.current_version, res.new_version
+	);
+	format_table(vec![
+		format!("Partitions moved:\t{}", res.partitions_moved),
+		format!("Partition copies to transfer:\t{}", res.partition_transfers),
+		format!(
+			"Estimated blocks to transfer:\t{} (of {} blocks)",
+			res.estimated_blocks_moved, res.total_blocks
+		),
+		format!(
+			"Estimated data to transfer:\t{} (of {} of objects)",
+			bytesize::ByteSize::b(res.estimated_bytes_moved),
+			bytesize::ByteSize::b(res.total_bytes)
+		),
+	]);
+
+	let print_matrix = |title: &str, transfers: &[LayoutTransferResp], short: usize| {
+		println!();
+		println!("==== {} ====", title);
+		if transfers.is_empty() {
+			println!("No data would be transferred.");
+			return;
+		}
+		let mut table = vec!["From\tTo\tPartitions\tBlocks\tData".to_string()];
+		for t in transfers.iter() {
+			let from = match &t.from {
+				Some(x) => x.chars().take(short).collect::<String>(),
+				None => "(new data)".into(),
+			};
+			table.push(format!(
+				"{}\t{}\t{}\t{}\t{}",
+				from,
+				t.to.chars().take(short).collect::<String>(),
+				t.partitions,
+				t.blocks,
+				bytesize::ByteSize::b(t.bytes)
+			));
+		}
+		format_table(table);
+	};
+	print_matrix("TRANSFERS BY ZONE", &res.by_zone, usize::MAX);
+	print_matrix("TRANSFERS BY NODE", &res.by_node, 16);
+
+	if !res.errors.is_empty() {
+		println!();
+		println!("Could not get block counts from some nodes, their partitions are assumed to hold an average number of blocks:");
+		for (node, err) in res.errors.iter() {
+			println!("  {}: {}", node, err);
+		}
+	}
+}
+
+pub fn print_cluster_stats(stats: &ClusterStatisticsResponse) {
+	let avail_str = |x: &Option<FreeSpaceResp>| match x {
+		Some(fs) => {
+			let pct = (fs.available as f64) / (fs.total as f64) * 100.;
+			let avail = bytesize::ByteSize::b(fs.available);
+			let total = bytesize::ByteSize::b(fs.total);
+			format!("{}/{} ({:.1}%)", avail, total, pct)
+		}
+		None => "?".into(),
+	};
+
+	println!("Storage nodes:");
+	let mut table = vec!["  ID\tHostname\tZone\tCapacity\tPart.\tDataAvail\tMetaAvail".into()];
+	for node in stats.storage_nodes.iter() {
+		table.push(format!(
+			"  {}\t{}\t{}\t{}\t{}\t{}\t{}",
+			&node.id[..16],
+			node.hostname.as_deref().unwrap_or("?"),
+			node.zone.as_deref().unwrap_or("?"),
+			node.capacity
+				.map(|c| bytesize::ByteSize::b(c).to_string_as(false))
+				.unwrap_or_else(|| "?".into()),
+			node.partitions,
+			avail_str(&node.data_partition),
+			avail_str(&node.metadata_partition),
+		));
+	}
+	format_table(table);
+
+	if let Some(space) = &stats.estimated_available_space {
+		let data_avail = bytesize::ByteSize(space.data);
+		let meta_avail = bytesize::ByteSize(space.metadata);
+		println!("\nEstimated available storage space cluster-wide (might be lower in practice):");
+		if !space.precise {
+			println!("  data: < {}", data_avail);
+			println!("  metadata: < {}", meta_avail);
+			println!("A precise estimate could not be given as information is missing for some storage nodes.");
+		} else {
+			println!("  data: {}", data_avail);
+			println!("  metadata: {}", meta_avail);
+		}
+	}
+}
+
+pub fn print_audit_records(records: Vec<AuditRecord>) {
+	if records.is_empty() {
+		println!("No changes recorded in the audit log.");
+		return;
+	}
+	for r in records {
+		println!(
+			"{}  {}  {}  {} (node {:?})",
+			msec_to_rfc3339(r.timestamp),
+			r.operation,
+			r.target,
+			r.actor,
+			r.node
+		);
+		if let Some(before) = &r.before {
+			println!("    before: {}", before);
+		}
+		if let Some(after) = &r.after {
+			println!("    after:  {}", after);
+		}
+	}
+}
+
 pub fn print_block_error_list(el: Vec<BlockResyncErrorInfo>) {
 	let now = now_msec();
 	let tf = timeago::Formatter::new();
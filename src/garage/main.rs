@@ -5,10 +5,13 @@
 extern crate tracing;
 
 mod admin;
+mod bootstrap;
 mod cli;
 mod repair;
 mod secrets;
 mod server;
+#[cfg(test)]
+mod test_util;
 #[cfg(feature = "telemetry-otlp")]
 mod tracing_setup;
 
@@ -62,6 +65,10 @@ struct Opt {
 	)]
 	pub config_file: PathBuf,
 
+	/// Output results as JSON instead of human-readable text
+	#[structopt(long = "json", global = true)]
+	pub json: bool,
+
 	#[structopt(subcommand)]
 	cmd: Command,
 }
@@ -80,6 +87,8 @@ async fn main() {
 		"consul-discovery",
 		#[cfg(feature = "kubernetes-discovery")]
 		"kubernetes-discovery",
+		#[cfg(feature = "dns-discovery")]
+		"dns-discovery",
 		#[cfg(feature = "metrics")]
 		"metrics",
 		#[cfg(feature = "telemetry-otlp")]
@@ -150,8 +159,11 @@ async fn main() {
 		Command::ConvertDb(conv_opt) => {
 			cli::convert_db::do_conversion(conv_opt).map_err(From::from)
 		}
+		Command::Meta(MetaOperation::Restore(restore_opt)) => {
+			cli::restore::meta_restore(opt.config_file, opt.secrets, restore_opt).await
+		}
 		Command::Node(NodeOperation::NodeId(node_id_opt)) => {
-			node_id_command(opt.config_file, node_id_opt.quiet)
+			node_id_command(opt.config_file, node_id_opt.quiet, opt.json)
 		}
 		_ => cli_command(opt).await,
 	};
@@ -284,7 +296,15 @@ async fn cli_command(opt: Opt) -> Result<(), Error> {
 	let system_rpc_endpoint = netapp.endpoint::<SystemRpc, ()>(SYSTEM_RPC_PATH.into());
 	let admin_rpc_endpoint = netapp.endpoint::<AdminRpc, ()>(ADMIN_RPC_PATH.into());
 
-	match cli_command_dispatch(opt.cmd, &system_rpc_endpoint, &admin_rpc_endpoint, id).await {
+	match cli_command_dispatch(
+		opt.cmd,
+		&system_rpc_endpoint,
+		&admin_rpc_endpoint,
+		id,
+		opt.json,
+	)
+	.await
+	{
 		Err(HelperError::Internal(i)) => Err(Error::Message(format!("Internal error: {}", i))),
 		Err(HelperError::BadRequest(b)) => Err(Error::Message(b)),
 		Err(e) => Err(Error::Message(format!("{}", e))),
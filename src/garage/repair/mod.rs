@@ -1,2 +1 @@
 pub mod offline;
-pub mod online;
@@ -39,6 +39,14 @@ pub async fn offline_repair(
 				.object_counter_table
 				.offline_recount_all(&garage.object_table)?;
 		}
+		OfflineRepairWhat::PrefixCounters => {
+			let depths = &garage.object_table.data.instance.prefix_usage_depths;
+			garage
+				.prefix_counter_table
+				.offline_recount_all_with(&garage.object_table, |object| {
+					object.prefix_usage(depths)
+				})?;
+		}
 	}
 
 	info!("Repair operation finished, shutting down...");
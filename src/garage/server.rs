@@ -7,6 +7,7 @@ use garage_util::config::*;
 use garage_util::error::Error;
 
 use garage_api_admin::api_server::AdminApiServer;
+use garage_api_admin::node::AdminNodeRpcHandler;
 use garage_api_s3::api_server::S3ApiServer;
 use garage_model::garage::Garage;
 use garage_web::WebServer;
@@ -15,6 +16,7 @@ use garage_web::WebServer;
 use garage_api_k2v::api_server::K2VApiServer;
 
 use crate::admin::*;
+use crate::bootstrap::run_bootstrap;
 use crate::secrets::{fill_secrets, Secrets};
 #[cfg(feature = "telemetry-otlp")]
 use crate::tracing_setup::*;
@@ -64,8 +66,9 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	}
 
 	info!("Initialize Admin API server and metrics collector...");
+	let admin_node_rpc = AdminNodeRpcHandler::new(garage.clone(), background.clone());
 	let admin_server = AdminApiServer::new(
-		garage.clone(),
+		admin_node_rpc.clone(),
 		#[cfg(feature = "metrics")]
 		metrics_exporter,
 	);
@@ -74,7 +77,16 @@ pub async fn run_server(config_file: PathBuf, secrets: Secrets) -> Result<(), Er
 	let run_system = tokio::spawn(garage.system.clone().run(watch_cancel.clone()));
 
 	info!("Create admin RPC handler...");
-	AdminRpcHandler::new(garage.clone(), background.clone());
+	AdminRpcHandler::new(garage.clone(), background.clone(), admin_node_rpc);
+
+	if let Some(bootstrap_config) = &config.bootstrap {
+		info!("Launching bootstrap of initial cluster configuration...");
+		tokio::spawn(run_bootstrap(
+			garage.clone(),
+			bootstrap_config.clone(),
+			watch_cancel.clone(),
+		));
+	}
 
 	// ---- Launch public-facing API servers ----
 
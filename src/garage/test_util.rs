@@ -0,0 +1,84 @@
+//! Single-node Garage instance running in the test process, for testing
+//! administration commands without the network layer
+use std::path::Path;
+use std::sync::Arc;
+
+use tokio::sync::watch;
+
+use garage_util::background::BackgroundRunner;
+use garage_util::config::Config;
+use garage_util::crdt::Crdt;
+
+use garage_rpc::layout::{NodeRole, NodeRoleV};
+
+use garage_api_admin::node::AdminNodeRpcHandler;
+use garage_model::garage::Garage;
+
+use crate::admin::AdminRpcHandler;
+
+/// Create a Garage instance whose metadata and data are stored in a
+/// temporary directory, which is deleted when the returned guard is dropped.
+/// The cluster layout of the instance is empty.
+pub(crate) fn test_garage() -> (Arc<Garage>, mktemp::Temp) {
+	let dir = mktemp::Temp::new_dir().unwrap();
+	(test_garage_in(&dir, ""), dir)
+}
+
+/// Create a Garage instance that stores its metadata and data in `dir`,
+/// so that a node can be restarted by calling this again with the same
+/// directory. `extra_config` is appended to the configuration file.
+pub(crate) fn test_garage_in(dir: &Path, extra_config: &str) -> Arc<Garage> {
+	// The sqlite engine is used as the database of a restarted node
+	// can be opened while the previous instance is still in memory
+	let config = format!(
+		r#"
+		metadata_dir = "{path}/meta"
+		data_dir = "{path}/data"
+		db_engine = "sqlite"
+		replication_factor = 1
+		rpc_bind_addr = "127.0.0.1:3901"
+		rpc_secret = "c3ea8cb80333d04e208d136698b1a01ae370d463f0d435ab2177510b3478bf44"
+
+		[s3_api]
+		s3_region = "garage"
+		api_bind_addr = "127.0.0.1:3900"
+
+		{extra_config}
+		"#,
+		path = dir.to_string_lossy(),
+		extra_config = extra_config,
+	);
+	let config: Config = toml::from_str(&config).unwrap();
+	Garage::new(config).unwrap()
+}
+
+/// Give a storage role to the node and apply the layout,
+/// so that writes to the metadata tables can succeed
+pub(crate) async fn apply_single_node_layout(garage: &Garage) {
+	let mut layout = garage.system.cluster_layout().inner().clone();
+	let role = NodeRole {
+		zone: "dc1".into(),
+		capacity: Some(1 << 30),
+		tags: vec![],
+	};
+	let staging = layout.staging.get_mut();
+	let update = staging
+		.roles
+		.update_mutator(garage.system.id, NodeRoleV(Some(role)));
+	staging.roles.merge(&update);
+	let (layout, _) = layout.apply_staged_changes(Some(1)).unwrap();
+	garage
+		.system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await
+		.unwrap();
+}
+
+/// Create the handler of administration commands of a Garage instance
+pub(crate) fn test_admin_rpc(garage: &Arc<Garage>) -> Arc<AdminRpcHandler> {
+	let (_, must_exit) = watch::channel(false);
+	let (background, _) = BackgroundRunner::new(must_exit);
+	let node_rpc = AdminNodeRpcHandler::new(garage.clone(), background.clone());
+	AdminRpcHandler::new(garage.clone(), background, node_rpc)
+}
@@ -0,0 +1,188 @@
+use garage_util::crdt::{self, AutoCrdt, Crdt};
+use garage_util::data::*;
+use garage_util::time::now_msec;
+
+use garage_table::{DeletedFilter, EmptyKey, Entry, TableSchema};
+
+mod v1 {
+	use garage_util::crdt;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// An admin API token, created through the admin API and stored in the cluster
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct AdminApiToken {
+		/// The public prefix of the token (immutable), used as sort key
+		pub prefix: String,
+
+		/// Internal state of the token
+		pub state: crdt::Deletable<AdminApiTokenParams>,
+	}
+
+	/// Configuration for an admin API token
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct AdminApiTokenParams {
+		/// Creation date of the token, in msec since UNIX epoch (immutable)
+		pub created: u64,
+
+		/// Hash of the secret part of the token (immutable)
+		pub token_hash: String,
+
+		/// Name of the token
+		pub name: crdt::Lww<String>,
+
+		/// Expiration date of the token, in msec since UNIX epoch
+		pub expiration: crdt::Lww<Option<u64>>,
+
+		/// What the token is allowed to do
+		pub scope: crdt::Lww<AdminApiTokenScope>,
+
+		/// Last time the token was used to authenticate a request,
+		/// in msec since UNIX epoch (0 if never used)
+		pub last_used: u64,
+	}
+
+	/// The set of operations an admin API token gives access to
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
+	pub struct AdminApiTokenScope {
+		/// Names of the admin API endpoints the token may call,
+		/// or `*` to allow all of them
+		pub endpoints: Vec<String>,
+
+		/// If set, the token can only be used on these buckets
+		pub buckets: Option<Vec<Uuid>>,
+	}
+
+	impl garage_util::migrate::InitialFormat for AdminApiToken {
+		const VERSION_MARKER: &'static [u8] = b"G1admtok";
+	}
+}
+
+pub use v1::*;
+
+/// Minimum delay between two updates of the last-used timestamp of a token,
+/// so that tokens used by scripts in a loop don't cause a write on every request.
+pub const LAST_USED_RESOLUTION_MSEC: u64 = 60 * 1000;
+
+impl AdminApiTokenScope {
+	/// Check whether the endpoint whose name is given is allowed by this scope
+	pub fn allows_endpoint(&self, endpoint: &str) -> bool {
+		self.endpoints.iter().any(|x| x == "*" || x == endpoint)
+	}
+
+	/// Check whether the endpoint whose name is given is listed by name in this
+	/// scope, not only allowed through the `*` wildcard
+	pub fn allows_endpoint_explicitly(&self, endpoint: &str) -> bool {
+		self.endpoints.iter().any(|x| x == endpoint)
+	}
+
+	/// Check whether the given bucket is allowed by this scope
+	pub fn allows_bucket(&self, bucket_id: &Uuid) -> bool {
+		self.buckets
+			.as_ref()
+			.map(|b| b.contains(bucket_id))
+			.unwrap_or(true)
+	}
+}
+
+impl AutoCrdt for AdminApiTokenScope {
+	const WARN_IF_DIFFERENT: bool = true;
+}
+
+impl Crdt for AdminApiTokenParams {
+	fn merge(&mut self, o: &Self) {
+		self.name.merge(&o.name);
+		self.expiration.merge(&o.expiration);
+		self.scope.merge(&o.scope);
+		self.last_used = std::cmp::max(self.last_used, o.last_used);
+	}
+}
+
+impl AdminApiToken {
+	/// Create a new token, returning the token entry and the secret bearer token
+	/// that must be given to the user. Only a hash of the secret is stored.
+	pub fn new(name: &str, hash_secret: impl FnOnce(&str) -> String) -> (Self, String) {
+		let prefix = hex::encode(&rand::random::<[u8; 12]>()[..]);
+		let secret = hex::encode(&rand::random::<[u8; 32]>()[..]);
+		let token = format!("{}.{}", prefix, secret);
+
+		let params = AdminApiTokenParams {
+			created: now_msec(),
+			token_hash: hash_secret(&secret),
+			name: crdt::Lww::new(name.to_string()),
+			expiration: crdt::Lww::new(None),
+			scope: crdt::Lww::new(AdminApiTokenScope {
+				endpoints: vec![],
+				buckets: None,
+			}),
+			last_used: 0,
+		};
+
+		let entry = Self {
+			prefix,
+			state: crdt::Deletable::present(params),
+		};
+		(entry, token)
+	}
+
+	/// Create a new AdminApiToken which can me merged to mark an existing token deleted
+	pub fn delete(prefix: String) -> Self {
+		Self {
+			prefix,
+			state: crdt::Deletable::Deleted,
+		}
+	}
+
+	/// Returns true if this represents a deleted token
+	pub fn is_deleted(&self) -> bool {
+		self.state.is_deleted()
+	}
+
+	/// Returns an option representing the params (None if in deleted state)
+	pub fn params(&self) -> Option<&AdminApiTokenParams> {
+		self.state.as_option()
+	}
+
+	/// Mutable version of `.params()`
+	pub fn params_mut(&mut self) -> Option<&mut AdminApiTokenParams> {
+		self.state.as_option_mut()
+	}
+
+	/// Returns true if the token has an expiration date that is in the past
+	pub fn is_expired(&self, now: u64) -> bool {
+		self.params()
+			.and_then(|p| *p.expiration.get())
+			.map(|exp| exp <= now)
+			.unwrap_or(false)
+	}
+}
+
+impl Entry<EmptyKey, String> for AdminApiToken {
+	fn partition_key(&self) -> &EmptyKey {
+		&EmptyKey
+	}
+	fn sort_key(&self) -> &String {
+		&self.prefix
+	}
+}
+
+impl Crdt for AdminApiToken {
+	fn merge(&mut self, other: &Self) {
+		self.state.merge(&other.state);
+	}
+}
+
+pub struct AdminApiTokenTable;
+
+impl TableSchema for AdminApiTokenTable {
+	const TABLE_NAME: &'static str = "admin_token";
+
+	type P = EmptyKey;
+	type S = String;
+	type E = AdminApiToken;
+	type Filter = DeletedFilter;
+
+	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
+		filter.apply(entry.is_deleted())
+	}
+}
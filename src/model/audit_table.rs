@@ -0,0 +1,106 @@
+use garage_util::crdt::Crdt;
+use garage_util::data::*;
+
+use garage_table::{EmptyKey, Entry, TableSchema};
+
+mod v1 {
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// A record of a change made to the configuration of the cluster,
+	/// through the admin API or the CLI. Audit records are never modified
+	/// once written.
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct AuditRecord {
+		/// Timestamp in hex followed by a random suffix, used as sort key
+		/// so that records are listed in chronological order
+		pub id: String,
+
+		/// Time at which the change was made, in msec since UNIX epoch
+		pub timestamp: u64,
+
+		/// Node on which the change was made
+		pub node: Uuid,
+
+		/// Who made the change: admin API token or CLI
+		pub actor: String,
+
+		/// Name of the operation, e.g. `CreateBucket`
+		pub operation: String,
+
+		/// The object that was modified, e.g. `bucket:<id>` or `key:<id>`
+		pub target: String,
+
+		/// Summary of the modified object before the change
+		pub before: Option<String>,
+
+		/// Summary of the modified object after the change
+		pub after: Option<String>,
+	}
+
+	impl garage_util::migrate::InitialFormat for AuditRecord {
+		const VERSION_MARKER: &'static [u8] = b"G1audit";
+	}
+}
+
+pub use v1::*;
+
+impl AuditRecord {
+	pub fn new(
+		timestamp: u64,
+		node: Uuid,
+		actor: String,
+		operation: String,
+		target: String,
+		before: Option<String>,
+		after: Option<String>,
+	) -> Self {
+		let suffix = hex::encode(&rand::random::<[u8; 8]>()[..]);
+		Self {
+			id: format!("{}{}", audit_sort_key_prefix(timestamp), suffix),
+			timestamp,
+			node,
+			actor,
+			operation,
+			target,
+			before,
+			after,
+		}
+	}
+}
+
+/// Returns the prefix of the sort keys of audit records made at a given
+/// time, which can be used as the start of a range query
+pub fn audit_sort_key_prefix(timestamp: u64) -> String {
+	format!("{:016x}", timestamp)
+}
+
+impl Entry<EmptyKey, String> for AuditRecord {
+	fn partition_key(&self) -> &EmptyKey {
+		&EmptyKey
+	}
+	fn sort_key(&self) -> &String {
+		&self.id
+	}
+}
+
+impl Crdt for AuditRecord {
+	fn merge(&mut self, _other: &Self) {
+		// Audit records are immutable, all copies are identical
+	}
+}
+
+pub struct AuditTable;
+
+impl TableSchema for AuditTable {
+	const TABLE_NAME: &'static str = "audit";
+
+	type P = EmptyKey;
+	type S = String;
+	type E = AuditRecord;
+	type Filter = ();
+
+	fn matches_filter(_entry: &Self::E, _filter: &Self::Filter) -> bool {
+		true
+	}
+}
@@ -54,6 +54,15 @@ mod v08 {
 		/// Bucket quotas
 		#[serde(default)]
 		pub quotas: crdt::Lww<BucketQuotas>,
+		/// Number of copies kept of the data blocks of objects in this bucket,
+		/// if lower than the replication factor of the cluster
+		/// (set at bucket creation)
+		#[serde(default)]
+		pub replication_factor: crdt::Lww<Option<usize>>,
+		/// Whether the metadata of objects in this bucket is indexed
+		/// in the object search table
+		#[serde(default)]
+		pub search_index: crdt::Lww<bool>,
 	}
 
 	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
@@ -137,6 +146,8 @@ impl BucketParams {
 			cors_config: crdt::Lww::new(None),
 			lifecycle_config: crdt::Lww::new(None),
 			quotas: crdt::Lww::new(BucketQuotas::default()),
+			replication_factor: crdt::Lww::new(None),
+			search_index: crdt::Lww::new(false),
 		}
 	}
 }
@@ -153,6 +164,8 @@ impl Crdt for BucketParams {
 		self.cors_config.merge(&o.cors_config);
 		self.lifecycle_config.merge(&o.lifecycle_config);
 		self.quotas.merge(&o.quotas);
+		self.replication_factor.merge(&o.replication_factor);
+		self.search_index.merge(&o.search_index);
 	}
 }
 
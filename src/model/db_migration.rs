@@ -0,0 +1,270 @@
+//! Migration of the metadata database to another engine while Garage is running
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_db as db;
+use garage_db::migrate::MigrationStep;
+
+use garage_util::background::*;
+use garage_util::config::Config;
+use garage_util::error::*;
+
+use crate::garage::Garage;
+
+/// File in the metadata directory that records the result of an online
+/// migration, so that the new database is also used after a restart
+const MIGRATED_ENGINE_FILE: &str = "db_engine_migrated";
+
+/// Number of batches of items copied in each step of the worker
+const BATCHES_PER_STEP: usize = 10;
+
+/// Path of the database for a given engine in the metadata directory
+pub fn db_path(metadata_dir: &Path, engine: db::Engine) -> PathBuf {
+	let mut db_path = metadata_dir.to_path_buf();
+	match engine {
+		db::Engine::Sqlite => {
+			db_path.push("db.sqlite");
+		}
+		db::Engine::Lmdb => {
+			db_path.push("db.lmdb");
+		}
+		db::Engine::Redb => {
+			db_path.push("db.redb");
+		}
+		db::Engine::Memory => (),
+	}
+	db_path
+}
+
+/// Options to open the database, from the configuration
+pub fn db_open_opt(config: &Config) -> db::OpenOpt {
+	db::OpenOpt {
+		fsync: config.metadata_fsync,
+		lmdb_map_size: match config.lmdb_map_size {
+			v if v == usize::default() => None,
+			v => Some(v),
+		},
+	}
+}
+
+/// Engine of the database to open at startup: the one from the configuration,
+/// unless the database has been migrated online to another engine
+pub fn startup_engine(config: &Config) -> Result<db::Engine, Error> {
+	let configured = db::Engine::from_str(&config.db_engine)
+		.ok_or_message("Invalid `db_engine` value in configuration file")?;
+
+	let marker = config.metadata_dir.join(MIGRATED_ENGINE_FILE);
+	let content = match fs::read_to_string(&marker) {
+		Ok(c) => c,
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(configured),
+		Err(e) => return Err(e.into()),
+	};
+	let engines = content
+		.split_whitespace()
+		.map(db::Engine::from_str)
+		.collect::<Result<Vec<_>, _>>()
+		.ok_or_message(format!("Invalid content in {}", marker.display()))?;
+
+	match engines[..] {
+		[from, to] if from == configured && to != configured => {
+			warn!(
+				"The metadata database has been migrated from {} to {}, using it instead of the \
+				engine in the configuration file. Please set `db_engine = \"{}\"` in your \
+				configuration file.",
+				from, to, to
+			);
+			Ok(to)
+		}
+		_ => Ok(configured),
+	}
+}
+
+/// Start migrating the metadata database to another engine,
+/// in a background worker
+pub fn launch_db_migration(
+	garage: &Arc<Garage>,
+	bg: &BackgroundRunner,
+	engine: db::Engine,
+) -> Result<String, Error> {
+	if engine == db::Engine::Memory {
+		return Err(Error::Message(
+			"Cannot migrate the metadata database to the in-memory engine".into(),
+		));
+	}
+	let current = startup_engine(&garage.config)?;
+	if engine == current {
+		return Err(Error::Message(format!(
+			"The metadata database already uses the {} engine",
+			engine
+		)));
+	}
+	let path = db_path(&garage.config.metadata_dir, engine);
+	if path.exists() {
+		return Err(Error::Message(format!(
+			"{} already exists, please move or delete it before starting the migration",
+			path.display()
+		)));
+	}
+
+	info!(
+		"Starting migration of metadata database from {} to {} at {}",
+		current,
+		engine,
+		path.display()
+	);
+	let new_db = db::open_db(&path, engine, &db_open_opt(&garage.config))?;
+	if let Err(e) = garage.migratable_db.start_migration(new_db) {
+		remove_db(&path);
+		return Err(e.into());
+	}
+
+	bg.spawn_worker(DbMigrationWorker {
+		garage: garage.clone(),
+		from: current,
+		to: engine,
+		path,
+		result: None,
+	});
+	Ok(format!(
+		"Migration of the metadata database from {} to {} has started, use `garage worker list` to follow its progress.",
+		current, engine
+	))
+}
+
+fn remove_db(path: &Path) {
+	let res = if path.is_dir() {
+		fs::remove_dir_all(path)
+	} else {
+		fs::remove_file(path)
+	};
+	if let Err(e) = res {
+		warn!("Could not remove {}: {}", path.display(), e);
+	}
+}
+
+/// Write the engine of the database that is now used, so that it is
+/// used again after a restart. The marker file is replaced atomically
+/// and the change is synced to disk before returning.
+fn write_migrated_engine(config: &Config, to: db::Engine) -> Result<(), Error> {
+	let configured = db::Engine::from_str(&config.db_engine)?;
+	let marker = config.metadata_dir.join(MIGRATED_ENGINE_FILE);
+	if configured == to {
+		match fs::remove_file(&marker) {
+			Ok(()) => (),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+			Err(e) => return Err(e.into()),
+		}
+	} else {
+		let tmp = config
+			.metadata_dir
+			.join(format!("{}.tmp", MIGRATED_ENGINE_FILE));
+		let mut file = fs::File::create(&tmp)?;
+		file.write_all(format!("{} {}\n", configured, to).as_bytes())?;
+		file.sync_all()?;
+		fs::rename(&tmp, &marker)?;
+	}
+	fs::File::open(&config.metadata_dir)?.sync_all()?;
+	Ok(())
+}
+
+// ---- worker ----
+
+struct DbMigrationWorker {
+	garage: Arc<Garage>,
+	from: db::Engine,
+	to: db::Engine,
+	path: PathBuf,
+	result: Option<String>,
+}
+
+#[async_trait]
+impl Worker for DbMigrationWorker {
+	fn name(&self) -> String {
+		"Metadata DB migration".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let mut freeform = vec![format!("Migrating from {} to {}", self.from, self.to)];
+		if let Some(result) = &self.result {
+			freeform.push(result.clone());
+			return WorkerStatus {
+				freeform,
+				..Default::default()
+			};
+		}
+
+		match self.garage.migratable_db.migration_progress() {
+			Some(p) => {
+				freeform.push(format!("Items copied: {}", p.items_copied));
+				WorkerStatus {
+					progress: Some(format!("{}/{} trees", p.trees_done, p.trees_total)),
+					freeform,
+					..Default::default()
+				}
+			}
+			None => WorkerStatus {
+				freeform,
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if self.result.is_some() {
+			return Ok(WorkerState::Done);
+		}
+
+		// The new database engine is recorded before switching to the new
+		// database, so that a restart never goes back to the old database
+		// once writes have stopped being done on it
+		let garage = self.garage.clone();
+		let to = self.to;
+		let res = tokio::task::spawn_blocking(move || {
+			let record_switch = || {
+				write_migrated_engine(&garage.config, to).map_err(|e| {
+					db::Error(format!("could not record the new database engine: {}", e).into())
+				})
+			};
+			for _ in 0..BATCHES_PER_STEP {
+				if garage.migratable_db.migration_step(record_switch)? == MigrationStep::Finished {
+					return Ok(MigrationStep::Finished);
+				}
+			}
+			Ok::<_, db::Error>(MigrationStep::Continue)
+		})
+		.await
+		.unwrap();
+
+		match res {
+			Ok(MigrationStep::Continue) => Ok(WorkerState::Busy),
+			Ok(MigrationStep::Finished) => {
+				info!(
+					"Migration of metadata database to {} finished. The old database is no longer used and can be deleted. Please set `db_engine = \"{}\"` in your configuration file.",
+					self.path.display(),
+					self.to
+				);
+				self.result = Some("Migration finished".into());
+				Ok(WorkerState::Done)
+			}
+			Err(e) => {
+				self.garage.migratable_db.abort_migration();
+				remove_db(&self.path);
+				self.result = Some(format!("Migration failed: {}", e));
+				Err(Error::Message(format!(
+					"Migration of metadata database failed, the old database is still in use: {}",
+					e
+				)))
+			}
+		}
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
@@ -1,5 +1,6 @@
-use std::str::FromStr;
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
 
 use garage_net::NetworkKey;
 
@@ -14,18 +15,26 @@ use garage_rpc::replication_mode::*;
 use garage_rpc::system::System;
 
 use garage_block::manager::*;
+use garage_table::data::TableData;
 use garage_table::replication::TableFullReplication;
 use garage_table::replication::TableShardedReplication;
+use garage_table::scrub::{MetaScrubPersisted, MetaScrubShared, TableScrubWorker};
 use garage_table::*;
 
 use crate::s3::block_ref_table::*;
 use crate::s3::lifecycle_worker;
 use crate::s3::mpu_table::*;
+use crate::s3::object_search_table::*;
 use crate::s3::object_table::*;
+use crate::s3::usage_history_table::*;
+use crate::s3::usage_history_worker::UsageHistoryWorker;
 use crate::s3::version_table::*;
 
+use crate::admin_token_table::*;
+use crate::audit_table::*;
 use crate::bucket_alias_table::*;
 use crate::bucket_table::*;
+use crate::db_migration;
 use crate::helper;
 use crate::index_counter::*;
 use crate::key_table::*;
@@ -45,6 +54,8 @@ pub struct Garage {
 
 	/// The local database
 	pub db: db::Db,
+	/// Wrapper of the local database, used to migrate it to another engine
+	pub migratable_db: Arc<db::migrate::MigratableDb>,
 	/// The membership manager
 	pub system: Arc<System>,
 	/// The block manager
@@ -56,6 +67,10 @@ pub struct Garage {
 	pub bucket_alias_table: Arc<Table<BucketAliasTable, TableFullReplication>>,
 	/// Table containing api keys
 	pub key_table: Arc<Table<KeyTable, TableFullReplication>>,
+	/// Table containing admin API tokens
+	pub admin_token_table: Arc<Table<AdminApiTokenTable, TableFullReplication>>,
+	/// Table containing the audit log of configuration changes
+	pub audit_table: Arc<Table<AuditTable, TableFullReplication>>,
 
 	/// Lock to prevent concurrent modification of buckets and access keys
 	bucket_lock: tokio::sync::Mutex<()>,
@@ -64,6 +79,13 @@ pub struct Garage {
 	pub object_table: Arc<Table<ObjectTable, TableShardedReplication>>,
 	/// Counting table containing object counters
 	pub object_counter_table: Arc<IndexCounter<Object>>,
+	/// Counting table containing object counters per prefix
+	pub prefix_counter_table: Arc<IndexCounter<ObjectPrefix>>,
+	/// Table containing the history of the object counters of buckets
+	pub usage_history_table: Arc<Table<UsageHistoryTable, TableShardedReplication>>,
+	/// Table containing the searchable metadata of objects in buckets
+	/// with the search index enabled
+	pub object_search_table: Arc<Table<ObjectSearchTable, TableShardedReplication>>,
 	/// Table containing S3 multipart uploads
 	pub mpu_table: Arc<Table<MultipartUploadTable, TableShardedReplication>>,
 	/// Counting table containing multipart object counters
@@ -75,6 +97,10 @@ pub struct Garage {
 
 	/// Persister for lifecycle worker info
 	pub lifecycle_persister: PersisterShared<lifecycle_worker::LifecycleWorkerPersisted>,
+	/// Persister for metadata scrub settings
+	pub meta_scrub_persister: PersisterShared<MetaScrubPersisted>,
+	/// Held by the metadata scrub workers while they are running
+	meta_scrub_running: Mutex<Weak<()>>,
 
 	#[cfg(feature = "k2v")]
 	pub k2v: GarageK2V,
@@ -114,26 +140,13 @@ impl Garage {
 		}
 
 		info!("Opening database...");
-		let db_engine = db::Engine::from_str(&config.db_engine)
-			.ok_or_message("Invalid `db_engine` value in configuration file")?;
-		let mut db_path = config.metadata_dir.clone();
-		match db_engine {
-			db::Engine::Sqlite => {
-				db_path.push("db.sqlite");
-			}
-			db::Engine::Lmdb => {
-				db_path.push("db.lmdb");
-			}
-		}
-		let db_opt = db::OpenOpt {
-			fsync: config.metadata_fsync,
-			lmdb_map_size: match config.lmdb_map_size {
-				v if v == usize::default() => None,
-				v => Some(v),
-			},
-		};
+		let db_engine = db_migration::startup_engine(&config)?;
+		let db_path = db_migration::db_path(&config.metadata_dir, db_engine);
+		let db_opt = db_migration::db_open_opt(&config);
 		let db = db::open_db(&db_path, db_engine, &db_opt)
 			.ok_or_message("Unable to open metadata db")?;
+		let migratable_db = db::migrate::MigratableDb::new(db);
+		let db = migratable_db.db();
 
 		info!("Initializing RPC...");
 		let network_key = hex::decode(config.rpc_secret.as_ref().ok_or_message(
@@ -185,13 +198,25 @@ impl Garage {
 			&db,
 		);
 		info!("Initialize key_table_table...");
-		let key_table = Table::new(KeyTable, control_rep_param, system.clone(), &db);
+		let key_table = Table::new(KeyTable, control_rep_param.clone(), system.clone(), &db);
+
+		info!("Initialize admin_token_table...");
+		let admin_token_table = Table::new(
+			AdminApiTokenTable,
+			control_rep_param.clone(),
+			system.clone(),
+			&db,
+		);
+
+		info!("Initialize audit_table...");
+		let audit_table = Table::new(AuditTable, control_rep_param, system.clone(), &db);
 
 		// ---- S3 tables ----
 		info!("Initialize block_ref_table...");
 		let block_ref_table = Table::new(
 			BlockRefTable {
 				block_manager: block_manager.clone(),
+				store: TableData::<BlockRefTable, TableShardedReplication>::open_store(&db)?,
 			},
 			meta_rep_param.clone(),
 			system.clone(),
@@ -225,6 +250,25 @@ impl Garage {
 		info!("Initialize object counter table...");
 		let object_counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), &db);
 
+		info!("Initialize prefix counter table...");
+		let prefix_counter_table = IndexCounter::new(system.clone(), meta_rep_param.clone(), &db);
+		let mut prefix_usage_depths = config.prefix_usage_depths.clone();
+		prefix_usage_depths.sort_unstable();
+		prefix_usage_depths.dedup();
+		if prefix_usage_depths.first() == Some(&0) {
+			return Err(Error::Message(
+				"Depths in `prefix_usage_depths` must be greater than zero".into(),
+			));
+		}
+
+		info!("Initialize object_search_table...");
+		let object_search_table = Table::new(
+			ObjectSearchTable,
+			meta_rep_param.clone(),
+			system.clone(),
+			&db,
+		);
+
 		info!("Initialize object_table...");
 		#[allow(clippy::redundant_clone)]
 		let object_table = Table::new(
@@ -232,17 +276,44 @@ impl Garage {
 				version_table: version_table.clone(),
 				mpu_table: mpu_table.clone(),
 				object_counter_table: object_counter_table.clone(),
+				prefix_counter_table: prefix_counter_table.clone(),
+				prefix_usage_depths,
+				bucket_table: bucket_table.clone(),
+				search_table: object_search_table.clone(),
 			},
 			meta_rep_param.clone(),
 			system.clone(),
 			&db,
 		);
 
+		info!("Initialize usage_history_table...");
+		let usage_history_table = Table::new(
+			UsageHistoryTable,
+			meta_rep_param.clone(),
+			system.clone(),
+			&db,
+		);
+
 		info!("Load lifecycle worker state...");
 		let lifecycle_persister =
 			PersisterShared::new(&system.metadata_dir, "lifecycle_worker_state");
 		lifecycle_worker::register_bg_vars(&lifecycle_persister, &mut bg_vars);
 
+		info!("Load metadata scrub settings...");
+		let meta_scrub_persister: PersisterShared<MetaScrubPersisted> =
+			PersisterShared::new(&system.metadata_dir, "meta_scrub_info");
+		bg_vars.register_rw(
+			&meta_scrub_persister,
+			"meta-scrub-tranquility",
+			|p| p.get_with(|x| x.tranquility),
+			|p, tranquility| p.set_with(|x| x.tranquility = tranquility),
+		);
+		bg_vars.register_ro(
+			&meta_scrub_persister,
+			"meta-scrub-corruptions-detected",
+			|p| p.get_with(|x| x.corruptions_detected),
+		);
+
 		// ---- K2V ----
 		#[cfg(feature = "k2v")]
 		let k2v = GarageK2V::new(system.clone(), &db, meta_rep_param);
@@ -254,6 +325,44 @@ impl Garage {
 			// other functions could be added here if we had other tables
 			// that hold references to data blocks
 		]);
+		block_manager
+			.set_calc_replication_factor(block_ref_replication_factor_fn(&block_ref_table));
+
+		// ---- setup tombstone retention of tables ----
+		let mut tombstone_retention = config
+			.tombstone_retention
+			.iter()
+			.map(|(table, retention)| {
+				let delay = parse_duration::parse(retention)
+					.ok_or_message(format!("Invalid `tombstone_retention` for table {}", table))?;
+				Ok((table.clone(), delay))
+			})
+			.collect::<Result<HashMap<_, _>, Error>>()?;
+		set_gc_delay(&bucket_table, &mut tombstone_retention);
+		set_gc_delay(&bucket_alias_table, &mut tombstone_retention);
+		set_gc_delay(&key_table, &mut tombstone_retention);
+		set_gc_delay(&admin_token_table, &mut tombstone_retention);
+		set_gc_delay(&audit_table, &mut tombstone_retention);
+		set_gc_delay(&object_table, &mut tombstone_retention);
+		set_gc_delay(&object_counter_table.table, &mut tombstone_retention);
+		set_gc_delay(&prefix_counter_table.table, &mut tombstone_retention);
+		set_gc_delay(&usage_history_table, &mut tombstone_retention);
+		set_gc_delay(&object_search_table, &mut tombstone_retention);
+		set_gc_delay(&mpu_table, &mut tombstone_retention);
+		set_gc_delay(&mpu_counter_table.table, &mut tombstone_retention);
+		set_gc_delay(&version_table, &mut tombstone_retention);
+		set_gc_delay(&block_ref_table, &mut tombstone_retention);
+		#[cfg(feature = "k2v")]
+		{
+			set_gc_delay(&k2v.item_table, &mut tombstone_retention);
+			set_gc_delay(&k2v.counter_table.table, &mut tombstone_retention);
+		}
+		if let Some(table) = tombstone_retention.keys().next() {
+			return Err(Error::Message(format!(
+				"Unknown table {} in `tombstone_retention`",
+				table
+			)));
+		}
 
 		// -- done --
 		Ok(Arc::new(Self {
@@ -261,19 +370,27 @@ impl Garage {
 			bg_vars,
 			replication_factor,
 			db,
+			migratable_db,
 			system,
 			block_manager,
 			bucket_table,
 			bucket_alias_table,
 			key_table,
+			admin_token_table,
+			audit_table,
 			bucket_lock: tokio::sync::Mutex::new(()),
 			object_table,
 			object_counter_table,
+			prefix_counter_table,
+			usage_history_table,
+			object_search_table,
 			mpu_table,
 			mpu_counter_table,
 			version_table,
 			block_ref_table,
 			lifecycle_persister,
+			meta_scrub_persister,
+			meta_scrub_running: Mutex::new(Weak::new()),
 			#[cfg(feature = "k2v")]
 			k2v,
 		}))
@@ -285,9 +402,14 @@ impl Garage {
 		self.bucket_table.spawn_workers(bg);
 		self.bucket_alias_table.spawn_workers(bg);
 		self.key_table.spawn_workers(bg);
+		self.admin_token_table.spawn_workers(bg);
+		self.audit_table.spawn_workers(bg);
 
 		self.object_table.spawn_workers(bg);
 		self.object_counter_table.spawn_workers(bg);
+		self.prefix_counter_table.spawn_workers(bg);
+		self.usage_history_table.spawn_workers(bg);
+		self.object_search_table.spawn_workers(bg);
 		self.mpu_table.spawn_workers(bg);
 		self.mpu_counter_table.spawn_workers(bg);
 		self.version_table.spawn_workers(bg);
@@ -316,6 +438,88 @@ impl Garage {
 			));
 		}
 
+		if let Some(cfg) = &self.config.usage_history {
+			let parse = |name: &str, value: &str| {
+				parse_duration::parse(value)
+					.ok_or_message(format!("Invalid `usage_history.{}`", name))
+			};
+			let sample_interval = parse("sample_interval", &cfg.sample_interval)?;
+			if sample_interval < Duration::from_secs(60) {
+				return Err(Error::Message(
+					"usage_history.sample_interval too small or negative".into(),
+				));
+			}
+
+			let hourly_retention = parse("hourly_retention", &cfg.hourly_retention)?;
+			let daily_retention = parse("daily_retention", &cfg.daily_retention)?;
+			if hourly_retention < Duration::from_secs(3600)
+				|| daily_retention < Duration::from_secs(24 * 3600)
+			{
+				return Err(Error::Message(
+					"usage_history retention must be longer than the period of the samples".into(),
+				));
+			}
+
+			bg.spawn_worker(UsageHistoryWorker::new(
+				self.clone(),
+				sample_interval,
+				hourly_retention,
+				daily_retention,
+			));
+		}
+
+		Ok(())
+	}
+
+	/// Launch a metadata scrub worker for each table, that checks the
+	/// integrity of the items stored on this node. Fails if the workers
+	/// of a previous scrub are still running.
+	pub fn spawn_meta_scrub_workers(&self, bg: &BackgroundRunner) -> Result<(), Error> {
+		let mut running = self.meta_scrub_running.lock().unwrap();
+		if running.upgrade().is_some() {
+			return Err(Error::Message(
+				"A metadata scrub is already running on this node".into(),
+			));
+		}
+		let p = MetaScrubShared {
+			persister: self.meta_scrub_persister.clone(),
+			running: Arc::new(()),
+		};
+		*running = Arc::downgrade(&p.running);
+
+		bg.spawn_worker(TableScrubWorker::new(&self.bucket_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.bucket_alias_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.key_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.admin_token_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.audit_table, p.clone()));
+
+		bg.spawn_worker(TableScrubWorker::new(&self.object_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(
+			&self.object_counter_table.table,
+			p.clone(),
+		));
+		bg.spawn_worker(TableScrubWorker::new(
+			&self.prefix_counter_table.table,
+			p.clone(),
+		));
+		bg.spawn_worker(TableScrubWorker::new(&self.usage_history_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.object_search_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.mpu_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(
+			&self.mpu_counter_table.table,
+			p.clone(),
+		));
+		bg.spawn_worker(TableScrubWorker::new(&self.version_table, p.clone()));
+		bg.spawn_worker(TableScrubWorker::new(&self.block_ref_table, p.clone()));
+
+		#[cfg(feature = "k2v")]
+		{
+			bg.spawn_worker(TableScrubWorker::new(&self.k2v.item_table, p.clone()));
+			bg.spawn_worker(TableScrubWorker::new(
+				&self.k2v.counter_table.table,
+				p.clone(),
+			));
+		}
 		Ok(())
 	}
 
@@ -327,12 +531,31 @@ impl Garage {
 		helper::key::KeyHelper(self)
 	}
 
+	pub fn audit_helper(&self) -> helper::audit::AuditHelper<'_> {
+		helper::audit::AuditHelper(self)
+	}
+
 	pub async fn locked_helper(&self) -> helper::locked::LockedHelper {
 		let lock = self.bucket_lock.lock().await;
 		helper::locked::LockedHelper(self, Some(lock))
 	}
 }
 
+/// Use the tombstone retention from the configuration for a table, if it is set
+fn set_gc_delay<F: TableSchema, R: replication::TableReplication>(
+	table: &Table<F, R>,
+	tombstone_retention: &mut HashMap<String, Duration>,
+) {
+	if let Some(delay) = tombstone_retention.remove(F::TABLE_NAME) {
+		info!(
+			"Tombstone retention for table {}: {:?}",
+			F::TABLE_NAME,
+			delay
+		);
+		table.set_gc_delay(delay);
+	}
+}
+
 #[cfg(feature = "k2v")]
 impl GarageK2V {
 	fn new(system: Arc<System>, db: &db::Db, meta_rep_param: TableShardedReplication) -> Self {
@@ -0,0 +1,304 @@
+use std::fmt::Write;
+
+use garage_util::data::*;
+use garage_util::time::*;
+
+use garage_table::util::*;
+
+use garage_rpc::layout::{LayoutHistory, NodeRoleV};
+
+use crate::audit_table::*;
+use crate::bucket_table::Bucket;
+use crate::garage::Garage;
+use crate::helper::error::*;
+use crate::key_table::Key;
+use crate::permission::BucketKeyPerm;
+
+/// The object modified by an operation recorded in the audit log
+#[derive(Debug, Clone)]
+pub enum AuditTarget {
+	Bucket(Uuid),
+	Key(String),
+	AdminToken(String),
+	ClusterLayout,
+}
+
+impl std::fmt::Display for AuditTarget {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Bucket(id) => write!(f, "bucket:{}", hex::encode(id)),
+			Self::Key(id) => write!(f, "key:{}", id),
+			Self::AdminToken(id) => write!(f, "admin_token:{}", id),
+			Self::ClusterLayout => write!(f, "layout"),
+		}
+	}
+}
+
+pub struct AuditHelper<'a>(pub(crate) &'a Garage);
+
+impl<'a> AuditHelper<'a> {
+	/// Returns a short textual description of the current state of the target,
+	/// or None if it does not exist.
+	pub async fn summary(&self, target: &AuditTarget) -> Result<Option<String>, Error> {
+		let garage = self.0;
+		let summary = match target {
+			AuditTarget::Bucket(id) => garage
+				.bucket_table
+				.get(&EmptyKey, id)
+				.await?
+				.as_ref()
+				.and_then(bucket_summary),
+			AuditTarget::Key(id) => garage
+				.key_table
+				.get(&EmptyKey, id)
+				.await?
+				.as_ref()
+				.and_then(key_summary),
+			AuditTarget::AdminToken(id) => garage
+				.admin_token_table
+				.get(&EmptyKey, id)
+				.await?
+				.and_then(|t| {
+					let p = t.params()?;
+					let scope = p.scope.get();
+					Some(format!(
+						"name={} expiration={} scope=[{}] buckets={}",
+						p.name.get(),
+						p.expiration
+							.get()
+							.map(msec_to_rfc3339)
+							.unwrap_or_else(|| "never".into()),
+						scope.endpoints.join(","),
+						match &scope.buckets {
+							None => "all".to_string(),
+							Some(b) => format!(
+								"[{}]",
+								b.iter().map(hex::encode).collect::<Vec<_>>().join(",")
+							),
+						}
+					))
+				}),
+			AuditTarget::ClusterLayout => {
+				let layout = garage.system.cluster_layout();
+				Some(layout_summary(layout.inner()))
+			}
+		};
+		Ok(summary)
+	}
+
+	/// Append a record to the audit log. The state of the target after
+	/// the change is read from the tables, the state before the change
+	/// must be obtained by the caller using `summary()` before making the change.
+	///
+	/// The change has already been made when it is recorded, so failing to
+	/// write the record (e.g. on a new cluster that has no layout yet) is only
+	/// logged and does not make the operation fail.
+	pub async fn record(
+		&self,
+		actor: &str,
+		operation: &str,
+		target: AuditTarget,
+		before: Option<String>,
+	) {
+		match self.summary(&target).await {
+			Ok(after) => {
+				self.record_with_after(actor, operation, target, before, after)
+					.await
+			}
+			Err(e) => warn!(
+				"Could not record {} on {} in audit log: {}",
+				operation, target, e
+			),
+		}
+	}
+
+	/// Same as `record()`, for the cases where the state after the change
+	/// is known by the caller.
+	pub async fn record_with_after(
+		&self,
+		actor: &str,
+		operation: &str,
+		target: AuditTarget,
+		before: Option<String>,
+		after: Option<String>,
+	) {
+		let garage = self.0;
+		let record = AuditRecord::new(
+			now_msec(),
+			garage.system.id,
+			actor.to_string(),
+			operation.to_string(),
+			target.to_string(),
+			before,
+			after,
+		);
+		if let Err(e) = garage.audit_table.insert(&record).await {
+			warn!(
+				"Could not record {} on {} in audit log: {}",
+				record.operation, record.target, e
+			);
+		}
+	}
+
+	/// List audit records made in the given time range (bounds in msec since
+	/// UNIX epoch, `until` excluded), in chronological order.
+	pub async fn list(
+		&self,
+		since: Option<u64>,
+		until: Option<u64>,
+		limit: usize,
+	) -> Result<Vec<AuditRecord>, Error> {
+		let start = since.map(audit_sort_key_prefix);
+		let mut records = self
+			.0
+			.audit_table
+			.get_range(&EmptyKey, start, None, limit, EnumerationOrder::Forward)
+			.await?;
+		if let Some(until) = until {
+			records.retain(|r| r.timestamp < until);
+		}
+		Ok(records)
+	}
+}
+
+// ---- summaries ----
+
+pub fn perm_summary(perm: &BucketKeyPerm) -> String {
+	let mut s = String::new();
+	if perm.allow_read {
+		s.push('R');
+	}
+	if perm.allow_write {
+		s.push('W');
+	}
+	if perm.allow_owner {
+		s.push('O');
+	}
+	if s.is_empty() {
+		s.push('-');
+	}
+	s
+}
+
+pub fn bucket_summary(bucket: &Bucket) -> Option<String> {
+	let p = bucket.state.as_option()?;
+
+	let mut s = String::new();
+	let aliases = p
+		.aliases
+		.items()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|(n, _, _)| n.to_string())
+		.collect::<Vec<_>>();
+	write!(&mut s, "aliases=[{}]", aliases.join(",")).unwrap();
+
+	let local_aliases = p
+		.local_aliases
+		.items()
+		.iter()
+		.filter(|(_, _, active)| *active)
+		.map(|((k, n), _, _)| format!("{}:{}", k, n))
+		.collect::<Vec<_>>();
+	write!(&mut s, " local_aliases=[{}]", local_aliases.join(",")).unwrap();
+
+	let keys = p
+		.authorized_keys
+		.items()
+		.iter()
+		.filter(|(_, perm)| perm.is_any())
+		.map(|(k, perm)| format!("{}:{}", k, perm_summary(perm)))
+		.collect::<Vec<_>>();
+	write!(&mut s, " keys=[{}]", keys.join(",")).unwrap();
+
+	write!(
+		&mut s,
+		" website={}",
+		if p.website_config.get().is_some() {
+			"on"
+		} else {
+			"off"
+		}
+	)
+	.unwrap();
+
+	let quotas = p.quotas.get();
+	write!(
+		&mut s,
+		" max_size={} max_objects={}",
+		quotas
+			.max_size
+			.map(|x| x.to_string())
+			.unwrap_or_else(|| "none".into()),
+		quotas
+			.max_objects
+			.map(|x| x.to_string())
+			.unwrap_or_else(|| "none".into()),
+	)
+	.unwrap();
+
+	write!(
+		&mut s,
+		" search_index={}",
+		if *p.search_index.get() { "on" } else { "off" }
+	)
+	.unwrap();
+
+	Some(s)
+}
+
+pub fn key_summary(key: &Key) -> Option<String> {
+	let p = key.params()?;
+
+	let buckets = p
+		.authorized_buckets
+		.items()
+		.iter()
+		.filter(|(_, perm)| perm.is_any())
+		.map(|(b, perm)| format!("{}:{}", hex::encode(&b.as_slice()[..8]), perm_summary(perm)))
+		.collect::<Vec<_>>();
+
+	Some(format!(
+		"name={} create_bucket={} buckets=[{}]",
+		p.name.get(),
+		p.allow_create_bucket.get(),
+		buckets.join(",")
+	))
+}
+
+pub fn layout_summary(layout: &LayoutHistory) -> String {
+	let fmt_role = |id: &Uuid, role: &NodeRoleV| match &role.0 {
+		None => format!("{}:remove", hex::encode(&id.as_slice()[..8])),
+		Some(r) => format!(
+			"{}:{}:{}",
+			hex::encode(&id.as_slice()[..8]),
+			r.zone,
+			r.capacity
+				.map(|c| c.to_string())
+				.unwrap_or_else(|| "gateway".into())
+		),
+	};
+
+	let current = layout.current();
+	let roles = current
+		.roles
+		.items()
+		.iter()
+		.filter(|(_, _, r)| r.0.is_some())
+		.map(|(id, _, r)| fmt_role(id, r))
+		.collect::<Vec<_>>();
+	let staging = layout.staging.get();
+	let staged = staging
+		.roles
+		.items()
+		.iter()
+		.map(|(id, _, r)| fmt_role(id, r))
+		.collect::<Vec<_>>();
+
+	format!(
+		"version={} roles=[{}] staged=[{}]",
+		current.version,
+		roles.join(","),
+		staged.join(",")
+	)
+}
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::time::Duration;
 
 use garage_util::data::*;
@@ -11,6 +12,7 @@ use crate::garage::Garage;
 use crate::helper::error::*;
 use crate::key_table::*;
 use crate::s3::object_table::*;
+use crate::s3::usage_history_table::*;
 
 pub struct BucketHelper<'a>(pub(crate) &'a Garage);
 
@@ -137,6 +139,26 @@ impl<'a> BucketHelper<'a> {
 
 	// ----
 
+	/// Check that a number of copies requested for the data of a bucket
+	/// is between 1 and the replication factor of the cluster. Buckets cannot
+	/// have their own replication factor when data blocks are erasure-coded.
+	pub fn check_replication_factor(&self, replication_factor: usize) -> Result<(), Error> {
+		if self.0.block_manager.erasure.is_some() {
+			return Err(Error::BadRequest(
+				"Buckets cannot have their own replication factor when erasure coding is enabled"
+					.into(),
+			));
+		}
+		let cluster_rf = self.0.replication_factor.replication_factor();
+		if replication_factor < 1 || replication_factor > cluster_rf {
+			return Err(Error::BadRequest(format!(
+				"Invalid replication factor {}: it must be between 1 and the replication factor of the cluster ({})",
+				replication_factor, cluster_rf
+			)));
+		}
+		Ok(())
+	}
+
 	pub async fn is_bucket_empty(&self, bucket_id: Uuid) -> Result<bool, Error> {
 		let objects = self
 			.0
@@ -182,6 +204,99 @@ impl<'a> BucketHelper<'a> {
 		Ok(true)
 	}
 
+	/// Get the usage counters of each prefix of a bucket, as counted
+	/// at the depths set in `prefix_usage_depths`, ordered by prefix
+	pub async fn get_prefix_usage(
+		&self,
+		bucket_id: &Uuid,
+	) -> Result<Vec<(String, HashMap<String, i64>)>, Error> {
+		let node_id_vec = self
+			.0
+			.system
+			.cluster_layout()
+			.all_nongateway_nodes()
+			.to_vec();
+
+		let mut ret = vec![];
+		let mut start: Option<String> = None;
+		loop {
+			let entries = self
+				.0
+				.prefix_counter_table
+				.table
+				.get_range(
+					bucket_id,
+					start.clone(),
+					Some((DeletedFilter::NotDeleted, node_id_vec.clone())),
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_entries = entries.len();
+			// get_range starts at `start` inclusively: skip the last entry
+			// of the previous batch
+			let prev = std::mem::replace(&mut start, entries.last().map(|e| e.sk.clone()));
+			ret.extend(
+				entries
+					.into_iter()
+					.filter(|e| Some(&e.sk) != prev.as_ref())
+					.map(|e| (e.sk.clone(), e.filtered_values_with_nodes(&node_id_vec))),
+			);
+			if n_entries < 1000 {
+				break;
+			}
+		}
+
+		Ok(ret)
+	}
+
+	/// Get the samples of the usage history of a bucket at a given resolution,
+	/// for the periods that start in the given time range (bounds in msec
+	/// since UNIX epoch, `until` excluded), in chronological order
+	pub async fn get_usage_history(
+		&self,
+		bucket_id: &Uuid,
+		resolution: UsageResolution,
+		since: Option<u64>,
+		until: Option<u64>,
+	) -> Result<Vec<UsageSample>, Error> {
+		let mut ret: Vec<UsageSample> = vec![];
+		let mut start = resolution.sort_key(resolution.period_start(since.unwrap_or(0)));
+		loop {
+			let entries = self
+				.0
+				.usage_history_table
+				.get_range(
+					bucket_id,
+					Some(start.clone()),
+					Some(DeletedFilter::NotDeleted),
+					1000,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			let n_entries = entries.len();
+			for sample in entries {
+				if sample.resolution != resolution
+					|| until.map(|u| sample.period_start >= u).unwrap_or(false)
+				{
+					return Ok(ret);
+				}
+				// get_range starts at `start` inclusively: skip the last entry
+				// of the previous batch
+				if ret.last().map(|s| s.sk == sample.sk).unwrap_or(false) {
+					continue;
+				}
+				start = sample.sk.clone();
+				ret.push(sample);
+			}
+			if n_entries < 1000 {
+				break;
+			}
+		}
+
+		Ok(ret)
+	}
+
 	// ----
 
 	/// Deletes all incomplete multipart uploads that are older than a certain time.
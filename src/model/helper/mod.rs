@@ -1,3 +1,4 @@
+pub mod audit;
 pub mod bucket;
 pub mod error;
 pub mod key;
@@ -256,6 +256,21 @@ impl<T: CountedItem> IndexCounter<T> {
 	where
 		TS: TableSchema<E = T>,
 		TR: TableReplication,
+	{
+		self.offline_recount_all_with(counted_table, |e| vec![e.clone()])
+	}
+
+	/// Recount all counters from the entries of a table, where each entry
+	/// of the table is counted as the items returned by `items`
+	pub fn offline_recount_all_with<TS, TR, F>(
+		&self,
+		counted_table: &Arc<Table<TS, TR>>,
+		items: F,
+	) -> Result<(), Error>
+	where
+		TS: TableSchema,
+		TR: TableReplication,
+		F: Fn(&TS::E) -> Vec<T>,
 	{
 		// 1. Set all old local counters to zero
 		let now = now_msec();
@@ -328,39 +343,41 @@ impl<T: CountedItem> IndexCounter<T> {
 			for (counted_entry_k, counted_entry) in batch {
 				let counted_entry = counted_table.data.decode_entry(&counted_entry)?;
 
-				let pk = counted_entry.counter_partition_key();
-				let sk = counted_entry.counter_sort_key();
-				let counts = counted_entry.counts();
-
-				let local_counter_key = self.table.data.tree_key(pk, sk);
-				let mut local_counter = match self.local_counter.get(&local_counter_key)? {
-					Some(old_bytes) => {
-						let ent = LocalCounterEntry::<T>::decode(&old_bytes)
-							.ok_or_message("Cannot decode local counter entry")?;
-						assert!(ent.pk == *pk);
-						assert!(ent.sk == *sk);
-						ent
+				for item in items(&counted_entry) {
+					let pk = item.counter_partition_key();
+					let sk = item.counter_sort_key();
+					let counts = item.counts();
+
+					let local_counter_key = self.table.data.tree_key(pk, sk);
+					let mut local_counter = match self.local_counter.get(&local_counter_key)? {
+						Some(old_bytes) => {
+							let ent = LocalCounterEntry::<T>::decode(&old_bytes)
+								.ok_or_message("Cannot decode local counter entry")?;
+							assert!(ent.pk == *pk);
+							assert!(ent.sk == *sk);
+							ent
+						}
+						None => LocalCounterEntry {
+							pk: pk.clone(),
+							sk: sk.clone(),
+							values: BTreeMap::new(),
+						},
+					};
+					for (s, v) in counts.iter() {
+						let tv = local_counter.values.entry(s.to_string()).or_insert((0, 0));
+						tv.0 = std::cmp::max(tv.0 + 1, now);
+						tv.1 += v;
 					}
-					None => LocalCounterEntry {
-						pk: pk.clone(),
-						sk: sk.clone(),
-						values: BTreeMap::new(),
-					},
-				};
-				for (s, v) in counts.iter() {
-					let tv = local_counter.values.entry(s.to_string()).or_insert((0, 0));
-					tv.0 = std::cmp::max(tv.0 + 1, now);
-					tv.1 += v;
-				}
 
-				let local_counter_bytes = local_counter.encode()?;
-				self.local_counter
-					.insert(&local_counter_key, local_counter_bytes)?;
+					let local_counter_bytes = local_counter.encode()?;
+					self.local_counter
+						.insert(&local_counter_key, local_counter_bytes)?;
 
-				let counter_entry = local_counter.into_counter_entry(self.this_node);
-				self.local_counter
-					.db()
-					.transaction(|tx| self.table.queue_insert(tx, &counter_entry))?;
+					let counter_entry = local_counter.into_counter_entry(self.this_node);
+					self.local_counter
+						.db()
+						.transaction(|tx| self.table.queue_insert(tx, &counter_entry))?;
+				}
 
 				next_start = Some(counted_entry_k);
 			}
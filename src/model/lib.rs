@@ -5,6 +5,8 @@ pub mod permission;
 
 pub mod index_counter;
 
+pub mod admin_token_table;
+pub mod audit_table;
 pub mod bucket_alias_table;
 pub mod bucket_table;
 pub mod key_table;
@@ -13,6 +15,8 @@ pub mod key_table;
 pub mod k2v;
 pub mod s3;
 
+pub mod db_migration;
 pub mod garage;
 pub mod helper;
 pub mod snapshot;
+pub mod snapshot_s3;
@@ -6,7 +6,7 @@ use garage_util::data::*;
 use garage_util::error::*;
 use garage_util::migrate::Migrate;
 
-use garage_block::CalculateRefcount;
+use garage_block::{CalculateRefcount, CalculateReplicationFactor};
 use garage_table::crdt::Crdt;
 use garage_table::replication::TableShardedReplication;
 use garage_table::*;
@@ -34,7 +34,47 @@ mod v08 {
 	impl garage_util::migrate::InitialFormat for BlockRef {}
 }
 
-pub use v08::*;
+mod v1 {
+	use garage_util::crdt;
+	use garage_util::data::{Hash, Uuid};
+	use serde::{Deserialize, Serialize};
+
+	use super::v08;
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct BlockRef {
+		/// Hash (blake2 sum) of the block, used as partition key
+		pub block: Hash,
+
+		/// Id of the Version for the object containing this block, used as sorting key
+		pub version: Uuid,
+
+		// Keep track of deleted status
+		/// Is the Version that contains this block deleted
+		pub deleted: crdt::Bool,
+
+		/// Number of copies of the block required by the bucket containing
+		/// the object, if it is lower than the replication factor of the cluster
+		pub replication_factor: Option<usize>,
+	}
+
+	impl garage_util::migrate::Migrate for BlockRef {
+		const VERSION_MARKER: &'static [u8] = b"G1s3br";
+
+		type Previous = v08::BlockRef;
+
+		fn migrate(old: v08::BlockRef) -> BlockRef {
+			BlockRef {
+				block: old.block,
+				version: old.version,
+				deleted: old.deleted,
+				replication_factor: None,
+			}
+		}
+	}
+}
+
+pub use v1::*;
 
 impl Entry<Hash, Uuid> for BlockRef {
 	fn partition_key(&self) -> &Hash {
@@ -51,11 +91,17 @@ impl Entry<Hash, Uuid> for BlockRef {
 impl Crdt for BlockRef {
 	fn merge(&mut self, other: &Self) {
 		self.deleted.merge(&other.deleted);
+		self.replication_factor =
+			max_replication_factor(self.replication_factor, other.replication_factor);
 	}
 }
 
 pub struct BlockRefTable {
 	pub block_manager: Arc<BlockManager>,
+	/// Tree in which the table stores its entries, read within the
+	/// transaction of an update to tell if it changes the replication
+	/// factor of a block
+	pub store: db::Tree,
 }
 
 impl TableSchema for BlockRefTable {
@@ -81,6 +127,29 @@ impl TableSchema for BlockRefTable {
 		if was_before && !is_after {
 			self.block_manager.block_decref(tx, block)?;
 		}
+
+		let rf_before = old
+			.filter(|x| !x.deleted.get())
+			.map(|x| x.replication_factor);
+		let rf_after = new
+			.filter(|x| !x.deleted.get())
+			.map(|x| x.replication_factor);
+		if rf_before != rf_after {
+			let version = old.or(new).unwrap().version;
+			if let Some(others) = self.others_replication_factor(tx, &block, &version)? {
+				// The block is still referenced by other objects, so its
+				// reference counter did not cross zero: check if this
+				// reference changes the number of copies to keep
+				let effective = |rf: Option<Option<usize>>| {
+					rf.map(|rf| max_replication_factor(others, rf))
+						.unwrap_or(others)
+						.filter(|rf| *rf > 0)
+				};
+				if effective(rf_before) != effective(rf_after) {
+					self.block_manager.block_replication_changed(block);
+				}
+			}
+		}
 		Ok(())
 	}
 
@@ -89,6 +158,40 @@ impl TableSchema for BlockRefTable {
 	}
 }
 
+impl BlockRefTable {
+	/// Highest replication factor required by the live references to a
+	/// block other than the one of `version`, or `None` if there are none
+	fn others_replication_factor(
+		&self,
+		tx: &db::Transaction,
+		block: &Hash,
+		version: &Uuid,
+	) -> db::TxOpResult<Option<Option<usize>>> {
+		let mut result = None;
+		for entry in tx.range(&self.store, block.as_slice()..)? {
+			let (key, value) = entry?;
+			if &key[..32] != block.as_slice() {
+				break;
+			}
+			if &key[32..] == version.as_slice() {
+				continue;
+			}
+			// Entries that cannot be decoded are left to the scrub
+			// worker, they don't prevent the update from being applied
+			match BlockRef::decode(&value) {
+				Some(value) if !value.deleted.get() => {
+					result = Some(max_replication_factor(
+						result.unwrap_or(Some(0)),
+						value.replication_factor,
+					));
+				}
+				_ => (),
+			}
+		}
+		Ok(result)
+	}
+}
+
 pub fn block_ref_recount_fn(
 	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
 ) -> CalculateRefcount {
@@ -123,3 +226,46 @@ fn calculate_refcount(
 	}
 	Ok(result)
 }
+
+pub fn block_ref_replication_factor_fn(
+	block_ref_table: &Arc<Table<BlockRefTable, TableShardedReplication>>,
+) -> CalculateReplicationFactor {
+	let table = Arc::downgrade(block_ref_table);
+	Box::new(move |block: &Hash| {
+		let table = table
+			.upgrade()
+			.ok_or_message("cannot upgrade weak ptr to block_ref_table")?;
+		calculate_replication_factor(&table, block)
+	})
+}
+
+/// Calculate the number of copies that must be kept of a block,
+/// which is the highest replication factor required by one
+/// of the objects that reference it
+fn calculate_replication_factor(
+	block_ref_table: &Table<BlockRefTable, TableShardedReplication>,
+	block: &Hash,
+) -> Result<Option<usize>, Error> {
+	let mut result = Some(0);
+	for entry in block_ref_table.data.store.range(block.as_slice()..)? {
+		let (key, value) = entry?;
+		if &key[..32] != block.as_slice() {
+			break;
+		}
+		let value = BlockRef::decode(&value).ok_or_message("could not decode block_ref")?;
+		if !value.deleted.get() {
+			result = max_replication_factor(result, value.replication_factor);
+		}
+	}
+	// If no live reference is found, keep the default behavior
+	Ok(result.filter(|rf| *rf > 0))
+}
+
+/// Maximum of two replication factors, `None` standing for
+/// the replication factor of the cluster
+fn max_replication_factor(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+	match (a, b) {
+		(Some(a), Some(b)) => Some(std::cmp::max(a, b)),
+		_ => None,
+	}
+}
@@ -1,6 +1,9 @@
 pub mod block_ref_table;
 pub mod mpu_table;
+pub mod object_search_table;
 pub mod object_table;
+pub mod usage_history_table;
 pub mod version_table;
 
 pub mod lifecycle_worker;
+pub mod usage_history_worker;
@@ -0,0 +1,390 @@
+use serde::{Deserialize, Serialize};
+
+use garage_util::crdt::Crdt;
+use garage_util::data::*;
+
+use garage_table::index::IndexRange;
+use garage_table::{Entry, TableSchema};
+
+use crate::s3::object_table::*;
+
+/// Index of the entries by object size
+pub const INDEX_SIZE: &str = "size";
+/// Index of the entries by last modification date
+pub const INDEX_LAST_MODIFIED: &str = "last_modified";
+/// Index of the entries by content type
+pub const INDEX_CONTENT_TYPE: &str = "content_type";
+/// Index of the entries by user metadata, with keys `<name>\0<value>`
+pub const INDEX_METADATA: &str = "metadata";
+
+/// Prefix of the headers containing user metadata
+const USER_METADATA_PREFIX: &str = "x-amz-meta-";
+
+mod v1 {
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// The searchable metadata of the current version of an object,
+	/// kept for the objects of buckets whose search index is enabled
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectSearchEntry {
+		/// Bucket of the object
+		pub bucket_id: Uuid,
+		/// Key of the object
+		pub key: String,
+
+		/// Timestamp of the object version described by this entry
+		pub timestamp: u64,
+		/// UUID of the object version described by this entry
+		pub version: Uuid,
+		/// Metadata of the object version, or `None` if the
+		/// object was deleted
+		pub meta: Option<ObjectSearchMeta>,
+	}
+
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct ObjectSearchMeta {
+		/// Size of the object
+		pub size: u64,
+		/// Etag of the object
+		pub etag: String,
+		/// Content type of the object, if it is not encrypted with SSE-C
+		pub content_type: Option<String>,
+		/// User metadata (`x-amz-meta-*` headers, without the prefix),
+		/// empty if the object is encrypted with SSE-C
+		pub user_metadata: Vec<(String, String)>,
+		/// Whether the headers of the object are encrypted with SSE-C,
+		/// in which case they are not indexed
+		pub encrypted: bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for ObjectSearchEntry {
+		const VERSION_MARKER: &'static [u8] = b"G1objsrch";
+	}
+}
+
+pub use v1::*;
+
+impl ObjectSearchEntry {
+	/// Entry of the current version of an object, or `None` if the object
+	/// has no complete version yet
+	pub fn from_object(object: &Object) -> Option<Self> {
+		let version = object
+			.versions()
+			.iter()
+			.rev()
+			.find(|v| matches!(v.state, ObjectVersionState::Complete(_)))?;
+		let meta = match &version.state {
+			ObjectVersionState::Complete(ObjectVersionData::Inline(meta, _))
+			| ObjectVersionState::Complete(ObjectVersionData::FirstBlock(meta, _)) => {
+				Some(ObjectSearchMeta::from_version_meta(meta))
+			}
+			_ => None,
+		};
+		Some(Self {
+			bucket_id: object.bucket_id,
+			key: object.key.clone(),
+			timestamp: version.timestamp,
+			version: version.uuid,
+			meta,
+		})
+	}
+
+	/// The same entry, marked as deleted
+	pub fn into_deleted(mut self) -> Self {
+		self.meta = None;
+		self
+	}
+}
+
+impl ObjectSearchMeta {
+	fn from_version_meta(meta: &ObjectVersionMeta) -> Self {
+		match &meta.encryption {
+			ObjectVersionEncryption::Plaintext { inner } => Self {
+				size: meta.size,
+				etag: meta.etag.clone(),
+				content_type: inner
+					.headers
+					.iter()
+					.find(|(name, _)| name == "content-type")
+					.map(|(_, value)| value.clone()),
+				user_metadata: inner
+					.headers
+					.iter()
+					.filter_map(|(name, value)| {
+						name.strip_prefix(USER_METADATA_PREFIX)
+							.map(|n| (n.to_string(), value.clone()))
+					})
+					.collect(),
+				encrypted: false,
+			},
+			// Headers encrypted with SSE-C are opaque to the server
+			ObjectVersionEncryption::SseC { .. } => Self {
+				size: meta.size,
+				etag: meta.etag.clone(),
+				content_type: None,
+				user_metadata: vec![],
+				encrypted: true,
+			},
+		}
+	}
+}
+
+impl Entry<Uuid, String> for ObjectSearchEntry {
+	fn partition_key(&self) -> &Uuid {
+		&self.bucket_id
+	}
+	fn sort_key(&self) -> &String {
+		&self.key
+	}
+	fn is_tombstone(&self) -> bool {
+		self.meta.is_none()
+	}
+}
+
+impl Crdt for ObjectSearchEntry {
+	fn merge(&mut self, other: &Self) {
+		let (t1, t2) = (
+			(self.timestamp, self.version),
+			(other.timestamp, other.version),
+		);
+		if t2 > t1 {
+			*self = other.clone();
+		} else if t2 == t1 && other.meta.is_none() {
+			// the object was removed after this version was indexed
+			self.meta = None;
+		}
+	}
+}
+
+/// Criteria of a search in the index of a bucket
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ObjectSearchFilter {
+	/// The content type of objects starts with this prefix
+	pub content_type_prefix: Option<String>,
+	/// The size of objects is at least this value
+	pub min_size: Option<u64>,
+	/// The size of objects is at most this value
+	pub max_size: Option<u64>,
+	/// Objects were last modified at or after this date (msec since UNIX epoch)
+	pub modified_since: Option<u64>,
+	/// Objects were last modified before this date (msec since UNIX epoch)
+	pub modified_until: Option<u64>,
+	/// Objects have user metadata with these names (without the `x-amz-meta-`
+	/// prefix, case-insensitive) and values
+	pub metadata: Vec<(String, String)>,
+}
+
+impl ObjectSearchFilter {
+	pub fn matches(&self, entry: &ObjectSearchEntry) -> bool {
+		let meta = match &entry.meta {
+			Some(m) => m,
+			None => return false,
+		};
+		if let Some(prefix) = &self.content_type_prefix {
+			match &meta.content_type {
+				Some(ct) if ct.starts_with(prefix.as_str()) => (),
+				_ => return false,
+			}
+		}
+		self.min_size.map(|s| meta.size >= s).unwrap_or(true)
+			&& self.max_size.map(|s| meta.size <= s).unwrap_or(true)
+			&& self
+				.modified_since
+				.map(|t| entry.timestamp >= t)
+				.unwrap_or(true)
+			&& self
+				.modified_until
+				.map(|t| entry.timestamp < t)
+				.unwrap_or(true)
+			&& self.metadata.iter().all(|(name, value)| {
+				meta.user_metadata
+					.iter()
+					.any(|(n, v)| n.eq_ignore_ascii_case(name) && v == value)
+			})
+	}
+
+	/// The index and the range in this index in which all the entries
+	/// matching this filter can be found. The other criteria of the
+	/// filter are checked on the entries read from the index.
+	pub fn index_range(&self) -> (&'static str, IndexRange) {
+		if let Some((name, value)) = self.metadata.first() {
+			return (
+				INDEX_METADATA,
+				IndexRange::prefix(&metadata_key(name, value)),
+			);
+		}
+		if let Some(prefix) = &self.content_type_prefix {
+			return (INDEX_CONTENT_TYPE, IndexRange::prefix(prefix.as_bytes()));
+		}
+		if self.modified_since.is_some() || self.modified_until.is_some() {
+			return (
+				INDEX_LAST_MODIFIED,
+				IndexRange {
+					start: self
+						.modified_since
+						.map(|t| t.to_be_bytes().to_vec())
+						.unwrap_or_default(),
+					end: self.modified_until.map(|t| t.to_be_bytes().to_vec()),
+					after: None,
+				},
+			);
+		}
+		(
+			INDEX_SIZE,
+			IndexRange {
+				start: self
+					.min_size
+					.map(|s| s.to_be_bytes().to_vec())
+					.unwrap_or_default(),
+				end: self
+					.max_size
+					.and_then(|s| s.checked_add(1))
+					.map(|s| s.to_be_bytes().to_vec()),
+				after: None,
+			},
+		)
+	}
+}
+
+fn metadata_key(name: &str, value: &str) -> Vec<u8> {
+	[
+		name.to_ascii_lowercase().as_bytes(),
+		&[0x00],
+		value.as_bytes(),
+	]
+	.concat()
+}
+
+pub struct ObjectSearchTable;
+
+impl TableSchema for ObjectSearchTable {
+	const TABLE_NAME: &'static str = "object_search";
+
+	type P = Uuid;
+	type S = String;
+	type E = ObjectSearchEntry;
+	type Filter = ObjectSearchFilter;
+
+	const INDEXES: &'static [&'static str] = &[
+		INDEX_SIZE,
+		INDEX_LAST_MODIFIED,
+		INDEX_CONTENT_TYPE,
+		INDEX_METADATA,
+	];
+
+	fn index_keys(entry: &Self::E) -> Vec<(&'static str, Vec<u8>)> {
+		let meta = match &entry.meta {
+			Some(m) => m,
+			None => return vec![],
+		};
+		let mut ret = vec![
+			(INDEX_SIZE, meta.size.to_be_bytes().to_vec()),
+			(INDEX_LAST_MODIFIED, entry.timestamp.to_be_bytes().to_vec()),
+		];
+		if let Some(ct) = &meta.content_type {
+			ret.push((INDEX_CONTENT_TYPE, ct.as_bytes().to_vec()));
+		}
+		for (name, value) in meta.user_metadata.iter() {
+			let key = (INDEX_METADATA, metadata_key(name, value));
+			if !ret.contains(&key) {
+				ret.push(key);
+			}
+		}
+		ret
+	}
+
+	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
+		filter.matches(entry)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn entry(
+		size: u64,
+		timestamp: u64,
+		content_type: &str,
+		meta: &[(&str, &str)],
+	) -> ObjectSearchEntry {
+		ObjectSearchEntry {
+			bucket_id: Uuid::from([0u8; 32]),
+			key: "key".into(),
+			timestamp,
+			version: Uuid::from([1u8; 32]),
+			meta: Some(ObjectSearchMeta {
+				size,
+				etag: "etag".into(),
+				content_type: Some(content_type.into()),
+				user_metadata: meta
+					.iter()
+					.map(|(n, v)| (n.to_string(), v.to_string()))
+					.collect(),
+				encrypted: false,
+			}),
+		}
+	}
+
+	#[test]
+	fn test_search_filter() {
+		let e = entry(1000, 5000, "image/png", &[("color", "blue")]);
+		let (index, range) = ObjectSearchFilter::default().index_range();
+		assert_eq!(index, INDEX_SIZE);
+		assert_eq!(range, IndexRange::default());
+
+		let filter = ObjectSearchFilter {
+			content_type_prefix: Some("image/".into()),
+			min_size: Some(1000),
+			max_size: Some(1000),
+			modified_since: Some(5000),
+			modified_until: Some(5001),
+			metadata: vec![("Color".into(), "blue".into())],
+		};
+		assert!(filter.matches(&e));
+		assert!(!filter.matches(&entry(1001, 5000, "image/png", &[("color", "blue")])));
+		assert!(!filter.matches(&entry(1000, 5001, "image/png", &[("color", "blue")])));
+		assert!(!filter.matches(&entry(1000, 5000, "text/plain", &[("color", "blue")])));
+		assert!(!filter.matches(&entry(1000, 5000, "image/png", &[("color", "bluegreen")])));
+		assert!(!filter.matches(&e.clone().into_deleted()));
+
+		// the chosen index range contains the keys of all matching entries
+		let keys = ObjectSearchTable::index_keys(&e);
+		let (index, range) = filter.index_range();
+		assert_eq!(index, INDEX_METADATA);
+		assert!(keys.iter().any(|(i, k)| *i == index && range.contains(k)));
+		let filter = ObjectSearchFilter {
+			metadata: vec![],
+			..filter
+		};
+		for filter in [
+			filter.clone(),
+			ObjectSearchFilter {
+				content_type_prefix: None,
+				..filter.clone()
+			},
+			ObjectSearchFilter {
+				content_type_prefix: None,
+				modified_since: None,
+				modified_until: None,
+				..filter.clone()
+			},
+		] {
+			let (index, range) = filter.index_range();
+			assert!(keys.iter().any(|(i, k)| *i == index && range.contains(k)));
+		}
+	}
+
+	#[test]
+	fn test_search_entry_merge() {
+		let mut a = entry(1000, 5000, "image/png", &[]);
+		let b = entry(2000, 6000, "image/png", &[]);
+		a.merge(&b);
+		assert_eq!(a, b);
+		a.merge(&entry(3000, 4000, "image/png", &[]));
+		assert_eq!(a, b);
+		a.merge(&b.clone().into_deleted());
+		assert!(a.is_tombstone());
+	}
+}
@@ -6,11 +6,13 @@ use garage_db as db;
 use garage_util::data::*;
 
 use garage_table::crdt::*;
-use garage_table::replication::TableShardedReplication;
+use garage_table::replication::{TableFullReplication, TableShardedReplication};
 use garage_table::*;
 
+use crate::bucket_table::*;
 use crate::index_counter::*;
 use crate::s3::mpu_table::*;
+use crate::s3::object_search_table::*;
 use crate::s3::version_table::*;
 
 pub const OBJECTS: &str = "objects";
@@ -407,6 +409,28 @@ impl Object {
 	pub fn versions(&self) -> &[ObjectVersion] {
 		&self.versions[..]
 	}
+
+	/// Get the usage of this object to be counted for each of its prefixes
+	/// at the given depths, i.e. up to and including the n-th `/` of its key.
+	/// Depths at which the key has no prefix are skipped.
+	pub fn prefix_usage(&self, depths: &[usize]) -> Vec<ObjectPrefix> {
+		let counts = self.counts();
+		let separators = self
+			.key
+			.match_indices('/')
+			.map(|(i, _)| i)
+			.collect::<Vec<_>>();
+		depths
+			.iter()
+			.filter(|d| **d > 0)
+			.filter_map(|d| separators.get(d - 1))
+			.map(|i| ObjectPrefix {
+				bucket_id: self.bucket_id,
+				prefix: self.key[..=*i].to_string(),
+				counts: counts.clone(),
+			})
+			.collect()
+	}
 }
 
 impl Crdt for ObjectVersionState {
@@ -530,6 +554,31 @@ pub struct ObjectTable {
 	pub version_table: Arc<Table<VersionTable, TableShardedReplication>>,
 	pub mpu_table: Arc<Table<MultipartUploadTable, TableShardedReplication>>,
 	pub object_counter_table: Arc<IndexCounter<Object>>,
+	pub prefix_counter_table: Arc<IndexCounter<ObjectPrefix>>,
+	/// Depths (in number of `/`-separated segments) of the prefixes
+	/// for which usage is counted in `prefix_counter_table`
+	pub prefix_usage_depths: Vec<usize>,
+	pub bucket_table: Arc<Table<BucketTable, TableFullReplication>>,
+	pub search_table: Arc<Table<ObjectSearchTable, TableShardedReplication>>,
+}
+
+impl ObjectTable {
+	/// Whether the search index is enabled for a bucket, read from the
+	/// local copy of the bucket table (which is fully replicated)
+	fn search_index_enabled(
+		&self,
+		tx: &mut db::Transaction,
+		bucket_id: &Uuid,
+	) -> db::TxOpResult<bool> {
+		let tree_key = self.bucket_table.data.tree_key(&EmptyKey, bucket_id);
+		let enabled = tx
+			.get(&self.bucket_table.data.store, tree_key)?
+			.and_then(|bytes| self.bucket_table.data.decode_entry(&bytes).ok())
+			.and_then(|bucket| bucket.state.into_option())
+			.map(|params| *params.search_index.get())
+			.unwrap_or(false);
+		Ok(enabled)
+	}
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
@@ -567,7 +616,52 @@ impl TableSchema for ObjectTable {
 			);
 		}
 
-		// 2. Enqueue propagation deletions to version table
+		// 2. Count per prefix
+		if !self.prefix_usage_depths.is_empty() {
+			// old and new have the same key, thus the same prefixes
+			let old_p = old.map(|o| o.prefix_usage(&self.prefix_usage_depths));
+			let new_p = new.map(|n| n.prefix_usage(&self.prefix_usage_depths));
+			let n_prefixes = std::cmp::max(
+				old_p.as_ref().map(Vec::len).unwrap_or(0),
+				new_p.as_ref().map(Vec::len).unwrap_or(0),
+			);
+			for i in 0..n_prefixes {
+				let old_i = old_p.as_ref().and_then(|p| p.get(i));
+				let new_i = new_p.as_ref().and_then(|p| p.get(i));
+				let counter_res = self.prefix_counter_table.count(tx, old_i, new_i);
+				if let Err(e) = db::unabort(counter_res)? {
+					error!(
+						"Unable to update prefix usage counter: {}. Index values will be wrong!",
+						e
+					);
+				}
+			}
+		}
+
+		// 3. Update search index
+		let old_s = old.and_then(ObjectSearchEntry::from_object);
+		let new_s = new.and_then(ObjectSearchEntry::from_object);
+		let search_entry = match (old_s, new_s) {
+			(old_s, new_s) if old_s == new_s => None,
+			(_, Some(new_s)) if !new_s.is_tombstone() => Some(new_s),
+			(Some(old_s), new_s) if !old_s.is_tombstone() => {
+				Some(new_s.unwrap_or_else(|| old_s.into_deleted()))
+			}
+			_ => None,
+		};
+		if let Some(search_entry) = search_entry {
+			if self.search_index_enabled(tx, &search_entry.bucket_id)? {
+				let res = self.search_table.queue_insert(tx, &search_entry);
+				if let Err(e) = db::unabort(res)? {
+					error!(
+						"Unable to update object search index: {}. A repair will be needed.",
+						e
+					);
+				}
+			}
+		}
+
+		// 4. Enqueue propagation deletions to version table
 		if let (Some(old_v), Some(new_v)) = (old, new) {
 			for v in old_v.versions.iter() {
 				let new_v_id = new_v
@@ -688,3 +782,59 @@ impl CountedItem for Object {
 		]
 	}
 }
+
+/// Usage of an object, counted for one of the prefixes of its key
+#[derive(Clone, PartialEq, Debug)]
+pub struct ObjectPrefix {
+	pub bucket_id: Uuid,
+	pub prefix: String,
+	pub counts: Vec<(&'static str, i64)>,
+}
+
+impl CountedItem for ObjectPrefix {
+	const COUNTER_TABLE_NAME: &'static str = "bucket_prefix_counter";
+
+	// Partition key = bucket id
+	type CP = Uuid;
+	// Sort key = prefix, ending with `/`
+	type CS = String;
+
+	fn counter_partition_key(&self) -> &Uuid {
+		&self.bucket_id
+	}
+	fn counter_sort_key(&self) -> &String {
+		&self.prefix
+	}
+
+	fn counts(&self) -> Vec<(&'static str, i64)> {
+		self.counts.clone()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_object_prefix_usage() {
+		let prefixes = |key: &str, depths: &[usize]| {
+			Object::new(Uuid::from([0u8; 32]), key.to_string(), vec![])
+				.prefix_usage(depths)
+				.into_iter()
+				.map(|p| p.prefix)
+				.collect::<Vec<_>>()
+		};
+
+		assert_eq!(
+			prefixes("projects/alpha/report.pdf", &[1, 2]),
+			vec!["projects/", "projects/alpha/"]
+		);
+		assert_eq!(
+			prefixes("projects/alpha/report.pdf", &[3]),
+			Vec::<String>::new()
+		);
+		assert_eq!(prefixes("projects/", &[1, 2]), vec!["projects/"]);
+		assert_eq!(prefixes("report.pdf", &[1]), Vec::<String>::new());
+		assert_eq!(prefixes("a/b/c", &[]), Vec::<String>::new());
+	}
+}
@@ -0,0 +1,208 @@
+use serde::{Deserialize, Serialize};
+
+use garage_util::crdt::Crdt;
+use garage_util::data::*;
+
+use garage_table::{DeletedFilter, Entry, TableSchema};
+
+/// Resolution of the samples of the usage history of buckets
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum UsageResolution {
+	Hourly,
+	Daily,
+}
+
+impl UsageResolution {
+	/// Duration of the period covered by a sample, in msec
+	pub fn period_msec(&self) -> u64 {
+		match self {
+			Self::Hourly => 3600 * 1000,
+			Self::Daily => 24 * 3600 * 1000,
+		}
+	}
+
+	/// Start of the period (in UTC) that contains a given timestamp
+	pub fn period_start(&self, timestamp: u64) -> u64 {
+		timestamp - timestamp % self.period_msec()
+	}
+
+	/// Sort key of the sample of the period starting at `period_start`,
+	/// or the start of a range query when used with any other timestamp
+	pub fn sort_key(&self, period_start: u64) -> String {
+		let prefix = match self {
+			Self::Hourly => 'h',
+			Self::Daily => 'd',
+		};
+		format!("{}{:016x}", prefix, period_start)
+	}
+}
+
+impl std::str::FromStr for UsageResolution {
+	type Err = &'static str;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s {
+			"hourly" => Ok(Self::Hourly),
+			"daily" => Ok(Self::Daily),
+			_ => Err("Invalid resolution, expected `hourly` or `daily`"),
+		}
+	}
+}
+
+impl std::fmt::Display for UsageResolution {
+	fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+		match self {
+			Self::Hourly => write!(f, "hourly"),
+			Self::Daily => write!(f, "daily"),
+		}
+	}
+}
+
+mod v1 {
+	use super::UsageResolution;
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+
+	/// The usage of a bucket during one hour or one day, as sampled
+	/// from the object counters by the usage history worker
+	#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+	pub struct UsageSample {
+		/// Bucket whose usage is sampled
+		pub bucket_id: Uuid,
+		/// Resolution followed by the start of the period in hex, used as
+		/// sort key so that samples are listed in chronological order
+		pub sk: String,
+
+		pub resolution: UsageResolution,
+		/// Start of the period covered by this sample, in msec since UNIX epoch
+		pub period_start: u64,
+		/// Time of the last sample taken in this period, whose values are kept
+		pub sampled_at: u64,
+
+		/// Number of objects at the last sample of the period
+		pub objects: i64,
+		/// Total size of objects at the last sample of the period
+		pub bytes: i64,
+		/// Number of unfinished uploads at the last sample of the period
+		pub unfinished_uploads: i64,
+		/// Smallest total size of objects sampled during the period
+		pub min_bytes: i64,
+		/// Largest total size of objects sampled during the period
+		pub max_bytes: i64,
+
+		/// Set when the sample has expired and can be garbage collected
+		pub deleted: bool,
+	}
+
+	impl garage_util::migrate::InitialFormat for UsageSample {
+		const VERSION_MARKER: &'static [u8] = b"G1usage";
+	}
+}
+
+pub use v1::*;
+
+impl UsageSample {
+	pub fn new(
+		bucket_id: Uuid,
+		resolution: UsageResolution,
+		sampled_at: u64,
+		objects: i64,
+		bytes: i64,
+		unfinished_uploads: i64,
+	) -> Self {
+		let period_start = resolution.period_start(sampled_at);
+		Self {
+			bucket_id,
+			sk: resolution.sort_key(period_start),
+			resolution,
+			period_start,
+			sampled_at,
+			objects,
+			bytes,
+			unfinished_uploads,
+			min_bytes: bytes,
+			max_bytes: bytes,
+			deleted: false,
+		}
+	}
+}
+
+impl Entry<Uuid, String> for UsageSample {
+	fn partition_key(&self) -> &Uuid {
+		&self.bucket_id
+	}
+	fn sort_key(&self) -> &String {
+		&self.sk
+	}
+	fn is_tombstone(&self) -> bool {
+		self.deleted
+	}
+}
+
+impl Crdt for UsageSample {
+	fn merge(&mut self, other: &Self) {
+		if (other.sampled_at, other.bytes) > (self.sampled_at, self.bytes) {
+			self.sampled_at = other.sampled_at;
+			self.objects = other.objects;
+			self.bytes = other.bytes;
+			self.unfinished_uploads = other.unfinished_uploads;
+		}
+		self.min_bytes = std::cmp::min(self.min_bytes, other.min_bytes);
+		self.max_bytes = std::cmp::max(self.max_bytes, other.max_bytes);
+		self.deleted = self.deleted || other.deleted;
+	}
+}
+
+pub struct UsageHistoryTable;
+
+impl TableSchema for UsageHistoryTable {
+	const TABLE_NAME: &'static str = "bucket_usage_history";
+
+	type P = Uuid;
+	type S = String;
+	type E = UsageSample;
+	type Filter = DeletedFilter;
+
+	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool {
+		filter.apply(entry.deleted)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_usage_sample_merge() {
+		let bucket_id = Uuid::from([0u8; 32]);
+		let t0 = 1_700_000_000_000 - 1_700_000_000_000 % 3_600_000;
+
+		let mut a = UsageSample::new(bucket_id, UsageResolution::Hourly, t0 + 1000, 10, 500, 0);
+		let b = UsageSample::new(bucket_id, UsageResolution::Hourly, t0 + 2000, 12, 300, 1);
+		let c = UsageSample::new(bucket_id, UsageResolution::Hourly, t0 + 3000, 8, 800, 0);
+		assert_eq!(a.sk, b.sk);
+
+		let mut a2 = c.clone();
+		a.merge(&b);
+		a.merge(&c);
+		a2.merge(&b);
+		a2.merge(&UsageSample::new(
+			bucket_id,
+			UsageResolution::Hourly,
+			t0 + 1000,
+			10,
+			500,
+			0,
+		));
+		assert_eq!(a, a2);
+
+		assert_eq!(a.sampled_at, t0 + 3000);
+		assert_eq!((a.objects, a.bytes), (8, 800));
+		assert_eq!((a.min_bytes, a.max_bytes), (300, 800));
+
+		let mut expired = a.clone();
+		expired.deleted = true;
+		a.merge(&expired);
+		assert!(a.is_tombstone());
+	}
+}
@@ -0,0 +1,235 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::time::*;
+
+use garage_table::*;
+
+use crate::s3::object_table::*;
+use crate::s3::usage_history_table::*;
+
+use crate::garage::Garage;
+
+/// Periodically samples the object counters of each bucket into the
+/// usage history table, and deletes the samples that have expired.
+///
+/// Each bucket is sampled by a single node, the first of the storage nodes
+/// of its partition in the current layout, so that counters are not read
+/// and written several times by different nodes.
+pub struct UsageHistoryWorker {
+	garage: Arc<Garage>,
+	sample_interval: Duration,
+	hourly_retention: Duration,
+	daily_retention: Duration,
+
+	state: State,
+}
+
+enum State {
+	Idle {
+		next_sample: Instant,
+		last_completed: Option<(u64, usize)>,
+	},
+	Running {
+		started: u64,
+		pos: Vec<u8>,
+		buckets_sampled: usize,
+	},
+}
+
+impl UsageHistoryWorker {
+	pub(crate) fn new(
+		garage: Arc<Garage>,
+		sample_interval: Duration,
+		hourly_retention: Duration,
+		daily_retention: Duration,
+	) -> Self {
+		Self {
+			garage,
+			sample_interval,
+			hourly_retention,
+			daily_retention,
+			state: State::Idle {
+				next_sample: Instant::now() + Duration::from_secs(60),
+				last_completed: None,
+			},
+		}
+	}
+
+	async fn sample_bucket(&self, bucket_id: Uuid, now: u64) -> Result<(), Error> {
+		let counters = self
+			.garage
+			.object_counter_table
+			.table
+			.get(&bucket_id, &EmptyKey)
+			.await?
+			.map(|x| x.filtered_values(&self.garage.system.cluster_layout()))
+			.unwrap_or_default();
+		let objects = *counters.get(OBJECTS).unwrap_or(&0);
+		let bytes = *counters.get(BYTES).unwrap_or(&0);
+		let unfinished_uploads = *counters.get(UNFINISHED_UPLOADS).unwrap_or(&0);
+
+		let mut entries = vec![];
+		for (resolution, retention) in [
+			(UsageResolution::Hourly, self.hourly_retention),
+			(UsageResolution::Daily, self.daily_retention),
+		] {
+			entries.push(UsageSample::new(
+				bucket_id,
+				resolution,
+				now,
+				objects,
+				bytes,
+				unfinished_uploads,
+			));
+
+			// Samples are listed in chronological order, so the expired
+			// ones are the first entries of each resolution
+			let cutoff = resolution.sort_key(now.saturating_sub(retention.as_millis() as u64));
+			let oldest = self
+				.garage
+				.usage_history_table
+				.get_range(
+					&bucket_id,
+					Some(resolution.sort_key(0)),
+					Some(DeletedFilter::NotDeleted),
+					100,
+					EnumerationOrder::Forward,
+				)
+				.await?;
+			entries.extend(
+				oldest
+					.into_iter()
+					.filter(|s| s.resolution == resolution && s.sk < cutoff)
+					.map(|mut s| {
+						s.deleted = true;
+						s
+					}),
+			);
+		}
+
+		self.garage.usage_history_table.insert_many(entries).await?;
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl Worker for UsageHistoryWorker {
+	fn name(&self) -> String {
+		"Bucket usage history worker".into()
+	}
+
+	fn status(&self) -> WorkerStatus {
+		match &self.state {
+			State::Idle {
+				next_sample,
+				last_completed,
+			} => {
+				let mut freeform = vec![];
+				if let Some((t, n)) = last_completed {
+					freeform.push(format!(
+						"Last completed: {}, buckets sampled: {}",
+						msec_to_rfc3339(*t),
+						n
+					));
+				}
+				freeform.push(format!(
+					"Next sample: {}",
+					(chrono::Utc::now() + (*next_sample - Instant::now())).to_rfc3339()
+				));
+				WorkerStatus {
+					freeform,
+					..Default::default()
+				}
+			}
+			State::Running {
+				started,
+				buckets_sampled,
+				..
+			} => WorkerStatus {
+				freeform: vec![
+					format!("Started: {}", msec_to_rfc3339(*started)),
+					format!("Buckets sampled: {}", buckets_sampled),
+				],
+				..Default::default()
+			},
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let (started, mut pos, mut buckets_sampled) = match &self.state {
+			State::Idle { .. } => return Ok(WorkerState::Idle),
+			State::Running {
+				started,
+				pos,
+				buckets_sampled,
+			} => (*started, pos.clone(), *buckets_sampled),
+		};
+
+		// Process a batch of 10 buckets before yielding to bg task scheduler
+		for _ in 0..10 {
+			let (next_pos, bucket_bytes) = match self.garage.bucket_table.data.store.get_gt(&pos)? {
+				Some(x) => x,
+				None => {
+					info!(
+						"Bucket usage history worker finished, buckets sampled: {}",
+						buckets_sampled
+					);
+					let rand_factor = 1f32 + rand::random::<f32>() / 10f32;
+					self.state = State::Idle {
+						next_sample: Instant::now() + self.sample_interval.mul_f32(rand_factor),
+						last_completed: Some((started, buckets_sampled)),
+					};
+					return Ok(WorkerState::Idle);
+				}
+			};
+			pos = next_pos;
+
+			let bucket = self.garage.bucket_table.data.decode_entry(&bucket_bytes)?;
+			if bucket.is_deleted() {
+				continue;
+			}
+			let sampler = self
+				.garage
+				.system
+				.cluster_layout()
+				.current_storage_nodes_of(&bucket.id)
+				.first()
+				.copied();
+			if sampler != Some(self.garage.system.id) {
+				continue;
+			}
+
+			if let Err(e) = self.sample_bucket(bucket.id, now_msec()).await {
+				warn!("Could not sample usage of bucket {:?}: {}", bucket.id, e);
+			} else {
+				buckets_sampled += 1;
+			}
+		}
+
+		self.state = State::Running {
+			started,
+			pos,
+			buckets_sampled,
+		};
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		if let State::Idle { next_sample, .. } = &self.state {
+			tokio::time::sleep_until((*next_sample).into()).await;
+			self.state = State::Running {
+				started: now_msec(),
+				pos: vec![],
+				buckets_sampled: 0,
+			};
+		}
+		WorkerState::Busy
+	}
+}
@@ -1,5 +1,5 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
@@ -8,10 +8,15 @@ use async_trait::async_trait;
 use rand::prelude::*;
 use tokio::sync::watch;
 
+use garage_db as db;
+
 use garage_util::background::*;
+use garage_util::config::Config;
 use garage_util::error::*;
 
+use crate::db_migration::{db_path, startup_engine};
 use crate::garage::Garage;
+use crate::snapshot_s3::SnapshotS3Client;
 
 // The two most recent snapshots are kept
 const KEEP_SNAPSHOTS: usize = 2;
@@ -20,18 +25,34 @@ static SNAPSHOT_MUTEX: Mutex<()> = Mutex::new(());
 
 // ================ snapshotting logic =====================
 
-/// Run snapshot_metadata in a blocking thread and async await on it
+/// Run snapshot_metadata in a blocking thread and async await on it,
+/// then upload the snapshot to S3 if this is enabled in the configuration
 pub async fn async_snapshot_metadata(garage: &Arc<Garage>) -> Result<(), Error> {
-	let garage = garage.clone();
-	let worker = tokio::task::spawn_blocking(move || snapshot_metadata(&garage));
-	worker.await.unwrap()?;
+	let garage2 = garage.clone();
+	let worker = tokio::task::spawn_blocking(move || snapshot_metadata(&garage2));
+	let snapshot_path = worker.await.unwrap()?;
+
+	if let Some(s3_config) = &garage.config.metadata_snapshots_s3 {
+		let client = SnapshotS3Client::new(s3_config, &hex::encode(garage.system.id))?;
+		client.upload_snapshot(&snapshot_path).await.map_err(|e| {
+			Error::Message(format!(
+				"Snapshot was saved locally, but could not be uploaded to S3: {}",
+				e
+			))
+		})?;
+		info!("Metadata db snapshot uploaded to S3");
+
+		if let Err(e) = client.cleanup_snapshots().await {
+			error!("Failed to do cleanup of snapshots in S3 bucket: {}", e);
+		}
+	}
 	Ok(())
 }
 
 /// Take a snapshot of the metadata database, and erase older
-/// snapshots if necessary.
+/// snapshots if necessary. Returns the path of the new snapshot.
 /// This is not an async function, it should be spawned on a thread pool
-pub fn snapshot_metadata(garage: &Garage) -> Result<(), Error> {
+pub fn snapshot_metadata(garage: &Garage) -> Result<PathBuf, Error> {
 	let lock = match SNAPSHOT_MUTEX.try_lock() {
 		Ok(lock) => lock,
 		Err(_) => {
@@ -64,7 +85,7 @@ pub fn snapshot_metadata(garage: &Garage) -> Result<(), Error> {
 
 	drop(lock);
 
-	Ok(())
+	Ok(new_path)
 }
 
 fn cleanup_snapshots(snapshots_dir: &PathBuf) -> Result<(), Error> {
@@ -91,6 +112,64 @@ fn cleanup_snapshots(snapshots_dir: &PathBuf) -> Result<(), Error> {
 	Ok(())
 }
 
+// ================ restoring a snapshot =====================
+
+/// Install a snapshot of the metadata database as the database of this node.
+/// The current database is kept next to it with a `.before-restore-<date>` suffix.
+/// Garage must not be running on the node.
+pub fn install_snapshot(config: &Config, snapshot_dir: &Path) -> Result<String, Error> {
+	let engine = startup_engine(config)?;
+	let snapshot_file = match engine {
+		db::Engine::Sqlite => "db.sqlite",
+		db::Engine::Lmdb => "data.mdb",
+		db::Engine::Redb => "db.redb",
+		db::Engine::Memory => {
+			return Err(Error::Message(
+				"Snapshots cannot be restored with the in-memory engine".into(),
+			))
+		}
+	};
+	if !snapshot_dir.join(snapshot_file).is_file() {
+		return Err(Error::Message(format!(
+			"{} does not contain a snapshot of a {} database. Use `garage convert-db` to convert the snapshot to the {} engine.",
+			snapshot_dir.display(),
+			engine,
+			engine
+		)));
+	}
+
+	let db_path = db_path(&config.metadata_dir, engine);
+	let suffix = format!(
+		".before-restore-{}",
+		chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+	);
+	let mut moved = vec![];
+	for ext in ["", "-wal", "-shm"] {
+		let mut path = db_path.clone().into_os_string();
+		path.push(ext);
+		let path = PathBuf::from(path);
+		if path.exists() {
+			let mut old_path = path.clone().into_os_string();
+			old_path.push(&suffix);
+			fs::rename(&path, &old_path)?;
+			moved.push(PathBuf::from(old_path));
+		}
+	}
+
+	if engine == db::Engine::Lmdb {
+		fs::rename(snapshot_dir, &db_path)?;
+	} else {
+		fs::rename(snapshot_dir.join(snapshot_file), &db_path)?;
+		fs::remove_dir_all(snapshot_dir)?;
+	}
+
+	let mut msg = format!("Snapshot installed as {}.", db_path.display());
+	for path in moved {
+		msg.push_str(&format!("\nPrevious database moved to {}.", path.display()));
+	}
+	Ok(msg)
+}
+
 // ================ auto snapshot worker =====================
 
 pub struct AutoSnapshotWorker {
@@ -140,3 +219,107 @@ impl Worker for AutoSnapshotWorker {
 		WorkerState::Busy
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn test_config(metadata_dir: &Path, db_engine: &str) -> Config {
+		let config = format!(
+			r#"
+			metadata_dir = "{path}"
+			data_dir = "{path}/data"
+			db_engine = "{db_engine}"
+			replication_factor = 1
+			rpc_bind_addr = "127.0.0.1:3901"
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "127.0.0.1:3900"
+			"#,
+			path = metadata_dir.to_string_lossy(),
+			db_engine = db_engine,
+		);
+		toml::from_str(&config).unwrap()
+	}
+
+	fn file_names(dir: &Path) -> Vec<String> {
+		let mut names = fs::read_dir(dir)
+			.unwrap()
+			.map(|f| f.unwrap().file_name().into_string().unwrap())
+			.collect::<Vec<_>>();
+		names.sort();
+		names
+	}
+
+	#[test]
+	fn test_install_snapshot_sqlite() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, "sqlite");
+		fs::write(dir.join("db.sqlite"), b"current").unwrap();
+		fs::write(dir.join("db.sqlite-wal"), b"current wal").unwrap();
+
+		let snapshot_dir = dir.join("restore-20240101T000000Z");
+		fs::create_dir(&snapshot_dir).unwrap();
+		fs::write(snapshot_dir.join("db.sqlite"), b"snapshot").unwrap();
+
+		install_snapshot(&config, &snapshot_dir).unwrap();
+
+		// The snapshot replaces the database and its directory is removed,
+		// the previous database and its WAL are kept under the same suffix
+		assert_eq!(fs::read(dir.join("db.sqlite")).unwrap(), b"snapshot");
+		assert!(!snapshot_dir.exists());
+		let names = file_names(&dir);
+		assert_eq!(names.len(), 3);
+		let suffix = names
+			.iter()
+			.find_map(|x| x.strip_prefix("db.sqlite.before-restore-"))
+			.unwrap();
+		assert_eq!(
+			fs::read(dir.join(format!("db.sqlite.before-restore-{}", suffix))).unwrap(),
+			b"current"
+		);
+		assert_eq!(
+			fs::read(dir.join(format!("db.sqlite-wal.before-restore-{}", suffix))).unwrap(),
+			b"current wal"
+		);
+	}
+
+	#[test]
+	fn test_install_snapshot_lmdb() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, "lmdb");
+
+		// There is no current database: nothing is moved
+		let snapshot_dir = dir.join("restore-20240101T000000Z");
+		fs::create_dir(&snapshot_dir).unwrap();
+		fs::write(snapshot_dir.join("data.mdb"), b"snapshot").unwrap();
+
+		let msg = install_snapshot(&config, &snapshot_dir).unwrap();
+		assert!(!msg.contains("Previous database"));
+		assert_eq!(file_names(&dir), ["db.lmdb"]);
+		assert_eq!(
+			fs::read(dir.join("db.lmdb").join("data.mdb")).unwrap(),
+			b"snapshot"
+		);
+	}
+
+	#[test]
+	fn test_install_snapshot_wrong_engine() {
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&dir, "sqlite");
+		fs::write(dir.join("db.sqlite"), b"current").unwrap();
+
+		let snapshot_dir = dir.join("restore-20240101T000000Z");
+		fs::create_dir(&snapshot_dir).unwrap();
+		fs::write(snapshot_dir.join("data.mdb"), b"snapshot").unwrap();
+
+		// Nothing is changed if the snapshot is not of the configured engine
+		assert!(install_snapshot(&config, &snapshot_dir).is_err());
+		assert_eq!(fs::read(dir.join("db.sqlite")).unwrap(), b"current");
+		assert_eq!(file_names(&snapshot_dir), ["data.mdb"]);
+
+		let config = test_config(&dir, "memory");
+		assert!(install_snapshot(&config, &snapshot_dir).is_err());
+	}
+}
@@ -0,0 +1,695 @@
+//! Upload of metadata snapshots to a bucket of an S3 endpoint, and download
+//! of these snapshots to restore the metadata database of a node
+use std::convert::TryInto;
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use http::header::ETAG;
+use http::{HeaderName, Method, Request, Response};
+use http_body_util::{BodyExt, Full as FullBody};
+use hyper::body::{Bytes, Incoming};
+use hyper_rustls::HttpsConnector;
+use hyper_util::client::legacy::{connect::HttpConnector, Client as HttpClient};
+use hyper_util::rt::TokioExecutor;
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::Deserialize;
+use tokio::io::AsyncWriteExt;
+
+use aws_sdk_config::config::Credentials;
+use aws_sigv4::http_request::{
+	sign, PercentEncodingMode, SignableBody, SignableRequest, SigningSettings,
+	UriPathNormalizationMode,
+};
+use aws_sigv4::sign::v4::SigningParams;
+
+use garage_util::config::SnapshotS3Config;
+use garage_util::error::*;
+
+type Body = FullBody<Bytes>;
+
+const SERVICE: &str = "s3";
+const AMZ_CONTENT_SHA256: HeaderName = HeaderName::from_static("x-amz-content-sha256");
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Size of the parts of multipart uploads. Files that are smaller
+/// are uploaded with a single PutObject request.
+const PART_SIZE: usize = 32 * 1024 * 1024;
+
+/// Suffix of the keys of compressed files
+const ZSTD_SUFFIX: &str = ".zst";
+
+const STRICT_ENCODE_SET: AsciiSet = NON_ALPHANUMERIC
+	.remove(b'_')
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'~');
+const PATH_ENCODE_SET: AsciiSet = NON_ALPHANUMERIC
+	.remove(b'/')
+	.remove(b'_')
+	.remove(b'-')
+	.remove(b'.')
+	.remove(b'~');
+
+/// Client for the bucket where the metadata snapshots of a node are stored.
+///
+/// Files of a snapshot are stored under `<prefix><node id>/<snapshot name>/`,
+/// where the name of a snapshot is the date at which it was taken.
+pub struct SnapshotS3Client {
+	config: SnapshotS3Config,
+	node_prefix: String,
+	client: HttpClient<HttpsConnector<HttpConnector>, Body>,
+}
+
+impl SnapshotS3Client {
+	/// Create a client for the snapshots of a node, given by its hex-encoded ID
+	pub fn new(config: &SnapshotS3Config, node: &str) -> Result<Self, Error> {
+		let connector = hyper_rustls::HttpsConnectorBuilder::new()
+			.with_native_roots()?
+			.https_or_http()
+			.enable_http1()
+			.enable_http2()
+			.build();
+		let client = HttpClient::builder(TokioExecutor::new()).build(connector);
+		Ok(Self {
+			config: config.clone(),
+			node_prefix: format!("{}{}/", config.prefix, node),
+			client,
+		})
+	}
+
+	/// Upload all files of a local snapshot directory
+	pub async fn upload_snapshot(&self, snapshot_dir: &Path) -> Result<(), Error> {
+		let name = snapshot_dir
+			.file_name()
+			.and_then(|x| x.to_str())
+			.ok_or_message("Invalid snapshot directory name")?;
+
+		let mut files = fs::read_dir(snapshot_dir)?.collect::<Result<Vec<_>, _>>()?;
+		files.sort_by_key(|x| x.file_name());
+		for file in files {
+			if !file.metadata()?.is_file() {
+				continue;
+			}
+			let file_name = file.file_name();
+			let file_name = file_name
+				.to_str()
+				.ok_or_message("Invalid snapshot file name")?;
+			let suffix = match self.config.compression_level {
+				Some(_) => ZSTD_SUFFIX,
+				None => "",
+			};
+			let key = format!("{}{}/{}{}", self.node_prefix, name, file_name, suffix);
+			info!("Uploading {} to {}", file.path().display(), key);
+			self.upload_file(&file.path(), &key).await?;
+		}
+		Ok(())
+	}
+
+	/// List the snapshots of the node in the bucket, oldest first
+	pub async fn list_snapshots(&self) -> Result<Vec<String>, Error> {
+		let (_, prefixes) = self.list(&self.node_prefix, true).await?;
+		let mut snapshots = prefixes
+			.iter()
+			.filter_map(|p| p.strip_prefix(&self.node_prefix))
+			.map(|p| p.trim_end_matches('/').to_string())
+			.collect::<Vec<_>>();
+		snapshots.sort();
+		Ok(snapshots)
+	}
+
+	/// Delete the oldest snapshots of the node from the bucket, so that
+	/// only the number of snapshots set in the configuration is kept
+	pub async fn cleanup_snapshots(&self) -> Result<(), Error> {
+		let snapshots = self.list_snapshots().await?;
+		for to_delete in snapshots.iter().rev().skip(self.config.keep) {
+			info!("Deleting snapshot {} from S3 bucket", to_delete);
+			let prefix = format!("{}{}/", self.node_prefix, to_delete);
+			let (keys, _) = self.list(&prefix, false).await?;
+			for key in keys {
+				self.request(Method::DELETE, &key, &[], Bytes::new())
+					.await?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Download the files of a snapshot to a local directory,
+	/// decompressing them if necessary
+	pub async fn download_snapshot(&self, name: &str, to: &Path) -> Result<(), Error> {
+		let prefix = format!("{}{}/", self.node_prefix, name);
+		let (keys, _) = self.list(&prefix, false).await?;
+		if keys.is_empty() {
+			return Err(Error::Message(format!(
+				"Snapshot {} not found in S3 bucket",
+				name
+			)));
+		}
+
+		fs::create_dir_all(to)?;
+		for key in keys {
+			let file_name = &key[prefix.len()..];
+			if file_name.is_empty() || file_name.contains('/') || file_name.starts_with('.') {
+				return Err(Error::Message(format!(
+					"Unexpected object in snapshot: {}",
+					key
+				)));
+			}
+			let (file_name, compressed) = match file_name.strip_suffix(ZSTD_SUFFIX) {
+				Some(f) => (f, true),
+				None => (file_name, false),
+			};
+			let path = to.join(file_name);
+			let download_path = to.join(format!("{}.download", file_name));
+
+			info!("Downloading {} to {}", key, path.display());
+			let resp = self.request(Method::GET, &key, &[], Bytes::new()).await?;
+			let mut body = resp.into_body();
+			let mut file = tokio::fs::File::create(&download_path).await?;
+			while let Some(frame) = body.frame().await {
+				if let Ok(data) = frame?.into_data() {
+					file.write_all(&data).await?;
+				}
+			}
+			file.sync_all().await?;
+			drop(file);
+
+			if compressed {
+				let (from, to) = (download_path.clone(), path.clone());
+				tokio::task::spawn_blocking(move || {
+					let input = fs::File::open(&from)?;
+					let output = fs::File::create(&to)?;
+					zstd::stream::copy_decode(input, &output)?;
+					output.sync_all()?;
+					fs::remove_file(&from)
+				})
+				.await??;
+			} else {
+				tokio::fs::rename(&download_path, &path).await?;
+			}
+		}
+		Ok(())
+	}
+
+	// ---- S3 requests ----
+
+	async fn upload_file(&self, path: &Path, key: &str) -> Result<(), Error> {
+		let file = fs::File::open(path)?;
+		let reader: Box<dyn Read + Send> = match self.config.compression_level {
+			Some(level) => Box::new(zstd::stream::read::Encoder::new(file, level)?),
+			None => Box::new(std::io::BufReader::new(file)),
+		};
+
+		let (mut reader, mut part) = read_part(reader).await?;
+		if part.len() < PART_SIZE {
+			self.request(Method::PUT, key, &[], part.into()).await?;
+			return Ok(());
+		}
+
+		let resp = self
+			.request_bytes(Method::POST, key, &[("uploads", "")], Bytes::new())
+			.await?;
+		let upload_id = quick_xml::de::from_reader::<_, InitiateMultipartUploadResult>(&resp[..])
+			.map_err(|e| Error::Message(format!("Invalid CreateMultipartUpload response: {}", e)))?
+			.upload_id;
+
+		let res = async {
+			let mut etags = vec![];
+			while !part.is_empty() {
+				let part_number = (etags.len() + 1).to_string();
+				let resp = self
+					.request(
+						Method::PUT,
+						key,
+						&[("partNumber", &part_number), ("uploadId", &upload_id)],
+						part.into(),
+					)
+					.await?;
+				let etag = resp
+					.headers()
+					.get(ETAG)
+					.ok_or_message("No ETag in UploadPart response")?
+					.to_str()?
+					.to_string();
+				etags.push(etag);
+				(reader, part) = read_part(reader).await?;
+			}
+
+			let mut complete = String::from("<CompleteMultipartUpload>");
+			for (i, etag) in etags.iter().enumerate() {
+				complete.push_str(&format!(
+					"<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>",
+					i + 1,
+					etag
+				));
+			}
+			complete.push_str("</CompleteMultipartUpload>");
+			self.request(
+				Method::POST,
+				key,
+				&[("uploadId", &upload_id)],
+				complete.into(),
+			)
+			.await?;
+			Ok::<_, Error>(())
+		}
+		.await;
+
+		if res.is_err() {
+			if let Err(e) = self
+				.request(
+					Method::DELETE,
+					key,
+					&[("uploadId", &upload_id)],
+					Bytes::new(),
+				)
+				.await
+			{
+				warn!("Could not abort multipart upload of {}: {}", key, e);
+			}
+		}
+		res
+	}
+
+	/// List the keys and common prefixes that start with a prefix
+	async fn list(
+		&self,
+		prefix: &str,
+		delimiter: bool,
+	) -> Result<(Vec<String>, Vec<String>), Error> {
+		let mut keys = vec![];
+		let mut prefixes = vec![];
+		let mut continuation_token: Option<String> = None;
+		loop {
+			let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+			if delimiter {
+				query.push(("delimiter", "/"));
+			}
+			if let Some(token) = &continuation_token {
+				query.push(("continuation-token", token.as_str()));
+			}
+			let resp = self
+				.request_bytes(Method::GET, "", &query, Bytes::new())
+				.await?;
+			let res = quick_xml::de::from_reader::<_, ListBucketResult>(&resp[..])
+				.map_err(|e| Error::Message(format!("Invalid ListObjectsV2 response: {}", e)))?;
+
+			keys.extend(res.contents.into_iter().map(|x| x.key));
+			prefixes.extend(res.common_prefixes.into_iter().map(|x| x.prefix));
+			match res.next_continuation_token {
+				Some(token) if res.is_truncated => continuation_token = Some(token),
+				_ => break,
+			}
+		}
+		Ok((keys, prefixes))
+	}
+
+	async fn request_bytes(
+		&self,
+		method: Method,
+		key: &str,
+		query: &[(&str, &str)],
+		body: Bytes,
+	) -> Result<Bytes, Error> {
+		let resp = self.request(method, key, query, body).await?;
+		Ok(resp.into_body().collect().await?.to_bytes())
+	}
+
+	/// Send a signed request, and return the response if it is successful
+	async fn request(
+		&self,
+		method: Method,
+		key: &str,
+		query: &[(&str, &str)],
+		body: Bytes,
+	) -> Result<Response<Incoming>, Error> {
+		let mut url = format!(
+			"{}/{}/",
+			self.config.endpoint.trim_end_matches('/'),
+			self.config.bucket
+		);
+		url.extend(utf8_percent_encode(key, &PATH_ENCODE_SET));
+		for (i, (k, v)) in query.iter().enumerate() {
+			url.push(if i == 0 { '?' } else { '&' });
+			url.extend(utf8_percent_encode(k, &STRICT_ENCODE_SET));
+			url.push('=');
+			url.extend(utf8_percent_encode(v, &STRICT_ENCODE_SET));
+		}
+
+		let mut req = Request::builder()
+			.method(method.clone())
+			.uri(&url)
+			.body(body)?;
+
+		use sha2::{Digest, Sha256};
+		let hash = hex::encode(Sha256::digest(req.body()));
+		req.headers_mut()
+			.insert(AMZ_CONTENT_SHA256, hash.try_into().unwrap());
+
+		let mut signing_settings = SigningSettings::default();
+		signing_settings.percent_encoding_mode = PercentEncodingMode::Single;
+		signing_settings.uri_path_normalization_mode = UriPathNormalizationMode::Disabled;
+		let identity = Credentials::new(
+			&self.config.access_key_id,
+			&self.config.secret_access_key,
+			None,
+			None,
+			"garage-snapshots",
+		)
+		.into();
+		let signing_params = SigningParams::builder()
+			.identity(&identity)
+			.region(&self.config.region)
+			.name(SERVICE)
+			.time(SystemTime::now())
+			.settings(signing_settings)
+			.build()
+			.map_err(|e| Error::Message(format!("Could not sign request: {}", e)))?
+			.into();
+		let headers = req
+			.headers()
+			.iter()
+			.map(|(k, v)| Ok((k.as_str(), v.to_str()?)))
+			.collect::<Result<Vec<_>, Error>>()?;
+		let signable_request = SignableRequest::new(
+			req.method().as_str(),
+			req.uri().to_string(),
+			headers.into_iter(),
+			SignableBody::Bytes(req.body().as_ref()),
+		)
+		.map_err(|e| Error::Message(format!("Could not sign request: {}", e)))?;
+		let (signing_instructions, _signature) = sign(signable_request, &signing_params)
+			.map_err(|e| Error::Message(format!("Could not sign request: {}", e)))?
+			.into_parts();
+		signing_instructions.apply_to_request_http1x(&mut req);
+
+		let resp = tokio::time::timeout(REQUEST_TIMEOUT, self.client.request(req.map(Body::from)))
+			.await
+			.map_err(|_| Error::Timeout)?
+			.map_err(|e| Error::Message(format!("S3 request failed: {}", e)))?;
+
+		if !resp.status().is_success() {
+			let status = resp.status();
+			let body = resp
+				.into_body()
+				.collect()
+				.await
+				.map(|x| x.to_bytes())
+				.unwrap_or_default();
+			return Err(Error::Message(format!(
+				"S3 request {} {} failed with status {}: {}",
+				method,
+				url,
+				status,
+				String::from_utf8_lossy(&body)
+			)));
+		}
+		Ok(resp)
+	}
+}
+
+/// Read the next part of a file to upload, in a blocking thread
+async fn read_part(
+	mut reader: Box<dyn Read + Send>,
+) -> Result<(Box<dyn Read + Send>, Vec<u8>), Error> {
+	tokio::task::spawn_blocking(move || {
+		let mut buf = Vec::with_capacity(PART_SIZE);
+		(&mut reader).take(PART_SIZE as u64).read_to_end(&mut buf)?;
+		Ok((reader, buf))
+	})
+	.await?
+}
+
+// ---- S3 responses ----
+
+#[derive(Deserialize)]
+struct InitiateMultipartUploadResult {
+	#[serde(rename = "UploadId")]
+	upload_id: String,
+}
+
+#[derive(Deserialize)]
+struct ListBucketResult {
+	#[serde(rename = "IsTruncated", default)]
+	is_truncated: bool,
+	#[serde(rename = "NextContinuationToken")]
+	next_continuation_token: Option<String>,
+	#[serde(rename = "Contents", default)]
+	contents: Vec<ListContents>,
+	#[serde(rename = "CommonPrefixes", default)]
+	common_prefixes: Vec<ListCommonPrefix>,
+}
+
+#[derive(Deserialize)]
+struct ListContents {
+	#[serde(rename = "Key")]
+	key: String,
+}
+
+#[derive(Deserialize)]
+struct ListCommonPrefix {
+	#[serde(rename = "Prefix")]
+	prefix: String,
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::collections::BTreeMap;
+	use std::sync::{Arc, Mutex};
+
+	use percent_encoding::percent_decode_str;
+	use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+	use tokio::net::TcpListener;
+
+	type Objects = Arc<Mutex<BTreeMap<String, Vec<u8>>>>;
+
+	/// Start a minimal S3 endpoint on a local port, that stores objects
+	/// in memory and does not check signatures
+	async fn mock_s3() -> (String, Objects) {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let endpoint = format!("http://{}", listener.local_addr().unwrap());
+		let objects = Objects::default();
+		let objects2 = objects.clone();
+		tokio::spawn(async move {
+			loop {
+				let (stream, _) = listener.accept().await.unwrap();
+				tokio::spawn(serve_connection(stream, objects2.clone()));
+			}
+		});
+		(endpoint, objects)
+	}
+
+	async fn serve_connection(stream: tokio::net::TcpStream, objects: Objects) {
+		let (read, mut write) = stream.into_split();
+		let mut read = BufReader::new(read);
+		loop {
+			let mut request_line = String::new();
+			if read.read_line(&mut request_line).await.unwrap_or(0) == 0 {
+				return;
+			}
+			let mut content_length = 0;
+			loop {
+				let mut header = String::new();
+				read.read_line(&mut header).await.unwrap();
+				let header = header.trim_end();
+				if header.is_empty() {
+					break;
+				}
+				if let Some((name, value)) = header.split_once(':') {
+					if name.eq_ignore_ascii_case("content-length") {
+						content_length = value.trim().parse().unwrap();
+					}
+				}
+			}
+			let mut body = vec![0; content_length];
+			read.read_exact(&mut body).await.unwrap();
+
+			let mut parts = request_line.split_whitespace();
+			let method = parts.next().unwrap().to_string();
+			let target = parts.next().unwrap();
+			let (path, query) = target.split_once('?').unwrap_or((target, ""));
+			let key = percent_decode_str(path.strip_prefix("/snapshots-bucket/").unwrap())
+				.decode_utf8()
+				.unwrap()
+				.to_string();
+			let query = query
+				.split('&')
+				.filter_map(|x| x.split_once('='))
+				.map(|(k, v)| {
+					(
+						k.to_string(),
+						percent_decode_str(v).decode_utf8().unwrap().to_string(),
+					)
+				})
+				.collect::<BTreeMap<_, _>>();
+
+			let (status, resp) = handle(&objects, &method, &key, &query, body);
+			let head = format!(
+				"HTTP/1.1 {}\r\nContent-Length: {}\r\n\r\n",
+				status,
+				resp.len()
+			);
+			write.write_all(head.as_bytes()).await.unwrap();
+			write.write_all(&resp).await.unwrap();
+		}
+	}
+
+	fn handle(
+		objects: &Objects,
+		method: &str,
+		key: &str,
+		query: &BTreeMap<String, String>,
+		body: Vec<u8>,
+	) -> (&'static str, Vec<u8>) {
+		let mut objects = objects.lock().unwrap();
+		match method {
+			"PUT" => {
+				objects.insert(key.to_string(), body);
+				("200 OK", vec![])
+			}
+			"DELETE" => {
+				objects.remove(key);
+				("204 No Content", vec![])
+			}
+			"GET" if key.is_empty() => {
+				let prefix = &query["prefix"];
+				let mut contents = String::new();
+				let mut prefixes = vec![];
+				for k in objects.keys().filter(|k| k.starts_with(prefix.as_str())) {
+					match k[prefix.len()..].split_once('/') {
+						Some((dir, _)) if query.contains_key("delimiter") => {
+							let p = format!("{}{}/", prefix, dir);
+							if !prefixes.contains(&p) {
+								prefixes.push(p);
+							}
+						}
+						_ => contents.push_str(&format!("<Contents><Key>{}</Key></Contents>", k)),
+					}
+				}
+				for p in prefixes {
+					contents.push_str(&format!(
+						"<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+						p
+					));
+				}
+				let xml = format!(
+					"<ListBucketResult><IsTruncated>false</IsTruncated>{}</ListBucketResult>",
+					contents
+				);
+				("200 OK", xml.into_bytes())
+			}
+			"GET" => match objects.get(key) {
+				Some(v) => ("200 OK", v.clone()),
+				None => ("404 Not Found", vec![]),
+			},
+			_ => ("501 Not Implemented", vec![]),
+		}
+	}
+
+	fn test_config(endpoint: &str, compression_level: Option<i32>) -> SnapshotS3Config {
+		SnapshotS3Config {
+			endpoint: endpoint.to_string(),
+			region: "garage".into(),
+			bucket: "snapshots-bucket".into(),
+			access_key_id: "GK31c2f218a2e44f485b94239e".into(),
+			secret_access_key: "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835"
+				.into(),
+			prefix: "garage/".into(),
+			compression_level,
+			keep: 2,
+		}
+	}
+
+	/// Create a local snapshot directory with a database file
+	fn local_snapshot(dir: &Path, name: &str, content: &[u8]) -> std::path::PathBuf {
+		let path = dir.join(name);
+		fs::create_dir_all(&path).unwrap();
+		fs::write(path.join("db.sqlite"), content).unwrap();
+		path
+	}
+
+	#[tokio::test]
+	async fn test_snapshot_key_layout() {
+		let (endpoint, objects) = mock_s3().await;
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let snapshot = local_snapshot(&dir, "2024-01-01T00:00:00Z", b"metadata");
+
+		let client = SnapshotS3Client::new(&test_config(&endpoint, Some(1)), "abcd").unwrap();
+		client.upload_snapshot(&snapshot).await.unwrap();
+		let raw = SnapshotS3Client::new(&test_config(&endpoint, None), "abcd").unwrap();
+		raw.upload_snapshot(&local_snapshot(&dir, "2024-01-02T00:00:00Z", b"metadata"))
+			.await
+			.unwrap();
+
+		let keys = objects.lock().unwrap().keys().cloned().collect::<Vec<_>>();
+		assert_eq!(
+			keys,
+			[
+				"garage/abcd/2024-01-01T00:00:00Z/db.sqlite.zst",
+				"garage/abcd/2024-01-02T00:00:00Z/db.sqlite",
+			]
+		);
+		assert_eq!(
+			client.list_snapshots().await.unwrap(),
+			["2024-01-01T00:00:00Z", "2024-01-02T00:00:00Z"]
+		);
+
+		// Compressed and uncompressed files are both restored as they were
+		for name in ["2024-01-01T00:00:00Z", "2024-01-02T00:00:00Z"] {
+			let to = dir.join("restore").join(name);
+			client.download_snapshot(name, &to).await.unwrap();
+			let files = fs::read_dir(&to)
+				.unwrap()
+				.map(|f| f.unwrap().file_name().into_string().unwrap())
+				.collect::<Vec<_>>();
+			assert_eq!(files, ["db.sqlite"]);
+			assert_eq!(fs::read(to.join("db.sqlite")).unwrap(), b"metadata");
+		}
+
+		assert!(client
+			.download_snapshot("2024-01-03T00:00:00Z", &dir.join("missing"))
+			.await
+			.is_err());
+	}
+
+	#[tokio::test]
+	async fn test_snapshot_retention() {
+		let (endpoint, objects) = mock_s3().await;
+		let dir = mktemp::Temp::new_dir().unwrap();
+		let config = test_config(&endpoint, None);
+
+		let other = SnapshotS3Client::new(&config, "ef01").unwrap();
+		other
+			.upload_snapshot(&local_snapshot(&dir, "2023-12-31T00:00:00Z", b"other"))
+			.await
+			.unwrap();
+
+		let client = SnapshotS3Client::new(&config, "abcd").unwrap();
+		for name in [
+			"2024-01-01T00:00:00Z",
+			"2024-01-02T00:00:00Z",
+			"2024-01-03T00:00:00Z",
+		] {
+			client
+				.upload_snapshot(&local_snapshot(&dir, name, b"metadata"))
+				.await
+				.unwrap();
+			client.cleanup_snapshots().await.unwrap();
+		}
+
+		// Only the two most recent snapshots of the node are kept,
+		// snapshots of other nodes are not affected
+		assert_eq!(
+			client.list_snapshots().await.unwrap(),
+			["2024-01-02T00:00:00Z", "2024-01-03T00:00:00Z"]
+		);
+		assert_eq!(
+			other.list_snapshots().await.unwrap(),
+			["2023-12-31T00:00:00Z"]
+		);
+		assert_eq!(objects.lock().unwrap().len(), 3);
+	}
+}
@@ -0,0 +1,520 @@
+//! Discovery of peers through DNS records: SRV records, or A/AAAA records
+//! with a known port, give the addresses of nodes, and TXT records give
+//! their public keys.
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use hickory_resolver::config::{
+	LookupIpStrategy, NameServerConfigGroup, ResolverConfig, ResolverOpts,
+};
+use hickory_resolver::error::{ResolveError, ResolveErrorKind};
+use hickory_resolver::TokioAsyncResolver;
+
+use garage_net::NodeID;
+
+use garage_util::config::DnsDiscoveryConfig;
+use garage_util::error::Error;
+
+const DNS_PORT: u16 = 53;
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const QUERY_ATTEMPTS: usize = 2;
+
+pub struct DnsDiscovery {
+	config: DnsDiscoveryConfig,
+	resolver: TokioAsyncResolver,
+}
+
+impl DnsDiscovery {
+	pub fn new(config: DnsDiscoveryConfig) -> Result<Self, Error> {
+		match (&config.srv_name, &config.hostname, config.port) {
+			(Some(_), None, None) | (None, Some(_), Some(_)) => (),
+			_ => {
+				return Err(Error::Message(
+					"dns_discovery needs either srv_name, or hostname and port".into(),
+				))
+			}
+		}
+
+		let resolver = match &config.resolver {
+			Some(r) => {
+				let addr = r
+					.parse::<SocketAddr>()
+					.or_else(|_| r.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, DNS_PORT)))
+					.map_err(|_| Error::Message(format!("Invalid DNS resolver address: {}", r)))?;
+				let name_servers =
+					NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+				let mut opts = ResolverOpts::default();
+				opts.use_hosts_file = false;
+				TokioAsyncResolver::tokio(
+					ResolverConfig::from_parts(None, vec![], name_servers),
+					resolver_opts(opts),
+				)
+			}
+			None => {
+				let (config, opts) =
+					hickory_resolver::system_conf::read_system_conf().map_err(|e| {
+						Error::Message(format!("Could not read DNS configuration: {}", e))
+					})?;
+				TokioAsyncResolver::tokio(config, resolver_opts(opts))
+			}
+		};
+
+		Ok(Self { config, resolver })
+	}
+
+	pub async fn get_dns_nodes(&self) -> Result<Vec<(NodeID, SocketAddr)>, Error> {
+		// Names and ports of the nodes
+		let targets = match (
+			&self.config.srv_name,
+			&self.config.hostname,
+			self.config.port,
+		) {
+			(Some(srv_name), _, _) => {
+				let mut srv = lookup_records(self.resolver.srv_lookup(srv_name.as_str()).await)?
+					.map(|srv| {
+						srv.iter()
+							.map(|r| (r.priority(), r.target().to_utf8(), r.port()))
+							.collect::<Vec<_>>()
+					})
+					.unwrap_or_default();
+				srv.sort();
+				srv.into_iter()
+					.map(|(_, target, port)| (target, port))
+					.collect::<Vec<_>>()
+			}
+			(None, Some(hostname), Some(port)) => vec![(hostname.clone(), port)],
+			_ => unreachable!(),
+		};
+
+		// A target that cannot be resolved does not prevent
+		// discovering the other ones
+		let mut ret = vec![];
+		for (name, port) in targets {
+			match self.get_target_nodes(&name, port).await {
+				Ok(nodes) => ret.extend(nodes),
+				Err(e) => warn!("Could not resolve {} for DNS discovery: {}", name, e),
+			}
+		}
+		debug!("Got nodes from DNS: {:?}", ret);
+
+		Ok(ret)
+	}
+
+	async fn get_target_nodes(
+		&self,
+		name: &str,
+		port: u16,
+	) -> Result<Vec<(NodeID, SocketAddr)>, Error> {
+		let ips = lookup_records(self.resolver.lookup_ip(name).await)?
+			.map(|ips| ips.iter().collect::<Vec<_>>())
+			.unwrap_or_default();
+
+		let keys = lookup_records(self.resolver.txt_lookup(name).await)?
+			.map(|txt| {
+				txt.iter()
+					.map(|r| {
+						r.iter()
+							.map(|data| String::from_utf8_lossy(data))
+							.collect::<String>()
+					})
+					.collect::<Vec<_>>()
+			})
+			.unwrap_or_default()
+			.into_iter()
+			.filter_map(|t| {
+				let key = parse_node_key(&t);
+				if key.is_none() {
+					debug!("Ignoring TXT record of {} for discovery: {}", name, t);
+				}
+				key
+			})
+			.collect::<Vec<_>>();
+
+		Ok(pair_keys_and_addrs(&keys, &ips, port))
+	}
+}
+
+/// Options of the resolver: addresses of both families are looked up,
+/// and queries are not cached as peers are looked up only periodically
+fn resolver_opts(mut opts: ResolverOpts) -> ResolverOpts {
+	opts.timeout = QUERY_TIMEOUT;
+	opts.attempts = QUERY_ATTEMPTS;
+	opts.ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+	opts.cache_size = 0;
+	opts
+}
+
+/// Names that do not exist or have no records of the requested type
+/// give no records instead of an error
+fn lookup_records<T>(res: Result<T, ResolveError>) -> Result<Option<T>, Error> {
+	match res {
+		Ok(records) => Ok(Some(records)),
+		Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(None),
+		Err(e) => Err(Error::Message(format!("DNS query failed: {}", e))),
+	}
+}
+
+/// Parse a TXT record carrying a node public key, either `<public key>`
+/// or `<public key>@<ip>` to give the key of the node that has that address
+fn parse_node_key(txt: &str) -> Option<(NodeID, Option<IpAddr>)> {
+	let (key, ip) = match txt.split_once('@') {
+		Some((key, ip)) => (key, Some(ip.parse::<IpAddr>().ok()?)),
+		None => (txt, None),
+	};
+	let key = hex::decode(key.trim()).ok()?;
+	Some((NodeID::from_slice(&key)?, ip))
+}
+
+/// Associate public keys with the addresses of a node name. Keys given with
+/// an address are used for that address only, other keys are tried on all
+/// addresses that have no key of their own.
+fn pair_keys_and_addrs(
+	keys: &[(NodeID, Option<IpAddr>)],
+	ips: &[IpAddr],
+	port: u16,
+) -> Vec<(NodeID, SocketAddr)> {
+	let bound_ips = keys
+		.iter()
+		.filter_map(|(_, ip)| *ip)
+		.collect::<HashSet<_>>();
+
+	let mut ret = vec![];
+	for (key, ip) in keys.iter() {
+		match ip {
+			Some(ip) => ret.push((*key, SocketAddr::new(*ip, port))),
+			None => {
+				for ip in ips.iter().filter(|ip| !bound_ips.contains(ip)) {
+					ret.push((*key, SocketAddr::new(*ip, port)));
+				}
+			}
+		}
+	}
+	ret
+}
+
+#[cfg(test)]
+mod tests {
+	use std::collections::HashMap;
+	use std::net::{Ipv4Addr, Ipv6Addr};
+	use std::str::FromStr;
+
+	use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+	use hickory_resolver::proto::rr::rdata::{A, AAAA, SRV, TXT};
+	use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+	use tokio::io::{AsyncReadExt, AsyncWriteExt};
+	use tokio::net::UdpSocket;
+
+	use super::*;
+
+	/// Names for which the stub resolver returns an error
+	const BROKEN_SUFFIX: &str = ".broken.example.com.";
+	/// Maximum number of answers the stub resolver sends over UDP
+	const MAX_UDP_ANSWERS: usize = 2;
+
+	#[derive(Debug, Clone)]
+	enum DnsRecord {
+		A(Ipv4Addr),
+		Aaaa(Ipv6Addr),
+		Srv {
+			priority: u16,
+			weight: u16,
+			port: u16,
+			target: String,
+		},
+		Txt(String),
+	}
+
+	impl DnsRecord {
+		fn rtype(&self) -> RecordType {
+			match self {
+				DnsRecord::A(_) => RecordType::A,
+				DnsRecord::Aaaa(_) => RecordType::AAAA,
+				DnsRecord::Srv { .. } => RecordType::SRV,
+				DnsRecord::Txt(_) => RecordType::TXT,
+			}
+		}
+
+		fn to_record(&self, name: &Name) -> Record {
+			let rdata = match self {
+				DnsRecord::A(ip) => RData::A(A(*ip)),
+				DnsRecord::Aaaa(ip) => RData::AAAA(AAAA(*ip)),
+				DnsRecord::Srv {
+					priority,
+					weight,
+					port,
+					target,
+				} => RData::SRV(SRV::new(
+					*priority,
+					*weight,
+					*port,
+					Name::from_str(target).unwrap(),
+				)),
+				DnsRecord::Txt(t) => RData::TXT(TXT::new(vec![t.clone()])),
+			};
+			Record::from_rdata(name.clone(), 60, rdata)
+		}
+	}
+
+	/// Start a stub DNS server that answers queries from a fixed set of records,
+	/// over UDP and TCP. Names ending with `BROKEN_SUFFIX` get a server failure,
+	/// and responses with more than `MAX_UDP_ANSWERS` are truncated over UDP.
+	async fn stub_resolver(records: Vec<(&str, DnsRecord)>) -> SocketAddr {
+		let mut zone = HashMap::<(String, RecordType), Vec<DnsRecord>>::new();
+		for (name, r) in records {
+			zone.entry((format!("{}.", name), r.rtype()))
+				.or_default()
+				.push(r);
+		}
+		let respond = move |query: &[u8], udp: bool| {
+			let query = Message::from_vec(query).unwrap();
+			let question = query.queries()[0].clone();
+			let name = question.name().to_utf8();
+
+			let mut resp = Message::new();
+			resp.set_id(query.id())
+				.set_message_type(MessageType::Response)
+				.set_recursion_desired(query.recursion_desired())
+				.set_recursion_available(true)
+				.add_query(question.clone());
+			if name.ends_with(BROKEN_SUFFIX) {
+				resp.set_response_code(ResponseCode::ServFail);
+				return resp.to_vec().unwrap();
+			}
+			let mut answers = zone
+				.get(&(name, question.query_type()))
+				.cloned()
+				.unwrap_or_default();
+			if udp && answers.len() > MAX_UDP_ANSWERS {
+				answers.truncate(MAX_UDP_ANSWERS);
+				resp.set_truncated(true);
+			}
+			for r in answers.iter() {
+				resp.add_answer(r.to_record(question.name()));
+			}
+			resp.to_vec().unwrap()
+		};
+
+		let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+		let addr = socket.local_addr().unwrap();
+		let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+
+		let udp_respond = respond.clone();
+		tokio::spawn(async move {
+			let mut buf = [0u8; 512];
+			loop {
+				let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+				let resp = udp_respond(&buf[..len], true);
+				socket.send_to(&resp, from).await.unwrap();
+			}
+		});
+		tokio::spawn(async move {
+			loop {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let respond = respond.clone();
+				tokio::spawn(async move {
+					while let Ok(len) = stream.read_u16().await {
+						let mut query = vec![0u8; len as usize];
+						stream.read_exact(&mut query).await.unwrap();
+						let resp = respond(&query, false);
+						stream
+							.write_all(&(resp.len() as u16).to_be_bytes())
+							.await
+							.unwrap();
+						stream.write_all(&resp).await.unwrap();
+					}
+				});
+			}
+		});
+		addr
+	}
+
+	fn discovery_config(resolver: SocketAddr) -> DnsDiscoveryConfig {
+		DnsDiscoveryConfig {
+			srv_name: None,
+			hostname: None,
+			port: None,
+			resolver: Some(resolver.to_string()),
+		}
+	}
+
+	#[tokio::test]
+	async fn test_srv_discovery() {
+		let key1 = [1u8; 32];
+		let key2 = [2u8; 32];
+		let resolver = stub_resolver(vec![
+			(
+				"_garage._tcp.example.com",
+				DnsRecord::Srv {
+					priority: 0,
+					weight: 0,
+					port: 3901,
+					target: "node1.example.com".into(),
+				},
+			),
+			(
+				"_garage._tcp.example.com",
+				DnsRecord::Srv {
+					priority: 0,
+					weight: 0,
+					port: 3902,
+					target: "node2.example.com".into(),
+				},
+			),
+			(
+				"node1.example.com",
+				DnsRecord::A("10.0.0.1".parse().unwrap()),
+			),
+			("node1.example.com", DnsRecord::Txt(hex::encode(key1))),
+			("node1.example.com", DnsRecord::Txt("v=spf1 -all".into())),
+			(
+				"node2.example.com",
+				DnsRecord::Aaaa("fd00::2".parse().unwrap()),
+			),
+			("node2.example.com", DnsRecord::Txt(hex::encode(key2))),
+		])
+		.await;
+
+		let disco = DnsDiscovery::new(DnsDiscoveryConfig {
+			srv_name: Some("_garage._tcp.example.com".into()),
+			..discovery_config(resolver)
+		})
+		.unwrap();
+		let mut nodes = disco.get_dns_nodes().await.unwrap();
+		nodes.sort();
+
+		assert_eq!(
+			nodes,
+			vec![
+				(
+					NodeID::from_slice(&key1).unwrap(),
+					"10.0.0.1:3901".parse().unwrap()
+				),
+				(
+					NodeID::from_slice(&key2).unwrap(),
+					"[fd00::2]:3902".parse().unwrap()
+				),
+			]
+		);
+	}
+
+	#[tokio::test]
+	async fn test_hostname_discovery() {
+		let key1 = [1u8; 32];
+		let key2 = [2u8; 32];
+		let resolver = stub_resolver(vec![
+			(
+				"garage.example.com",
+				DnsRecord::A("10.0.0.1".parse().unwrap()),
+			),
+			(
+				"garage.example.com",
+				DnsRecord::A("10.0.0.2".parse().unwrap()),
+			),
+			(
+				"garage.example.com",
+				DnsRecord::Txt(format!("{}@10.0.0.1", hex::encode(key1))),
+			),
+			("garage.example.com", DnsRecord::Txt(hex::encode(key2))),
+		])
+		.await;
+
+		let disco = DnsDiscovery::new(DnsDiscoveryConfig {
+			hostname: Some("garage.example.com".into()),
+			port: Some(3901),
+			..discovery_config(resolver)
+		})
+		.unwrap();
+		let mut nodes = disco.get_dns_nodes().await.unwrap();
+		nodes.sort();
+
+		// key2 is not bound to an address, it is tried on the addresses
+		// that have no key of their own
+		assert_eq!(
+			nodes,
+			vec![
+				(
+					NodeID::from_slice(&key1).unwrap(),
+					"10.0.0.1:3901".parse().unwrap()
+				),
+				(
+					NodeID::from_slice(&key2).unwrap(),
+					"10.0.0.2:3901".parse().unwrap()
+				),
+			]
+		);
+
+		// unknown names give no nodes
+		let disco = DnsDiscovery::new(DnsDiscoveryConfig {
+			hostname: Some("other.example.com".into()),
+			port: Some(3901),
+			..discovery_config(resolver)
+		})
+		.unwrap();
+		assert!(disco.get_dns_nodes().await.unwrap().is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_discovery_failures() {
+		let key1 = [1u8; 32];
+		let key2 = [2u8; 32];
+		let mut records = vec![
+			(
+				"_garage._tcp.example.com",
+				DnsRecord::Srv {
+					priority: 0,
+					weight: 0,
+					port: 3901,
+					target: "node1.example.com".into(),
+				},
+			),
+			(
+				"_garage._tcp.example.com",
+				DnsRecord::Srv {
+					priority: 0,
+					weight: 0,
+					port: 3901,
+					target: "node2.broken.example.com".into(),
+				},
+			),
+			("node1.example.com", DnsRecord::Txt(hex::encode(key1))),
+			("node1.example.com", DnsRecord::Txt(hex::encode(key2))),
+		];
+		// More addresses than fit in a UDP response of the stub resolver
+		for i in 1..=4 {
+			records.push((
+				"node1.example.com",
+				DnsRecord::A(Ipv4Addr::new(10, 0, 0, i)),
+			));
+		}
+		let resolver = stub_resolver(records).await;
+
+		let disco = DnsDiscovery::new(DnsDiscoveryConfig {
+			srv_name: Some("_garage._tcp.example.com".into()),
+			..discovery_config(resolver)
+		})
+		.unwrap();
+		let nodes = disco.get_dns_nodes().await.unwrap();
+
+		// node2 could not be resolved and is skipped, and all addresses
+		// of node1 are obtained by retrying the truncated query over TCP
+		assert_eq!(nodes.len(), 8);
+		for i in 1..=4 {
+			let addr = SocketAddr::new(Ipv4Addr::new(10, 0, 0, i).into(), 3901);
+			for key in [key1, key2] {
+				assert!(nodes.contains(&(NodeID::from_slice(&key).unwrap(), addr)));
+			}
+		}
+	}
+
+	#[test]
+	fn test_invalid_config() {
+		let resolver = "127.0.0.1:53".parse().unwrap();
+		assert!(DnsDiscovery::new(discovery_config(resolver)).is_err());
+		assert!(DnsDiscovery::new(DnsDiscoveryConfig {
+			hostname: Some("garage.example.com".into()),
+			..discovery_config(resolver)
+		})
+		.is_err());
+	}
+}
@@ -224,6 +224,47 @@ impl LayoutHelper {
 		ver.nodes_of(position, ver.replication_factor).collect()
 	}
 
+	/// Nodes that store the shards of an erasure-coded data block in the
+	/// current layout version: shard `i` is stored on the `i`-th node
+	pub fn current_shard_nodes_of(&self, position: &Hash, shards: usize) -> Vec<Uuid> {
+		self.current().shard_nodes_of(position, shards)
+	}
+
+	/// Nodes that might store the shards of an erasure-coded data block,
+	/// as (shard number, node) pairs, for the current layout version
+	/// first and then for older versions
+	pub fn shard_read_nodes_of(&self, position: &Hash, shards: usize) -> Vec<(usize, Uuid)> {
+		let versions = self
+			.versions()
+			.iter()
+			.rev()
+			.chain(self.inner().old_versions.iter().rev());
+
+		let mut ret = vec![];
+		for ver in versions {
+			for shard in ver.shard_nodes_of(position, shards).into_iter().enumerate() {
+				if !ret.contains(&shard) {
+					ret.push(shard);
+				}
+			}
+		}
+		ret
+	}
+
+	/// Same as `current_storage_nodes_of`, for data that is stored with
+	/// a lower replication factor than the one of the cluster.
+	/// If `factor` is `None`, the replication factor of the cluster is used.
+	pub fn current_storage_nodes_with_factor(
+		&self,
+		position: &Hash,
+		factor: Option<usize>,
+	) -> Vec<Uuid> {
+		match factor {
+			Some(f) => self.current().nodes_of_with_factor(position, f),
+			None => self.current_storage_nodes_of(position),
+		}
+	}
+
 	pub fn trackers_hash(&self) -> Hash {
 		self.trackers_hash
 	}
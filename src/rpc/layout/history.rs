@@ -67,6 +67,57 @@ impl LayoutHistory {
 		}
 	}
 
+	// ---- removal of nodes ----
+
+	/// If `node` has no storage role in the current layout version, returns
+	/// the partitions it stored in the last version in which it had one
+	/// (including old versions kept in the history), along with whether the
+	/// nodes that store each of these partitions in the current version have
+	/// finished syncing their data for it. Returns `None` if the node still
+	/// stores data in the current version, or if it stored no data in any of
+	/// the versions known to the history.
+	pub fn drained_partitions(&self, node: &Uuid) -> Option<Vec<(Partition, bool)>> {
+		let current = self.current();
+		if current.nongateway_nodes().contains(node) {
+			return None;
+		}
+
+		let last_stored = self
+			.old_versions
+			.iter()
+			.chain(self.versions.iter())
+			.rev()
+			.find(|v| v.nongateway_nodes().contains(node))?;
+
+		let min_version = self.min_stored();
+		let partitions = last_stored
+			.partitions()
+			.filter(|(_, pos)| {
+				last_stored
+					.nodes_of(pos, last_stored.replication_factor)
+					.any(|n| n == *node)
+			})
+			.map(|(partition, pos)| {
+				let nodes = current
+					.nodes_of(&pos, current.replication_factor)
+					.collect::<Vec<_>>();
+				let synced =
+					self.update_trackers.sync_map.min_among(&nodes, min_version) >= current.version;
+				(partition, synced)
+			})
+			.collect();
+		Some(partitions)
+	}
+
+	/// Returns true if `node` stores no data in any of the layout versions
+	/// that are still active, i.e. no other node can still need to read
+	/// data from it
+	pub fn is_drained(&self, node: &Uuid) -> bool {
+		self.versions
+			.iter()
+			.all(|v| !v.nongateway_nodes().contains(node))
+	}
+
 	// ---- housekeeping (all invoked by LayoutHelper) ----
 
 	pub(crate) fn keep_current_version_only(&mut self) {
@@ -45,6 +45,17 @@ const NB_PARTITIONS: usize = 1usize << PARTITION_BITS;
 pub type CompactNodeType = u8;
 pub const MAX_NODE_NUMBER: usize = 256;
 
+/// A copy of a partition that a node has to receive when a new layout
+/// version is applied (see `LayoutVersion::partition_transfers`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartitionTransfer {
+	pub partition: Partition,
+	/// Node that stored the partition in the previous layout version and
+	/// from which the data is expected to come, if there is one
+	pub from: Option<Uuid>,
+	pub to: Uuid,
+}
+
 // ======== actual data structures for the layout data ========
 // ======== that is persisted to disk                  ========
 // some small utility impls are at the end of this file,
@@ -149,6 +160,10 @@ mod v09 {
 		Maximum,
 	}
 
+	impl garage_util::crdt::AutoCrdt for LayoutParameters {
+		const WARN_IF_DIFFERENT: bool = true;
+	}
+
 	impl garage_util::migrate::Migrate for ClusterLayout {
 		const VERSION_MARKER: &'static [u8] = b"G09layout";
 
@@ -311,6 +326,13 @@ mod v010 {
 	#[derive(Clone, Debug, Serialize, Deserialize, Default, PartialEq)]
 	pub struct UpdateTracker(pub BTreeMap<Uuid, u64>);
 
+	impl garage_util::crdt::Crdt for LayoutStaging {
+		fn merge(&mut self, other: &LayoutStaging) {
+			self.parameters.merge(&other.parameters);
+			self.roles.merge(&other.roles);
+		}
+	}
+
 	impl garage_util::migrate::Migrate for LayoutHistory {
 		const VERSION_MARKER: &'static [u8] = b"G010lh";
 
@@ -340,8 +362,7 @@ mod v010 {
 				ring_assignment_data: previous.ring_assignment_data,
 			};
 			let update_tracker = UpdateTracker(
-				version
-					.nongateway_nodes()
+				version.node_id_vec[..version.nongateway_node_count]
 					.iter()
 					.copied()
 					.map(|x| (x, version.version))
@@ -365,7 +386,146 @@ mod v010 {
 	}
 }
 
-pub use v010::*;
+mod v011 {
+	use super::v010;
+	use crate::layout::CompactNodeType;
+	use garage_util::crdt::{Lww, LwwMap};
+	use garage_util::data::Uuid;
+	use serde::{Deserialize, Serialize};
+	pub use v010::{
+		NodeRole, NodeRoleV, UpdateTracker, UpdateTrackers, ZoneRedundancy, OLD_VERSION_COUNT,
+	};
+
+	/// The history of cluster layouts, with trackers to keep a record
+	/// of which nodes are up-to-date to current cluster data
+	#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+	pub struct LayoutHistory {
+		/// The versions currently in use in the cluster
+		pub versions: Vec<LayoutVersion>,
+		/// At most 5 of the previous versions, not used by the garage_table
+		/// module, but useful for the garage_block module to find data blocks
+		/// that have not yet been moved
+		pub old_versions: Vec<LayoutVersion>,
+
+		/// Update trackers
+		pub update_trackers: UpdateTrackers,
+
+		/// Staged changes for the next version
+		pub staging: Lww<LayoutStaging>,
+	}
+
+	/// A version of the layout of the cluster, i.e. the list of roles
+	/// which are assigned to each cluster node
+	#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+	pub struct LayoutVersion {
+		/// The number of this version
+		pub version: u64,
+
+		/// Roles assigned to nodes in this version
+		pub roles: LwwMap<Uuid, NodeRoleV>,
+		/// Parameters used to compute the assignment currently given by
+		/// ring_assignment_data
+		pub parameters: LayoutParameters,
+
+		/// The number of replicas for each data partition
+		pub replication_factor: usize,
+		/// This attribute is only used to retain the previously computed partition size,
+		/// to know to what extent does it change with the layout update.
+		pub partition_size: u64,
+
+		/// node_id_vec: a vector of node IDs with a role assigned
+		/// in the system (this includes gateway nodes).
+		/// The order here is different than the vec stored by `roles`, because:
+		/// 1. non-gateway nodes are first so that they have lower numbers
+		/// 2. nodes that don't have a role are excluded (but they need to
+		///    stay in the CRDT as tombstones)
+		pub node_id_vec: Vec<Uuid>,
+		/// number of non-gateway nodes, which are the first ids in node_id_vec
+		pub nongateway_node_count: usize,
+		/// The assignation of data partitions to nodes, the values
+		/// are indices in node_id_vec
+		#[serde(with = "serde_bytes")]
+		pub ring_assignment_data: Vec<CompactNodeType>,
+	}
+
+	/// The staged changes for the next layout version
+	#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+	pub struct LayoutStaging {
+		/// Parameters to be used in the next partition assignment computation.
+		pub parameters: Lww<LayoutParameters>,
+		/// Role changes which are staged for the next version of the layout
+		pub roles: LwwMap<Uuid, NodeRoleV>,
+	}
+
+	/// This struct is used to set the parameters to be used in the assignment computation
+	/// algorithm. It is stored as a Crdt.
+	#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Serialize, Deserialize)]
+	pub struct LayoutParameters {
+		pub zone_redundancy: ZoneRedundancy,
+		pub objective: LayoutObjective,
+	}
+
+	/// Objective of the layout calculation, under the replication and zone
+	/// redundancy constraints.
+	#[derive(
+		PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Debug, Default, Serialize, Deserialize,
+	)]
+	pub enum LayoutObjective {
+		/// Maximize the usable capacity of the cluster, and among the assignments
+		/// that do so, move as few partitions as possible
+		#[default]
+		Capacity,
+		/// Move as few partitions as possible, accepting a usable capacity that
+		/// is at most the given percentage below the optimal value
+		Movement(u8),
+	}
+
+	impl garage_util::migrate::Migrate for LayoutHistory {
+		const VERSION_MARKER: &'static [u8] = b"G011lh";
+
+		type Previous = v010::LayoutHistory;
+
+		fn migrate(previous: Self::Previous) -> Self {
+			// Layouts computed before the objective could be chosen
+			// maximized the usable capacity
+			let migrate_parameters = |p: v010::LayoutParameters| LayoutParameters {
+				zone_redundancy: p.zone_redundancy,
+				objective: LayoutObjective::Capacity,
+			};
+			let migrate_version = |v: v010::LayoutVersion| LayoutVersion {
+				version: v.version,
+				roles: v.roles,
+				parameters: migrate_parameters(v.parameters),
+				replication_factor: v.replication_factor,
+				partition_size: v.partition_size,
+				node_id_vec: v.node_id_vec,
+				nongateway_node_count: v.nongateway_node_count,
+				ring_assignment_data: v.ring_assignment_data,
+			};
+
+			let staging_ts = previous.staging.timestamp();
+			let staging = previous.staging.take();
+			let parameters_ts = staging.parameters.timestamp();
+			let staging = LayoutStaging {
+				parameters: Lww::raw(parameters_ts, migrate_parameters(staging.parameters.take())),
+				roles: staging.roles,
+			};
+
+			Self {
+				versions: previous.versions.into_iter().map(migrate_version).collect(),
+				old_versions: previous
+					.old_versions
+					.into_iter()
+					.map(migrate_version)
+					.collect(),
+				update_trackers: previous.update_trackers,
+				staging: Lww::raw(staging_ts, staging),
+			}
+		}
+	}
+}
+
+pub use v011::*;
 
 // ---- utility functions ----
 
@@ -421,6 +581,38 @@ impl core::str::FromStr for ZoneRedundancy {
 	}
 }
 
+impl fmt::Display for LayoutObjective {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			LayoutObjective::Capacity => write!(f, "capacity"),
+			LayoutObjective::Movement(t) => write!(f, "movement:{}", t),
+		}
+	}
+}
+
+impl core::str::FromStr for LayoutObjective {
+	type Err = &'static str;
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		const ERR: &str =
+			"layout objective must be 'capacity' or 'movement:<tolerance>', with a tolerance in percent below 100";
+		match s {
+			"capacity" => Ok(LayoutObjective::Capacity),
+			x => {
+				let t = x
+					.strip_prefix("movement:")
+					.ok_or(ERR)?
+					.trim_end_matches('%')
+					.parse::<u8>()
+					.map_err(|_| ERR)?;
+				if t >= 100 {
+					return Err(ERR);
+				}
+				Ok(LayoutObjective::Movement(t))
+			}
+		}
+	}
+}
+
 impl UpdateTracker {
 	fn merge(&mut self, other: &UpdateTracker) -> bool {
 		let mut changed = false;
@@ -1,5 +1,5 @@
 use std::cmp::min;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use garage_util::crdt::Crdt;
 use garage_util::error::*;
@@ -113,6 +113,7 @@ fn update_layout(
 	}
 	staging.parameters.update(LayoutParameters {
 		zone_redundancy: ZoneRedundancy::AtLeast(zone_redundancy),
+		objective: LayoutObjective::Capacity,
 	});
 }
 
@@ -156,3 +157,249 @@ fn test_assignment() {
 	assert_eq!(cl.check(), Ok(()));
 	assert!(check_against_naive(cl.current()).unwrap());
 }
+
+#[test]
+fn test_nodes_of_with_factor() {
+	let node_capacity_vec = vec![4000, 4000, 4000, 4000];
+	let node_zone_vec = vec!["A", "A", "B", "C"];
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 3);
+	let (cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+	let ver = cl.current();
+
+	for (_, pos) in ver.partitions() {
+		let all = ver.nodes_of(&pos, 3).collect::<Vec<_>>();
+
+		let one = ver.nodes_of_with_factor(&pos, 1);
+		assert_eq!(one, vec![all[0]]);
+
+		// the two copies are always in different zones
+		let two = ver.nodes_of_with_factor(&pos, 2);
+		assert_eq!(two.len(), 2);
+		assert!(two.iter().all(|n| all.contains(n)));
+		assert_ne!(ver.get_node_zone(&two[0]), ver.get_node_zone(&two[1]));
+
+		assert_eq!(ver.nodes_of_with_factor(&pos, 3), all);
+	}
+}
+
+#[test]
+fn test_shard_nodes_of() {
+	let node_capacity_vec = vec![4000, 4000, 4000, 4000, 4000, 4000];
+	let node_zone_vec = vec!["A", "A", "B", "C", "D", "D"];
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(2).unwrap());
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 2);
+	let (cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+	let ver = cl.current();
+
+	let mut extra_nodes = HashSet::new();
+	for (_, pos) in ver.partitions() {
+		let all = ver.nodes_of(&pos, 2).collect::<Vec<_>>();
+
+		// with less shards than the replication factor,
+		// shards are stored on the nodes of the partition
+		assert_eq!(ver.shard_nodes_of(&pos, 1), vec![all[0]]);
+
+		// with more shards, the first ones are stored on the nodes of the
+		// partition, and the others on other nodes, in distinct zones
+		let shards = ver.shard_nodes_of(&pos, 4);
+		assert_eq!(shards.len(), 4);
+		assert_eq!(&shards[..2], &all[..]);
+		assert_eq!(shards.iter().collect::<HashSet<_>>().len(), 4);
+		let zones = shards
+			.iter()
+			.map(|n| ver.get_node_zone(n))
+			.collect::<HashSet<_>>();
+		assert_eq!(zones.len(), 4);
+		extra_nodes.extend(shards[2..].iter().copied());
+
+		// zones are reused only when there are not enough of them
+		let shards = ver.shard_nodes_of(&pos, 6);
+		assert_eq!(shards.iter().collect::<HashSet<_>>().len(), 6);
+		assert_eq!(ver.shard_nodes_of(&pos, 7).len(), 6);
+	}
+	// the shards that are not stored on the nodes of their partition
+	// are spread over the cluster
+	assert_eq!(extra_nodes.len(), 6);
+}
+
+#[test]
+fn test_drained_partitions() {
+	let node_capacity_vec = vec![4000, 4000, 4000, 4000];
+	let node_zone_vec = vec!["A", "B", "C", "C"];
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 3);
+	let (mut cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+
+	let removed: Uuid = [3u8; 32].into();
+	assert!(cl.drained_partitions(&removed).is_none());
+	let stored = cl
+		.current()
+		.partitions()
+		.filter(|(_, pos)| cl.current().nodes_of(pos, 3).any(|n| n == removed))
+		.count();
+	assert!(stored > 0);
+
+	let staging = cl.staging.get_mut();
+	let update = staging.roles.update_mutator(removed, NodeRoleV(None));
+	staging.roles.merge(&update);
+	let (mut cl, _msg) = cl.apply_staged_changes(Some(2)).unwrap();
+
+	// nothing is synced yet, and the old version is still active
+	let drained = cl.drained_partitions(&removed).unwrap();
+	assert_eq!(drained.len(), stored);
+	assert!(drained.iter().all(|(_, synced)| !synced));
+	assert!(!cl.is_drained(&removed));
+
+	// once the remaining nodes have synced, all partitions are handed over
+	let remaining = cl.current().nongateway_nodes().to_vec();
+	for node in remaining.iter() {
+		cl.update_trackers.sync_map.set_max(*node, 2);
+	}
+	let drained = cl.drained_partitions(&removed).unwrap();
+	assert_eq!(drained.len(), stored);
+	assert!(drained.iter().all(|(_, synced)| *synced));
+	assert!(!cl.is_drained(&removed));
+
+	// once they have acknowledged it, the old version is no longer active
+	for node in remaining.iter() {
+		cl.update_trackers.sync_ack_map.set_max(*node, 2);
+	}
+	cl.cleanup_old_versions();
+	assert!(cl.is_drained(&removed));
+	assert_eq!(cl.drained_partitions(&removed).unwrap().len(), stored);
+}
+
+#[test]
+fn test_partition_transfers() {
+	let node_capacity_vec = vec![4000, 4000, 4000];
+	let node_zone_vec = vec!["A", "B", "C"];
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	let empty = cl.current().clone();
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 3);
+	let (mut cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+	let v1 = cl.current().clone();
+
+	// the first layout version receives everything from nowhere
+	let transfers = empty.partition_transfers(&v1);
+	assert_eq!(transfers.len(), 3 * NB_PARTITIONS);
+	assert!(transfers.iter().all(|t| t.from.is_none()));
+	assert!(v1.partition_transfers(&v1).is_empty());
+
+	// add a node in a new zone: it takes over partitions from nodes that
+	// stored them before, and every copy it now stores is transferred once
+	update_layout(&mut cl, &[4000, 4000, 4000, 8000], &["A", "B", "C", "D"], 3);
+	let (cl, _msg) = cl.apply_staged_changes(Some(2)).unwrap();
+	let v2 = cl.current();
+
+	let new_node: Uuid = [3u8; 32].into();
+	let transfers = v1.partition_transfers(v2);
+	assert!(!transfers.is_empty());
+	for t in transfers.iter() {
+		let pos = v2.partitions().nth(t.partition as usize).unwrap().1;
+		assert_eq!(t.to, new_node);
+		let from = t.from.unwrap();
+		assert!(v1.nodes_of(&pos, 3).any(|n| n == from));
+		assert!(!v2.nodes_of(&pos, 3).any(|n| n == from));
+	}
+	let stored = v2
+		.partitions()
+		.filter(|(_, pos)| v2.nodes_of(pos, 3).any(|n| n == new_node))
+		.count();
+	assert_eq!(transfers.len(), stored);
+}
+
+#[test]
+fn test_movement_objective() {
+	let node_capacity_vec = vec![4000000, 4000000, 4000000, 4000000];
+	let node_zone_vec = vec!["A", "B", "C", "D"];
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &node_capacity_vec, &node_zone_vec, 3);
+	let (cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+	let v1 = cl.current().clone();
+
+	// add a small node in a new zone, with each objective
+	let add_small_node = |objective| {
+		let mut cl = cl.clone();
+		update_layout(
+			&mut cl,
+			&[4000000, 4000000, 4000000, 4000000, 400000],
+			&["A", "B", "C", "D", "E"],
+			3,
+		);
+		let staging = cl.staging.get_mut();
+		let mut parameters = *staging.parameters.get();
+		parameters.objective = objective;
+		staging.parameters.update(parameters);
+		let (cl, msg) = cl.apply_staged_changes(Some(2)).unwrap();
+		show_msg(&msg);
+		assert_eq!(cl.check(), Ok(()));
+		cl.current().clone()
+	};
+
+	let v2_capacity = add_small_node(LayoutObjective::Capacity);
+	let v2_movement = add_small_node(LayoutObjective::Movement(10));
+
+	let moved_capacity = v1.partition_transfers(&v2_capacity).len();
+	let moved_movement = v1.partition_transfers(&v2_movement).len();
+	assert!(moved_capacity > 0);
+	assert!(moved_movement < moved_capacity);
+
+	// the capacity lost is within the tolerance
+	assert!(v2_movement.partition_size <= v2_capacity.partition_size);
+	assert!(v2_movement.partition_size * 100 >= v2_capacity.partition_size * 90);
+}
+
+#[test]
+fn test_migrate_v010_layout() {
+	use garage_util::migrate::Migrate;
+
+	let mut cl = LayoutHistory::new(ReplicationFactor::new(3).unwrap());
+	update_layout(&mut cl, &[4000, 1000, 1000, 3000], &["A", "B", "C", "C"], 2);
+	let (cl, _msg) = cl.apply_staged_changes(Some(1)).unwrap();
+
+	// encode the same layout in the format used before the objective existed
+	let to_v010 = |v: &LayoutVersion| super::v010::LayoutVersion {
+		version: v.version,
+		roles: v.roles.clone(),
+		parameters: super::v010::LayoutParameters {
+			zone_redundancy: v.parameters.zone_redundancy,
+		},
+		replication_factor: v.replication_factor,
+		partition_size: v.partition_size,
+		node_id_vec: v.node_id_vec.clone(),
+		nongateway_node_count: v.nongateway_node_count,
+		ring_assignment_data: v.ring_assignment_data.clone(),
+	};
+	let staging = cl.staging.get();
+	let old = super::v010::LayoutHistory {
+		versions: cl.versions.iter().map(to_v010).collect(),
+		old_versions: cl.old_versions.iter().map(to_v010).collect(),
+		update_trackers: cl.update_trackers.clone(),
+		staging: garage_util::crdt::Lww::raw(
+			cl.staging.timestamp(),
+			super::v010::LayoutStaging {
+				parameters: garage_util::crdt::Lww::raw(
+					staging.parameters.timestamp(),
+					super::v010::LayoutParameters {
+						zone_redundancy: staging.parameters.get().zone_redundancy,
+					},
+				),
+				roles: staging.roles.clone(),
+			},
+		),
+	};
+
+	let migrated = LayoutHistory::decode(&old.encode().unwrap()).unwrap();
+	assert_eq!(migrated, cl);
+	assert_eq!(
+		migrated.current().parameters.objective,
+		LayoutObjective::Capacity
+	);
+	assert_eq!(migrated.check(), Ok(()));
+}
@@ -21,6 +21,7 @@ impl LayoutVersion {
 		// possible value will be used depending on the cluster topology
 		let parameters = LayoutParameters {
 			zone_redundancy: ZoneRedundancy::Maximum,
+			objective: LayoutObjective::Capacity,
 		};
 
 		LayoutVersion {
@@ -134,6 +135,134 @@ impl LayoutVersion {
 			.map(move |i| self.node_id_vec[*i as usize])
 	}
 
+	/// Return the nodes that store data with a replication factor of `factor`
+	/// at a given position, when `factor` is lower than the replication factor
+	/// of the layout. These are taken among the nodes assigned to the partition,
+	/// in the order of the assignment, preferring nodes in zones that are not
+	/// used yet so that copies are spread over as many zones as possible.
+	///
+	/// No separate assignment is computed for each replication factor, so the
+	/// data of such buckets is not balanced according to node capacities as
+	/// precisely as the data stored with the replication factor of the layout.
+	/// The spread over zones still holds: the assignment puts the copies of
+	/// each partition in at least `zone_redundancy` distinct zones, so the
+	/// `factor` nodes returned are in distinct zones as long as `factor` is
+	/// not greater than the effective zone redundancy.
+	pub fn nodes_of_with_factor(&self, position: &Hash, factor: usize) -> Vec<Uuid> {
+		let nodes = self
+			.nodes_of(position, self.replication_factor)
+			.collect::<Vec<_>>();
+		if factor >= nodes.len() {
+			return nodes;
+		}
+
+		let mut ret = Vec::with_capacity(factor);
+		let mut zones = HashSet::new();
+		for node in nodes.iter() {
+			if ret.len() < factor && zones.insert(self.get_node_zone(node)) {
+				ret.push(*node);
+			}
+		}
+		for node in nodes.iter() {
+			if ret.len() < factor && !ret.contains(node) {
+				ret.push(*node);
+			}
+		}
+		ret
+	}
+
+	/// Return the nodes that store the shards of an erasure-coded block at a
+	/// given position: shard `i` is stored on the `i`-th node. The first shards
+	/// are stored on the nodes assigned to the partition, chosen as in
+	/// `nodes_of_with_factor`. When there are more shards than the replication
+	/// factor, the remaining ones are stored on other storage nodes of the
+	/// layout, taken in an order that depends on the partition so that they
+	/// are spread over the cluster, and preferring nodes in zones that are not
+	/// used yet. Less than `shards` nodes are returned if the layout does not
+	/// have enough storage nodes.
+	pub fn shard_nodes_of(&self, position: &Hash, shards: usize) -> Vec<Uuid> {
+		let mut ret =
+			self.nodes_of_with_factor(position, std::cmp::min(shards, self.replication_factor));
+		if ret.len() >= shards || ret.len() < self.replication_factor {
+			return ret;
+		}
+
+		let partition = self.partition_of(position);
+		let mut others = self
+			.nongateway_nodes()
+			.iter()
+			.filter(|n| !ret.contains(n))
+			.copied()
+			.collect::<Vec<_>>();
+		others.sort_by_key(|n| blake2sum(&[&partition.to_be_bytes()[..], n.as_slice()].concat()));
+
+		let mut zones = ret
+			.iter()
+			.map(|n| self.get_node_zone(n))
+			.collect::<HashSet<_>>();
+		while ret.len() < shards && !others.is_empty() {
+			let i = others
+				.iter()
+				.position(|n| !zones.contains(&self.get_node_zone(n)))
+				.unwrap_or(0);
+			let node = others.remove(i);
+			zones.insert(self.get_node_zone(&node));
+			ret.push(node);
+		}
+		ret
+	}
+
+	/// Compute the copies of partitions that nodes have to receive when going
+	/// from this layout version to `next`. For each partition, each node that
+	/// is assigned to it in `next` but not in this version receives it from a
+	/// node that stored it before, preferably one that no longer stores it and
+	/// that is in the same zone.
+	pub fn partition_transfers(&self, next: &LayoutVersion) -> Vec<PartitionTransfer> {
+		let mut ret = vec![];
+		for (partition, pos) in next.partitions() {
+			let old_nodes = if self.ring_assignment_data.is_empty() {
+				vec![]
+			} else {
+				self.nodes_of(&pos, self.replication_factor)
+					.collect::<Vec<_>>()
+			};
+			let new_nodes = next
+				.nodes_of(&pos, next.replication_factor)
+				.collect::<Vec<_>>();
+
+			let mut givers = old_nodes
+				.iter()
+				.filter(|n| !new_nodes.contains(n))
+				.copied()
+				.collect::<Vec<_>>();
+			let kept = old_nodes
+				.iter()
+				.filter(|n| new_nodes.contains(n))
+				.copied()
+				.collect::<Vec<_>>();
+			let receivers = new_nodes.iter().filter(|n| !old_nodes.contains(n)).unique();
+
+			for (i, to) in receivers.enumerate() {
+				let zone = next.get_node_zone(to);
+				let from = match givers.iter().position(|n| self.get_node_zone(n) == zone) {
+					Some(j) => Some(givers.remove(j)),
+					None if !givers.is_empty() => Some(givers.remove(0)),
+					None => kept
+						.iter()
+						.find(|n| self.get_node_zone(n) == zone)
+						.or_else(|| kept.get(i % kept.len().max(1)))
+						.copied(),
+				};
+				ret.push(PartitionTransfer {
+					partition,
+					from,
+					to: *to,
+				});
+			}
+		}
+		ret
+	}
+
 	// ===================== internal information extractors ======================
 
 	pub(crate) fn expect_get_node_capacity(&self, uuid: &Uuid) -> u64 {
@@ -259,11 +388,13 @@ impl LayoutVersion {
 		}
 
 		// Check that the partition size stored is the one computed by the asignation
-		// algorithm.
+		// algorithm, or within the tolerance of the layout objective.
 		let cl2 = self.clone();
 		let (_, zone_to_id) = cl2.generate_nongateway_zone_ids().unwrap();
 		match cl2.compute_optimal_partition_size(&zone_to_id, zone_redundancy) {
-			Ok(s) if s != self.partition_size => {
+			Ok(s)
+				if self.partition_size > s || self.partition_size < self.min_partition_size(s) =>
+			{
 				return Err(format!(
 					"partition_size ({}) is different than optimal value ({})",
 					self.partition_size, s
@@ -343,19 +474,28 @@ impl LayoutVersion {
 		// Capacities should be given in a unit so that partition size is at least 100.
 		// In this case, integer rounding plays a marginal role in the percentages of
 		// optimality.
-		let partition_size = self.compute_optimal_partition_size(&zone_to_id, zone_redundancy)?;
+		let optimal_partition_size =
+			self.compute_optimal_partition_size(&zone_to_id, zone_redundancy)?;
+		// If the objective is to minimize data movement, we compute the assignment
+		// with the smallest partition size allowed: nodes can then be assigned more
+		// partitions than in the optimal assignment, and keep more of the partitions
+		// they already store.
+		let partition_size = match old_assignment_opt {
+			Some(_) => self.min_partition_size(optimal_partition_size),
+			None => optimal_partition_size,
+		};
 
 		msg.push("".into());
 		if old_assignment_opt.is_some() {
 			msg.push(format!(
 				"Optimal partition size:                     {} ({} in previous layout)",
-				ByteSize::b(partition_size).to_string_as(false),
+				ByteSize::b(optimal_partition_size).to_string_as(false),
 				ByteSize::b(self.partition_size).to_string_as(false)
 			));
 		} else {
 			msg.push(format!(
 				"Optimal partition size:                     {}",
-				ByteSize::b(partition_size).to_string_as(false)
+				ByteSize::b(optimal_partition_size).to_string_as(false)
 			));
 		}
 		// We write the partition size.
@@ -377,12 +517,27 @@ impl LayoutVersion {
 			self.minimize_rebalance_load(&mut gflow, &zone_to_id, assoc)?;
 		}
 
-		// We display statistics of the computation
-		msg.extend(self.output_stat(&gflow, &old_assignment_opt, &zone_to_id, &id_to_zone)?);
-
 		// We update the layout structure
 		self.update_ring_from_flow(id_to_zone.len(), &gflow)?;
 
+		if let LayoutObjective::Movement(tolerance) = self.parameters.objective {
+			// The assignment that minimizes movement might not use all of the
+			// capacity that was allowed: the actual partition size is the largest
+			// one for which all nodes can hold the partitions they are assigned.
+			self.partition_size = std::cmp::min(
+				optimal_partition_size,
+				self.max_partition_size_of_assignment(),
+			);
+			msg.push(format!(
+				"Partition size with minimal movement:       {} (tolerance of {}% below optimal)",
+				ByteSize::b(self.partition_size).to_string_as(false),
+				tolerance
+			));
+		}
+
+		// We display statistics of the computation
+		msg.extend(self.output_stat(&gflow, &old_assignment_opt, &zone_to_id, &id_to_zone)?);
+
 		if let Err(e) = self.check() {
 			return Err(Error::Message(
 				format!("Layout check returned an error: {}\nOriginal result of computation: <<<<\n{}\n>>>>", e, msg.join("\n"))
@@ -495,6 +650,32 @@ impl LayoutVersion {
 		Ok((id_to_zone, zone_to_id))
 	}
 
+	/// Returns the smallest partition size that is allowed by the objective of the
+	/// layout computation, given the optimal partition size
+	fn min_partition_size(&self, optimal_partition_size: u64) -> u64 {
+		match self.parameters.objective {
+			LayoutObjective::Capacity => optimal_partition_size,
+			LayoutObjective::Movement(tolerance) => std::cmp::max(
+				1,
+				optimal_partition_size * (100 - std::cmp::min(tolerance, 99) as u64) / 100,
+			),
+		}
+	}
+
+	/// Returns the largest partition size for which all nodes can store the
+	/// partitions that are assigned to them in ring_assignment_data
+	fn max_partition_size_of_assignment(&self) -> u64 {
+		self.ring_assignment_data
+			.iter()
+			.sorted()
+			.dedup_with_count()
+			.map(|(npart, n)| {
+				self.expect_get_node_capacity(&self.node_id_vec[*n as usize]) / npart as u64
+			})
+			.min()
+			.unwrap_or(0)
+	}
+
 	/// This function computes by dichotomy the largest realizable partition size, given
 	/// the layout roles and parameters.
 	fn compute_optimal_partition_size(
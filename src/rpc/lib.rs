@@ -8,6 +8,8 @@ mod system_metrics;
 
 #[cfg(feature = "consul-discovery")]
 mod consul;
+#[cfg(feature = "dns-discovery")]
+mod dns;
 #[cfg(feature = "kubernetes-discovery")]
 mod kubernetes;
 
@@ -6,6 +6,8 @@ pub struct RpcMetrics {
 	pub(crate) rpc_timeout_counter: Counter<u64>,
 	pub(crate) rpc_netapp_error_counter: Counter<u64>,
 	pub(crate) rpc_garage_error_counter: Counter<u64>,
+	pub(crate) rpc_hedge_sent_counter: Counter<u64>,
+	pub(crate) rpc_hedge_won_counter: Counter<u64>,
 
 	pub(crate) rpc_duration: ValueRecorder<f64>,
 }
@@ -29,6 +31,14 @@ impl RpcMetrics {
 				.u64_counter("rpc.garage_error_counter")
 				.with_description("Number of RPC errors (errors happening when handling the RPC)")
 				.init(),
+			rpc_hedge_sent_counter: meter
+				.u64_counter("rpc.hedge_sent_counter")
+				.with_description("Number of hedged read requests, sent to an additional node because the first ones did not answer in time")
+				.init(),
+			rpc_hedge_won_counter: meter
+				.u64_counter("rpc.hedge_won_counter")
+				.with_description("Number of hedged read requests whose response was used")
+				.init(),
 			rpc_duration: meter
 				.f64_value_recorder("rpc.duration")
 				.with_description("Duration of RPCs")
@@ -1,7 +1,7 @@
 //! Contain structs related to making RPCs
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, RwLock};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use futures::future::join_all;
 use futures::stream::futures_unordered::FuturesUnordered;
@@ -32,12 +32,22 @@ use crate::metrics::RpcMetrics;
 // Default RPC timeout = 5 minutes
 const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
 
+// A read request is hedged when it has not been answered after this many
+// times the median and the maximum recent ping of the node it was sent to,
+// within the bounds below
+const HEDGE_MED_PING_FACTOR: u32 = 8;
+const HEDGE_MAX_PING_FACTOR: u32 = 2;
+const HEDGE_MIN_DELAY: Duration = Duration::from_millis(50);
+const HEDGE_MAX_DELAY: Duration = Duration::from_secs(2);
+
 /// Strategy to apply when making RPC
 pub struct RequestStrategy<T> {
 	/// Min number of response to consider the request successful
 	rs_quorum: Option<usize>,
 	/// Send all requests at once
 	rs_send_all_at_once: Option<bool>,
+	/// Send the request to additional nodes when the first ones are slow
+	rs_hedging: bool,
 	/// Request priority
 	rs_priority: RequestPriority,
 	/// Custom timeout for this request
@@ -58,6 +68,7 @@ impl Clone for RequestStrategy<()> {
 		RequestStrategy {
 			rs_quorum: self.rs_quorum,
 			rs_send_all_at_once: self.rs_send_all_at_once,
+			rs_hedging: self.rs_hedging,
 			rs_priority: self.rs_priority,
 			rs_timeout: self.rs_timeout,
 			rs_drop_on_complete: (),
@@ -71,6 +82,7 @@ impl RequestStrategy<()> {
 		RequestStrategy {
 			rs_quorum: None,
 			rs_send_all_at_once: None,
+			rs_hedging: false,
 			rs_priority: prio,
 			rs_timeout: Timeout::Default,
 			rs_drop_on_complete: (),
@@ -81,6 +93,7 @@ impl RequestStrategy<()> {
 		RequestStrategy {
 			rs_quorum: self.rs_quorum,
 			rs_send_all_at_once: self.rs_send_all_at_once,
+			rs_hedging: self.rs_hedging,
 			rs_priority: self.rs_priority,
 			rs_timeout: self.rs_timeout,
 			rs_drop_on_complete: drop_on_complete,
@@ -99,6 +112,14 @@ impl<T> RequestStrategy<T> {
 		self.rs_send_all_at_once = Some(value);
 		self
 	}
+	/// Hedge this request: if the nodes it was sent to are slow to answer,
+	/// also send it to other nodes. This must only be used for idempotent
+	/// requests such as reads, as the request may be handled by more than
+	/// `quorum` nodes.
+	pub fn with_hedging(mut self) -> Self {
+		self.rs_hedging = true;
+		self
+	}
 	/// Deactivate timeout for this request
 	pub fn without_timeout(mut self) -> Self {
 		self.rs_timeout = Timeout::None;
@@ -115,6 +136,7 @@ impl<T> RequestStrategy<T> {
 			RequestStrategy {
 				rs_quorum: self.rs_quorum,
 				rs_send_all_at_once: self.rs_send_all_at_once,
+				rs_hedging: self.rs_hedging,
 				rs_priority: self.rs_priority,
 				rs_timeout: self.rs_timeout,
 				rs_drop_on_complete: (),
@@ -133,6 +155,9 @@ struct RpcHelperInner {
 	layout: Arc<RwLock<LayoutHelper>>,
 	metrics: RpcMetrics,
 	rpc_timeout: Duration,
+	/// Nodes that announced they are in maintenance, to which no requests
+	/// are sent unless they are required to reach a quorum
+	maintenance: RwLock<HashSet<Uuid>>,
 }
 
 impl RpcHelper {
@@ -150,6 +175,7 @@ impl RpcHelper {
 			layout,
 			metrics,
 			rpc_timeout: rpc_timeout.unwrap_or(DEFAULT_TIMEOUT),
+			maintenance: RwLock::new(HashSet::new()),
 		}))
 	}
 
@@ -157,6 +183,70 @@ impl RpcHelper {
 		self.0.rpc_timeout
 	}
 
+	/// Returns whether a node has announced that it is in maintenance
+	pub fn is_in_maintenance(&self, node: &Uuid) -> bool {
+		self.0.maintenance.read().unwrap().contains(node)
+	}
+
+	/// Returns the nodes among `nodes` that have announced that they are
+	/// in maintenance, without duplicates
+	pub fn nodes_in_maintenance<'a>(&self, nodes: impl IntoIterator<Item = &'a Uuid>) -> Vec<Uuid> {
+		let maintenance = self.0.maintenance.read().unwrap();
+		let mut ret = vec![];
+		for node in nodes {
+			if maintenance.contains(node) && !ret.contains(node) {
+				ret.push(*node);
+			}
+		}
+		ret
+	}
+
+	/// Delay after which a read request to `node` that has not been answered
+	/// is hedged, i.e. also sent to another node. It is derived from the ping
+	/// times of the node: a node that takes many times its usual round-trip
+	/// time to answer is likely to be slow (busy disk, overloaded node, etc).
+	pub fn hedge_delay(&self, node: &Uuid) -> Duration {
+		let peer_list = self.0.peering.get_peer_list();
+		let pings = peer_list
+			.iter()
+			.find(|x| x.id.as_ref() == node.as_slice())
+			.and_then(|pi| Some((pi.med_ping?, pi.max_ping?)));
+		match pings {
+			Some((med_ping, max_ping)) => (HEDGE_MED_PING_FACTOR * med_ping)
+				.max(HEDGE_MAX_PING_FACTOR * max_ping)
+				.clamp(HEDGE_MIN_DELAY, HEDGE_MAX_DELAY),
+			None => HEDGE_MAX_DELAY,
+		}
+	}
+
+	/// Count a hedged request sent by a caller that does its own hedging
+	pub fn record_hedge_sent(&self, endpoint_path: &str) {
+		self.0.metrics.rpc_hedge_sent_counter.add(
+			1,
+			&[KeyValue::new("rpc_endpoint", endpoint_path.to_string())],
+		);
+	}
+
+	/// Count a hedged request whose response was used, for a caller
+	/// that does its own hedging
+	pub fn record_hedge_won(&self, endpoint_path: &str) {
+		self.0.metrics.rpc_hedge_won_counter.add(
+			1,
+			&[KeyValue::new("rpc_endpoint", endpoint_path.to_string())],
+		);
+	}
+
+	pub(crate) fn set_in_maintenance(&self, node: Uuid, maintenance: bool) {
+		let mut set = self.0.maintenance.write().unwrap();
+		if maintenance {
+			if set.insert(node) {
+				info!("Node {:?} is now in maintenance", node);
+			}
+		} else if set.remove(&node) {
+			info!("Node {:?} is no longer in maintenance", node);
+		}
+	}
+
 	pub async fn call<M, N, H, S>(
 		&self,
 		endpoint: &Endpoint<M, H>,
@@ -282,6 +372,13 @@ impl RpcHelper {
 	/// the `RpcHelper::request_order` function, which takes into account
 	/// parameters such as node zones and measured ping values.
 	///
+	/// If RequestStrategy has hedging enabled, requests that are slow to be
+	/// answered are also sent to the next nodes in that order, so more than
+	/// `quorum` nodes may handle the request even without errors.
+	///
+	/// Nodes that are in maintenance are only sent a request if the quorum
+	/// cannot be reached with the other nodes.
+	///
 	/// In both cases, the basic contract of this function is that even in the
 	/// absence of failures, the RPC call might not be driven to completion
 	/// on all of the specified nodes. It is therefore unfit for broadcast
@@ -338,27 +435,37 @@ impl RpcHelper {
 		// What we do here is only send the required number of requests
 		// to reach a quorum, priorizing nodes with the lowest latency.
 		// When there are errors, we start new requests to compensate.
-
-		// TODO: this could be made more aggressive, e.g. if after 2x the
-		// average ping of a given request, the response is not yet received,
-		// preemptively send an additional request to any remaining nodes.
+		// If the strategy allows it (see `with_hedging`), when a request is not
+		// answered within a delay derived from the ping times of the node
+		// (see `hedge_delay`), we also send the same request to an additional
+		// node and use whichever response arrives first (request hedging),
+		// so that a slow node does not slow down the request.
 
 		// Reorder requests to priorize closeness / low latency
 		let request_order =
 			self.request_order(&self.0.layout.read().unwrap().current(), to.iter().copied());
 		let send_all_at_once = strategy.rs_send_all_at_once.unwrap_or(false);
+		let hedging = strategy.rs_hedging;
+		// Nodes in maintenance are at the end of the request order, so that
+		// they are only used as a last resort
+		let num_available = request_order
+			.iter()
+			.filter(|x| !self.is_in_maintenance(x))
+			.count();
 
 		// Build future for each request
 		// They are not started now: they are added below in a FuturesUnordered
 		// object that will take care of polling them (see below)
 		let msg = msg.into_req().map_err(garage_net::error::Error::from)?;
-		let mut requests = request_order.into_iter().map(|to| {
+		let make_request = |to: Uuid, hedge: bool| {
 			let self2 = self.clone();
 			let msg = msg.clone();
 			let endpoint2 = endpoint.clone();
 			let strategy = strategy.clone();
-			async move { self2.call(&endpoint2, to, msg, strategy).await }
-		});
+			async move { (hedge, self2.call(&endpoint2, to, msg, strategy).await) }
+		};
+		let mut nodes = request_order.into_iter();
+		let metric_tags = [KeyValue::new("rpc_endpoint", endpoint.path().to_string())];
 
 		// Vectors in which success results and errors will be collected
 		let mut successes = vec![];
@@ -367,14 +474,20 @@ impl RpcHelper {
 		// resp_stream will contain all of the requests that are currently in flight.
 		// (for the moment none, they will be added in the loop below)
 		let mut resp_stream = FuturesUnordered::new();
+		let mut num_sent = 0;
+		let mut hedge_at = Instant::now();
 
 		// Do some requests and collect results
 		while successes.len() < quorum {
 			// If the current set of requests that are running is not enough to possibly
 			// reach quorum, start some new requests.
-			while send_all_at_once || successes.len() + resp_stream.len() < quorum {
-				if let Some(fut) = requests.next() {
-					resp_stream.push(fut)
+			while (send_all_at_once && num_sent < num_available)
+				|| successes.len() + resp_stream.len() < quorum
+			{
+				if let Some(to) = nodes.next() {
+					resp_stream.push(make_request(to, false));
+					num_sent += 1;
+					hedge_at = Instant::now() + self.hedge_delay(&to);
 				} else {
 					break;
 				}
@@ -385,9 +498,28 @@ impl RpcHelper {
 				break;
 			}
 
-			// Wait for one request to terminate
-			match resp_stream.next().await.unwrap() {
+			// Wait for one request to terminate, or send a hedged request
+			// if none terminates in time
+			let (hedge, resp) = if hedging && num_sent < num_available {
+				select! {
+					resp = resp_stream.next() => resp.unwrap(),
+					_ = tokio::time::sleep_until(hedge_at.into()) => {
+						let to = nodes.next().unwrap();
+						resp_stream.push(make_request(to, true));
+						num_sent += 1;
+						hedge_at = Instant::now() + self.hedge_delay(&to);
+						self.0.metrics.rpc_hedge_sent_counter.add(1, &metric_tags);
+						continue;
+					}
+				}
+			} else {
+				resp_stream.next().await.unwrap()
+			};
+			match resp {
 				Ok(msg) => {
+					if hedge {
+						self.0.metrics.rpc_hedge_won_counter.add(1, &metric_tags);
+					}
 					successes.push(msg);
 				}
 				Err(e) => {
@@ -429,6 +561,11 @@ impl RpcHelper {
 	/// changes, where data has to be written both in the old layout and in the
 	/// new one as long as all nodes have not successfully tranisitionned and
 	/// moved all data to the new layout.
+	///
+	/// Nodes that are in maintenance are not sent any request, and count as
+	/// failed nodes in their quorum sets: the caller is responsible for storing
+	/// the data that they miss until they come back (see hinted handoff in
+	/// the table crate).
 	pub async fn try_write_many_sets<M, N, H, S, T>(
 		&self,
 		endpoint: &Arc<Endpoint<M, H>>,
@@ -486,13 +623,25 @@ impl RpcHelper {
 
 		let (strategy, drop_on_complete) = strategy.extract_drop_on_complete();
 
+		// Nodes in maintenance are skipped and count as failures
+		let (in_maintenance, peers): (Vec<Uuid>, Vec<Uuid>) = result_tracker
+			.nodes
+			.keys()
+			.partition(|peer| self.is_in_maintenance(peer));
+		for node in in_maintenance {
+			result_tracker.register_result(node, Err(Error::NodeInMaintenance(node)));
+		}
+		if result_tracker.too_many_failures() {
+			return Err(result_tracker.quorum_error());
+		}
+
 		// Send one request to each peer of the quorum sets
 		let msg = msg.into_req().map_err(garage_net::error::Error::from)?;
-		let requests = result_tracker.nodes.keys().map(|peer| {
+		let requests = peers.into_iter().map(|peer| {
 			let self2 = self.clone();
 			let msg = msg.clone();
 			let endpoint2 = endpoint.clone();
-			let to = *peer;
+			let to = peer;
 			let strategy = strategy.clone();
 			async move { (to, self2.call(&endpoint2, to, msg, strategy).await) }
 		});
@@ -566,7 +715,8 @@ impl RpcHelper {
 	///
 	/// The preference order, for each layout version, is given by `request_order`,
 	/// based on factors such as nodes being in the same datacenter,
-	/// having low ping, etc.
+	/// having low ping, etc. Nodes that are in maintenance are always
+	/// asked last.
 	pub fn block_read_nodes_of(&self, position: &Hash, rpc_helper: &RpcHelper) -> Vec<Uuid> {
 		let layout = self.0.layout.read().unwrap();
 
@@ -615,6 +765,12 @@ impl RpcHelper {
 			}
 		}
 
+		// Nodes in maintenance are only asked as a last resort
+		let (mut ret, in_maintenance): (Vec<Uuid>, Vec<Uuid>) = ret
+			.into_iter()
+			.partition(|node| !rpc_helper.is_in_maintenance(node));
+		ret.extend(in_maintenance);
+
 		ret
 	}
 
@@ -629,10 +785,12 @@ impl RpcHelper {
 
 		// Augment requests with some information used to sort them.
 		// The tuples are as follows:
-		//         (is another node?, is another zone?, latency, node ID, request future)
+		//         (is in maintenance?, is another node?, is another zone?, latency, node ID)
 		// We store all of these tuples in a vec that we can sort.
 		// By sorting this vec, we priorize ourself, then nodes in the same zone,
 		// and within a same zone we priorize nodes with the lowest latency.
+		// Nodes in maintenance always come last.
+		let maintenance = self.0.maintenance.read().unwrap();
 		let mut nodes = nodes
 			.map(|to| {
 				let peer_zone = layout.get_node_zone(&to).unwrap_or("");
@@ -642,6 +800,7 @@ impl RpcHelper {
 					.and_then(|pi| pi.avg_ping)
 					.unwrap_or_else(|| Duration::from_secs(10));
 				(
+					maintenance.contains(&to),
 					to != self.0.our_node_id,
 					peer_zone != our_zone,
 					peer_avg_ping,
@@ -650,12 +809,15 @@ impl RpcHelper {
 			})
 			.collect::<Vec<_>>();
 
-		// Sort requests by (priorize ourself, priorize same zone, priorize low latency)
-		nodes.sort_by_key(|(diffnode, diffzone, ping, _to)| (*diffnode, *diffzone, *ping));
+		// Sort requests by (avoid maintenance, priorize ourself, priorize same zone,
+		// priorize low latency)
+		nodes.sort_by_key(|(maint, diffnode, diffzone, ping, _to)| {
+			(*maint, *diffnode, *diffzone, *ping)
+		});
 
 		nodes
 			.into_iter()
-			.map(|(_, _, _, to)| to)
+			.map(|(_, _, _, _, to)| to)
 			.collect::<Vec<_>>()
 	}
 }
@@ -764,3 +926,160 @@ where
 		)
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use std::sync::atomic::{AtomicUsize, Ordering};
+
+	use serde::{Deserialize, Serialize};
+	use sodiumoxide::crypto::{auth, sign::ed25519};
+
+	use super::*;
+	use crate::layout::LayoutHistory;
+	use crate::replication_mode::{ConsistencyMode, ReplicationFactor};
+
+	#[derive(Serialize, Deserialize)]
+	struct TestRpc;
+
+	impl Rpc for TestRpc {
+		type Response = Result<(), Error>;
+	}
+
+	/// Answers requests slower than the hedging delay of any node
+	struct SlowHandler(AtomicUsize);
+
+	impl EndpointHandler<TestRpc> for SlowHandler {
+		async fn handle(self: &Arc<Self>, _msg: &TestRpc, _from: NodeID) -> Result<(), Error> {
+			self.0.fetch_add(1, Ordering::SeqCst);
+			tokio::time::sleep(HEDGE_MAX_DELAY + Duration::from_millis(500)).await;
+			Ok(())
+		}
+	}
+
+	/// Answers requests immediately
+	struct CountingHandler(AtomicUsize);
+
+	impl EndpointHandler<TestRpc> for CountingHandler {
+		async fn handle(self: &Arc<Self>, _msg: &TestRpc, _from: NodeID) -> Result<(), Error> {
+			self.0.fetch_add(1, Ordering::SeqCst);
+			Ok(())
+		}
+	}
+
+	fn test_helper() -> (Arc<NetApp>, RpcHelper) {
+		let (_pk, sk) = ed25519::gen_keypair();
+		let netapp = NetApp::new(0, auth::gen_key(), sk, None);
+		let peering = PeeringManager::new(netapp.clone(), vec![], None);
+		let layout = LayoutHelper::new(
+			ReplicationFactor::new(3).unwrap(),
+			ConsistencyMode::Consistent,
+			LayoutHistory::new(ReplicationFactor::new(3).unwrap()),
+			Default::default(),
+		);
+		let helper = RpcHelper::new(
+			netapp.id.into(),
+			peering,
+			Arc::new(RwLock::new(layout)),
+			None,
+		);
+		(netapp, helper)
+	}
+
+	#[tokio::test]
+	async fn test_hedging_is_opt_in() {
+		let (netapp, helper) = test_helper();
+		let our_id: Uuid = netapp.id.into();
+
+		let make_endpoint = |path: &str| {
+			let handler = Arc::new(SlowHandler(AtomicUsize::new(0)));
+			let endpoint = netapp.endpoint::<TestRpc, SlowHandler>(path.into());
+			endpoint.set_handler(handler.clone());
+			(endpoint, handler)
+		};
+		let (endpoint, handler) = make_endpoint("garage_rpc/test/not_hedged");
+		let (hedged_endpoint, hedged_handler) = make_endpoint("garage_rpc/test/hedged");
+
+		// Requests to ourself are handled locally, so the same node can be
+		// used several times to simulate a set of nodes that all answer
+		let to = [our_id; 3];
+		let strategy = RequestStrategy::with_priority(PRIO_NORMAL).with_quorum(1);
+		let (res, hedged_res) = futures::join!(
+			helper.try_call_many(&endpoint, &to, TestRpc, strategy.clone()),
+			helper.try_call_many(
+				&hedged_endpoint,
+				&to,
+				TestRpc,
+				strategy.clone().with_hedging()
+			),
+		);
+		assert_eq!(res.unwrap().len(), 1);
+		assert_eq!(hedged_res.unwrap().len(), 1);
+
+		// Without hedging, no more than `quorum` requests are sent
+		// when there are no errors
+		assert_eq!(handler.0.load(Ordering::SeqCst), 1);
+		assert_eq!(hedged_handler.0.load(Ordering::SeqCst), 2);
+	}
+
+	#[test]
+	fn test_request_order_maintenance_last() {
+		let (netapp, helper) = test_helper();
+		let our_id: Uuid = netapp.id.into();
+		let (node1, node2) = (gen_uuid(), gen_uuid());
+		let layout = helper.0.layout.read().unwrap().current().clone();
+		let order = |nodes: &[Uuid]| helper.request_order(&layout, nodes.iter().copied());
+
+		// we are asked first when we are not in maintenance
+		assert_eq!(order(&[node1, our_id])[0], our_id);
+
+		helper.set_in_maintenance(our_id, true);
+		helper.set_in_maintenance(node1, true);
+		let ret = order(&[node1, our_id, node2]);
+		assert_eq!(ret[0], node2);
+		assert_eq!(
+			ret[1..].iter().collect::<HashSet<_>>(),
+			[our_id, node1].iter().collect()
+		);
+
+		helper.set_in_maintenance(our_id, false);
+		assert_eq!(order(&[node1, our_id, node2]), vec![our_id, node2, node1]);
+	}
+
+	#[tokio::test]
+	async fn test_write_sets_maintenance_failures() {
+		let (netapp, helper) = test_helper();
+		let our_id: Uuid = netapp.id.into();
+		let handler = Arc::new(CountingHandler(AtomicUsize::new(0)));
+		let endpoint = netapp.endpoint::<TestRpc, CountingHandler>("garage_rpc/test/write".into());
+		endpoint.set_handler(handler.clone());
+
+		let node = gen_uuid();
+		helper.set_in_maintenance(node, true);
+		let to_sets = [vec![our_id, node]];
+		let strategy = |quorum| RequestStrategy::with_priority(PRIO_NORMAL).with_quorum(quorum);
+
+		// the node in maintenance is not sent a request, the write succeeds
+		// if the other nodes are enough for a quorum
+		let res = helper
+			.try_write_many_sets(&endpoint, &to_sets, TestRpc, strategy(1))
+			.await;
+		assert_eq!(res.unwrap().len(), 1);
+		assert_eq!(handler.0.load(Ordering::SeqCst), 1);
+
+		// the node in maintenance counts as a failure: no request
+		// is sent when a quorum cannot be reached without it
+		let res = helper
+			.try_write_many_sets(&endpoint, &to_sets, TestRpc, strategy(2))
+			.await;
+		match res {
+			Err(Error::Quorum(2, Some(1), 0, 2, errors)) => {
+				assert_eq!(
+					errors,
+					vec![format!("{:?}: {}", node, Error::NodeInMaintenance(node))]
+				);
+			}
+			_ => panic!("expected a quorum error"),
+		}
+		assert_eq!(handler.0.load(Ordering::SeqCst), 1);
+	}
+}
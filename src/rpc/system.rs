@@ -29,6 +29,8 @@ use garage_util::time::*;
 
 #[cfg(feature = "consul-discovery")]
 use crate::consul::ConsulDiscovery;
+#[cfg(feature = "dns-discovery")]
+use crate::dns::DnsDiscovery;
 #[cfg(feature = "kubernetes-discovery")]
 use crate::kubernetes::*;
 use crate::layout::{
@@ -45,7 +47,9 @@ const STATUS_EXCHANGE_INTERVAL: Duration = Duration::from_secs(10);
 /// Version tag used for version check upon Netapp connection.
 /// Cluster nodes with different version tags are deemed
 /// incompatible and will refuse to connect.
-pub const GARAGE_VERSION_TAG: u64 = 0x6761726167650010; // garage 0x0010 (1.0)
+/// It must be changed when the format of the data exchanged between nodes
+/// changes, e.g. a new version of the cluster layout or of table entries.
+pub const GARAGE_VERSION_TAG: u64 = 0x6761726167650011; // garage 0x0011 (1.1)
 
 /// RPC endpoint used for calls related to membership
 pub const SYSTEM_RPC_PATH: &str = "garage_rpc/system.rs/SystemRpc";
@@ -106,6 +110,8 @@ pub struct System {
 	consul_discovery: Option<ConsulDiscovery>,
 	#[cfg(feature = "kubernetes-discovery")]
 	kubernetes_discovery: Option<KubernetesDiscoveryConfig>,
+	#[cfg(feature = "dns-discovery")]
+	dns_discovery: Option<DnsDiscovery>,
 
 	pub layout_manager: Arc<LayoutManager>,
 
@@ -136,6 +142,12 @@ pub struct NodeStatus {
 	/// Disk usage on partition containing data directory (tuple: `(avail, total)`)
 	#[serde(default)]
 	pub data_disk_avail: Option<(u64, u64)>,
+
+	/// Whether the node is in maintenance, i.e. it is expected to be unavailable
+	/// for some time and should not be sent requests. This is not persisted,
+	/// so that a node leaves maintenance when it restarts.
+	#[serde(default)]
+	pub maintenance: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -159,17 +171,20 @@ pub struct ClusterHealth {
 	pub storage_nodes: usize,
 	/// Number of storage nodes currently connected
 	pub storage_nodes_ok: usize,
+	/// Number of storage nodes that are in maintenance
+	pub storage_nodes_maintenance: usize,
 	/// Number of partitions in the layout
 	pub partitions: usize,
 	/// Number of partitions for which we have a quorum of connected nodes
 	pub partitions_quorum: usize,
 	/// Number of partitions for which all storage nodes are connected
+	/// or in maintenance
 	pub partitions_all_ok: usize,
 }
 
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub enum ClusterHealthStatus {
-	/// All nodes are available
+	/// All nodes are available, except nodes in maintenance
 	Healthy,
 	/// Some storage nodes are unavailable, but quorum is still
 	/// achieved for all partitions
@@ -304,6 +319,16 @@ impl System {
 			warn!("Kubernetes discovery is not enabled in this build.");
 		}
 
+		#[cfg(feature = "dns-discovery")]
+		let dns_discovery = match &config.dns_discovery {
+			Some(cfg) => Some(DnsDiscovery::new(cfg.clone())?),
+			None => None,
+		};
+		#[cfg(not(feature = "dns-discovery"))]
+		if config.dns_discovery.is_some() {
+			warn!("DNS discovery is not enabled in this build.");
+		}
+
 		// ---- almost done ----
 		let sys = Arc::new(System {
 			id: netapp.id.into(),
@@ -321,6 +346,8 @@ impl System {
 			consul_discovery,
 			#[cfg(feature = "kubernetes-discovery")]
 			kubernetes_discovery: config.kubernetes_discovery.clone(),
+			#[cfg(feature = "dns-discovery")]
+			dns_discovery,
 			layout_manager,
 			metrics: ArcSwapOption::new(None),
 
@@ -427,6 +454,27 @@ impl System {
 		}
 	}
 
+	/// Put this node in maintenance or take it out of maintenance, and announce
+	/// it immediately to the other nodes. While a node is in maintenance, the
+	/// other nodes avoid sending it requests, and keep the writes that it misses
+	/// to replay them when it is back. Maintenance is not persisted: the node
+	/// leaves maintenance when it restarts.
+	pub async fn set_maintenance(&self, maintenance: bool) {
+		self.local_status.write().unwrap().maintenance = maintenance;
+		self.rpc_helper().set_in_maintenance(self.id, maintenance);
+
+		let local_status: NodeStatus = self.local_status.read().unwrap().clone();
+		let _ = self
+			.rpc_helper()
+			.broadcast(
+				&self.system_endpoint,
+				SystemRpc::AdvertiseStatus(local_status),
+				RequestStrategy::with_priority(PRIO_HIGH)
+					.with_custom_timeout(STATUS_EXCHANGE_INTERVAL),
+			)
+			.await;
+	}
+
 	pub fn health(&self) -> ClusterHealth {
 		let quorum = self
 			.replication_factor
@@ -443,6 +491,9 @@ impl System {
 			.collect::<HashMap<Uuid, _>>();
 		let connected_nodes = nodes.iter().filter(|(_, n)| n.is_up).count();
 		let node_up = |x: &Uuid| nodes.get(x).map(|n| n.is_up).unwrap_or(false);
+		// Nodes in maintenance are expected to be down, they do not make
+		// the cluster degraded
+		let node_expected = |x: &Uuid| node_up(x) || self.rpc_helper().is_in_maintenance(x);
 
 		// Acquire a rwlock read-lock to the current cluster layout
 		let layout = self.cluster_layout();
@@ -460,10 +511,16 @@ impl System {
 			)
 		}
 		let storage_nodes_ok = storage_nodes.iter().filter(|x| node_up(x)).count();
+		let storage_nodes_maintenance = storage_nodes
+			.iter()
+			.filter(|x| self.rpc_helper().is_in_maintenance(x))
+			.count();
+		let storage_nodes_expected = storage_nodes.iter().filter(|x| node_expected(x)).count();
 
 		// Determine the number of partitions that have:
 		// - a quorum of up nodes for all write sets (i.e. are available)
-		// - for which all nodes in all write sets are up (i.e. are fully healthy)
+		// - for which all nodes in all write sets are up or in maintenance
+		//   (i.e. are fully healthy)
 		let partitions = layout.current().partitions().collect::<Vec<_>>();
 		let mut partitions_quorum = 0;
 		let mut partitions_all_ok = 0;
@@ -475,7 +532,7 @@ impl System {
 			let has_quorum = write_sets
 				.clone()
 				.all(|set| set.filter(|x| node_up(x)).count() >= quorum);
-			let all_ok = write_sets.all(|mut set| set.all(|x| node_up(&x)));
+			let all_ok = write_sets.all(|mut set| set.all(|x| node_expected(&x)));
 			if has_quorum {
 				partitions_quorum += 1;
 			}
@@ -485,14 +542,16 @@ impl System {
 		}
 
 		// Determine overall cluster status
-		let status =
-			if partitions_all_ok == partitions.len() && storage_nodes_ok == storage_nodes.len() {
-				ClusterHealthStatus::Healthy
-			} else if partitions_quorum == partitions.len() {
-				ClusterHealthStatus::Degraded
-			} else {
-				ClusterHealthStatus::Unavailable
-			};
+		let status = if partitions_all_ok == partitions.len()
+			&& partitions_quorum == partitions.len()
+			&& storage_nodes_expected == storage_nodes.len()
+		{
+			ClusterHealthStatus::Healthy
+		} else if partitions_quorum == partitions.len() {
+			ClusterHealthStatus::Degraded
+		} else {
+			ClusterHealthStatus::Unavailable
+		};
 
 		ClusterHealth {
 			status,
@@ -500,6 +559,7 @@ impl System {
 			connected_nodes,
 			storage_nodes: storage_nodes.len(),
 			storage_nodes_ok,
+			storage_nodes_maintenance,
 			partitions: partitions.len(),
 			partitions_quorum,
 			partitions_all_ok,
@@ -588,6 +648,7 @@ impl System {
 
 		self.layout_manager
 			.handle_advertise_status(from, &info.layout_digest);
+		self.rpc_helper().set_in_maintenance(from, info.maintenance);
 
 		drop(local_info);
 
@@ -683,6 +744,19 @@ impl System {
 					}
 				}
 
+				// Fetch peer list from DNS
+				#[cfg(feature = "dns-discovery")]
+				if let Some(d) = &self.dns_discovery {
+					match d.get_dns_nodes().await {
+						Ok(node_list) => {
+							ping_list.extend(node_list);
+						}
+						Err(e) => {
+							warn!("Could not retrieve node list from DNS: {}", e);
+						}
+					}
+				}
+
 				if !not_configured && !no_peers {
 					// If the layout is configured, and we already have some connections
 					// to other nodes in the cluster, we can skip trying to connect to
@@ -790,6 +864,7 @@ impl NodeStatus {
 			layout_digest: layout_manager.layout().digest(),
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			maintenance: false,
 		}
 	}
 
@@ -800,6 +875,7 @@ impl NodeStatus {
 			layout_digest: Default::default(),
 			meta_disk_avail: None,
 			data_disk_avail: None,
+			maintenance: false,
 		}
 	}
 
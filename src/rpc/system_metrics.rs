@@ -22,6 +22,7 @@ pub struct SystemMetrics {
 	pub(crate) _connected_nodes: ValueObserver<u64>,
 	pub(crate) _storage_nodes: ValueObserver<u64>,
 	pub(crate) _storage_nodes_ok: ValueObserver<u64>,
+	pub(crate) _storage_nodes_maintenance: ValueObserver<u64>,
 	pub(crate) _partitions: ValueObserver<u64>,
 	pub(crate) _partitions_quorum: ValueObserver<u64>,
 	pub(crate) _partitions_all_ok: ValueObserver<u64>,
@@ -174,6 +175,16 @@ impl SystemMetrics {
 					.with_description("Number of storage nodes currently connected")
 					.init()
 			},
+			_storage_nodes_maintenance: {
+				let get_health = get_health.clone();
+				meter
+					.u64_value_observer("cluster_storage_nodes_maintenance", move |observer| {
+						let h = get_health();
+						observer.observe(h.storage_nodes_maintenance as u64, &[]);
+					})
+					.with_description("Number of storage nodes that are in maintenance")
+					.init()
+			},
 			_partitions: {
 				let get_health = get_health.clone();
 				meter
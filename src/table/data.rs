@@ -15,6 +15,7 @@ use garage_rpc::system::System;
 
 use crate::crdt::Crdt;
 use crate::gc::GcTodoEntry;
+use crate::index::*;
 use crate::metrics::*;
 use crate::replication::*;
 use crate::schema::*;
@@ -35,16 +36,28 @@ pub struct TableData<F: TableSchema, R: TableReplication> {
 	pub(crate) insert_queue: db::Tree,
 	pub(crate) insert_queue_notify: Arc<Notify>,
 
+	pub(crate) hints: db::Tree,
+	pub(crate) hints_notify: Arc<Notify>,
+
 	pub(crate) gc_todo: db::Tree,
 
+	/// Trees of the secondary indexes, and tree recording which of
+	/// them are ready (not opened if the table has no index)
+	pub(crate) indexes: Vec<(&'static str, db::Tree)>,
+	pub(crate) index_state: Option<db::Tree>,
+
 	pub(crate) metrics: TableMetrics,
 }
 
 impl<F: TableSchema, R: TableReplication> TableData<F, R> {
+	/// Open the tree in which the entries of the table are stored, for
+	/// schemas that read the other entries of their table when one is updated
+	pub fn open_store(db: &db::Db) -> db::Result<db::Tree> {
+		db.open_tree(format!("{}:table", F::TABLE_NAME))
+	}
+
 	pub fn new(system: Arc<System>, instance: F, replication: R, db: &db::Db) -> Arc<Self> {
-		let store = db
-			.open_tree(format!("{}:table", F::TABLE_NAME))
-			.expect("Unable to open DB tree");
+		let store = Self::open_store(db).expect("Unable to open DB tree");
 
 		let merkle_tree = db
 			.open_tree(format!("{}:merkle_tree", F::TABLE_NAME))
@@ -57,10 +70,32 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 			.open_tree(format!("{}:insert_queue", F::TABLE_NAME))
 			.expect("Unable to open insert queue DB tree");
 
+		let hints = db
+			.open_tree(format!("{}:hints", F::TABLE_NAME))
+			.expect("Unable to open hinted handoff DB tree");
+
 		let gc_todo = db
 			.open_tree(format!("{}:gc_todo_v2", F::TABLE_NAME))
 			.expect("Unable to open GC DB tree");
 
+		let indexes = F::INDEXES
+			.iter()
+			.map(|index| {
+				let tree = db
+					.open_tree(format!("{}:index:{}", F::TABLE_NAME, index))
+					.expect("Unable to open index DB tree");
+				(*index, tree)
+			})
+			.collect::<Vec<_>>();
+		let index_state = if indexes.is_empty() {
+			None
+		} else {
+			Some(
+				db.open_tree(format!("{}:index_state", F::TABLE_NAME))
+					.expect("Unable to open index state DB tree"),
+			)
+		};
+
 		let metrics = TableMetrics::new(
 			F::TABLE_NAME,
 			store.clone(),
@@ -79,7 +114,11 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 			merkle_todo_notify: Notify::new(),
 			insert_queue,
 			insert_queue_notify: Arc::new(Notify::new()),
+			hints,
+			hints_notify: Arc::new(Notify::new()),
 			gc_todo,
+			indexes,
+			index_state,
 			metrics,
 		})
 	}
@@ -160,10 +199,62 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		Ok(ret)
 	}
 
+	/// Read the entries of a partition whose keys in a secondary index are
+	/// in a given range, in the order of the index. Returns the entries along
+	/// with their key in the index.
+	pub fn read_index_range(
+		&self,
+		partition_key: &F::P,
+		index: &str,
+		range: &IndexRange,
+		filter: &Option<F::Filter>,
+		limit: usize,
+	) -> Result<Vec<(ByteBuf, Arc<ByteBuf>)>, Error> {
+		let tree = self.index_tree(index)?;
+		if !self.index_ready(index)? {
+			return Err(Error::Message(format!(
+				"Index {} of table {} is being built on this node",
+				index,
+				F::TABLE_NAME
+			)));
+		}
+
+		let partition_hash = partition_key.hash();
+		let mut ret = vec![];
+		for item in tree.range::<Vec<u8>, _>(range.tree_bounds(&partition_hash))? {
+			let (k, _) = item?;
+			if &k[..32] != partition_hash.as_slice() {
+				break;
+			}
+			let (index_key, sort_key) = match parse_index_tree_key(&k) {
+				Some(x) => x,
+				None => continue,
+			};
+			let value = match self.store.get([&k[..32], sort_key].concat())? {
+				Some(v) => v,
+				None => continue,
+			};
+			let entry = self.decode_entry(&value)?;
+			if !self.entry_has_index_key(&entry, index, &index_key) {
+				continue;
+			}
+			if let Some(f) = filter {
+				if !F::matches_filter(&entry, f) {
+					continue;
+				}
+			}
+			ret.push((ByteBuf::from(index_key), Arc::new(ByteBuf::from(value))));
+			if ret.len() >= limit {
+				break;
+			}
+		}
+		Ok(ret)
+	}
+
 	// Mutation functions
 	// When changing this code, take care of propagating modifications correctly:
 	// - When an entry is modified or deleted, call the updated() function
-	//   on the table instance
+	//   on the table instance, and update the secondary indexes
 	// - When an entry is modified or deleted, add it to the merkle updater's todo list.
 	//   This has to be done atomically with the modification for the merkle updater
 	//   to maintain consistency. The merkle updater must then be notified with todo_notify.
@@ -232,6 +323,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 
 				self.instance
 					.updated(tx, old_entry.as_ref(), Some(&new_entry))?;
+				self.update_indexes(tx, &tree_key, old_entry.as_ref(), Some(&new_entry))?;
 
 				Ok(Some((new_entry, new_bytes_hash)))
 			} else {
@@ -277,6 +369,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					self.instance.updated(tx, Some(&old_entry), None)?;
+					self.update_indexes(tx, k, Some(&old_entry), None)?;
 					Ok(true)
 				}
 				_ => Ok(false),
@@ -305,6 +398,7 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 					tx.insert(&self.merkle_todo, k, vec![])?;
 
 					self.instance.updated(tx, Some(&old_entry), None)?;
+					self.update_indexes(tx, k, Some(&old_entry), None)?;
 					Ok(true)
 				}
 				_ => Ok(false),
@@ -317,6 +411,84 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		Ok(removed)
 	}
 
+	// ---- Secondary indexes ----
+
+	pub(crate) fn index_tree(&self, index: &str) -> Result<&db::Tree, Error> {
+		self.indexes
+			.iter()
+			.find(|(name, _)| *name == index)
+			.map(|(_, tree)| tree)
+			.ok_or_else(|| {
+				Error::Message(format!("Table {} has no index {}", F::TABLE_NAME, index))
+			})
+	}
+
+	/// Whether a secondary index has been built on this node
+	pub fn index_ready(&self, index: &str) -> Result<bool, Error> {
+		match &self.index_state {
+			Some(tree) => Ok(tree.get(index)?.is_some()),
+			None => Ok(false),
+		}
+	}
+
+	pub(crate) fn set_index_ready(&self, index: &str) -> Result<(), Error> {
+		if let Some(tree) = &self.index_state {
+			tree.insert(index, b"ready")?;
+		}
+		Ok(())
+	}
+
+	pub(crate) fn entry_index_keys(&self, entry: &F::E) -> Vec<(&'static str, Vec<u8>)> {
+		if entry.is_tombstone() {
+			vec![]
+		} else {
+			F::index_keys(entry)
+		}
+	}
+
+	pub(crate) fn entry_has_index_key(&self, entry: &F::E, index: &str, index_key: &[u8]) -> bool {
+		self.entry_index_keys(entry)
+			.iter()
+			.any(|(i, k)| *i == index && k == index_key)
+	}
+
+	/// Update the secondary indexes after an entry has changed,
+	/// in the transaction where the entry is changed
+	fn update_indexes(
+		&self,
+		tx: &mut db::Transaction,
+		tree_key: &[u8],
+		old: Option<&F::E>,
+		new: Option<&F::E>,
+	) -> db::TxOpResult<()> {
+		if self.indexes.is_empty() {
+			return Ok(());
+		}
+		let old_keys = old.map(|e| self.entry_index_keys(e)).unwrap_or_default();
+		let new_keys = new.map(|e| self.entry_index_keys(e)).unwrap_or_default();
+		let (partition_hash, sort_key) = tree_key.split_at(32);
+
+		for (index, index_key) in old_keys.iter() {
+			if !new_keys.contains(&(*index, index_key.clone())) {
+				if let Ok(tree) = self.index_tree(index) {
+					tx.remove(tree, index_tree_key(partition_hash, index_key, sort_key))?;
+				}
+			}
+		}
+		for (index, index_key) in new_keys.iter() {
+			if !old_keys.contains(&(*index, index_key.clone())) {
+				if let Ok(tree) = self.index_tree(index) {
+					tx.insert(
+						tree,
+						index_tree_key(partition_hash, index_key, sort_key),
+						[],
+					)?;
+				}
+			}
+		}
+		Ok(())
+	}
+
 	// ---- Insert queue functions ----
 
 	pub(crate) fn queue_insert(
@@ -345,6 +517,44 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 		Ok(())
 	}
 
+	/// Keep entries that could not be sent to nodes because they are in
+	/// maintenance, so that they can be replayed when the nodes are back.
+	/// Hints are keyed by node ID followed by the tree key of the entry,
+	/// and are all stored in a single transaction.
+	pub(crate) fn queue_hints<E: Borrow<F::E>>(&self, hints: &[(Uuid, E)]) -> Result<(), Error> {
+		if hints.is_empty() {
+			return Ok(());
+		}
+
+		self.hints.db().transaction(|tx| {
+			for (node, ins) in hints.iter() {
+				let ins = ins.borrow();
+				let hint_key = [
+					node.as_slice(),
+					&self.tree_key(ins.partition_key(), ins.sort_key()),
+				]
+				.concat();
+
+				let new_entry = match tx.get(&self.hints, &hint_key)? {
+					Some(old_v) => {
+						let mut entry = self.decode_entry(&old_v).map_err(db::TxError::Abort)?;
+						entry.merge(ins);
+						entry.encode()
+					}
+					None => ins.encode(),
+				};
+				let new_entry = new_entry
+					.map_err(Error::RmpEncode)
+					.map_err(db::TxError::Abort)?;
+				tx.insert(&self.hints, &hint_key, new_entry)?;
+			}
+			Ok(())
+		})?;
+
+		self.hints_notify.notify_one();
+		Ok(())
+	}
+
 	// ---- Utility functions ----
 
 	pub fn tree_key(&self, p: &F::P, s: &F::S) -> Vec<u8> {
@@ -370,4 +580,153 @@ impl<F: TableSchema, R: TableReplication> TableData<F, R> {
 	pub fn gc_todo_len(&self) -> Result<usize, Error> {
 		Ok(self.gc_todo.len()?)
 	}
+
+	/// Timestamp of the oldest tombstone waiting in the GC todo queue
+	pub fn gc_todo_oldest(&self) -> Result<Option<u64>, Error> {
+		Ok(self
+			.gc_todo
+			.first()?
+			.map(|(k, _)| GcTodoEntry::parse_timestamp(&k)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	fn update(table: &TestTable, entry: &TestEntry) {
+		table.data.update_entry(&entry.encode().unwrap()).unwrap();
+	}
+
+	fn index_len(table: &TestTable) -> usize {
+		table.data.index_tree("value").unwrap().len().unwrap()
+	}
+
+	/// Sort keys of the entries of partition `p` in a range of the `value` index
+	fn index_range(table: &TestTable, range: &IndexRange, limit: usize) -> Vec<(String, String)> {
+		table
+			.data
+			.read_index_range(&"p".into(), "value", range, &None, limit)
+			.unwrap()
+			.into_iter()
+			.map(|(index_key, v)| {
+				let entry = table.data.decode_entry(&v).unwrap();
+				(String::from_utf8(index_key.to_vec()).unwrap(), entry.sort)
+			})
+			.collect()
+	}
+
+	#[tokio::test]
+	async fn test_index_update_and_delete() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+		table.data.set_index_ready("value").unwrap();
+
+		update(&table, &TestEntry::new("p", "a", 1, "x"));
+		update(&table, &TestEntry::new("p", "b", 1, "x"));
+		assert_eq!(index_len(&table), 2);
+		assert_eq!(
+			index_range(&table, &IndexRange::prefix(b"x"), 10),
+			vec![("x".into(), "a".into()), ("x".into(), "b".into())]
+		);
+
+		// the old index key is removed when the value changes
+		update(&table, &TestEntry::new("p", "a", 2, "y"));
+		assert_eq!(index_len(&table), 2);
+		assert_eq!(
+			index_range(&table, &IndexRange::prefix(b"x"), 10),
+			vec![("x".into(), "b".into())]
+		);
+		assert_eq!(
+			index_range(&table, &IndexRange::prefix(b"y"), 10),
+			vec![("y".into(), "a".into())]
+		);
+
+		// an entry that is not the one stored is not deleted
+		let tree_key = table.data.tree_key(&"p".into(), &"a".into());
+		let old = TestEntry::new("p", "a", 1, "x").encode().unwrap();
+		assert!(!table.data.delete_if_equal(&tree_key, &old).unwrap());
+		assert_eq!(index_len(&table), 2);
+
+		let cur = table.data.store.get(&tree_key).unwrap().unwrap();
+		assert!(table.data.delete_if_equal(&tree_key, &cur).unwrap());
+		assert_eq!(index_len(&table), 1);
+		assert!(index_range(&table, &IndexRange::prefix(b"y"), 10).is_empty());
+	}
+
+	#[tokio::test]
+	async fn test_tombstones_not_indexed() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+
+		update(&table, &TestEntry::tombstone("p", "a", 1));
+		assert_eq!(index_len(&table), 0);
+
+		update(&table, &TestEntry::new("p", "b", 1, "x"));
+		assert_eq!(index_len(&table), 1);
+		update(&table, &TestEntry::tombstone("p", "b", 2));
+		assert_eq!(index_len(&table), 0);
+		assert!(table
+			.data
+			.read_entry(&"p".into(), &"b".into())
+			.unwrap()
+			.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_index_range_pagination() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+		table.data.set_index_ready("value").unwrap();
+
+		// several entries with the same value are ordered by sort key
+		for (sort, value) in [
+			("a", "v2"),
+			("b", "v1"),
+			("c", "v2"),
+			("d", "v3"),
+			("e", "v2"),
+		] {
+			update(&table, &TestEntry::new("p", sort, 1, value));
+		}
+		update(&table, &TestEntry::new("q", "a", 1, "v2"));
+		let expected = [
+			("v1", "b"),
+			("v2", "a"),
+			("v2", "c"),
+			("v2", "e"),
+			("v3", "d"),
+		]
+		.iter()
+		.map(|(v, s)| (v.to_string(), s.to_string()))
+		.collect::<Vec<_>>();
+
+		for limit in 1..=5 {
+			let mut range = IndexRange::default();
+			let mut all = vec![];
+			loop {
+				let page = index_range(&table, &range, limit);
+				assert!(page.len() <= limit);
+				let last = match page.last() {
+					Some(x) => x.clone(),
+					None => break,
+				};
+				all.extend(page);
+				range.after = Some(IndexPos {
+					index_key: last.0.into_bytes(),
+					sort_key: last.1.into_bytes(),
+				});
+			}
+			assert_eq!(all, expected);
+		}
+
+		// the end of the range is respected when continuing a query
+		let mut range = IndexRange::prefix(b"v2");
+		range.after = Some(IndexPos {
+			index_key: b"v2".to_vec(),
+			sort_key: b"a".to_vec(),
+		});
+		assert_eq!(index_range(&table, &range, 10), expected[2..4].to_vec());
+	}
 }
@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 use std::convert::TryInto;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
@@ -27,14 +28,17 @@ use crate::schema::*;
 
 const TABLE_GC_BATCH_SIZE: usize = 1024;
 
-// GC delay for table entries: 1 day (24 hours)
+// Default GC delay for table entries: 1 day (24 hours)
 // (the delay before the entry is added in the GC todo list
-// and the moment the garbage collection actually happens)
-const TABLE_GC_DELAY: Duration = Duration::from_secs(24 * 3600);
+// and the moment the garbage collection actually happens),
+// can be changed for each table with `tombstone_retention`
+// in the configuration file
+const DEFAULT_TABLE_GC_DELAY: Duration = Duration::from_secs(24 * 3600);
 
 pub(crate) struct TableGc<F: TableSchema, R: TableReplication> {
 	system: Arc<System>,
 	data: Arc<TableData<F, R>>,
+	delay_msec: AtomicU64,
 
 	endpoint: Arc<Endpoint<GcRpc, Self>>,
 }
@@ -59,6 +63,7 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 		let gc = Arc::new(Self {
 			system,
 			data,
+			delay_msec: AtomicU64::new(DEFAULT_TABLE_GC_DELAY.as_millis() as u64),
 			endpoint,
 		});
 		gc.endpoint.set_handler(gc.clone());
@@ -70,8 +75,18 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 		bg.spawn_worker(GcWorker::new(self.clone()));
 	}
 
+	pub(crate) fn delay(&self) -> Duration {
+		Duration::from_millis(self.delay_msec.load(Ordering::Relaxed))
+	}
+
+	pub(crate) fn set_delay(&self, delay: Duration) {
+		self.delay_msec
+			.store(delay.as_millis() as u64, Ordering::Relaxed);
+	}
+
 	async fn gc_loop_iter(&self) -> Result<Option<Duration>, Error> {
 		let now = now_msec();
+		let delay = self.delay();
 
 		// List entries in the GC todo list
 		// These entries are put there when a tombstone is inserted in the table
@@ -81,12 +96,12 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 			let (k, vhash) = entry_kv?;
 			let todo_entry = GcTodoEntry::parse(&k, &vhash);
 
-			if todo_entry.deletion_time() > now {
+			if todo_entry.deletion_time(delay) > now {
 				if candidates.is_empty() {
 					// If the earliest entry in the todo list shouldn't yet be processed,
 					// return a duration to wait in the loop
 					return Ok(Some(Duration::from_millis(
-						todo_entry.deletion_time() - now,
+						todo_entry.deletion_time(delay) - now,
 					)));
 				} else {
 					// Otherwise we have some entries to process, do a normal iteration.
@@ -185,6 +200,7 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 		if errs.is_empty() {
 			Ok(None)
 		} else {
+			self.data.metrics.gc_errors.add(errs.len() as u64);
 			Err(Error::Message(
 				errs.into_iter()
 					.map(|x| format!("{}", x))
@@ -268,6 +284,7 @@ impl<F: TableSchema, R: TableReplication> TableGc<F, R> {
 			item.remove_if_equal(&self.data.gc_todo)
 				.err_context("GC: remove from todo list after successful GC")?;
 		}
+		self.data.metrics.gc_deleted_items.add(n_items as u64);
 
 		Ok(())
 	}
@@ -368,7 +385,7 @@ impl GcTodoEntry {
 	/// Parses a GcTodoEntry from a (k, v) pair stored in the gc_todo tree
 	pub(crate) fn parse(db_k: &[u8], db_v: &[u8]) -> Self {
 		Self {
-			tombstone_timestamp: u64::from_be_bytes(db_k[0..8].try_into().unwrap()),
+			tombstone_timestamp: Self::parse_timestamp(db_k),
 			key: db_k[8..].to_vec(),
 			value_hash: Hash::try_from(db_v).unwrap(),
 			value: None,
@@ -405,7 +422,13 @@ impl GcTodoEntry {
 		.concat()
 	}
 
-	fn deletion_time(&self) -> u64 {
-		self.tombstone_timestamp + TABLE_GC_DELAY.as_millis() as u64
+	/// Returns the timestamp of the tombstone of a GC todo entry,
+	/// from its key in the gc_todo tree
+	pub(crate) fn parse_timestamp(db_k: &[u8]) -> u64 {
+		u64::from_be_bytes(db_k[0..8].try_into().unwrap())
+	}
+
+	fn deletion_time(&self, delay: Duration) -> u64 {
+		self.tombstone_timestamp + delay.as_millis() as u64
 	}
 }
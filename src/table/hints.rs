@@ -0,0 +1,268 @@
+//! Hinted handoff: entries that could not be written to a node because it was
+//! in maintenance are kept locally, and sent to that node once it is back.
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_bytes::ByteBuf;
+use tokio::select;
+use tokio::sync::watch;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::{Error, OkOrMessage};
+
+use garage_rpc::*;
+
+use crate::replication::*;
+use crate::schema::*;
+use crate::table::*;
+
+const BATCH_SIZE: usize = 256;
+
+// How often to check whether nodes that have pending hints are back
+const HINTS_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+pub(crate) struct HintedHandoffWorker<F, R>(pub(crate) Arc<Table<F, R>>)
+where
+	F: TableSchema,
+	R: TableReplication;
+
+impl<F: TableSchema, R: TableReplication> HintedHandoffWorker<F, R> {
+	/// Find the first node for which there are pending hints, starting at `from`
+	fn next_hinted_node(&self, from: &[u8]) -> Result<Option<Uuid>, Error> {
+		match self.0.data.hints.range(from..)?.next() {
+			None => Ok(None),
+			Some(kv) => {
+				let (k, _) = kv?;
+				let node = Uuid::try_from(&k[..32]).ok_or_message("Invalid hint key")?;
+				Ok(Some(node))
+			}
+		}
+	}
+
+	/// Send a batch of the hints for a node to that node, or drop them if
+	/// `send` is false, and remove them from the queue.
+	async fn handoff_batch(&self, node: Uuid, send: bool) -> Result<(), Error> {
+		let mut kv_pairs = vec![];
+		for item in self.0.data.hints.range(node.as_slice()..)? {
+			let (k, v) = item?;
+			if !k.starts_with(node.as_slice()) || kv_pairs.len() >= BATCH_SIZE {
+				break;
+			}
+			kv_pairs.push((k, v));
+		}
+
+		if send {
+			let values = kv_pairs
+				.iter()
+				.map(|(_, v)| Arc::new(ByteBuf::from(v.clone())))
+				.collect::<Vec<_>>();
+			let n = values.len();
+			self.0
+				.system
+				.rpc_helper()
+				.call(
+					&self.0.endpoint,
+					node,
+					TableRpc::<F>::Update(values),
+					RequestStrategy::with_priority(PRIO_BACKGROUND),
+				)
+				.await?;
+			debug!(
+				"({}) Replayed {} hinted entries to node {:?}",
+				F::TABLE_NAME,
+				n,
+				node
+			);
+		} else {
+			info!(
+				"({}) Dropping {} hinted entries for node {:?} which is no longer in the cluster layout",
+				F::TABLE_NAME,
+				kv_pairs.len(),
+				node
+			);
+		}
+
+		// Remove hints, unless they have been updated in the meantime
+		self.0.data.hints.db().transaction(|tx| {
+			for (k, v) in kv_pairs.iter() {
+				if let Some(v2) = tx.get(&self.0.data.hints, k)? {
+					if &v2 == v {
+						tx.remove(&self.0.data.hints, k)?;
+					}
+				}
+			}
+			Ok(())
+		})?;
+
+		Ok(())
+	}
+}
+
+#[async_trait]
+impl<F: TableSchema, R: TableReplication> Worker for HintedHandoffWorker<F, R> {
+	fn name(&self) -> String {
+		format!("{} hinted handoff", F::TABLE_NAME)
+	}
+
+	fn status(&self) -> WorkerStatus {
+		WorkerStatus {
+			queue_length: Some(self.0.data.hints.len().unwrap_or(0) as u64),
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		let up_nodes = self
+			.0
+			.system
+			.get_known_nodes()
+			.into_iter()
+			.filter(|n| n.is_up)
+			.map(|n| n.id)
+			.collect::<Vec<_>>();
+
+		// Hints are sorted by node: go through the nodes that have hints, and
+		// hand them over to the first node that is back
+		let mut cursor = vec![];
+		while let Some(node) = self.next_hinted_node(&cursor)? {
+			let in_layout = self.0.system.cluster_layout().all_nodes().contains(&node);
+			if !in_layout {
+				self.handoff_batch(node, false).await?;
+				return Ok(WorkerState::Busy);
+			}
+
+			let rpc_helper = self.0.system.rpc_helper();
+			if up_nodes.contains(&node) && !rpc_helper.is_in_maintenance(&node) {
+				self.handoff_batch(node, true).await?;
+				return Ok(WorkerState::Busy);
+			}
+
+			// Skip all hints of this node
+			cursor = match next_node_prefix(&node) {
+				Some(x) => x,
+				None => break,
+			};
+		}
+
+		Ok(WorkerState::Idle)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		select! {
+			_ = tokio::time::sleep(HINTS_RETRY_INTERVAL) => (),
+			_ = self.0.data.hints_notify.notified() => (),
+		}
+		WorkerState::Busy
+	}
+}
+
+/// Smallest key that is greater than all keys prefixed by the ID of `node`
+fn next_node_prefix(node: &Uuid) -> Option<Vec<u8>> {
+	let mut key = node.as_slice().to_vec();
+	for i in (0..key.len()).rev() {
+		if key[i] == 0xff {
+			key[i] = 0;
+		} else {
+			key[i] += 1;
+			return Some(key);
+		}
+	}
+	None
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	fn hint_count(table: &TestTable) -> usize {
+		table.data.hints.len().unwrap()
+	}
+
+	#[tokio::test]
+	async fn test_hints_replayed_when_node_is_back() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+		let (_tx, mut must_exit) = watch::channel(false);
+		let mut worker = HintedHandoffWorker(table.clone());
+
+		// the only storage node is in maintenance: the write misses its
+		// quorum, but the entry is kept for the node
+		system.set_maintenance(true).await;
+		let entry = TestEntry::new("p", "s", 1, "v1");
+		assert!(table.insert(&entry).await.is_err());
+		table
+			.insert_many([
+				TestEntry::new("p", "s", 2, "v2"),
+				TestEntry::new("p", "t", 1, "v"),
+			])
+			.await
+			.unwrap_err();
+		assert_eq!(hint_count(&table), 2);
+		assert!(table
+			.data
+			.read_entry(&"p".into(), &"s".into())
+			.unwrap()
+			.is_none());
+
+		// hints are kept while the node is in maintenance
+		assert!(matches!(
+			worker.work(&mut must_exit).await,
+			Ok(WorkerState::Idle)
+		));
+		assert_eq!(hint_count(&table), 2);
+
+		// once the node is back, hints are sent to it and removed
+		system.set_maintenance(false).await;
+		assert!(matches!(
+			worker.work(&mut must_exit).await,
+			Ok(WorkerState::Busy)
+		));
+		assert!(matches!(
+			worker.work(&mut must_exit).await,
+			Ok(WorkerState::Idle)
+		));
+		assert_eq!(hint_count(&table), 0);
+
+		let stored = table.data.read_entry(&"p".into(), &"s".into()).unwrap();
+		let stored = table.data.decode_entry(&stored.unwrap()).unwrap();
+		assert_eq!(stored, TestEntry::new("p", "s", 2, "v2"));
+		assert!(table
+			.data
+			.read_entry(&"p".into(), &"t".into())
+			.unwrap()
+			.is_some());
+	}
+
+	#[tokio::test]
+	async fn test_hints_dropped_when_node_leaves_layout() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+		let (_tx, mut must_exit) = watch::channel(false);
+		let mut worker = HintedHandoffWorker(table.clone());
+
+		let gone_node = gen_uuid();
+		table
+			.data
+			.queue_hints(&[(gone_node, TestEntry::new("p", "s", 1, "v"))])
+			.unwrap();
+		assert_eq!(hint_count(&table), 1);
+
+		assert!(matches!(
+			worker.work(&mut must_exit).await,
+			Ok(WorkerState::Busy)
+		));
+		assert!(matches!(
+			worker.work(&mut must_exit).await,
+			Ok(WorkerState::Idle)
+		));
+		assert_eq!(hint_count(&table), 0);
+		assert!(table
+			.data
+			.read_entry(&"p".into(), &"s".into())
+			.unwrap()
+			.is_none());
+	}
+}
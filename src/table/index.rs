@@ -0,0 +1,368 @@
+//! Secondary indexes of tables.
+//!
+//! A table schema can declare indexes in `TableSchema::INDEXES`, and the keys
+//! of each entry in these indexes with `TableSchema::index_keys`. Each node
+//! maintains the index entries of the table entries it stores, in the same
+//! transaction as the entries themselves: index entries are thus replicated
+//! along with the partition of the table entry they are derived from, and an
+//! index can only be queried within a partition.
+//!
+//! Format of the key of an index entry in the index tree:
+//! - 32 bytes: hash of the partition key of the table entry
+//! - n bytes: key in the index, where each 0x00 byte is escaped as 0x00 0xFF,
+//!   followed by the terminator 0x00 0x00 (this keeps the ordering of keys)
+//! - m bytes: sort key of the table entry
+//!
+//! The value of an index entry is empty.
+use core::ops::Bound;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::error::Error;
+use garage_util::migrate::Migrate;
+
+use crate::data::*;
+use crate::replication::*;
+use crate::schema::*;
+
+/// Number of table entries read in each step of the index rebuild worker
+const INDEX_REBUILD_BATCH_SIZE: usize = 1000;
+
+/// A range of keys in a secondary index, within a partition of the table
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexRange {
+	/// First key of the range (inclusive)
+	pub start: Vec<u8>,
+	/// End of the range (exclusive), or `None` for the end of the index
+	pub end: Option<Vec<u8>>,
+	/// Only read entries that come after this position, to continue
+	/// reading from the position returned by a previous query
+	pub after: Option<IndexPos>,
+}
+
+/// Position of a table entry in a secondary index
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexPos {
+	/// Key of the entry in the index
+	pub index_key: Vec<u8>,
+	/// Sort key of the table entry
+	pub sort_key: Vec<u8>,
+}
+
+impl IndexRange {
+	/// The range of all keys of the index that start with `prefix`
+	pub fn prefix(prefix: &[u8]) -> Self {
+		let mut end = prefix.to_vec();
+		while let Some(last) = end.pop() {
+			if last < 0xFF {
+				end.push(last + 1);
+				break;
+			}
+		}
+		Self {
+			start: prefix.to_vec(),
+			end: if end.is_empty() { None } else { Some(end) },
+			after: None,
+		}
+	}
+
+	/// Whether a key of the index is in this range
+	pub fn contains(&self, index_key: &[u8]) -> bool {
+		index_key >= &self.start[..]
+			&& self
+				.end
+				.as_ref()
+				.map(|e| index_key < &e[..])
+				.unwrap_or(true)
+	}
+
+	/// Bounds of the range in the index tree, for a partition
+	pub(crate) fn tree_bounds(&self, partition_hash: &Hash) -> (Bound<Vec<u8>>, Bound<Vec<u8>>) {
+		let start = [partition_hash.as_slice(), &escape(&self.start)].concat();
+		let start = match &self.after {
+			Some(pos) => {
+				let after =
+					index_tree_key(partition_hash.as_slice(), &pos.index_key, &pos.sort_key);
+				if after >= start {
+					Bound::Excluded(after)
+				} else {
+					Bound::Included(start)
+				}
+			}
+			None => Bound::Included(start),
+		};
+		let end = match &self.end {
+			Some(end) => Bound::Excluded([partition_hash.as_slice(), &escape(end)].concat()),
+			None => Bound::Unbounded,
+		};
+		(start, end)
+	}
+}
+
+/// Key of an index entry in the index tree
+pub(crate) fn index_tree_key(partition_hash: &[u8], index_key: &[u8], sort_key: &[u8]) -> Vec<u8> {
+	let mut ret = Vec::with_capacity(partition_hash.len() + index_key.len() + sort_key.len() + 4);
+	ret.extend_from_slice(partition_hash);
+	ret.extend(escape(index_key));
+	ret.extend_from_slice(&[0x00, 0x00]);
+	ret.extend_from_slice(sort_key);
+	ret
+}
+
+/// Returns the key in the index and the sort key of the table entry,
+/// from the key of an index entry in the index tree
+pub(crate) fn parse_index_tree_key(tree_key: &[u8]) -> Option<(Vec<u8>, &[u8])> {
+	let mut index_key = vec![];
+	let mut i = 32;
+	while i + 1 < tree_key.len() {
+		match (tree_key[i], tree_key[i + 1]) {
+			(0x00, 0x00) => return Some((index_key, &tree_key[i + 2..])),
+			(0x00, 0xFF) => {
+				index_key.push(0x00);
+				i += 2;
+			}
+			(0x00, _) => return None,
+			(b, _) => {
+				index_key.push(b);
+				i += 1;
+			}
+		}
+	}
+	None
+}
+
+fn escape(key: &[u8]) -> Vec<u8> {
+	let mut ret = Vec::with_capacity(key.len() + 2);
+	for b in key.iter() {
+		ret.push(*b);
+		if *b == 0x00 {
+			ret.push(0xFF);
+		}
+	}
+	ret
+}
+
+// ---- rebuild worker ----
+
+/// Worker that builds the secondary indexes that are not ready on this node,
+/// e.g. because they were just added to the schema of the table
+pub(crate) struct IndexRebuildWorker<F: TableSchema, R: TableReplication> {
+	data: Arc<TableData<F, R>>,
+	indexes: Vec<&'static str>,
+	cleared: bool,
+	pos: Vec<u8>,
+	counter: u64,
+	done: bool,
+}
+
+impl<F: TableSchema, R: TableReplication> IndexRebuildWorker<F, R> {
+	/// Returns a worker if some indexes of the table have to be built
+	pub(crate) fn new(data: Arc<TableData<F, R>>) -> Result<Option<Self>, Error> {
+		let mut indexes = vec![];
+		for index in F::INDEXES.iter() {
+			if !data.index_ready(index)? {
+				indexes.push(*index);
+			}
+		}
+		if indexes.is_empty() {
+			return Ok(None);
+		}
+		Ok(Some(Self {
+			data,
+			indexes,
+			cleared: false,
+			pos: vec![],
+			counter: 0,
+			done: false,
+		}))
+	}
+
+	fn rebuild_batch(&mut self) -> Result<bool, Error> {
+		if !self.cleared {
+			for index in self.indexes.iter() {
+				self.data.index_tree(index)?.clear()?;
+			}
+			self.cleared = true;
+		}
+
+		let keys = self
+			.data
+			.store
+			.range::<&[u8], _>((Bound::Excluded(&self.pos[..]), Bound::Unbounded))?
+			.take(INDEX_REBUILD_BATCH_SIZE)
+			.map(|item| item.map(|(k, _)| k))
+			.collect::<Result<Vec<_>, _>>()?;
+
+		let data = &self.data;
+		let indexes = &self.indexes;
+		data.store.db().transaction(|tx| {
+			for k in keys.iter() {
+				// The entry is read again in the transaction, so that the index
+				// entries are consistent with updates made during the rebuild
+				let entry = match tx.get(&data.store, k)? {
+					Some(v) => match F::E::decode(&v) {
+						Some(e) => e,
+						None => continue,
+					},
+					None => continue,
+				};
+				for (index, index_key) in data.entry_index_keys(&entry) {
+					if indexes.contains(&index) {
+						let tree = data.index_tree(index).map_err(db::TxError::Abort)?;
+						tx.insert(tree, index_tree_key(&k[..32], &index_key, &k[32..]), [])?;
+					}
+				}
+			}
+			Ok::<_, db::TxError<Error>>(())
+		})?;
+		self.counter += keys.len() as u64;
+
+		match keys.last() {
+			Some(k) if keys.len() == INDEX_REBUILD_BATCH_SIZE => {
+				self.pos = k.clone();
+				Ok(true)
+			}
+			_ => {
+				for index in self.indexes.iter() {
+					self.data.set_index_ready(index)?;
+				}
+				Ok(false)
+			}
+		}
+	}
+}
+
+#[async_trait]
+impl<F: TableSchema, R: TableReplication> Worker for IndexRebuildWorker<F, R> {
+	fn name(&self) -> String {
+		format!("{} index rebuild", F::TABLE_NAME)
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let progress = if self.done {
+			format!("{} items, done", self.counter)
+		} else {
+			let done = match self.pos.get(0..2) {
+				Some(p) => u16::from_be_bytes([p[0], p[1]]) as f32 / 65536.,
+				None => 0.,
+			};
+			format!("{} items, {:.2}%", self.counter, done * 100.)
+		};
+		WorkerStatus {
+			progress: Some(progress),
+			freeform: vec![format!("Indexes: {}", self.indexes.join(", "))],
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		if self.done {
+			return Ok(WorkerState::Done);
+		}
+		if !self.rebuild_batch()? {
+			info!(
+				"({}) Indexes {} have been built from {} items",
+				F::TABLE_NAME,
+				self.indexes.join(", "),
+				self.counter
+			);
+			self.done = true;
+			return Ok(WorkerState::Done);
+		}
+		Ok(WorkerState::Busy)
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn tree_key(index_key: &[u8], sort_key: &[u8]) -> Vec<u8> {
+		index_tree_key(&[0x42; 32], index_key, sort_key)
+	}
+
+	#[test]
+	fn test_index_tree_key_order() {
+		let keys: Vec<&[u8]> = vec![
+			b"",
+			b"\x00",
+			b"\x00\x00",
+			b"\x00\x01",
+			b"a",
+			b"a\x00",
+			b"a\x00b",
+			b"ab",
+			b"b",
+			b"\xff",
+			b"\xff\xff",
+		];
+		for (i, k1) in keys.iter().enumerate() {
+			for k2 in keys[i + 1..].iter() {
+				// the order of index keys is kept whatever the sort keys
+				assert!(tree_key(k1, b"zzz") < tree_key(k2, b""));
+			}
+		}
+		assert!(tree_key(b"a", b"x") < tree_key(b"a", b"y"));
+	}
+
+	#[test]
+	fn test_parse_index_tree_key() {
+		for (index_key, sort_key) in [
+			(&b""[..], &b""[..]),
+			(b"\x00", b"\x00\x00"),
+			(b"a\x00\xffb", b"sort"),
+			(b"\xff\x00", b""),
+		] {
+			let k = tree_key(index_key, sort_key);
+			let (ik, sk) = parse_index_tree_key(&k).unwrap();
+			assert_eq!(ik, index_key);
+			assert_eq!(sk, sort_key);
+		}
+		assert!(parse_index_tree_key(&[0x42; 33]).is_none());
+	}
+
+	#[test]
+	fn test_index_range() {
+		let range = IndexRange::prefix(b"ab");
+		assert_eq!(range.end.as_deref(), Some(&b"ac"[..]));
+		assert!(range.contains(b"ab"));
+		assert!(range.contains(b"ab\xff"));
+		assert!(!range.contains(b"ac"));
+		assert!(!range.contains(b"a"));
+
+		let range = IndexRange::prefix(b"a\xff\xff");
+		assert_eq!(range.end.as_deref(), Some(&b"b"[..]));
+		assert_eq!(IndexRange::prefix(b"\xff").end, None);
+
+		let hash = Hash::from([0x42; 32]);
+		let range = IndexRange::prefix(b"ab");
+		let (start, end) = range.tree_bounds(&hash);
+		let in_range = |k: &Vec<u8>| {
+			(match &start {
+				Bound::Included(s) => k >= s,
+				Bound::Excluded(s) => k > s,
+				Bound::Unbounded => true,
+			}) && (match &end {
+				Bound::Excluded(e) => k < e,
+				_ => true,
+			})
+		};
+		assert!(in_range(&tree_key(b"ab", b"")));
+		assert!(in_range(&tree_key(b"ab\x00", b"x")));
+		assert!(in_range(&tree_key(b"abzz", b"x")));
+		assert!(!in_range(&tree_key(b"a", b"zzz")));
+		assert!(!in_range(&tree_key(b"ac", b"")));
+	}
+}
@@ -8,15 +8,21 @@ pub mod schema;
 pub mod util;
 
 pub mod data;
+pub mod index;
 pub mod replication;
+pub mod scrub;
 pub mod table;
 
 mod gc;
+mod hints;
 mod merkle;
 mod metrics;
 mod queue;
 mod sync;
 
+#[cfg(test)]
+mod test_util;
+
 pub use schema::*;
 pub use table::*;
 pub use util::*;
@@ -336,7 +336,7 @@ impl<F: TableSchema, R: TableReplication> Worker for MerkleWorker<F, R> {
 }
 
 impl MerkleNodeKey {
-	fn encode(&self) -> Vec<u8> {
+	pub(crate) fn encode(&self) -> Vec<u8> {
 		let mut ret = Vec::with_capacity(2 + self.prefix.len());
 		ret.extend(&u16::to_be_bytes(self.partition)[..]);
 		ret.extend(&self.prefix[..]);
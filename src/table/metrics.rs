@@ -2,12 +2,17 @@ use opentelemetry::{global, metrics::*, KeyValue};
 
 use garage_db as db;
 
+use garage_util::time::now_msec;
+
+use crate::gc::GcTodoEntry;
+
 /// TableMetrics reference all counter used for metrics
 pub struct TableMetrics {
 	pub(crate) _table_size: ValueObserver<u64>,
 	pub(crate) _merkle_tree_size: ValueObserver<u64>,
 	pub(crate) _merkle_todo_len: ValueObserver<u64>,
 	pub(crate) _gc_todo_len: ValueObserver<u64>,
+	pub(crate) _gc_oldest_tombstone_age: ValueObserver<f64>,
 
 	pub(crate) get_request_counter: BoundCounter<u64>,
 	pub(crate) get_request_duration: BoundValueRecorder<f64>,
@@ -19,6 +24,9 @@ pub struct TableMetrics {
 
 	pub(crate) sync_items_sent: Counter<u64>,
 	pub(crate) sync_items_received: Counter<u64>,
+
+	pub(crate) gc_deleted_items: BoundCounter<u64>,
+	pub(crate) gc_errors: BoundCounter<u64>,
 }
 impl TableMetrics {
 	pub fn new(
@@ -29,6 +37,7 @@ impl TableMetrics {
 		gc_todo: db::Tree,
 	) -> Self {
 		let meter = global::meter(table_name);
+		let gc_todo_first = gc_todo.clone();
 		TableMetrics {
 			_table_size: meter
 				.u64_value_observer(
@@ -86,6 +95,23 @@ impl TableMetrics {
 				)
 				.with_description("Table garbage collector TODO queue length")
 				.init(),
+			_gc_oldest_tombstone_age: meter
+				.f64_value_observer(
+					"table.gc_oldest_tombstone_age",
+					move |observer| {
+						let age = match gc_todo_first.first() {
+							Ok(Some((k, _))) => {
+								now_msec().saturating_sub(GcTodoEntry::parse_timestamp(&k)) as f64
+									/ 1000.
+							}
+							Ok(None) => 0.,
+							Err(_) => return,
+						};
+						observer.observe(age, &[KeyValue::new("table_name", table_name)]);
+					},
+				)
+				.with_description("Age of the oldest tombstone in the table garbage collector TODO queue, in seconds")
+				.init(),
 
 			get_request_counter: meter
 				.u64_counter("table.get_request_counter")
@@ -127,6 +153,17 @@ impl TableMetrics {
 				.u64_counter("table.sync_items_received")
 				.with_description("Number of data items received from other nodes during resync procedures")
 				.init(),
+
+			gc_deleted_items: meter
+				.u64_counter("table.gc_deleted_items")
+				.with_description("Number of tombstones deleted by the table garbage collector")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
+			gc_errors: meter
+				.u64_counter("table.gc_errors")
+				.with_description("Number of failed attempts of the table garbage collector to delete a batch of tombstones")
+				.init()
+				.bind(&[KeyValue::new("table_name", table_name)]),
 		}
 	}
 }
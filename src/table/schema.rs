@@ -100,4 +100,17 @@ pub trait TableSchema: Send + Sync + 'static {
 	}
 
 	fn matches_filter(entry: &Self::E, filter: &Self::Filter) -> bool;
+
+	/// Names of the secondary indexes of the table (see the `index` module).
+	/// When an index is added, it is built by a background worker on each node
+	/// at startup; when the keys of an existing index change, it must be
+	/// renamed so that it is built again.
+	const INDEXES: &'static [&'static str] = &[];
+
+	/// Keys of an entry in the secondary indexes of the table, as a list
+	/// of (index name, key in the index). An entry can have any number of
+	/// keys in an index. Tombstones are never indexed.
+	fn index_keys(_entry: &Self::E) -> Vec<(&'static str, Vec<u8>)> {
+		vec![]
+	}
 }
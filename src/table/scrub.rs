@@ -0,0 +1,388 @@
+use core::ops::Bound;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tokio::sync::watch;
+
+use garage_db as db;
+
+use garage_util::background::*;
+use garage_util::data::*;
+use garage_util::encode::nonversioned_decode;
+use garage_util::error::Error;
+use garage_util::migrate::Migrate;
+use garage_util::persister::PersisterShared;
+use garage_util::tranquilizer::Tranquilizer;
+
+use garage_rpc::system::System;
+
+use crate::data::*;
+use crate::merkle::*;
+use crate::replication::*;
+use crate::schema::*;
+use crate::table::Table;
+
+// This module checks the integrity of the items stored locally in a table:
+// - each item can be decoded (with migrations from older formats)
+// - each item is stored under the key derived from its partition and sort keys
+// - each item belongs to a partition that this node should store
+// - the Merkle tree contains the hash of each item
+
+const INITIAL_META_SCRUB_TRANQUILITY: u32 = 2;
+
+/// Number of items checked at each step of the worker
+const SCRUB_BATCH_SIZE: usize = 100;
+
+mod v1 {
+	use serde::{Deserialize, Serialize};
+
+	#[derive(Serialize, Deserialize)]
+	pub struct MetaScrubPersisted {
+		pub tranquility: u32,
+		pub corruptions_detected: u64,
+	}
+
+	impl garage_util::migrate::InitialFormat for MetaScrubPersisted {}
+}
+
+pub use v1::*;
+
+impl Default for MetaScrubPersisted {
+	fn default() -> Self {
+		MetaScrubPersisted {
+			tranquility: INITIAL_META_SCRUB_TRANQUILITY,
+			corruptions_detected: 0,
+		}
+	}
+}
+
+/// State shared by the scrub workers of the tables of a node
+#[derive(Clone)]
+pub struct MetaScrubShared {
+	pub persister: PersisterShared<MetaScrubPersisted>,
+	/// Held by the workers until they exit, so that a new scrub
+	/// is not started while one is still running
+	pub running: Arc<()>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScrubIssue {
+	/// The item cannot be decoded
+	Undecodable,
+	/// The item is not stored under the key derived from its content
+	Misplaced,
+	/// The item belongs to a partition that is not stored on this node
+	Orphaned,
+	/// The Merkle tree does not contain the hash of the item
+	MerkleMismatch,
+}
+
+pub struct TableScrubWorker<F: TableSchema, R: TableReplication> {
+	system: Arc<System>,
+	data: Arc<TableData<F, R>>,
+	persister: PersisterShared<MetaScrubPersisted>,
+	tranquilizer: Tranquilizer,
+
+	pos: Vec<u8>,
+	counter: u64,
+	undecodable: u64,
+	misplaced: u64,
+	orphaned: u64,
+	merkle_mismatches: u64,
+	done: bool,
+
+	_running: Arc<()>,
+}
+
+impl<F: TableSchema, R: TableReplication> TableScrubWorker<F, R> {
+	pub fn new(table: &Table<F, R>, shared: MetaScrubShared) -> Self {
+		Self {
+			system: table.system.clone(),
+			data: table.data.clone(),
+			persister: shared.persister,
+			tranquilizer: Tranquilizer::new(30),
+			pos: vec![],
+			counter: 0,
+			undecodable: 0,
+			misplaced: 0,
+			orphaned: 0,
+			merkle_mismatches: 0,
+			done: false,
+			_running: shared.running,
+		}
+	}
+
+	fn issues(&self) -> u64 {
+		self.undecodable + self.misplaced + self.orphaned + self.merkle_mismatches
+	}
+
+	fn check_item(&self, k: &[u8], v: &[u8]) -> Result<Option<ScrubIssue>, Error> {
+		let entry = match F::E::decode(v) {
+			Some(e) => e,
+			None => return Ok(Some(ScrubIssue::Undecodable)),
+		};
+		if self.data.tree_key(entry.partition_key(), entry.sort_key()) != k {
+			return Ok(Some(ScrubIssue::Misplaced));
+		}
+
+		let hash = Hash::try_from(&k[0..32]).unwrap();
+		if !self
+			.data
+			.replication
+			.storage_nodes(&hash)
+			.contains(&self.system.id)
+		{
+			return Ok(Some(ScrubIssue::Orphaned));
+		}
+
+		if self.data.merkle_todo.get(k)?.is_none() && !self.merkle_leaf_matches(k, v)? {
+			return Ok(Some(ScrubIssue::MerkleMismatch));
+		}
+		Ok(None)
+	}
+
+	/// Check that the leaf of the Merkle tree for an item contains
+	/// the hash of its current value
+	fn merkle_leaf_matches(&self, k: &[u8], v: &[u8]) -> Result<bool, Error> {
+		let khash = blake2sum(k);
+		let mut key = MerkleNodeKey {
+			partition: self
+				.data
+				.replication
+				.partition_of(&Hash::try_from(&k[0..32]).unwrap()),
+			prefix: vec![],
+		};
+		loop {
+			let node = match self.data.merkle_tree.get(key.encode())? {
+				None => return Ok(false),
+				Some(bytes) => nonversioned_decode::<MerkleNode>(&bytes)?,
+			};
+			match node {
+				MerkleNode::Empty => return Ok(false),
+				MerkleNode::Leaf(leaf_k, leaf_vhash) => {
+					return Ok(leaf_k == k && leaf_vhash == blake2sum(v));
+				}
+				MerkleNode::Intermediate(children) => {
+					if key.prefix.len() == khash.as_slice().len() {
+						return Ok(false);
+					}
+					let next = key.next_key(&khash);
+					if !children
+						.iter()
+						.any(|(b, _)| *b == next.prefix[key.prefix.len()])
+					{
+						return Ok(false);
+					}
+					key = next;
+				}
+			}
+		}
+	}
+
+	/// Add an item to the Merkle todo queue, so that its leaf in the
+	/// Merkle tree is recalculated, if it has not changed in the meantime
+	fn requeue_merkle_update(&self, k: &[u8], v: &[u8]) -> Result<(), Error> {
+		let data = &self.data;
+		data.store.db().transaction(|tx| {
+			if tx.get(&data.store, k)?.as_deref() == Some(v)
+				&& tx.get(&data.merkle_todo, k)?.is_none()
+			{
+				tx.insert(&data.merkle_todo, k, blake2sum(v).as_slice())?;
+			}
+			Ok::<_, db::TxError<Error>>(())
+		})?;
+		data.merkle_todo_notify.notify_one();
+		Ok(())
+	}
+
+	fn scrub_batch(&mut self) -> Result<bool, Error> {
+		let items = self
+			.data
+			.store
+			.range::<&[u8], _>((Bound::Excluded(&self.pos[..]), Bound::Unbounded))?
+			.take(SCRUB_BATCH_SIZE)
+			.collect::<Result<Vec<_>, _>>()?;
+
+		for (k, v) in items.iter() {
+			self.counter += 1;
+			let issue = match self.check_item(k, v)? {
+				None => continue,
+				Some(ScrubIssue::Undecodable) => ScrubIssue::Undecodable,
+				Some(issue) => {
+					// The item might have been modified during the check,
+					// only report it if it is still found after a second check
+					if self.data.store.get(k)?.as_deref() != Some(&v[..]) {
+						continue;
+					}
+					match self.check_item(k, v)? {
+						Some(issue2) if issue2 == issue => issue,
+						_ => continue,
+					}
+				}
+			};
+
+			match issue {
+				ScrubIssue::Undecodable => {
+					error!(
+						"({}) Metadata scrub: item {} cannot be decoded",
+						F::TABLE_NAME,
+						hex::encode(k)
+					);
+					self.undecodable += 1;
+				}
+				ScrubIssue::Misplaced => {
+					error!(
+						"({}) Metadata scrub: item {} is not stored under the key derived from its content",
+						F::TABLE_NAME,
+						hex::encode(k)
+					);
+					self.misplaced += 1;
+				}
+				ScrubIssue::Orphaned => {
+					warn!(
+						"({}) Metadata scrub: item {} belongs to a partition not stored on this node",
+						F::TABLE_NAME,
+						hex::encode(k)
+					);
+					self.orphaned += 1;
+				}
+				ScrubIssue::MerkleMismatch => {
+					error!(
+						"({}) Metadata scrub: Merkle tree does not match item {}, queuing Merkle tree update",
+						F::TABLE_NAME,
+						hex::encode(k)
+					);
+					self.merkle_mismatches += 1;
+					self.requeue_merkle_update(k, v)?;
+				}
+			}
+			if issue != ScrubIssue::Orphaned {
+				self.persister.set_with(|p| p.corruptions_detected += 1)?;
+			}
+		}
+
+		match items.last() {
+			Some((k, _)) if items.len() == SCRUB_BATCH_SIZE => {
+				self.pos = k.clone();
+				Ok(true)
+			}
+			_ => Ok(false),
+		}
+	}
+}
+
+#[async_trait]
+impl<F: TableSchema, R: TableReplication> Worker for TableScrubWorker<F, R> {
+	fn name(&self) -> String {
+		format!("{} metadata scrub", F::TABLE_NAME)
+	}
+
+	fn status(&self) -> WorkerStatus {
+		let progress = if self.done {
+			format!("{} items, done", self.counter)
+		} else {
+			let done = match self.pos.get(0..2) {
+				Some(p) => u16::from_be_bytes([p[0], p[1]]) as f32 / 65536.,
+				None => 0.,
+			};
+			format!("{} items, {:.2}%", self.counter, done * 100.)
+		};
+
+		let mut freeform = vec![];
+		if self.undecodable > 0 {
+			freeform.push(format!("Undecodable items: {}", self.undecodable));
+		}
+		if self.misplaced > 0 {
+			freeform.push(format!("Misplaced items: {}", self.misplaced));
+		}
+		if self.orphaned > 0 {
+			freeform.push(format!(
+				"Items of partitions not stored on this node: {}",
+				self.orphaned
+			));
+		}
+		if self.merkle_mismatches > 0 {
+			freeform.push(format!(
+				"Items not matching the Merkle tree (requeued): {}",
+				self.merkle_mismatches
+			));
+		}
+
+		WorkerStatus {
+			progress: Some(progress),
+			tranquility: Some(self.persister.get_with(|p| p.tranquility)),
+			persistent_errors: Some(self.issues()),
+			freeform,
+			..Default::default()
+		}
+	}
+
+	async fn work(&mut self, _must_exit: &mut watch::Receiver<bool>) -> Result<WorkerState, Error> {
+		self.tranquilizer.reset();
+		if !self.scrub_batch()? {
+			info!(
+				"({}) Metadata scrub finished: {} items checked, {} issues found",
+				F::TABLE_NAME,
+				self.counter,
+				self.issues()
+			);
+			self.done = true;
+			return Ok(WorkerState::Done);
+		}
+
+		Ok(self
+			.tranquilizer
+			.tranquilize_worker(self.persister.get_with(|p| p.tranquility)))
+	}
+
+	async fn wait_for_work(&mut self) -> WorkerState {
+		unreachable!()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::test_util::*;
+
+	#[tokio::test]
+	async fn test_scrub_detects_corruptions() {
+		let (system, _dir) = test_system().await;
+		let table = test_table(&system);
+
+		let valid = TestEntry::new("p", "a", 1, "x");
+		table.data.update_entry(&valid.encode().unwrap()).unwrap();
+
+		// An item that cannot be decoded
+		let undecodable = table.data.tree_key(&"p".into(), &"b".into());
+		table.data.store.insert(&undecodable, b"garbage").unwrap();
+
+		// A valid item stored under the key of another item
+		let misplaced = table.data.tree_key(&"p".into(), &"c".into());
+		let entry = TestEntry::new("p", "d", 1, "y");
+		table
+			.data
+			.store
+			.insert(&misplaced, entry.encode().unwrap())
+			.unwrap();
+
+		let persister = PersisterShared::new(&system.metadata_dir, "meta_scrub_info");
+		let mut worker = TableScrubWorker::new(
+			&table,
+			MetaScrubShared {
+				persister: persister.clone(),
+				running: Arc::new(()),
+			},
+		);
+		assert!(!worker.scrub_batch().unwrap());
+
+		assert_eq!(worker.counter, 3);
+		assert_eq!(worker.undecodable, 1);
+		assert_eq!(worker.misplaced, 1);
+		assert_eq!(worker.orphaned, 0);
+		assert_eq!(worker.merkle_mismatches, 0);
+		assert_eq!(worker.status().persistent_errors, Some(2));
+		assert_eq!(persister.get_with(|p| p.corruptions_detected), 2);
+	}
+}
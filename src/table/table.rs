@@ -1,5 +1,5 @@
 use std::borrow::Borrow;
-use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
 
 use futures::stream::*;
@@ -26,6 +26,8 @@ use garage_rpc::*;
 use crate::crdt::Crdt;
 use crate::data::*;
 use crate::gc::*;
+use crate::hints::HintedHandoffWorker;
+use crate::index::*;
 use crate::merkle::*;
 use crate::queue::InsertQueueWorker;
 use crate::replication::*;
@@ -39,7 +41,7 @@ pub struct Table<F: TableSchema, R: TableReplication> {
 	pub merkle_updater: Arc<MerkleUpdater<F, R>>,
 	pub syncer: Arc<TableSyncer<F, R>>,
 	gc: Arc<TableGc<F, R>>,
-	endpoint: Arc<Endpoint<TableRpc<F>, Self>>,
+	pub(crate) endpoint: Arc<Endpoint<TableRpc<F>, Self>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -59,6 +61,16 @@ pub(crate) enum TableRpc<F: TableSchema> {
 	},
 
 	Update(Vec<Arc<ByteBuf>>),
+
+	// Read the entries of partition P in the order of a secondary index
+	ReadIndexRange {
+		partition: F::P,
+		index: String,
+		range: IndexRange,
+		filter: Option<F::Filter>,
+		limit: usize,
+	},
+	ReadIndexRangeResponse(Vec<(ByteBuf, Arc<ByteBuf>)>),
 }
 
 impl<F: TableSchema> Rpc for TableRpc<F> {
@@ -101,6 +113,26 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		self.syncer.spawn_workers(bg);
 		self.gc.spawn_workers(bg);
 		bg.spawn_worker(InsertQueueWorker(self.clone()));
+		bg.spawn_worker(HintedHandoffWorker(self.clone()));
+		match IndexRebuildWorker::new(self.data.clone()) {
+			Ok(Some(worker)) => bg.spawn_worker(worker),
+			Ok(None) => (),
+			Err(e) => error!(
+				"({}) Could not check state of indexes: {}",
+				F::TABLE_NAME,
+				e
+			),
+		}
+	}
+
+	/// Delay between the moment an entry becomes a tombstone
+	/// and the moment it is garbage collected
+	pub fn gc_delay(&self) -> std::time::Duration {
+		self.gc.delay()
+	}
+
+	pub fn set_gc_delay(&self, delay: std::time::Duration) {
+		self.gc.set_delay(delay);
 	}
 
 	pub async fn insert(&self, e: &F::E) -> Result<(), Error> {
@@ -121,6 +153,17 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		let hash = e.partition_key().hash();
 		let who = self.data.replication.write_sets(&hash);
 
+		// Nodes in maintenance are skipped by try_write_many_sets,
+		// keep the entry for them until they are back
+		let hints = self
+			.system
+			.rpc_helper()
+			.nodes_in_maintenance(who.as_ref().iter().flatten())
+			.into_iter()
+			.map(|node| (node, e))
+			.collect::<Vec<_>>();
+		self.data.queue_hints(&hints)?;
+
 		let e_enc = Arc::new(ByteBuf::from(e.encode()?));
 		let rpc = TableRpc::<F>::Update(vec![e_enc]);
 
@@ -190,6 +233,7 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		// These locks are released when entries_vec is dropped, i.e. when this
 		// function returns.
 		let mut entries_vec = Vec::new();
+		let mut hints = Vec::new();
 		for entry in entries.into_iter() {
 			let entry = entry.borrow();
 			let hash = entry.partition_key().hash();
@@ -199,9 +243,15 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				// nodes but in possibly different orders
 				set.sort();
 			}
+			// Nodes in maintenance will miss this entry, keep it for them
+			let rpc_helper = self.system.rpc_helper();
+			for node in rpc_helper.nodes_in_maintenance(write_sets.as_ref().iter().flatten()) {
+				hints.push((node, entry.clone()));
+			}
 			let e_enc = Arc::new(ByteBuf::from(entry.encode()?));
 			entries_vec.push((write_sets, e_enc));
 		}
+		self.data.queue_hints(&hints)?;
 
 		if entries_vec.is_empty() {
 			return Ok(());
@@ -219,11 +269,26 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 
 		let mut result_tracker = QuorumSetResultTracker::new(&write_sets, quorum);
 
+		// Nodes in maintenance are not sent any request, hints have been
+		// queued for them above. They count as failures in their write sets.
+		let in_maintenance = self
+			.system
+			.rpc_helper()
+			.nodes_in_maintenance(result_tracker.nodes.keys())
+			.into_iter()
+			.collect::<HashSet<Uuid>>();
+		for node in in_maintenance.iter() {
+			result_tracker.register_result(*node, Err(Error::NodeInMaintenance(*node)));
+		}
+		if result_tracker.too_many_failures() {
+			return Err(result_tracker.quorum_error());
+		}
+
 		// Build a map of all nodes to the entries that must be sent to that node.
 		let mut call_list: HashMap<Uuid, Vec<_>> = HashMap::new();
 		for (write_sets, entry_enc) in entries_vec.iter() {
 			for write_set in write_sets.as_ref().iter() {
-				for node in write_set.iter() {
+				for node in write_set.iter().filter(|n| !in_maintenance.contains(n)) {
 					let node_entries = call_list.entry(*node).or_default();
 					match node_entries.last() {
 						Some(x) if Arc::ptr_eq(x, entry_enc) => {
@@ -320,7 +385,8 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who,
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.read_quorum()),
+					.with_quorum(self.data.replication.read_quorum())
+					.with_hedging(),
 			)
 			.await?;
 
@@ -415,7 +481,8 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 				&who,
 				rpc,
 				RequestStrategy::with_priority(PRIO_NORMAL)
-					.with_quorum(self.data.replication.read_quorum()),
+					.with_quorum(self.data.replication.read_quorum())
+					.with_hedging(),
 			)
 			.await?;
 
@@ -482,6 +549,137 @@ impl<F: TableSchema, R: TableReplication> Table<F, R> {
 		Ok(ret_vec)
 	}
 
+	/// Read the entries of a partition whose keys in a secondary index of the
+	/// table are in `range`, in the order of the index. Returns at most `limit`
+	/// entries along with their key in the index, and the position from which
+	/// to continue reading if there might be more entries in the range.
+	pub async fn get_index_range(
+		self: &Arc<Self>,
+		partition_key: &F::P,
+		index: &str,
+		range: IndexRange,
+		filter: Option<F::Filter>,
+		limit: usize,
+	) -> Result<(Vec<(Vec<u8>, F::E)>, Option<IndexPos>), Error> {
+		let tracer = opentelemetry::global::tracer("garage_table");
+		let span = tracer.start(format!("{} get_index_range", F::TABLE_NAME));
+
+		let res = self
+			.get_index_range_internal(partition_key, index, range, filter, limit)
+			.bound_record_duration(&self.data.metrics.get_request_duration)
+			.with_context(Context::current_with_span(span))
+			.await?;
+
+		self.data.metrics.get_request_counter.add(1);
+
+		Ok(res)
+	}
+
+	async fn get_index_range_internal(
+		self: &Arc<Self>,
+		partition_key: &F::P,
+		index: &str,
+		range: IndexRange,
+		filter: Option<F::Filter>,
+		limit: usize,
+	) -> Result<(Vec<(Vec<u8>, F::E)>, Option<IndexPos>), Error> {
+		self.data.index_tree(index)?;
+
+		let hash = partition_key.hash();
+		let who = self.data.replication.read_nodes(&hash);
+
+		let rpc = TableRpc::<F>::ReadIndexRange {
+			partition: partition_key.clone(),
+			index: index.to_string(),
+			range,
+			filter: filter.clone(),
+			limit,
+		};
+
+		let resps = self
+			.system
+			.rpc_helper()
+			.try_call_many(
+				&self.endpoint,
+				&who,
+				rpc,
+				RequestStrategy::with_priority(PRIO_NORMAL)
+					.with_quorum(self.data.replication.read_quorum())
+					.with_hedging(),
+			)
+			.await?;
+
+		// Entries are indexed by the key of their index entry,
+		// which sorts them in the order of the index
+		let mut ret: BTreeMap<Vec<u8>, (Vec<u8>, F::E)> = BTreeMap::new();
+		let mut to_repair = BTreeSet::new();
+		let mut more = false;
+		for resp in resps {
+			if let TableRpc::ReadIndexRangeResponse(entries) = resp {
+				more = more || entries.len() >= limit;
+				for (index_key, entry_bytes) in entries.iter() {
+					let entry = self.data.decode_entry(entry_bytes.as_slice())?;
+					let index_tree_key =
+						index_tree_key(hash.as_slice(), index_key, entry.sort_key().sort_key());
+					match ret.get_mut(&index_tree_key) {
+						Some((_, e)) => {
+							if *e != entry {
+								e.merge(&entry);
+								to_repair.insert(index_tree_key.clone());
+							}
+						}
+						None => {
+							ret.insert(index_tree_key, (index_key.to_vec(), entry));
+						}
+					}
+				}
+			} else {
+				return Err(Error::unexpected_rpc_message(resp));
+			}
+		}
+
+		if !to_repair.is_empty() {
+			let self2 = self.clone();
+			let to_repair = to_repair
+				.into_iter()
+				.map(|k| ret.get(&k).unwrap().1.clone())
+				.collect::<Vec<_>>();
+			tokio::spawn(async move {
+				for v in to_repair {
+					if let Err(e) = self2.repair_on_read(&who[..], v).await {
+						warn!("Error doing repair on read: {}", e);
+					}
+				}
+			});
+		}
+
+		// As in get_range, only the first `limit` entries can be returned.
+		// Entries merged from several nodes might no longer have the key
+		// with which they were found in the index, or no longer match the
+		// filter: they are skipped, but the position of the last entry is
+		// still returned so that reading can continue after it.
+		let entries = ret.into_values().take(limit).collect::<Vec<_>>();
+		let next = match entries.last() {
+			Some((index_key, entry)) if more => Some(IndexPos {
+				index_key: index_key.clone(),
+				sort_key: entry.sort_key().sort_key().to_vec(),
+			}),
+			_ => None,
+		};
+		let entries = entries
+			.into_iter()
+			.filter(|(index_key, entry)| {
+				self.data.entry_has_index_key(entry, index, index_key)
+					&& filter
+						.as_ref()
+						.map(|f| F::matches_filter(entry, f))
+						.unwrap_or(true)
+			})
+			.collect::<Vec<_>>();
+
+		Ok((entries, next))
+	}
+
 	// =============== UTILITY FUNCTION FOR CLIENT OPERATIONS ===============
 
 	async fn repair_on_read(&self, who: &[Uuid], what: F::E) -> Result<(), Error> {
@@ -530,6 +728,18 @@ impl<F: TableSchema, R: TableReplication> EndpointHandler<TableRpc<F>> for Table
 				self.data.update_many(pairs)?;
 				Ok(TableRpc::Ok)
 			}
+			TableRpc::ReadIndexRange {
+				partition,
+				index,
+				range,
+				filter,
+				limit,
+			} => {
+				let values = self
+					.data
+					.read_index_range(partition, index, range, filter, *limit)?;
+				Ok(TableRpc::ReadIndexRangeResponse(values))
+			}
 			m => Err(Error::unexpected_rpc_message(m)),
 		}
 	}
@@ -0,0 +1,158 @@
+//! Single-node table running in the test process, with a simple schema,
+//! for testing table internals without the network layer
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use garage_db as db;
+
+use garage_util::config::Config;
+use garage_util::migrate::InitialFormat;
+
+use garage_net::NetworkKey;
+
+use garage_rpc::layout::{NodeRole, NodeRoleV};
+use garage_rpc::replication_mode::{ConsistencyMode, ReplicationFactor};
+use garage_rpc::system::System;
+
+use crate::crdt::Crdt;
+use crate::replication::TableShardedReplication;
+use crate::schema::*;
+use crate::table::Table;
+
+/// An entry with a value, which is also its key in the `value` index
+#[derive(PartialEq, Eq, Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct TestEntry {
+	pub partition: String,
+	pub sort: String,
+	pub timestamp: u64,
+	pub value: String,
+	pub deleted: bool,
+}
+
+impl TestEntry {
+	pub(crate) fn new(partition: &str, sort: &str, timestamp: u64, value: &str) -> Self {
+		Self {
+			partition: partition.to_string(),
+			sort: sort.to_string(),
+			timestamp,
+			value: value.to_string(),
+			deleted: false,
+		}
+	}
+
+	pub(crate) fn tombstone(partition: &str, sort: &str, timestamp: u64) -> Self {
+		Self {
+			deleted: true,
+			..Self::new(partition, sort, timestamp, "")
+		}
+	}
+}
+
+impl InitialFormat for TestEntry {}
+
+impl Crdt for TestEntry {
+	fn merge(&mut self, other: &Self) {
+		if (other.timestamp, other.deleted, &other.value)
+			> (self.timestamp, self.deleted, &self.value)
+		{
+			*self = other.clone();
+		}
+	}
+}
+
+impl Entry<String, String> for TestEntry {
+	fn partition_key(&self) -> &String {
+		&self.partition
+	}
+	fn sort_key(&self) -> &String {
+		&self.sort
+	}
+	fn is_tombstone(&self) -> bool {
+		self.deleted
+	}
+}
+
+pub(crate) struct TestSchema;
+
+impl TableSchema for TestSchema {
+	const TABLE_NAME: &'static str = "test";
+
+	type P = String;
+	type S = String;
+	type E = TestEntry;
+	type Filter = ();
+
+	fn matches_filter(_entry: &TestEntry, _filter: &()) -> bool {
+		true
+	}
+
+	const INDEXES: &'static [&'static str] = &["value"];
+
+	fn index_keys(entry: &TestEntry) -> Vec<(&'static str, Vec<u8>)> {
+		vec![("value", entry.value.as_bytes().to_vec())]
+	}
+}
+
+pub(crate) type TestTable = Table<TestSchema, TableShardedReplication>;
+
+/// Create a System whose metadata is stored in a temporary directory,
+/// which is deleted when the returned guard is dropped. The node is given
+/// a storage role in the cluster layout, so that writes to tables succeed.
+pub(crate) async fn test_system() -> (Arc<System>, mktemp::Temp) {
+	let dir = mktemp::Temp::new_dir().unwrap();
+	let config = format!(
+		r#"
+		metadata_dir = "{path}/meta"
+		data_dir = "{path}/data"
+		replication_factor = 1
+		rpc_bind_addr = "127.0.0.1:3901"
+
+		[s3_api]
+		s3_region = "garage"
+		api_bind_addr = "127.0.0.1:3900"
+		"#,
+		path = dir.to_string_lossy()
+	);
+	let config: Config = toml::from_str(&config).unwrap();
+	let network_key = NetworkKey::from_slice(&[0x42; 32]).unwrap();
+	let system = System::new(
+		network_key,
+		ReplicationFactor::new(1).unwrap(),
+		ConsistencyMode::Consistent,
+		&config,
+	)
+	.unwrap();
+
+	let mut layout = system.cluster_layout().inner().clone();
+	let role = NodeRole {
+		zone: "dc1".into(),
+		capacity: Some(1 << 30),
+		tags: vec![],
+	};
+	let staging = layout.staging.get_mut();
+	let update = staging
+		.roles
+		.update_mutator(system.id, NodeRoleV(Some(role)));
+	staging.roles.merge(&update);
+	let (layout, _) = layout.apply_staged_changes(Some(1)).unwrap();
+	system
+		.layout_manager
+		.update_cluster_layout(&layout)
+		.await
+		.unwrap();
+
+	(system, dir)
+}
+
+/// Create the test table on `system`, stored in an in-memory database
+pub(crate) fn test_table(system: &Arc<System>) -> Arc<TestTable> {
+	let replication = TableShardedReplication {
+		system: system.clone(),
+		replication_factor: 1,
+		read_quorum: 1,
+		write_quorum: 1,
+	};
+	let db = db::memory_adapter::MemDb::init();
+	Table::new(TestSchema, replication, system.clone(), &db)
+}
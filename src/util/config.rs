@@ -38,6 +38,10 @@ pub struct Config {
 	#[serde(default)]
 	pub metadata_auto_snapshot_interval: Option<String>,
 
+	/// Optional S3 bucket where metadata snapshots are also uploaded
+	#[serde(default)]
+	pub metadata_snapshots_s3: Option<SnapshotS3Config>,
+
 	/// Size of data blocks to save to disk
 	#[serde(
 		deserialize_with = "deserialize_capacity",
@@ -60,6 +64,11 @@ pub struct Config {
 	/// Legacy option
 	pub replication_mode: Option<String>,
 
+	/// Erasure coding of data blocks. If not set, data blocks are
+	/// stored as `replication_factor` full copies.
+	#[serde(default)]
+	pub erasure_coding: Option<ErasureCodingConfig>,
+
 	/// Zstd compression level used on data blocks
 	#[serde(
 		deserialize_with = "deserialize_compression",
@@ -112,6 +121,9 @@ pub struct Config {
 	/// Configuration for automatic node discovery through Kubernetes
 	#[serde(default)]
 	pub kubernetes_discovery: Option<KubernetesDiscoveryConfig>,
+	/// Configuration for automatic node discovery through DNS records
+	#[serde(default)]
+	pub dns_discovery: Option<DnsDiscoveryConfig>,
 
 	// -- DB
 	/// Database engine to use for metadata (options: sqlite, lmdb)
@@ -122,6 +134,21 @@ pub struct Config {
 	#[serde(deserialize_with = "deserialize_capacity", default)]
 	pub lmdb_map_size: usize,
 
+	/// Delay before tombstones are garbage collected, for each table
+	/// (table name -> duration), 24 hours for tables not listed here
+	#[serde(default)]
+	pub tombstone_retention: std::collections::HashMap<String, String>,
+
+	/// Depths of the prefixes (in number of `/`-separated segments of object keys)
+	/// at which the usage of buckets is counted, none if empty
+	#[serde(default)]
+	pub prefix_usage_depths: Vec<usize>,
+
+	/// Periodic sampling of the usage of buckets into a history table,
+	/// disabled if not set
+	#[serde(default)]
+	pub usage_history: Option<UsageHistoryConfig>,
+
 	// -- APIs
 	/// Configuration for S3 api
 	pub s3_api: S3ApiConfig,
@@ -139,6 +166,11 @@ pub struct Config {
 	/// Allow punycode in bucket names
 	#[serde(default)]
 	pub allow_punycode: bool,
+
+	/// Initial layout, keys and buckets, created when the node starts
+	/// (intended for test and CI clusters)
+	#[serde(default)]
+	pub bootstrap: Option<BootstrapConfig>,
 }
 
 /// Value for data_dir: either a single directory or a list of dirs with attributes
@@ -259,6 +291,136 @@ pub struct KubernetesDiscoveryConfig {
 	pub skip_crd: bool,
 }
 
+#[derive(Deserialize, Debug, Clone)]
+pub struct DnsDiscoveryConfig {
+	/// Name of the SRV records that give the names and RPC ports of nodes
+	pub srv_name: Option<String>,
+	/// Name of the A/AAAA records that give the addresses of nodes,
+	/// if `srv_name` is not used
+	pub hostname: Option<String>,
+	/// RPC port of nodes, used with `hostname`
+	pub port: Option<u16>,
+	/// Address of the DNS server to query (defaults to the first name server
+	/// of /etc/resolv.conf)
+	pub resolver: Option<String>,
+}
+
+/// Upload of metadata snapshots to a bucket of an S3 endpoint,
+/// which can be another Garage cluster
+#[derive(Deserialize, Debug, Clone)]
+pub struct SnapshotS3Config {
+	/// S3 endpoint (e.g. `https://s3.example.com`), used with path-style requests
+	pub endpoint: String,
+	/// Region to use when signing requests
+	#[serde(default = "default_snapshots_s3_region")]
+	pub region: String,
+	/// Bucket where the snapshots are uploaded
+	pub bucket: String,
+	/// Access key ID
+	pub access_key_id: String,
+	/// Secret access key
+	pub secret_access_key: String,
+	/// Prefix of the keys of uploaded snapshots, to which the ID of the node is appended
+	#[serde(default)]
+	pub prefix: String,
+	/// Zstd compression level of uploaded snapshots, or `none` to disable compression
+	#[serde(
+		deserialize_with = "deserialize_compression",
+		default = "default_compression"
+	)]
+	pub compression_level: Option<i32>,
+	/// Number of snapshots of this node kept in the bucket
+	#[serde(default = "default_snapshots_s3_keep")]
+	pub keep: usize,
+}
+
+/// Configuration of the history of bucket usage
+#[derive(Deserialize, Debug, Clone)]
+pub struct UsageHistoryConfig {
+	/// Interval between two samples of the usage of each bucket
+	#[serde(default = "default_usage_history_sample_interval")]
+	pub sample_interval: String,
+	/// How long hourly samples are kept
+	#[serde(default = "default_usage_history_hourly_retention")]
+	pub hourly_retention: String,
+	/// How long daily samples are kept
+	#[serde(default = "default_usage_history_daily_retention")]
+	pub daily_retention: String,
+}
+
+/// Configuration of the erasure coding of data blocks
+#[derive(Deserialize, Debug, Clone, Copy)]
+pub struct ErasureCodingConfig {
+	/// Number of data shards each block is split into
+	pub data_shards: usize,
+	/// Number of parity shards computed for each block
+	pub parity_shards: usize,
+}
+
+/// Initial configuration of a single-node cluster, applied at startup
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct BootstrapConfig {
+	/// Role of this node, assigned if the cluster has no layout yet
+	pub layout: Option<BootstrapLayoutConfig>,
+	/// Access keys to import if they don't exist
+	#[serde(default)]
+	pub keys: Vec<BootstrapKeyConfig>,
+	/// Buckets to create if they don't exist
+	#[serde(default)]
+	pub buckets: Vec<BootstrapBucketConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BootstrapLayoutConfig {
+	/// Zone of this node
+	pub zone: String,
+	/// Storage capacity of this node
+	#[serde(deserialize_with = "deserialize_capacity")]
+	pub capacity: usize,
+	/// Tags of this node
+	#[serde(default)]
+	pub tags: Vec<String>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BootstrapKeyConfig {
+	/// Access key ID (GK...)
+	pub id: String,
+	/// Secret access key
+	pub secret: String,
+	/// Name of the key
+	#[serde(default)]
+	pub name: String,
+	/// Whether the key is allowed to create buckets
+	#[serde(default)]
+	pub allow_create_bucket: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BootstrapBucketConfig {
+	/// Global alias of the bucket
+	pub name: String,
+	/// Number of copies kept of the data of the bucket, if lower than
+	/// the replication factor of the cluster (only used at creation)
+	#[serde(default)]
+	pub replication_factor: Option<usize>,
+	/// Permissions given to access keys on the bucket
+	#[serde(default)]
+	pub grants: Vec<BootstrapGrantConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct BootstrapGrantConfig {
+	/// Access key ID
+	pub key: String,
+	#[serde(default)]
+	pub read: bool,
+	#[serde(default)]
+	pub write: bool,
+	#[serde(default)]
+	pub owner: bool,
+}
+
 /// Read and parse configuration
 pub fn read_config(config_file: PathBuf) -> Result<Config, Error> {
 	let config = std::fs::read_to_string(config_file)?;
@@ -285,6 +447,26 @@ fn default_compression() -> Option<i32> {
 	Some(1)
 }
 
+fn default_snapshots_s3_region() -> String {
+	"garage".into()
+}
+
+fn default_snapshots_s3_keep() -> usize {
+	2
+}
+
+fn default_usage_history_sample_interval() -> String {
+	"15m".into()
+}
+
+fn default_usage_history_hourly_retention() -> String {
+	"7d".into()
+}
+
+fn default_usage_history_daily_retention() -> String {
+	"400d".into()
+}
+
 fn deserialize_compression<'de, D>(deserializer: D) -> Result<Option<i32>, D::Error>
 where
 	D: de::Deserializer<'de>,
@@ -409,4 +591,89 @@ mod tests {
 
 		Ok(())
 	}
+
+	#[test]
+	fn test_bootstrap() -> Result<(), Error> {
+		let path2 = mktemp::Temp::new_file()?;
+		let mut file2 = File::create(path2.as_path())?;
+		writeln!(
+			file2,
+			r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_factor = 1
+			rpc_bind_addr = "[::]:3901"
+			rpc_secret = "foo"
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "[::]:3900"
+
+			[bootstrap.layout]
+			zone = "dc1"
+			capacity = "1G"
+
+			[[bootstrap.keys]]
+			id = "GK31c2f218a2e44f485b94239e"
+			secret = "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835"
+			name = "test"
+
+			[[bootstrap.buckets]]
+			name = "test-bucket"
+			grants = [{{ key = "GK31c2f218a2e44f485b94239e", read = true, write = true }}]
+			"#
+		)?;
+
+		let config = super::read_config(path2.to_path_buf())?;
+		let bootstrap = config.bootstrap.unwrap();
+		assert_eq!(bootstrap.layout.unwrap().capacity, 1_000_000_000);
+		assert_eq!(bootstrap.keys.len(), 1);
+		assert_eq!(bootstrap.keys[0].name, "test");
+		assert!(!bootstrap.keys[0].allow_create_bucket);
+		assert_eq!(bootstrap.buckets[0].name, "test-bucket");
+		assert!(bootstrap.buckets[0].grants[0].write);
+		assert!(!bootstrap.buckets[0].grants[0].owner);
+		drop(path2);
+		drop(file2);
+
+		Ok(())
+	}
+
+	#[test]
+	fn test_metadata_snapshots_s3() -> Result<(), Error> {
+		let path2 = mktemp::Temp::new_file()?;
+		let mut file2 = File::create(path2.as_path())?;
+		writeln!(
+			file2,
+			r#"
+			metadata_dir = "/tmp/garage/meta"
+			data_dir = "/tmp/garage/data"
+			replication_factor = 3
+			rpc_bind_addr = "[::]:3901"
+			rpc_secret = "foo"
+
+			[s3_api]
+			s3_region = "garage"
+			api_bind_addr = "[::]:3900"
+
+			[metadata_snapshots_s3]
+			endpoint = "https://s3.example.com"
+			bucket = "snapshots"
+			access_key_id = "GK31c2f218a2e44f485b94239e"
+			secret_access_key = "b892c0665f0ada8a4755dae98baa3b133590e11dae3bcc1f9d769d67f16c3835"
+			compression_level = "none"
+			"#
+		)?;
+
+		let config = super::read_config(path2.to_path_buf())?;
+		let s3 = config.metadata_snapshots_s3.unwrap();
+		assert_eq!(s3.region, "garage");
+		assert_eq!(s3.prefix, "");
+		assert_eq!(s3.compression_level, None);
+		assert_eq!(s3.keep, 2);
+		drop(path2);
+		drop(file2);
+
+		Ok(())
+	}
 }
@@ -64,6 +64,9 @@ pub enum Error {
 	)]
 	Quorum(usize, Option<usize>, usize, usize, Vec<String>),
 
+	#[error(display = "Node {:?} is in maintenance", _0)]
+	NodeInMaintenance(Uuid),
+
 	#[error(display = "Unexpected RPC message: {}", _0)]
 	UnexpectedRpcMessage(String),
 